@@ -0,0 +1,136 @@
+//! `bux.toml`: defaults for options that would otherwise be repeated on
+//! every command.
+//!
+//! The file is the first of `./bux.toml` and `$BUX_HOME/bux.toml` (default
+//! `<platform_data_dir>/bux/bux.toml`) that exists. Each setting resolves,
+//! highest precedence first: command-line flag, environment variable, config
+//! file, built-in default.
+//!
+//! ```toml
+//! cpus = 2
+//! ram = 2048          # MiB, like --memory
+//! log_level = "warn"
+//! store_dir = "images" # relative to the file's directory
+//!
+//! [auth]              # basic auth for every registry
+//! username = "me"
+//! password = "token"
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use bux::LogLevel;
+use serde::Deserialize;
+
+/// File name searched for in each config directory.
+const FILE_NAME: &str = "bux.toml";
+
+/// Settings read from `bux.toml`; every field is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Default for `bux run --cpus`.
+    pub cpus: Option<u8>,
+    /// Default for `bux run --memory`, in MiB.
+    pub ram: Option<u32>,
+    /// Default for `bux run --log-level`.
+    #[serde(default, deserialize_with = "log_level")]
+    pub log_level: Option<LogLevel>,
+    /// Default for `--store-dir`.
+    pub store_dir: Option<PathBuf>,
+    /// Registry credentials, overridden by `BUX_REGISTRY_USERNAME` and
+    /// `BUX_REGISTRY_PASSWORD`.
+    pub auth: Option<Auth>,
+}
+
+/// Basic-auth credentials used for every registry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Auth {
+    pub username: String,
+    pub password: String,
+}
+
+impl Config {
+    /// Loads the first config file found, or defaults if there is none.
+    pub fn load() -> Result<Self> {
+        let Some(path) = find() else {
+            return Ok(Self::default());
+        };
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading {}", path.display()))?;
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        Self::parse(&text, base).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// Parses config text, resolving relative paths against `base`.
+    fn parse(text: &str, base: &Path) -> Result<Self> {
+        let mut config: Self = toml::from_str(text)?;
+        if let Some(dir) = &mut config.store_dir
+            && dir.is_relative()
+        {
+            *dir = base.join(&*dir);
+        }
+        Ok(config)
+    }
+
+    /// Registry credentials: the environment overrides the file, field by
+    /// field.
+    pub fn registry_auth(&self) -> Option<Auth> {
+        let env = |key| std::env::var(key).ok().filter(|v| !v.is_empty());
+        let file = self.auth.as_ref();
+        let username = env("BUX_REGISTRY_USERNAME").or_else(|| Some(file?.username.clone()))?;
+        let password = env("BUX_REGISTRY_PASSWORD").or_else(|| Some(file?.password.clone()))?;
+        Some(Auth { username, password })
+    }
+}
+
+/// Returns the first existing config file: `./bux.toml`, then
+/// `$BUX_HOME/bux.toml`.
+fn find() -> Option<PathBuf> {
+    let home = std::env::var_os("BUX_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(dirs::data_dir()?.join("bux")));
+    [
+        Some(PathBuf::from(FILE_NAME)),
+        home.map(|h| h.join(FILE_NAME)),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|p| p.is_file())
+}
+
+/// Accepts log levels in any case, like `--log-level`.
+fn log_level<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<LogLevel>, D::Error> {
+    let s = String::deserialize(d)?;
+    s.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_settings_and_rejects_unknown_keys() {
+        let config = Config::parse(
+            "cpus = 4\nram = 2048\nlog_level = \"WARN\"\nstore_dir = \"images\"\n\
+             [auth]\nusername = \"me\"\npassword = \"secret\"\n",
+            Path::new("/etc/bux"),
+        )
+        .unwrap();
+        assert_eq!(config.cpus, Some(4));
+        assert_eq!(config.ram, Some(2048));
+        assert_eq!(config.log_level, Some(LogLevel::Warn));
+        assert_eq!(config.store_dir.unwrap(), Path::new("/etc/bux/images"));
+        assert_eq!(config.auth.unwrap().username, "me");
+
+        let empty = Config::parse("", Path::new(".")).unwrap();
+        assert!(empty.cpus.is_none() && empty.auth.is_none());
+
+        let err = Config::parse("memory = 512\n", Path::new(".")).unwrap_err();
+        assert!(err.to_string().contains("unknown field `memory`"));
+        assert!(Config::parse("log_level = \"loud\"\n", Path::new(".")).is_err());
+    }
+}
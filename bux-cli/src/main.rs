@@ -6,17 +6,39 @@
     clippy::missing_docs_in_private_items
 )]
 
+mod config;
+#[cfg(unix)]
+mod progress;
+mod report;
 mod run;
+mod table;
+#[cfg(unix)]
+mod term;
 mod vm;
 
-use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
 use bux::{Feature, Vm};
 use clap::{CommandFactory, Parser, Subcommand};
 use clap_complete::Shell;
 
+use crate::config::Config;
+use crate::report::Reporter;
+
 #[derive(Parser)]
 #[command(name = "bux", version, about = "Micro-VM sandbox powered by libkrun")]
 struct Cli {
+    #[command(flatten)]
+    store: StoreOpts,
+
+    /// Suppress progress and status output; results and errors still print.
+    ///
+    /// `bux ps -q` prints only VM IDs.
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -53,8 +75,14 @@ enum Command {
     /// Block until one or more VMs stop.
     Wait(vm::WaitArgs),
 
-    /// Remove all stopped VMs.
-    Prune,
+    /// List files added, changed or deleted in a running VM since boot.
+    Diff(vm::DiffArgs),
+
+    /// Stream a VM's console log until it stops (Ctrl-C detaches).
+    Attach(vm::AttachArgs),
+
+    /// Reclaim space from stopped VMs, image blobs and disk bases.
+    Prune(vm::PruneArgs),
 
     /// Rename a VM.
     Rename(vm::RenameArgs),
@@ -63,6 +91,14 @@ enum Command {
     Pull {
         /// Image reference (e.g., ubuntu:latest).
         image: String,
+
+        /// Output format for the pulled image summary.
+        #[arg(long, default_value = "table")]
+        format: OutputFormat,
+
+        /// Give up after this long, including extraction (e.g. `90s`, `5m`).
+        #[arg(long, value_parser = parse_duration)]
+        timeout: Option<Duration>,
     },
 
     /// List locally stored images.
@@ -70,15 +106,59 @@ enum Command {
         /// Output format.
         #[arg(long, default_value = "table")]
         format: OutputFormat,
+        /// Only show images matching `label=KEY[=VALUE]` or `reference=PATTERN` (repeatable).
+        #[arg(short, long = "filter")]
+        filters: Vec<bux_oci::ImageFilter>,
+        /// Table columns, comma-separated: reference, digest, size, created,
+        /// disk (extracted rootfs). `NAME:WIDTH` fixes a column's width,
+        /// cutting longer values, e.g. `reference:30,digest:71,disk`.
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<table::ColumnSpec<ImageColumn>>,
     },
 
     /// Remove one or more locally stored images.
     Rmi {
-        /// Image references to remove.
+        /// Image references or digest prefixes to remove. A digest prefix
+        /// removes every reference to that image.
         #[arg(required = true, num_args = 1..)]
         images: Vec<String>,
     },
 
+    /// Show how a stored image was built, one step per line.
+    History {
+        /// Image reference or digest prefix (as `bux images` shows it).
+        image: String,
+        /// Output format.
+        #[arg(long, default_value = "table")]
+        format: OutputFormat,
+        /// Show full digests and commands instead of truncating them.
+        #[arg(long)]
+        no_trunc: bool,
+    },
+
+    /// List the tags of a repository in its registry.
+    Tags {
+        /// Repository, e.g. `alpine` or `ghcr.io/org/app`.
+        repo: String,
+        /// Output format.
+        #[arg(long, default_value = "table")]
+        format: OutputFormat,
+    },
+
+    /// Create a local alias for a stored image without re-pulling.
+    Tag {
+        /// Existing image reference or digest prefix.
+        source: String,
+        /// New reference to create.
+        target: String,
+    },
+
+    /// Manage the local image store.
+    Image {
+        #[command(subcommand)]
+        action: ImageAction,
+    },
+
     /// Display system capabilities and libkrun feature support.
     Info {
         /// Output format.
@@ -100,6 +180,73 @@ enum Command {
     },
 }
 
+/// Image store and registry options shared by every subcommand.
+#[derive(clap::Args)]
+pub(crate) struct StoreOpts {
+    /// Image store directory (overrides `BUX_HOME` for images).
+    #[arg(long, global = true, env = "BUX_STORE_DIR")]
+    store_dir: Option<PathBuf>,
+
+    /// Registry to reach over unverified HTTPS or plain HTTP (repeatable).
+    #[arg(long = "insecure-registry", global = true, value_name = "HOST[:PORT]")]
+    insecure_registries: Vec<String>,
+
+    /// Registry credentials from the environment or `bux.toml`.
+    #[arg(skip)]
+    auth: Option<config::Auth>,
+}
+
+impl StoreOpts {
+    /// Fills in what the command line and environment left unset from the
+    /// config file.
+    fn apply(&mut self, config: &Config) {
+        if self.store_dir.is_none() {
+            self.store_dir.clone_from(&config.store_dir);
+        }
+        self.auth = config.registry_auth();
+    }
+}
+
+/// Subcommands for `bux image`.
+#[derive(Subcommand)]
+enum ImageAction {
+    /// Prune unreferenced blobs and compact the image index.
+    ///
+    /// Rewrites the index database; run occasionally, not after every pull.
+    Gc {
+        /// Then remove least recently used images until the store fits in
+        /// this size (e.g. `10GB`); images of existing VMs are kept.
+        #[arg(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+    },
+    /// Show a stored image's config and ordered layers as JSON.
+    Inspect {
+        /// Image reference or digest prefix (as `bux images` shows it).
+        image: String,
+    },
+    /// Re-hash an image's layer blobs and check its extracted rootfs.
+    ///
+    /// Exits non-zero if anything is missing or corrupt.
+    Verify {
+        /// Image reference or digest prefix (as `bux images` shows it).
+        image: String,
+        /// Re-download only the bad layers and re-extract the rootfs.
+        ///
+        /// Replaces the rootfs in place; stop VMs using the image first.
+        #[arg(long)]
+        repair: bool,
+        /// Output format.
+        #[arg(long, default_value = "table")]
+        format: OutputFormat,
+    },
+    /// Show which images share which layers, and the space sharing saves.
+    Tree {
+        /// Output format (`json`: each layer with the images using it).
+        #[arg(long, default_value = "table")]
+        format: OutputFormat,
+    },
+}
+
 /// Subcommands for `bux disk`.
 #[derive(Subcommand)]
 enum DiskAction {
@@ -109,6 +256,9 @@ enum DiskAction {
         rootfs: String,
         /// Digest identifier for the base image.
         digest: String,
+        /// Leave paths matched by this `.dockerignore`-style file out of the image.
+        #[arg(long)]
+        ignore_file: Option<String>,
     },
     /// List all base disk images.
     #[command(visible_alias = "ls")]
@@ -120,6 +270,41 @@ enum DiskAction {
     },
 }
 
+/// A column of the `bux images` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ImageColumn {
+    Reference,
+    Digest,
+    Size,
+    Created,
+    Disk,
+}
+
+impl ImageColumn {
+    /// The columns shown without `--columns`.
+    const DEFAULT: [Self; 3] = [Self::Reference, Self::Digest, Self::Size];
+
+    const fn column(self) -> table::Column {
+        match self {
+            Self::Reference => table::Column::new("REFERENCE", 50),
+            Self::Digest => table::Column::new("DIGEST", 20).max(19),
+            Self::Size => table::Column::new("SIZE", 10).right(),
+            Self::Created => table::Column::new("CREATED", 19),
+            Self::Disk => table::Column::new("DISK", 10).right(),
+        }
+    }
+
+    fn cell(self, oci: &bux_oci::Oci, img: &bux_oci::ImageMeta) -> String {
+        match self {
+            Self::Reference => img.reference.clone(),
+            Self::Digest => img.digest.clone(),
+            Self::Size => human_size(img.size),
+            Self::Created => img.created_at.clone(),
+            Self::Disk => human_size(oci.rootfs_size(&img.digest)),
+        }
+    }
+}
+
 /// Output format for list/info commands.
 #[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
 pub(crate) enum OutputFormat {
@@ -139,22 +324,47 @@ async fn main() {
 }
 
 impl Cli {
-    async fn dispatch(self) -> Result<()> {
+    async fn dispatch(mut self) -> Result<()> {
+        let report = Reporter::new(self.quiet);
+        let defaults = Config::load()?;
+        self.store.apply(&defaults);
         match self.command {
-            Command::Run(args) => args.run().await,
+            Command::Run(args) => args.run(&self.store, &defaults, report).await,
             Command::Exec(args) => vm::exec(args).await,
-            Command::Ps(ref args) => vm::ps(args),
+            Command::Ps(ref args) => vm::ps(args, report),
             Command::Stop(args) => vm::stop(args).await,
             Command::Kill(ref args) => vm::kill(args),
-            Command::Rm(ref args) => vm::rm(args),
-            Command::Inspect(ref args) => vm::inspect(args),
-            Command::Cp(args) => vm::cp(args).await,
+            Command::Rm(ref args) => vm::rm(args, report),
+            Command::Inspect(args) => vm::inspect(args).await,
+            Command::Cp(args) => vm::cp(args, report).await,
             Command::Wait(args) => vm::wait(args).await,
-            Command::Prune => vm::prune(),
+            Command::Diff(ref args) => vm::diff(args).await,
+            Command::Attach(ref args) => vm::attach(args).await,
+            Command::Prune(ref args) => vm::prune(args, &self.store, report),
             Command::Rename(ref args) => vm::rename(args),
-            Command::Pull { image } => pull(&image).await,
-            Command::Images { format } => images(format),
-            Command::Rmi { images } => rmi(&images),
+            Command::Pull {
+                image,
+                format,
+                timeout,
+            } => {
+                let mut config = oci_config(&self.store);
+                config.pull_timeout = timeout;
+                pull(&bux_oci::Oci::open_with(config)?, &image, format, report).await
+            }
+            Command::Images {
+                format,
+                filters,
+                columns,
+            } => images(&open_oci(&self.store)?, format, &filters, &columns),
+            Command::Rmi { images } => rmi(&open_oci(&self.store)?, &images),
+            Command::History {
+                image,
+                format,
+                no_trunc,
+            } => history(&open_oci(&self.store)?, &image, format, no_trunc),
+            Command::Tags { repo, format } => tags(&open_oci(&self.store)?, &repo, format).await,
+            Command::Tag { source, target } => Ok(open_oci(&self.store)?.tag(&source, &target)?),
+            Command::Image { action } => image_cmd(&open_oci(&self.store)?, &action, report).await,
             Command::Info { format } => info(format),
             Command::Disk { action } => disk_cmd(action),
             Command::Completion { shell } => {
@@ -165,16 +375,125 @@ async fn dispatch(self) -> Result<()> {
     }
 }
 
-async fn pull(image: &str) -> Result<()> {
-    let oci = bux_oci::Oci::open()?;
-    let result = oci.pull(image, |msg| eprintln!("{msg}")).await?;
+/// Opens the image store.
+///
+/// Precedence: `--store-dir` > `BUX_STORE_DIR` > `store_dir` in
+/// `bux.toml` > `BUX_HOME` > platform default. The first two arrive
+/// together via clap's `env` fallback.
+///
+/// Proxies and extra CA certificates come from the environment (see
+/// [`bux_oci::OciConfig`]); `--insecure-registry` adds to
+/// `BUX_INSECURE_REGISTRIES`.
+pub(crate) fn open_oci(opts: &StoreOpts) -> Result<bux_oci::Oci> {
+    Ok(bux_oci::Oci::open_with(oci_config(opts))?)
+}
+
+/// Builds the store configuration behind [`open_oci`].
+fn oci_config(opts: &StoreOpts) -> bux_oci::OciConfig {
+    let mut config = bux_oci::OciConfig::default();
+    if let Some(dir) = &opts.store_dir {
+        config.store_dir.clone_from(dir);
+    }
+    if let Some(auth) = &opts.auth {
+        config.auth = bux_oci::RegistryAuth::Basic(auth.username.clone(), auth.password.clone());
+    }
+    config
+        .insecure_registries
+        .extend(opts.insecure_registries.iter().cloned());
+    if config.accept_invalid_certs {
+        eprintln!(
+            "warning: BUX_ACCEPT_INVALID_CERTS is set; registry TLS certificates are NOT verified"
+        );
+    }
+    config
+}
+
+/// Parses `90`, `90s`, `500ms`, `5m` or `1h`.
+fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(split);
+    let n: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid duration {s:?}"))?;
+    let secs = |scale: u64| {
+        n.checked_mul(scale)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("duration {s:?} is too long"))
+    };
+    match unit {
+        "ms" => Ok(Duration::from_millis(n)),
+        "" | "s" => Ok(Duration::from_secs(n)),
+        "m" => secs(60),
+        "h" => secs(3600),
+        _ => Err(format!(
+            "invalid duration unit {unit:?} (use ms, s, m or h)"
+        )),
+    }
+}
+
+/// Parses a byte size such as `512MB`, `10G` or `1TiB`, in powers of 1024
+/// like [`human_size`].
+fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(split);
+    let n: u64 = digits.parse().map_err(|_| format!("invalid size {s:?}"))?;
+    let prefix = unit
+        .strip_suffix("iB")
+        .or_else(|| unit.strip_suffix('B'))
+        .unwrap_or(unit);
+    let shift = match prefix {
+        "" => 0,
+        "K" | "k" => 10,
+        "M" | "m" => 20,
+        "G" | "g" => 30,
+        "T" | "t" => 40,
+        _ => {
+            return Err(format!(
+                "invalid size unit {unit:?} (use B, KB, MB, GB or TB)"
+            ));
+        }
+    };
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| format!("size {s:?} is too large"))
+}
+
+async fn pull(
+    oci: &bux_oci::Oci,
+    image: &str,
+    format: OutputFormat,
+    report: Reporter,
+) -> Result<()> {
+    let result = oci.pull(image, report.pull_observer()).await?;
+
+    if matches!(format, OutputFormat::Json) {
+        let summary = serde_json::json!({
+            "reference": result.reference,
+            "digest": result.digest,
+            "rootfs": result.rootfs,
+            "size": result.size,
+            "layers": result.layers,
+        });
+        println!("{summary}");
+        return Ok(());
+    }
     println!("{}", result.reference);
     Ok(())
 }
 
-fn images(format: OutputFormat) -> Result<()> {
-    let oci = bux_oci::Oci::open()?;
-    let list = oci.images()?;
+fn images(
+    oci: &bux_oci::Oci,
+    format: OutputFormat,
+    filters: &[bux_oci::ImageFilter],
+    columns: &[table::ColumnSpec<ImageColumn>],
+) -> Result<()> {
+    let listing = oci.image_list(filters)?;
+    if listing.skipped > 0 {
+        eprintln!(
+            "warning: skipped {} unreadable image record(s); `bux image gc` removes them",
+            listing.skipped
+        );
+    }
+    let list = listing.images;
 
     if matches!(format, OutputFormat::Json) {
         println!("{}", serde_json::to_string_pretty(&list)?);
@@ -185,21 +504,92 @@ fn images(format: OutputFormat) -> Result<()> {
         println!("No images.");
         return Ok(());
     }
-    println!("{:<50} {:<20} {:>10}", "REFERENCE", "DIGEST", "SIZE");
+    let chosen: Vec<table::ColumnSpec<ImageColumn>> = if columns.is_empty() {
+        ImageColumn::DEFAULT
+            .map(|column| table::ColumnSpec {
+                column,
+                width: None,
+            })
+            .to_vec()
+    } else {
+        columns.to_vec()
+    };
+    let mut table = table::Table::new(
+        chosen
+            .iter()
+            .map(|spec| {
+                let column = spec.column.column();
+                spec.width.map_or(column, |width| column.fixed(width))
+            })
+            .collect(),
+    );
     for img in &list {
-        let short = &img.digest[..img.digest.len().min(19)];
+        table.row(chosen.iter().map(|spec| spec.column.cell(oci, img)));
+    }
+    table.print();
+    Ok(())
+}
+
+async fn tags(oci: &bux_oci::Oci, repo: &str, format: OutputFormat) -> Result<()> {
+    let tags = oci.list_tags(repo).await?;
+    if matches!(format, OutputFormat::Json) {
+        println!("{}", serde_json::to_string_pretty(&tags)?);
+    } else {
+        for tag in &tags {
+            println!("{tag}");
+        }
+    }
+    Ok(())
+}
+
+fn history(oci: &bux_oci::Oci, image: &str, format: OutputFormat, no_trunc: bool) -> Result<()> {
+    let steps = oci.history(image)?;
+
+    if matches!(format, OutputFormat::Json) {
+        println!("{}", serde_json::to_string_pretty(&steps)?);
+        return Ok(());
+    }
+
+    let cut = |s: &str, max: usize| -> String {
+        if no_trunc || s.chars().count() <= max {
+            s.to_owned()
+        } else {
+            let head: String = s.chars().take(max - 3).collect();
+            format!("{head}...")
+        }
+    };
+    println!(
+        "{:<20} {:<20} {:>10}  CREATED BY",
+        "LAYER", "CREATED", "SIZE"
+    );
+    for step in &steps {
+        let layer = step.digest.as_deref().map_or_else(
+            || "<empty>".to_owned(),
+            |d| {
+                if no_trunc {
+                    d.to_owned()
+                } else {
+                    d[..d.len().min(19)].to_owned()
+                }
+            },
+        );
+        // RFC 3339 down to the second; registries often record nanoseconds.
+        let created = step
+            .created
+            .as_deref()
+            .map_or("-", |c| c.get(..19).unwrap_or(c));
         println!(
-            "{:<50} {:<20} {:>10}",
-            img.reference,
-            short,
-            human_size(img.size)
+            "{:<20} {:<20} {:>10}  {}",
+            layer,
+            created,
+            human_size(step.size.unwrap_or(0)),
+            cut(step.created_by.as_deref().unwrap_or("-"), 60)
         );
     }
     Ok(())
 }
 
-fn rmi(refs: &[String]) -> Result<()> {
-    let oci = bux_oci::Oci::open()?;
+fn rmi(oci: &bux_oci::Oci, refs: &[String]) -> Result<()> {
     for r in refs {
         oci.remove(r)?;
         println!("{r}");
@@ -207,47 +597,155 @@ fn rmi(refs: &[String]) -> Result<()> {
     Ok(())
 }
 
-const FEATURES: &[(Feature, &str)] = &[
-    (Feature::Net, "net"),
-    (Feature::Blk, "blk"),
-    (Feature::Gpu, "gpu"),
-    (Feature::Snd, "snd"),
-    (Feature::Input, "input"),
-    (Feature::Efi, "efi"),
-    (Feature::Tee, "tee"),
-    (Feature::AmdSev, "amd-sev"),
-    (Feature::IntelTdx, "intel-tdx"),
-    (Feature::AwsNitro, "aws-nitro"),
-    (Feature::VirglResourceMap2, "virgl-resource-map2"),
-];
+async fn image_cmd(oci: &bux_oci::Oci, action: &ImageAction, report: Reporter) -> Result<()> {
+    match action {
+        ImageAction::Gc { max_size } => {
+            let pinned = vm::pinned_images()?;
+            let pruned = match max_size {
+                Some(max) => oci.prune_to_size(*max, &pinned)?,
+                None => oci.prune(&pinned)?,
+            };
+            let compacted = oci.maintain()?;
+            println!("pruned blobs:    {}", human_size(pruned));
+            println!("compacted index: {}", human_size(compacted));
+            println!("total reclaimed: {}", human_size(pruned + compacted));
+        }
+        ImageAction::Inspect { image } => {
+            println!("{}", serde_json::to_string_pretty(&oci.inspect(image)?)?);
+        }
+        ImageAction::Verify {
+            image,
+            repair,
+            format,
+        } => {
+            let result = if *repair {
+                oci.repair(image, report.pull_observer()).await?
+            } else {
+                oci.verify(image)?
+            };
+            if matches!(format, OutputFormat::Json) {
+                println!("{}", serde_json::to_string_pretty(&result)?);
+            } else {
+                for layer in &result.layers {
+                    println!("{:<72} {}", layer.digest, layer.status.as_str());
+                }
+                let rootfs = if result.rootfs_complete {
+                    "ok"
+                } else {
+                    "incomplete"
+                };
+                println!("{:<72} {rootfs}", "rootfs");
+            }
+            if !result.is_ok() {
+                let hint = if *repair { "" } else { "; run with --repair" };
+                anyhow::bail!("{} failed verification{hint}", result.reference);
+            }
+        }
+        ImageAction::Tree { format } => image_tree(oci, *format)?,
+    }
+    Ok(())
+}
+
+/// Prints shared layers with their images, then each image's own layers,
+/// then layers nothing uses.
+fn image_tree(oci: &bux_oci::Oci, format: OutputFormat) -> Result<()> {
+    let layers = oci.layer_refs()?;
+    if matches!(format, OutputFormat::Json) {
+        println!("{}", serde_json::to_string_pretty(&layers)?);
+        return Ok(());
+    }
+    if layers.is_empty() {
+        println!("No layers.");
+        return Ok(());
+    }
 
-fn info(format: OutputFormat) -> Result<()> {
-    let max_vcpus = Vm::max_vcpus()?;
-    let supported: Vec<&str> = FEATURES
+    let short = |digest: &str| digest[..digest.len().min(19)].to_owned();
+    let branch = |i: usize, n: usize| if i + 1 == n { "└─" } else { "├─" };
+    let (shared, rest): (Vec<_>, Vec<_>) = layers.iter().partition(|l| l.images.len() > 1);
+    let mut own: std::collections::BTreeMap<&str, Vec<&bux_oci::LayerRefs>> =
+        std::collections::BTreeMap::new();
+    let mut unused = Vec::new();
+    for layer in rest {
+        match layer.images.first() {
+            Some(image) => own.entry(image).or_default().push(layer),
+            None => unused.push(layer),
+        }
+    }
+
+    if !shared.is_empty() {
+        println!("SHARED LAYERS");
+        for layer in &shared {
+            println!(
+                "{:<20} {:>10}  {} images",
+                short(&layer.digest),
+                human_size(layer.size),
+                layer.images.len()
+            );
+            for (i, image) in layer.images.iter().enumerate() {
+                println!("  {} {image}", branch(i, layer.images.len()));
+            }
+        }
+    }
+    if !own.is_empty() {
+        println!(
+            "{}UNSHARED LAYERS",
+            if shared.is_empty() { "" } else { "\n" }
+        );
+        for (image, mine) in &own {
+            let total: u64 = mine.iter().map(|l| l.size).sum();
+            println!("{image} ({})", human_size(total));
+            for (i, layer) in mine.iter().enumerate() {
+                println!(
+                    "  {} {:<20} {:>10}",
+                    branch(i, mine.len()),
+                    short(&layer.digest),
+                    human_size(layer.size)
+                );
+            }
+        }
+    }
+    if !unused.is_empty() {
+        println!("\nUNREFERENCED (removed by `bux image gc`)");
+        for layer in &unused {
+            println!(
+                "  {:<20} {:>10}",
+                short(&layer.digest),
+                human_size(layer.size)
+            );
+        }
+    }
+
+    let saved: u64 = shared
         .iter()
-        .filter(|(f, _)| Vm::has_feature(*f).unwrap_or(false))
-        .map(|(_, name)| *name)
-        .collect();
-    let nested = Vm::check_nested_virt().ok();
+        .map(|l| l.size * (l.images.len() as u64 - 1))
+        .sum();
+    let stored: u64 = layers.iter().map(|l| l.size).sum();
+    println!(
+        "\n{} layers, {} stored; sharing saves {}",
+        layers.len(),
+        human_size(stored),
+        human_size(saved)
+    );
+    Ok(())
+}
+
+fn info(format: OutputFormat) -> Result<()> {
+    let caps = Vm::capabilities()?;
 
     if matches!(format, OutputFormat::Json) {
-        let obj = serde_json::json!({
-            "max_vcpus": max_vcpus,
-            "features": supported,
-            "nested_virt": nested,
-        });
-        println!("{}", serde_json::to_string_pretty(&obj)?);
+        println!("{}", serde_json::to_string_pretty(&caps)?);
         return Ok(());
     }
 
-    println!("max vCPUs: {max_vcpus}");
+    println!("max vCPUs: {}", caps.max_vcpus);
+    let supported: Vec<&str> = caps.supported().map(Feature::as_str).collect();
     let label = if supported.is_empty() {
         "none"
     } else {
         &supported.join(", ")
     };
     println!("features:  {label}");
-    match nested {
+    match caps.nested_virt {
         Some(true) => println!("nested:    supported"),
         Some(false) => println!("nested:    not supported"),
         None => {}
@@ -255,16 +753,36 @@ fn info(format: OutputFormat) -> Result<()> {
     Ok(())
 }
 
+/// Opens the disk manager under `data_dir`, building base images in
+/// `BUX_STAGING_DIR` when it is set.
+#[cfg(unix)]
+fn disk_manager(data_dir: &Path) -> Result<bux::DiskManager> {
+    let dm = bux::DiskManager::open(data_dir)?;
+    Ok(match std::env::var_os("BUX_STAGING_DIR") {
+        Some(dir) => dm.with_staging_dir(dir)?,
+        None => dm,
+    })
+}
+
 #[cfg(unix)]
 fn disk_cmd(action: DiskAction) -> Result<()> {
     let data_dir = dirs::data_dir()
         .ok_or_else(|| anyhow::anyhow!("no platform data directory"))?
         .join("bux");
-    let dm = bux::DiskManager::open(&data_dir)?;
+    let dm = disk_manager(&data_dir)?;
 
     match action {
-        DiskAction::Create { rootfs, digest } => {
-            let path = dm.create_base(std::path::Path::new(&rootfs), &digest)?;
+        DiskAction::Create {
+            rootfs,
+            digest,
+            ignore_file,
+        } => {
+            let ignore = match ignore_file {
+                Some(f) => bux::IgnoreRules::load(Path::new(&f))
+                    .with_context(|| format!("failed to read {f}"))?,
+                None => bux::IgnoreRules::default(),
+            };
+            let path = dm.create_base_filtered(Path::new(&rootfs), &digest, &ignore)?;
             println!("{}", path.display());
         }
         DiskAction::List => {
@@ -0,0 +1,232 @@
+//! Single-line transfer progress on stderr.
+
+use std::cell::RefCell;
+use std::io::{IsTerminal, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::human_size;
+use crate::report::Reporter;
+
+/// Minimum interval between redraws.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Byte counter rendered as `label done / total (pct%)`, followed by
+/// ` — suffix` when one is set.
+///
+/// Drawing is disabled when quiet or when stderr is not a terminal.
+pub struct Progress {
+    label: String,
+    total: Option<u64>,
+    done: u64,
+    suffix: String,
+    enabled: bool,
+    last_draw: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(label: impl Into<String>, total: Option<u64>, quiet: bool) -> Self {
+        Self {
+            label: label.into(),
+            total,
+            done: 0,
+            suffix: String::new(),
+            enabled: !quiet && std::io::stderr().is_terminal(),
+            last_draw: None,
+        }
+    }
+
+    /// Sets the absolute byte count (e.g. after seeking to a resume offset).
+    pub fn set(&mut self, done: u64) {
+        self.done = done;
+        self.draw(false);
+    }
+
+    /// Sets the text drawn after the count, e.g. a rate, from the next
+    /// redraw on.
+    pub fn set_suffix(&mut self, suffix: String) {
+        self.suffix = suffix;
+    }
+
+    pub fn add(&mut self, n: u64) {
+        self.set(self.done + n);
+    }
+
+    /// Draws the final count and ends the line.
+    pub fn finish(&mut self) {
+        self.draw(true);
+        if self.last_draw.take().is_some() {
+            eprintln!();
+        }
+    }
+
+    fn draw(&mut self, force: bool) {
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        if !force && self.last_draw.is_some_and(|t| now - t < REDRAW_INTERVAL) {
+            return;
+        }
+        self.last_draw = Some(now);
+
+        let mut line = match self.total {
+            Some(total) if total > 0 => format!(
+                "{} {} / {} ({}%)",
+                self.label,
+                human_size(self.done),
+                human_size(total),
+                self.done.min(total) * 100 / total
+            ),
+            _ => format!("{} {}", self.label, human_size(self.done)),
+        };
+        if !self.suffix.is_empty() {
+            line = format!("{line} — {}", self.suffix);
+        }
+        eprint!("\r\x1b[2K{line}");
+        let _ = std::io::stderr().flush();
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // Leave the cursor on a fresh line if the transfer was interrupted.
+        if self.last_draw.is_some() {
+            eprintln!();
+        }
+    }
+}
+
+/// Renders [`bux_oci::Oci::pull`] progress as one line per downloading
+/// layer, with the rate and ETA the library measures.
+pub struct PullLine {
+    report: Reporter,
+    /// Line of the layer being downloaded, ended by the next status message.
+    current: RefCell<Option<(usize, Progress)>>,
+}
+
+impl PullLine {
+    pub const fn new(report: Reporter) -> Self {
+        Self {
+            report,
+            current: RefCell::new(None),
+        }
+    }
+}
+
+impl bux_oci::PullObserver for PullLine {
+    fn status(&self, message: &str) {
+        if let Some((_, mut line)) = self.current.take() {
+            line.finish();
+        }
+        self.report.status(message);
+    }
+
+    fn progress(&self, progress: &bux_oci::PullProgress) {
+        let mut current = self.current.borrow_mut();
+        if current
+            .as_ref()
+            .is_none_or(|(layer, _)| *layer != progress.layer)
+        {
+            if let Some((_, mut line)) = current.take() {
+                line.finish();
+            }
+            let label = format!("Downloading layer {}/{}", progress.layer, progress.layers);
+            *current = Some((
+                progress.layer,
+                self.report.progress(label, progress.layer_size),
+            ));
+        }
+        let Some((_, line)) = current.as_mut() else {
+            return;
+        };
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let mut suffix = progress
+            .bytes_per_sec
+            .map_or_else(String::new, |rate| format!("{}/s", human_size(rate as u64)));
+        if let Some(eta) = progress.eta() {
+            suffix = format!("{suffix}, ETA {}", short_duration(eta));
+        }
+        line.set_suffix(suffix);
+        line.set(progress.layer_bytes);
+    }
+}
+
+/// Formats `d` as e.g. `45s`, `1m20s` or `2h05m`, rounded to the second.
+fn short_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Reader/writer adapter that reports transferred bytes to a [`Progress`].
+pub struct Tracked<'a, T> {
+    inner: T,
+    progress: &'a mut Progress,
+}
+
+impl<'a, T> Tracked<'a, T> {
+    pub const fn new(inner: T, progress: &'a mut Progress) -> Self {
+        Self { inner, progress }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Tracked<'_, T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if matches!(poll, Poll::Ready(Ok(()))) {
+            this.progress.add((buf.filled().len() - before) as u64);
+        }
+        poll
+    }
+}
+
+impl<T: AsyncSeek + Unpin> AsyncSeek for Tracked<'_, T> {
+    fn start_seek(self: Pin<&mut Self>, position: std::io::SeekFrom) -> std::io::Result<()> {
+        Pin::new(&mut self.get_mut().inner).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_complete(cx);
+        if let Poll::Ready(Ok(pos)) = poll {
+            this.progress.set(pos);
+        }
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Tracked<'_, T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            this.progress.add(n as u64);
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,53 @@
+//! Status output shared by all subcommands.
+
+use std::fmt::Display;
+
+#[cfg(unix)]
+use crate::progress::{Progress, PullLine};
+
+/// Routes progress and status lines to stderr unless `--quiet` is set.
+///
+/// Primary results go to stdout, and errors and warnings are printed
+/// directly; none of them pass through here, so `-q` never hides them.
+#[derive(Debug, Clone, Copy)]
+pub struct Reporter {
+    quiet: bool,
+}
+
+impl Reporter {
+    /// Creates a reporter that stays silent when `quiet` is set.
+    pub const fn new(quiet: bool) -> Self {
+        Self { quiet }
+    }
+
+    /// Returns `true` if status lines are suppressed (`--quiet`).
+    pub const fn is_quiet(self) -> bool {
+        self.quiet
+    }
+
+    /// Prints a status line (pull progress, reclaimed space, VM IDs on attach).
+    pub fn status(self, msg: impl Display) {
+        if !self.quiet {
+            eprintln!("{msg}");
+        }
+    }
+
+    /// Starts a transfer progress line, drawn only when not quiet.
+    #[cfg(unix)]
+    pub fn progress(self, label: impl Into<String>, total: Option<u64>) -> Progress {
+        Progress::new(label, total, self.quiet)
+    }
+
+    /// Observer for image pulls: status lines, plus a rate and ETA line per
+    /// downloading layer where progress can be drawn.
+    #[cfg(unix)]
+    pub const fn pull_observer(self) -> PullLine {
+        PullLine::new(self)
+    }
+
+    /// Observer for image pulls: status lines only.
+    #[cfg(not(unix))]
+    pub fn pull_observer(self) -> impl bux_oci::PullObserver {
+        move |msg: &str| self.status(msg)
+    }
+}
@@ -3,7 +3,11 @@
 //! Follows the Docker CLI convention: `bux run [OPTIONS] IMAGE [COMMAND] [ARG...]`
 
 use anyhow::{Context, Result};
-use bux::{LogLevel, Vm};
+use bux::{KernelFormat, LogLevel, Pid1, Tee, TeeConfig, Vm};
+
+use crate::StoreOpts;
+use crate::config::Config;
+use crate::report::Reporter;
 
 /// Arguments for `bux run`.
 ///
@@ -11,7 +15,8 @@
 #[derive(clap::Args)]
 #[command(trailing_var_arg = true)]
 pub struct RunArgs {
-    /// OCI image reference (e.g., ubuntu:latest). Conflicts with --root/--root-disk.
+    /// OCI image reference (e.g., ubuntu:latest), or the digest prefix of a
+    /// stored image. Conflicts with --root/--root-disk.
     #[arg(conflicts_with_all = ["root", "root_disk"], required_unless_present_any = ["root", "root_disk"])]
     image: Option<String>,
 
@@ -23,10 +28,20 @@ pub struct RunArgs {
     #[arg(long, conflicts_with = "root")]
     root_disk: Option<String>,
 
-    /// Auto-create ext4 disk image from OCI rootfs.
+    /// When to pull the image: if it is missing from the store, always
+    /// (re-fetching only if the tag moved), or never (fail unless stored).
+    #[arg(long, value_enum, default_value_t, conflicts_with_all = ["root", "root_disk"])]
+    pull: PullPolicy,
+
+    /// Auto-create ext4 disk image from the image's layers (from its
+    /// rootfs with --ignore-file or --root).
     #[arg(long)]
     disk: bool,
 
+    /// Leave paths matched by this `.dockerignore`-style file out of the disk image.
+    #[arg(long, requires = "disk")]
+    ignore_file: Option<String>,
+
     /// Assign a name to the VM.
     #[arg(long)]
     name: Option<String>,
@@ -35,39 +50,68 @@ pub struct RunArgs {
     #[arg(short = 'd', long)]
     detach: bool,
 
+    /// Key sequence that detaches from a foreground run and leaves the VM
+    /// running: comma-separated `ctrl-<key>` or single characters.
+    #[arg(long, value_name = "KEYS", default_value = "ctrl-p,ctrl-q", value_parser = parse_detach_keys)]
+    detach_keys: DetachKeys,
+
     /// Automatically remove the VM when it stops.
     #[arg(long)]
     rm: bool,
 
-    /// Number of virtual CPUs.
-    #[arg(long, default_value_t = 1)]
-    cpus: u8,
+    /// Write the VM ID to this file once it starts; fails if the file
+    /// exists. A foreground run removes it again on a clean exit.
+    #[arg(long, value_name = "PATH")]
+    cidfile: Option<std::path::PathBuf>,
 
-    /// Memory in MiB.
-    #[arg(long, short = 'm', default_value_t = 512)]
-    memory: u32,
+    /// Number of virtual CPUs [default: `cpus` in bux.toml, else 1].
+    #[arg(long, env = "BUX_CPUS")]
+    cpus: Option<u8>,
+
+    /// Memory in MiB [default: `ram` in bux.toml, else 512].
+    #[arg(long, short = 'm', env = "BUX_MEMORY")]
+    memory: Option<u32>,
 
     /// Working directory inside the VM.
     #[arg(short = 'w', long)]
     workdir: Option<String>,
 
+    /// Create the working directory in the image if it does not exist
+    /// (not possible on a --root-disk, which bux does not modify).
+    #[arg(long, conflicts_with = "root_disk")]
+    mkdir_workdir: bool,
+
     /// Publish a port (format: hostPort:guestPort[/tcp|udp]).
     #[arg(short = 'p', long = "publish")]
     publish: Vec<String>,
 
-    /// Bind mount a volume (format: hostPath:guestPath[:ro]).
+    /// Publish every TCP port the image exposes to a random host port.
+    #[arg(short = 'P', long)]
+    publish_all: bool,
+
+    /// Share a host directory, mounted in the guest at boot
+    /// (format: [tag:]hostPath:guestPath[:ro|:rw], default rw).
     #[arg(short = 'v', long = "volume")]
     volume: Vec<String>,
 
+    /// Attach a named data disk as an extra block device, creating it
+    /// empty if a size is given and it does not exist yet
+    /// (format: name[:size][:ro|:rw], e.g. `cache:10G`).
+    ///
+    /// The guest sees the disks in order after the root disk and does not
+    /// mount them; `bux rm --volumes` deletes them with the VM.
+    #[arg(long = "data-disk")]
+    data_disk: Vec<String>,
+
     /// Set environment variables.
     #[arg(short = 'e', long = "env")]
     env: Vec<String>,
 
-    /// Read environment variables from a file.
+    /// Read environment variables from a file (`KEY=VALUE` or bare `KEY`).
     #[arg(long)]
     env_file: Vec<String>,
 
-    /// User inside the VM (format: uid[:gid]).
+    /// User inside the VM (format: uid[:gid] or name[:group]).
     #[arg(short = 'u', long = "user")]
     user: Option<String>,
 
@@ -95,13 +139,84 @@ pub struct RunArgs {
     #[arg(long)]
     snd: bool,
 
-    /// Redirect console output to a file.
+    /// Do not seed the guest kernel's random number generator from the host.
+    #[arg(long)]
+    no_rng: bool,
+
+    /// Snapshot the root filesystem at boot so `bux diff` can list changes.
+    #[arg(long, conflicts_with = "no_agent")]
+    track_changes: bool,
+
+    /// Redirect console output to a file [default with -d: a per-VM log
+    /// that `bux attach` streams].
     #[arg(long)]
     console_output: Option<String>,
 
-    /// libkrun log level.
-    #[arg(long, default_value = "info")]
-    log_level: LogLevel,
+    /// Shell command the guest runs to completion before the VM is ready
+    /// (via `/bin/sh -c`); its output goes to the console.
+    #[arg(long)]
+    init: Option<String>,
+
+    /// Keep booting if the --init command fails.
+    #[arg(long, requires = "init")]
+    init_may_fail: bool,
+
+    /// Make the guest agent require this token on every connection.
+    #[arg(long, env = "BUX_AUTH_TOKEN", hide_env_values = true)]
+    auth_token: Option<String>,
+
+    /// Boot the guest agent at this path in the rootfs, which then starts
+    /// the command (for images whose entrypoint does not start the agent).
+    #[arg(long, value_name = "PATH", conflicts_with = "no_agent")]
+    agent_path: Option<String>,
+
+    /// Boot the command as is, with no guest agent: exec, cp, diff and
+    /// other agent commands are unavailable for this VM.
+    #[arg(long, conflicts_with_all = ["init", "auth_token"])]
+    no_agent: bool,
+
+    /// Once the agent has booted, hand PID 1 to this init (split on
+    /// whitespace, e.g. '/sbin/tini --'), which gets the command appended;
+    /// the agent keeps serving alongside.
+    #[arg(long, value_name = "CMD", conflicts_with = "no_agent")]
+    pid1: Option<String>,
+
+    /// Shell command that checks the VM's health in the guest (via
+    /// `/bin/sh -c`); with -d, wait for it to pass before returning.
+    #[arg(long, value_name = "CMD", conflicts_with = "no_agent")]
+    health_cmd: Option<String>,
+
+    /// Time between health checks, e.g. 10s [default: 30s].
+    #[arg(long, value_name = "DURATION", value_parser = crate::parse_duration, requires = "health_cmd")]
+    health_interval: Option<std::time::Duration>,
+
+    /// Failed health checks in a row before the VM is unhealthy [default: 3].
+    #[arg(long, value_name = "N", requires = "health_cmd")]
+    health_retries: Option<u32>,
+
+    /// Boot this kernel image instead of the bundled one.
+    #[arg(long)]
+    kernel: Option<String>,
+
+    /// Format of the --kernel image.
+    #[arg(long, default_value = "raw", requires = "kernel")]
+    kernel_format: KernelFormat,
+
+    /// Guest kernel command line (requires --kernel; e.g. "quiet console=hvc0").
+    #[arg(long, requires = "kernel")]
+    kernel_cmdline: Option<String>,
+
+    /// Launch as a confidential VM with this TEE (needs a libkrun-sev build).
+    #[arg(long, requires = "tee_config")]
+    tee: Option<Tee>,
+
+    /// libkrun TEE config file (workload ID, attestation URL, ...).
+    #[arg(long, requires = "tee")]
+    tee_config: Option<String>,
+
+    /// libkrun log level [default: `log_level` in bux.toml, else info].
+    #[arg(long, env = "BUX_LOG_LEVEL")]
+    log_level: Option<LogLevel>,
 
     /// Command and arguments to run inside the VM.
     #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
@@ -109,37 +224,67 @@ pub struct RunArgs {
 }
 
 impl RunArgs {
-    pub async fn run(self) -> Result<()> {
-        let (rootfs, oci_cfg) = self.resolve_rootfs().await?;
+    pub async fn run(self, store: &StoreOpts, config: &Config, report: Reporter) -> Result<()> {
+        // Fail before pulling or booting anything.
+        if let Some(ref path) = self.cidfile
+            && path.exists()
+        {
+            anyhow::bail!(
+                "cidfile {} already exists; is another VM using it?",
+                path.display()
+            );
+        }
+        let (rootfs, oci_cfg, digest, usage, layers) = self.resolve_rootfs(store, report).await?;
 
-        let image = self.image.clone();
+        let image = self
+            .image
+            .clone()
+            .map(|reference| bux::ImageRef::new(reference, digest.clone()));
         let name = self.name;
-        let detach = self.detach;
+        let foreground = (!self.detach).then(|| self.detach_keys.clone());
         let auto_remove = self.rm;
+        let cidfile = self.cidfile.clone();
         let root_disk = self.root_disk.clone();
         let use_disk = self.disk;
 
         let mut b = Vm::builder()
-            .vcpus(self.cpus)
-            .ram_mib(self.memory)
-            .log_level(self.log_level);
+            .vcpus(self.cpus.or(config.cpus).unwrap_or(1))
+            .ram_mib(self.memory.or(config.ram).unwrap_or(512))
+            .log_level(self.log_level.or(config.log_level).unwrap_or_default());
+
+        // Working directory: CLI flag > OCI config > none.
+        let workdir = self
+            .workdir
+            .or_else(|| oci_cfg.as_ref()?.working_dir.clone())
+            .filter(|w| !w.is_empty());
+        if let Some(ref wd) = workdir
+            && root_disk.is_none()
+            && !rootfs.is_empty()
+        {
+            prepare_workdir(std::path::Path::new(&rootfs), wd, self.mkdir_workdir)?;
+        }
 
         // Root filesystem: explicit disk > --disk (auto QCOW2 overlay) > directory.
         if let Some(ref disk) = root_disk {
             b = b.root_disk(disk);
         } else if use_disk && !rootfs.is_empty() {
-            let base_path = create_disk_from_rootfs(&rootfs)?;
+            // A created workdir must reach the disk even if a base for the
+            // same rootfs was cached before it existed.
+            let made = workdir.as_deref().filter(|_| self.mkdir_workdir);
+            // Ignore rules need the rootfs directory; pulled images are
+            // otherwise built from their layers.
+            let base_path = match digest {
+                Some(ref manifest) if self.ignore_file.is_none() => {
+                    create_disk_from_layers(&layers, manifest, &rootfs, made)?
+                }
+                _ => create_disk_from_rootfs(&rootfs, self.ignore_file.as_deref(), made, usage)?,
+            };
             b = b.base_disk(base_path);
         } else {
             b = b.root(&rootfs);
         }
 
-        // Working directory: CLI flag > OCI config > none.
-        if let Some(ref wd) = self
-            .workdir
-            .or_else(|| oci_cfg.as_ref()?.working_dir.clone())
-            .filter(|w| !w.is_empty())
-        {
+        if let Some(ref wd) = workdir {
             b = b.workdir(wd);
         }
 
@@ -161,19 +306,25 @@ pub async fn run(self) -> Result<()> {
             b = b.exec(&cmd[0], &args);
         }
 
-        // Environment: OCI defaults + --env-file + CLI -e overrides.
+        // Environment: -e overrides --env-file overrides OCI defaults.
         let mut env_file_vars = Vec::new();
         for path in &self.env_file {
             env_file_vars.extend(crate::vm::read_env_file(path)?);
         }
-        let merged_env: Vec<String> = oci_cfg
+        let image_env = oci_cfg
             .as_ref()
             .and_then(|c| c.env.clone())
-            .unwrap_or_default()
-            .into_iter()
-            .chain(env_file_vars)
-            .chain(self.env)
+            .unwrap_or_default();
+        let cli_env: Vec<String> = self
+            .env
+            .iter()
+            .filter_map(|e| crate::vm::resolve_env_entry(e))
             .collect();
+        let merged_env = crate::vm::merge_env([
+            image_env.as_slice(),
+            env_file_vars.as_slice(),
+            cli_env.as_slice(),
+        ]);
         if !merged_env.is_empty() {
             let refs: Vec<&str> = merged_env.iter().map(String::as_str).collect();
             b = b.env(&refs);
@@ -185,11 +336,46 @@ pub async fn run(self) -> Result<()> {
             b = b.port(port_part);
         }
 
-        // Volumes: -v hostPath:guestPath[:ro]  →  auto-generate virtiofs tag.
+        // -P: image EXPOSE ports not already published → random host ports.
+        if self.publish_all {
+            let taken: Vec<u16> = self.publish.iter().filter_map(|s| guest_port(s)).collect();
+            let exposed = oci_cfg
+                .as_ref()
+                .map(bux_oci::ImageConfig::exposed_ports_parsed)
+                .unwrap_or_default();
+            for spec in exposed {
+                if spec.proto != bux_oci::Proto::Tcp {
+                    report.status(format_args!(
+                        "skipping {}/{}: only TCP ports can be published",
+                        spec.port,
+                        spec.proto.as_str()
+                    ));
+                    continue;
+                }
+                if taken.contains(&spec.port) {
+                    continue;
+                }
+                let host_port = random_host_port()?;
+                report.status(format_args!("{host_port} -> {}/tcp", spec.port));
+                b = b.port(format!("{host_port}:{}", spec.port));
+            }
+        }
+
+        // Volumes: -v [tag:]hostPath:guestPath[:ro|:rw]  →  mounted by the guest agent.
         for (idx, spec) in self.volume.iter().enumerate() {
-            let (host, _guest, _ro) = parse_volume(spec)?;
-            let tag = format!("vol{idx}");
-            b = b.virtiofs(&tag, &host);
+            let vol = parse_volume(spec)?;
+            let host_dir = std::fs::canonicalize(&vol.host).with_context(|| {
+                format!("volume {spec:?}: host path {} does not exist", vol.host)
+            })?;
+            let tag = vol.tag.unwrap_or_else(|| format!("vol{idx}"));
+            b = b.virtiofs_mount(tag, host_dir.to_string_lossy(), vol.guest, vol.read_only);
+        }
+
+        // Data disks: --data-disk name[:size][:ro|:rw]  →  extra block devices.
+        for spec in &self.data_disk {
+            let disk = parse_data_disk(spec)?;
+            let path = data_disk_path(&disk.name, disk.size)?;
+            b = b.data_disk(disk.name, path, disk.read_only);
         }
 
         // Ulimits.
@@ -197,13 +383,12 @@ pub async fn run(self) -> Result<()> {
             b = b.rlimit(ul);
         }
 
-        // User: --user uid[:gid]
+        // User: --user uid[:gid] | name[:group], names looked up in the rootfs.
         if let Some(ref user_spec) = self.user {
-            let (uid, gid) = parse_user(user_spec)?;
-            b = b.uid(uid);
-            if let Some(g) = gid {
-                b = b.gid(g);
-            }
+            let rootfs_dir =
+                (root_disk.is_none() && !rootfs.is_empty()).then(|| std::path::Path::new(&rootfs));
+            let (uid, gid) = resolve_user(user_spec, rootfs_dir)?;
+            b = b.uid(uid).gid(gid);
         }
 
         if self.nested_virt {
@@ -212,123 +397,808 @@ pub async fn run(self) -> Result<()> {
         if self.snd {
             b = b.snd_device(true);
         }
-        if let Some(path) = self.console_output {
-            b = b.console_output(path);
+        if self.no_rng {
+            b = b.rng(false);
+        }
+        if self.track_changes {
+            b = b.track_changes();
+        }
+        if let Some(ref init) = self.init {
+            b = b
+                .guest_init(&["/bin/sh", "-c", init])
+                .guest_init_may_fail(self.init_may_fail);
+        }
+        if let Some(ref token) = self.auth_token {
+            b = b.auth_token(token);
+        }
+        if let Some(ref path) = self.agent_path {
+            b = b.agent_path(path);
+        }
+        if self.no_agent {
+            b = b.no_agent();
+        }
+        if let Some(ref init) = self.pid1 {
+            let argv = init.split_whitespace().map(str::to_owned).collect();
+            b = b.pid1(Pid1::AgentThenExec(argv));
+        }
+        if let Some(ref check) = self.health_cmd {
+            b = b.health_cmd(&["/bin/sh", "-c", check]);
+        }
+        if let Some(interval) = self.health_interval {
+            b = b.health_interval(interval);
+        }
+        if let Some(retries) = self.health_retries {
+            b = b.health_retries(retries);
+        }
+        if let Some(kernel) = self.kernel {
+            b = b.kernel(kernel, self.kernel_format);
+        }
+        if let Some(ref cmdline) = self.kernel_cmdline {
+            b = b.kernel_cmdline(cmdline);
+        }
+        if let (Some(tee), Some(config_file)) = (self.tee, self.tee_config) {
+            b = b.tee(TeeConfig::new(tee, config_file));
+        }
+        match self.console_output {
+            Some(path) => b = b.console_output(path),
+            None if foreground.is_none() => b = b.console_log(),
+            None => {}
         }
 
-        spawn_vm(b, image, name, detach, auto_remove).await
+        spawn_vm(
+            b,
+            image,
+            name,
+            foreground,
+            auto_remove,
+            cidfile.as_deref(),
+            report,
+        )
+        .await
     }
 
-    /// Resolves rootfs path and optional OCI config.
-    async fn resolve_rootfs(&self) -> Result<(String, Option<bux_oci::ImageConfig>)> {
+    /// Resolves rootfs path and optional OCI config, plus the rootfs size
+    /// if it was just extracted.
+    async fn resolve_rootfs(
+        &self,
+        store: &StoreOpts,
+        report: Reporter,
+    ) -> Result<(
+        String,
+        Option<bux_oci::ImageConfig>,
+        Option<String>,
+        Option<bux_oci::RootfsUsage>,
+        Vec<std::path::PathBuf>,
+    )> {
         match (&self.image, &self.root, &self.root_disk) {
             (Some(img), None, None) => {
-                let oci = bux_oci::Oci::open()?;
-                let r = oci.ensure(img, |msg| eprintln!("{msg}")).await?;
-                Ok((r.rootfs.to_string_lossy().into_owned(), r.config))
+                let oci = crate::open_oci(store)?;
+                let r = oci
+                    .ensure_with_policy(img, self.pull.into(), report.pull_observer())
+                    .await?;
+                let layers = r.layers.iter().map(|d| oci.layer_path(d)).collect();
+                Ok((
+                    r.rootfs.to_string_lossy().into_owned(),
+                    r.config,
+                    Some(r.digest),
+                    r.rootfs_usage,
+                    layers,
+                ))
             }
-            (None, Some(root), None) => Ok((root.clone(), None)),
-            (None, None, Some(_)) => Ok((String::new(), None)),
+            (None, Some(root), None) => Ok((root.clone(), None, None, None, Vec::new())),
+            (None, None, Some(_)) => Ok((String::new(), None, None, None, Vec::new())),
             _ => unreachable!("clap validation"),
         }
     }
 }
 
-/// Parses Docker-style volume spec: `hostPath:guestPath[:ro]`.
-fn parse_volume(spec: &str) -> Result<(String, String, bool)> {
-    let parts: Vec<&str> = spec.splitn(3, ':').collect();
-    match parts.as_slice() {
-        [host, guest] => Ok((host.to_string(), guest.to_string(), false)),
-        [host, guest, opts] => {
-            let ro = opts.split(',').any(|o| o.eq_ignore_ascii_case("ro"));
-            Ok((host.to_string(), guest.to_string(), ro))
+/// `--pull` values, named as in `docker run`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+enum PullPolicy {
+    /// Pull only if the image is not in the store.
+    #[default]
+    Missing,
+    /// Check the registry on every run.
+    Always,
+    /// Use the store only.
+    Never,
+}
+
+impl From<PullPolicy> for bux_oci::PullPolicy {
+    fn from(policy: PullPolicy) -> Self {
+        match policy {
+            PullPolicy::Missing => Self::IfNotPresent,
+            PullPolicy::Always => Self::Always,
+            PullPolicy::Never => Self::Never,
+        }
+    }
+}
+
+/// Bytes typed to detach from a foreground run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DetachKeys(Vec<u8>);
+
+/// Parses a `--detach-keys` value, as in `docker run`: `ctrl-<key>` for a
+/// letter or one of `@[\\]^_`, or any single character, separated by commas.
+fn parse_detach_keys(spec: &str) -> Result<DetachKeys> {
+    spec.split(',')
+        .map(|key| {
+            let mut chars = key.chars();
+            match (key.strip_prefix("ctrl-"), chars.next(), chars.next()) {
+                (Some(rest), ..) => match rest.as_bytes() {
+                    &[c @ (b'a'..=b'z' | b'@' | b'[' | b'\\' | b']' | b'^' | b'_')] => {
+                        Ok(c.to_ascii_uppercase() & 0x1f)
+                    }
+                    _ => anyhow::bail!("unknown key {key:?}"),
+                },
+                (None, Some(c), None) if c.is_ascii() => Ok(c as u8),
+                _ => anyhow::bail!("unknown key {key:?}"),
+            }
+        })
+        .collect::<Result<_>>()
+        .map(DetachKeys)
+}
+
+/// Writes `id` to `path` atomically, failing if `path` already exists.
+///
+/// The ID goes to a temporary file next to `path` first and is hard-linked
+/// into place, so readers never see a partial file.
+fn write_cidfile(path: &std::path::Path, id: &str) -> Result<()> {
+    let name = path
+        .file_name()
+        .with_context(|| format!("cidfile {} has no file name", path.display()))?;
+    let tmp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    std::fs::write(&tmp, id).with_context(|| format!("writing {}", tmp.display()))?;
+    let linked = std::fs::hard_link(&tmp, path);
+    std::fs::remove_file(&tmp).ok();
+    linked.with_context(|| format!("creating cidfile {}", path.display()))
+}
+
+/// Returns the guest port of a `-p hostPort:guestPort[/proto]` spec.
+fn guest_port(spec: &str) -> Option<u16> {
+    let ports = spec.split('/').next()?;
+    ports.rsplit(':').next()?.parse().ok()
+}
+
+/// Picks a free host TCP port for `-P`.
+///
+/// The probe listener is closed before the VM binds the port, so another
+/// process could grab it in between; the VM then fails to publish it.
+fn random_host_port() -> Result<u16> {
+    let listener = std::net::TcpListener::bind(("0.0.0.0", 0))?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// A parsed `-v` volume.
+#[derive(Debug)]
+struct Volume {
+    /// Explicit virtio-fs tag; generated when `None`.
+    tag: Option<String>,
+    /// Host directory as given.
+    host: String,
+    /// Absolute mount point inside the guest.
+    guest: String,
+    /// Share read-only.
+    read_only: bool,
+}
+
+/// Parses a volume spec: `[tag:]hostPath:guestPath[:ro|:rw]`.
+///
+/// With three fields, a last field of `ro`/`rw` marks options; anything
+/// else means the first field is a tag.
+fn parse_volume(spec: &str) -> Result<Volume> {
+    /// Parses a comma-separated `ro`/`rw` list; `None` if it is not one.
+    fn access(opts: &str) -> Option<bool> {
+        let mut ro = false;
+        for opt in opts.split(',') {
+            match opt.to_ascii_lowercase().as_str() {
+                "ro" => ro = true,
+                "rw" => ro = false,
+                _ => return None,
+            }
+        }
+        Some(ro)
+    }
+
+    let parts: Vec<&str> = spec.split(':').collect();
+    let (tag, host, guest, read_only) = match parts.as_slice() {
+        [host, guest] => (None, *host, *guest, false),
+        [host, guest, opts] if access(opts).is_some() => {
+            (None, *host, *guest, access(opts).unwrap_or_default())
+        }
+        [tag, host, guest] => (Some(*tag), *host, *guest, false),
+        [tag, host, guest, opts] => {
+            let Some(ro) = access(opts) else {
+                anyhow::bail!("invalid volume options {opts:?} in {spec:?}; use ro or rw");
+            };
+            (Some(*tag), *host, *guest, ro)
+        }
+        _ => anyhow::bail!("invalid volume spec {spec:?}; use [tag:]hostPath:guestPath[:ro|:rw]"),
+    };
+    if tag.is_some_and(|t| t.is_empty() || t.contains('/')) {
+        anyhow::bail!("invalid volume spec {spec:?}; tag must be a plain name");
+    }
+    if host.is_empty() || !guest.starts_with('/') {
+        anyhow::bail!("invalid volume spec {spec:?}; guest path must be absolute");
+    }
+    Ok(Volume {
+        tag: tag.map(ToOwned::to_owned),
+        host: host.to_owned(),
+        guest: guest.to_owned(),
+        read_only,
+    })
+}
+
+/// Checks that `workdir` exists inside a directory rootfs, creating it when
+/// `create` is set, so a missing directory fails before boot with a clear
+/// message.
+///
+/// Stops at the first symlink: resolving it on the host could escape the
+/// rootfs, so the rest of the path is left to the guest.
+fn prepare_workdir(rootfs: &std::path::Path, workdir: &str, create: bool) -> Result<()> {
+    use std::path::Component;
+
+    let mut path = rootfs.to_path_buf();
+    for comp in std::path::Path::new(workdir).components() {
+        match comp {
+            Component::Normal(name) => path.push(name),
+            Component::ParentDir => {
+                anyhow::bail!("working directory {workdir} must not contain '..'")
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => continue,
+        }
+        match std::fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_symlink() => return Ok(()),
+            Ok(meta) if meta.is_dir() => {}
+            Ok(_) => anyhow::bail!("working directory {workdir} is not a directory"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                anyhow::ensure!(
+                    create,
+                    "working directory {workdir} does not exist (use --mkdir-workdir to create it)"
+                );
+                std::fs::create_dir(&path)
+                    .with_context(|| format!("failed to create working directory {workdir}"))?;
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to inspect working directory {workdir}"));
+            }
         }
-        _ => anyhow::bail!("invalid volume spec {spec:?}; use hostPath:guestPath[:ro]"),
     }
+    Ok(())
+}
+
+/// Resolves a `uid[:gid]` or `name[:group]` user spec against the rootfs's
+/// `/etc/passwd` and `/etc/group`.
+///
+/// Without a rootfs directory to read (e.g. `--root-disk`), only numeric
+/// specs resolve.
+fn resolve_user(spec: &str, rootfs: Option<&std::path::Path>) -> Result<(u32, u32)> {
+    let read = |file: &str| {
+        rootfs
+            .and_then(|dir| std::fs::read_to_string(dir.join("etc").join(file)).ok())
+            .unwrap_or_default()
+    };
+    bux_proto::resolve_user(spec, &read("passwd"), &read("group")).map_err(|e| match rootfs {
+        Some(_) => anyhow::anyhow!(e),
+        None => anyhow::anyhow!("{e} (names need a rootfs directory; use a numeric uid:gid)"),
+    })
 }
 
-/// Parses `uid[:gid]` user spec.
-pub fn parse_user(spec: &str) -> Result<(u32, Option<u32>)> {
-    if let Some((u, g)) = spec.split_once(':') {
-        let uid = u.parse().context("invalid UID")?;
-        let gid = g.parse().context("invalid GID")?;
-        Ok((uid, Some(gid)))
-    } else {
-        let uid = spec.parse().context("invalid UID")?;
-        Ok((uid, None))
+/// A parsed `--data-disk`.
+#[derive(Debug)]
+struct DataDiskSpec {
+    /// Disk name.
+    name: String,
+    /// Size to create the disk with if it does not exist.
+    size: Option<u64>,
+    /// Attach read-only.
+    read_only: bool,
+}
+
+/// Parses a data disk spec: `name[:size][:ro|:rw]`.
+fn parse_data_disk(spec: &str) -> Result<DataDiskSpec> {
+    let mut parts = spec.split(':');
+    let name = parts.next().unwrap_or_default().to_owned();
+    let mut disk = DataDiskSpec {
+        name,
+        size: None,
+        read_only: false,
+    };
+    for (i, part) in parts.enumerate() {
+        match part.to_ascii_lowercase().as_str() {
+            "ro" => disk.read_only = true,
+            "rw" => disk.read_only = false,
+            _ if i == 0 => {
+                let size = crate::parse_size(part)
+                    .map_err(|e| anyhow::anyhow!("data disk {spec:?}: {e}"))?;
+                disk.size = Some(size);
+            }
+            _ => anyhow::bail!("invalid data disk spec {spec:?}; use name[:size][:ro|:rw]"),
+        }
     }
+    Ok(disk)
 }
 
-/// Creates an ext4 disk image from an OCI rootfs directory.
+/// Returns the path of the named data disk, creating it with `size` bytes
+/// if it does not exist yet.
 #[cfg(unix)]
-fn create_disk_from_rootfs(rootfs: &str) -> Result<String> {
+fn data_disk_path(name: &str, size: Option<u64>) -> Result<String> {
+    let data_dir = dirs::data_dir()
+        .ok_or_else(|| anyhow::anyhow!("no platform data directory"))?
+        .join("bux");
+    let path = bux::DiskManager::open(data_dir)?.volume(name, size)?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+fn data_disk_path(_name: &str, _size: Option<u64>) -> Result<String> {
+    anyhow::bail!("Data disks require Linux or macOS")
+}
+
+/// Creates an ext4 disk image from an OCI rootfs directory, sized from
+/// `usage` when the pull counted it and by walking `rootfs` otherwise.
+#[cfg(unix)]
+fn create_disk_from_rootfs(
+    rootfs: &str,
+    ignore_file: Option<&str>,
+    workdir: Option<&str>,
+    usage: Option<bux_oci::RootfsUsage>,
+) -> Result<String> {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
 
     let data_dir = dirs::data_dir()
         .ok_or_else(|| anyhow::anyhow!("no platform data directory"))?
         .join("bux");
-    let dm = bux::DiskManager::open(&data_dir)?;
+    let dm = crate::disk_manager(&data_dir)?;
+
+    let ignore_text = ignore_file
+        .map(|f| std::fs::read_to_string(f).with_context(|| format!("failed to read {f}")))
+        .transpose()?
+        .unwrap_or_default();
 
     let mut h = DefaultHasher::new();
     rootfs.hash(&mut h);
+    ignore_text.hash(&mut h);
+    if let Some(wd) = workdir {
+        wd.hash(&mut h);
+    }
     let digest = format!("{:016x}", h.finish());
 
-    let base = dm.create_base(std::path::Path::new(rootfs), &digest)?;
+    let ignore = bux::IgnoreRules::parse(&ignore_text);
+    let path = std::path::Path::new(rootfs);
+    let base = match usage {
+        Some(u) => dm.create_base_sized(
+            path,
+            &digest,
+            &ignore,
+            bux::image_size_for(u.data_bytes, u.inodes),
+        )?,
+        None => dm.create_base_filtered(path, &digest, &ignore)?,
+    };
     Ok(base.to_string_lossy().into_owned())
 }
 
+/// Creates an ext4 disk image straight from an image's layer tarballs
+/// (bottom first), with a created `workdir` from `rootfs` on top.
+#[cfg(unix)]
+fn create_disk_from_layers(
+    layers: &[std::path::PathBuf],
+    digest: &str,
+    rootfs: &str,
+    workdir: Option<&str>,
+) -> Result<String> {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let data_dir = dirs::data_dir()
+        .ok_or_else(|| anyhow::anyhow!("no platform data directory"))?
+        .join("bux");
+    let dm = crate::disk_manager(&data_dir)?;
+
+    let mut h = DefaultHasher::new();
+    digest.hash(&mut h);
+    workdir.hash(&mut h);
+    let key = format!("{:016x}", h.finish());
+    if dm.base_path(&key).exists() {
+        return Ok(dm.base_path(&key).to_string_lossy().into_owned());
+    }
+
+    let mut all = layers.to_vec();
+    let top = workdir
+        .map(|wd| {
+            let path = std::env::temp_dir().join(format!("bux-workdir-{}.tar", std::process::id()));
+            write_workdir_layer(std::path::Path::new(rootfs), wd, &path).map(|()| path)
+        })
+        .transpose()?;
+    all.extend(top.clone());
+    let base = dm.create_base_from_layers(&all, &key);
+    if let Some(path) = top {
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(base?.to_string_lossy().into_owned())
+}
+
 #[cfg(not(unix))]
-fn create_disk_from_rootfs(_rootfs: &str) -> Result<String> {
+fn create_disk_from_layers(
+    _layers: &[std::path::PathBuf],
+    _digest: &str,
+    _rootfs: &str,
+    _workdir: Option<&str>,
+) -> Result<String> {
     anyhow::bail!("Disk image creation requires Linux or macOS")
 }
 
+/// Writes a layer tarball to `out` holding the directories of `workdir`
+/// as they are in `rootfs`, up to the first symlink like
+/// [`prepare_workdir`].
+#[cfg(unix)]
+fn write_workdir_layer(
+    rootfs: &std::path::Path,
+    workdir: &str,
+    out: &std::path::Path,
+) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Component;
+
+    let mut builder = tar::Builder::new(std::fs::File::create(out)?);
+    let mut path = std::path::PathBuf::new();
+    for comp in std::path::Path::new(workdir).components() {
+        let Component::Normal(name) = comp else {
+            continue;
+        };
+        path.push(name);
+        let meta = std::fs::symlink_metadata(rootfs.join(&path))?;
+        if !meta.is_dir() {
+            break;
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(meta.mode() & 0o7777);
+        header.set_uid(meta.uid().into());
+        header.set_gid(meta.gid().into());
+        header.set_mtime(meta.mtime().try_into().unwrap_or(0));
+        header.set_size(0);
+        builder.append_data(&mut header, &path, std::io::empty())?;
+    }
+    builder.into_inner()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_disk_from_rootfs(
+    _rootfs: &str,
+    _ignore_file: Option<&str>,
+    _workdir: Option<&str>,
+    _usage: Option<bux_oci::RootfsUsage>,
+) -> Result<String> {
+    anyhow::bail!("Disk image creation requires Linux or macOS")
+}
+
+/// Spawns the VM, then either prints its name (no `foreground` keys, i.e.
+/// `-d`) or waits for it to exit. A detached VM with a health check is
+/// only reported once the check passes.
+///
+/// In the foreground, SIGINT, SIGQUIT, SIGTSTP and SIGTERM are forwarded to
+/// the guest's primary process, so the workload decides how to react; when
+/// the guest cannot take them, SIGINT and SIGTERM stop the VM instead.
+/// Typing the detach keys on a terminal stops waiting and leaves the VM
+/// running, restoring the terminal first.
 #[cfg(unix)]
 async fn spawn_vm(
     builder: bux::VmBuilder,
-    image: Option<String>,
+    image: Option<bux::ImageRef>,
     name: Option<String>,
-    detach: bool,
+    foreground: Option<DetachKeys>,
     auto_remove: bool,
+    cidfile: Option<&std::path::Path>,
+    report: Reporter,
 ) -> Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+
     let rt = crate::vm::open_runtime()?;
     let mut handle = rt.spawn(builder, image, name, auto_remove).await?;
 
-    let id = &handle.state().id;
-    if detach {
-        println!("{}", handle.state().name.as_deref().unwrap_or(id));
-        return Ok(());
+    let id = handle.state().id.clone();
+    if let Some(path) = cidfile
+        && let Err(e) = write_cidfile(path, &id)
+    {
+        // Without the file the caller has no handle on the VM.
+        handle.stop().await.ok();
+        return Err(e);
     }
+    let Some(DetachKeys(keys)) = foreground else {
+        let config = &handle.state().config;
+        if config.health_cmd.is_some() {
+            let limit = health_wait(config.health_interval, config.health_retries);
+            handle.wait_healthy(limit).await.with_context(|| {
+                format!("health check of VM {id} did not pass; the VM is left running")
+            })?;
+        }
+        println!("{}", handle.state().name.as_deref().unwrap_or(&id));
+        return Ok(());
+    };
 
-    eprintln!("{id}");
+    report.status(&id);
 
-    // Race: wait for VM exit vs. SIGTERM/SIGINT for graceful shutdown.
-    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
-    let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigquit = signal(SignalKind::quit())?;
+    let mut sigtstp = signal(SignalKind::from_raw(
+        nix::sys::signal::Signal::SIGTSTP as i32,
+    ))?;
+    let mut sigterm = signal(SignalKind::terminate())?;
 
-    tokio::select! {
-        result = handle.wait() => result?,
-        _ = sigterm.recv() => {
-            eprintln!("\n[bux] received SIGTERM, stopping VM {id}...");
-            handle.stop().await?;
+    let raw = crate::term::RawInput::enable();
+    let mut input = raw.as_ref().map(|_| crate::term::input());
+    let mut detach = crate::term::DetachMatcher::new(&keys);
+
+    loop {
+        let sig = tokio::select! {
+            result = handle.wait() => {
+                result?;
+                break;
+            }
+            Some(bytes) = async { input.as_mut()?.recv().await } => {
+                if detach.feed(&bytes) {
+                    drop(raw);
+                    report.status(format_args!("\n[bux] detached from VM {id}"));
+                    return Ok(());
+                }
+                continue;
+            }
+            _ = sigint.recv() => nix::sys::signal::Signal::SIGINT,
+            _ = sigquit.recv() => nix::sys::signal::Signal::SIGQUIT,
+            _ = sigtstp.recv() => nix::sys::signal::Signal::SIGTSTP,
+            _ = sigterm.recv() => nix::sys::signal::Signal::SIGTERM,
+        };
+        if forward_signal(&handle, sig as i32).await {
+            continue;
         }
-        _ = sigint.recv() => {
-            eprintln!("\n[bux] received SIGINT, stopping VM {id}...");
+        if matches!(
+            sig,
+            nix::sys::signal::Signal::SIGINT | nix::sys::signal::Signal::SIGTERM
+        ) {
+            report.status(format_args!("\n[bux] received {sig}, stopping VM {id}..."));
             handle.stop().await?;
+            break;
         }
+        eprintln!("warning: {id}: cannot forward {sig} to the guest");
     }
+    drop(raw);
 
+    if let Some(path) = cidfile {
+        std::fs::remove_file(path).ok();
+    }
     Ok(())
 }
 
+/// How long `bux run -d` waits for a health check to pass: time for the
+/// agent to come up, then every allowed check running out its interval
+/// before the next one.
+#[cfg(unix)]
+fn health_wait(interval: Option<std::time::Duration>, retries: Option<u32>) -> std::time::Duration {
+    let each = interval.unwrap_or(bux::health::DEFAULT_INTERVAL);
+    let checks = retries.unwrap_or(bux::health::DEFAULT_RETRIES);
+    std::time::Duration::from_secs(30) + each.saturating_mul(checks.saturating_mul(2))
+}
+
+/// Delivers `sig` to the guest's primary process; false when the VM has no
+/// agent, the agent predates signal forwarding, or it started no command.
+#[cfg(unix)]
+async fn forward_signal(handle: &bux::VmHandle, sig: i32) -> bool {
+    let Ok(client) = handle.client() else {
+        return false;
+    };
+    client.signal_primary(sig).await.is_ok()
+}
+
 #[cfg(not(unix))]
 #[allow(clippy::unused_async)]
 async fn spawn_vm(
     _builder: bux::VmBuilder,
-    _image: Option<String>,
+    _image: Option<bux::ImageRef>,
     _name: Option<String>,
-    _detach: bool,
+    _foreground: Option<DetachKeys>,
     _auto_remove: bool,
+    _cidfile: Option<&std::path::Path>,
+    _report: Reporter,
 ) -> Result<()> {
     anyhow::bail!("VM execution requires Linux or macOS")
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    /// Parses `bux run` arguments as the CLI would.
+    fn run_args(argv: &[&str]) -> Result<RunArgs, clap::Error> {
+        #[derive(clap::Parser)]
+        struct Run {
+            #[command(flatten)]
+            args: RunArgs,
+        }
+        <Run as clap::Parser>::try_parse_from(std::iter::once("run").chain(argv.iter().copied()))
+            .map(|run| run.args)
+    }
+
+    #[test]
+    fn health_interval_rejects_overflowing_durations() {
+        /// Parses a run with `--health-interval value`.
+        fn interval(value: &str) -> Result<RunArgs, clap::Error> {
+            run_args(&["--health-cmd", "true", "--health-interval", value, "a"])
+        }
+        assert_eq!(
+            interval("2m").unwrap().health_interval,
+            Some(std::time::Duration::from_mins(2))
+        );
+        let max = u64::MAX.to_string();
+        assert!(interval(&max).is_ok());
+        assert!(interval(&format!("{max}m")).is_err());
+        assert!(interval(&format!("{}h", u64::MAX / 3600 + 1)).is_err());
+    }
+
+    #[test]
+    fn mkdir_workdir_needs_a_rootfs_it_may_change() {
+        assert!(run_args(&["--mkdir-workdir", "-w", "/srv", "alpine"]).is_ok());
+        assert!(run_args(&["--mkdir-workdir", "--disk", "-w", "/srv", "alpine"]).is_ok());
+        assert!(run_args(&["--mkdir-workdir", "--root-disk", "/tmp/root.img"]).is_err());
+    }
+
+    #[test]
+    fn prepare_workdir_checks_and_creates() {
+        let rootfs = std::env::temp_dir().join(format!("bux_workdir_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&rootfs);
+        std::fs::create_dir_all(rootfs.join("srv")).unwrap();
+        std::fs::write(rootfs.join("file"), b"").unwrap();
+        std::os::unix::fs::symlink("/elsewhere", rootfs.join("link")).unwrap();
+
+        prepare_workdir(&rootfs, "/srv", false).unwrap();
+        assert!(prepare_workdir(&rootfs, "/srv/app/data", false).is_err());
+        assert!(!rootfs.join("srv/app").exists());
+        prepare_workdir(&rootfs, "/srv/app/data", true).unwrap();
+        assert!(rootfs.join("srv/app/data").is_dir());
+
+        assert!(prepare_workdir(&rootfs, "/file", true).is_err());
+        assert!(prepare_workdir(&rootfs, "/srv/../etc", true).is_err());
+        // Symlinks resolve in the guest; the rest is left to it.
+        prepare_workdir(&rootfs, "/link/sub", true).unwrap();
+        assert!(!rootfs.join("link/sub").exists());
+        let _ = std::fs::remove_dir_all(&rootfs);
+    }
+
+    #[test]
+    fn workdir_layer_holds_the_created_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let rootfs = std::env::temp_dir().join(format!("bux_wdlayer_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&rootfs);
+        std::fs::create_dir_all(rootfs.join("srv/app")).unwrap();
+        for (dir, mode) in [("srv", 0o755), ("srv/app", 0o750)] {
+            std::fs::set_permissions(rootfs.join(dir), std::fs::Permissions::from_mode(mode))
+                .unwrap();
+        }
+        std::os::unix::fs::symlink("/elsewhere", rootfs.join("link")).unwrap();
+
+        let out = rootfs.with_extension("tar");
+        let entries = |workdir: &str| {
+            write_workdir_layer(&rootfs, workdir, &out).unwrap();
+            let mut archive = tar::Archive::new(std::fs::File::open(&out).unwrap());
+            archive
+                .entries()
+                .unwrap()
+                .map(|raw| {
+                    let entry = raw.unwrap();
+                    assert!(entry.header().entry_type().is_dir());
+                    let path = entry.path().unwrap().display().to_string();
+                    (path, entry.header().mode().unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            entries("/srv/app"),
+            [("srv".to_owned(), 0o755), ("srv/app".to_owned(), 0o750)]
+        );
+        // Symlinks resolve in the guest, as in `prepare_workdir`.
+        assert!(entries("/link/sub").is_empty());
+        let _ = std::fs::remove_file(&out);
+        let _ = std::fs::remove_dir_all(&rootfs);
+    }
+
+    #[test]
+    fn guest_port_reads_publish_specs() {
+        assert_eq!(guest_port("8080:80"), Some(80));
+        assert_eq!(guest_port("8053:53/udp"), Some(53));
+        assert_eq!(guest_port("bogus"), None);
+    }
+
+    #[test]
+    fn parse_data_disk_specs() {
+        let disk = parse_data_disk("cache:10G").unwrap();
+        assert_eq!(
+            (disk.name.as_str(), disk.size, disk.read_only),
+            ("cache", Some(10 << 30), false)
+        );
+        let ro = parse_data_disk("cache:ro").unwrap();
+        assert_eq!((ro.size, ro.read_only), (None, true));
+        let sized = parse_data_disk("cache:512M:RO").unwrap();
+        assert_eq!((sized.size, sized.read_only), (Some(512 << 20), true));
+        assert!(parse_data_disk("cache").unwrap().size.is_none());
+        assert!(parse_data_disk("cache:ro:1G").is_err());
+        assert!(parse_data_disk("cache:lots").is_err());
+    }
+
+    #[test]
+    fn parse_volume_access_modes() {
+        let vol = parse_volume("./src:/src").unwrap();
+        assert_eq!(
+            (
+                vol.tag,
+                vol.host.as_str(),
+                vol.guest.as_str(),
+                vol.read_only
+            ),
+            (None, "./src", "/src", false)
+        );
+        assert!(parse_volume("/a:/b:ro").unwrap().read_only);
+        assert!(!parse_volume("/a:/b:rw").unwrap().read_only);
+        assert!(parse_volume("/a:/b:RO").unwrap().read_only);
+    }
+
+    #[test]
+    fn parse_volume_explicit_tag() {
+        let vol = parse_volume("data:/host/data:/data").unwrap();
+        assert_eq!(vol.tag.as_deref(), Some("data"));
+        assert_eq!(
+            (vol.host.as_str(), vol.guest.as_str()),
+            ("/host/data", "/data")
+        );
+        assert!(!vol.read_only);
+        assert!(parse_volume("data:/host/data:/data:ro").unwrap().read_only);
+    }
+
+    #[test]
+    fn cidfile_is_written_once() {
+        let dir = std::env::temp_dir().join(format!("bux_cidfile_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("vm.cid");
+
+        write_cidfile(&path, "abc123").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "abc123");
+        assert!(write_cidfile(&path, "def456").is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "abc123");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn parse_detach_keys_like_docker() {
+        assert_eq!(
+            parse_detach_keys("ctrl-p,ctrl-q").unwrap(),
+            DetachKeys(vec![0x10, 0x11])
+        );
+        assert_eq!(
+            parse_detach_keys("ctrl-@,ctrl-\\,x").unwrap(),
+            DetachKeys(vec![0x00, 0x1c, b'x'])
+        );
+        assert!(parse_detach_keys("").is_err());
+        assert!(parse_detach_keys("ctrl-1").is_err());
+        assert!(parse_detach_keys("ab").is_err());
+    }
+
+    #[test]
+    fn parse_volume_rejects_bad_specs() {
+        assert!(parse_volume("/a").is_err());
+        assert!(parse_volume("/a:/b:rx").is_err());
+        assert!(parse_volume("/a:relative").is_err());
+        assert!(parse_volume(":/b").is_err());
+        assert!(parse_volume("data:/a:/b:rx").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Column-aligned tables for the list commands (`images`, `ps`).
+
+use std::fmt::Write as _;
+use std::str::FromStr;
+
+/// One column of a [`Table`].
+#[derive(Debug, Clone, Copy)]
+pub struct Column {
+    /// Header text.
+    header: &'static str,
+    /// Minimum width; cells are padded to it.
+    width: usize,
+    /// Cells longer than this are cut, e.g. digests.
+    max: Option<usize>,
+    /// Pad on the left, for numbers.
+    right: bool,
+}
+
+impl Column {
+    /// A left-aligned column at least `width` characters wide.
+    pub const fn new(header: &'static str, width: usize) -> Self {
+        Self {
+            header,
+            width,
+            max: None,
+            right: false,
+        }
+    }
+
+    /// Aligns cells to the right.
+    pub const fn right(mut self) -> Self {
+        self.right = true;
+        self
+    }
+
+    /// Cuts cells to `max` characters.
+    pub const fn max(mut self, max: usize) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    /// Exactly `width` characters wide, for a user-chosen width.
+    pub const fn fixed(mut self, width: usize) -> Self {
+        self.width = width;
+        self.max = Some(width);
+        self
+    }
+}
+
+/// Rows under a header, each cell padded to its column's width and
+/// separated by a space.
+#[derive(Debug)]
+pub struct Table {
+    /// Column layout.
+    columns: Vec<Column>,
+    /// Rendered lines, header first.
+    out: String,
+}
+
+impl Table {
+    /// Starts a table with the header line of `columns`.
+    pub fn new(columns: Vec<Column>) -> Self {
+        let mut table = Self {
+            columns,
+            out: String::new(),
+        };
+        let headers: Vec<&str> = table.columns.iter().map(|c| c.header).collect();
+        table.row(headers);
+        table
+    }
+
+    /// Adds a row; missing cells are left empty.
+    pub fn row<S: AsRef<str>>(&mut self, cells: impl IntoIterator<Item = S>) {
+        let mut line = String::new();
+        let mut rest = cells.into_iter();
+        for (i, column) in self.columns.iter().enumerate() {
+            let cell = rest.next();
+            let full = cell.as_ref().map_or("", AsRef::as_ref);
+            let text = column.max.map_or(full, |max| {
+                full.char_indices()
+                    .nth(max)
+                    .map_or(full, |(at, _)| &full[..at])
+            });
+            if i > 0 {
+                line.push(' ');
+            }
+            let width = column.width;
+            let _ = if column.right {
+                write!(line, "{text:>width$}")
+            } else {
+                write!(line, "{text:<width$}")
+            };
+        }
+        self.out.push_str(line.trim_end());
+        self.out.push('\n');
+    }
+
+    /// Writes the table to stdout.
+    pub fn print(&self) {
+        print!("{}", self.out);
+    }
+}
+
+/// A column picked by `--columns`: a name, optionally with `:WIDTH`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColumnSpec<C> {
+    /// The column.
+    pub column: C,
+    /// User-chosen width, if any.
+    pub width: Option<usize>,
+}
+
+impl<C: clap::ValueEnum> FromStr for ColumnSpec<C> {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, width) = match s.split_once(':') {
+            Some((name, digits)) => {
+                let width = digits
+                    .parse()
+                    .map_err(|_| format!("invalid column width in {s:?}"))?;
+                (name, Some(width))
+            }
+            None => (s, None),
+        };
+        let column = C::from_str(name, true).map_err(|_| {
+            let names: Vec<String> = C::value_variants()
+                .iter()
+                .filter_map(|c| Some(c.to_possible_value()?.get_name().to_owned()))
+                .collect();
+            format!("unknown column {name:?} (one of {})", names.join(", "))
+        })?;
+        Ok(Self { column, width })
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+    enum Col {
+        Name,
+        Size,
+    }
+
+    #[test]
+    fn rows_pad_cut_and_align() {
+        let mut table = Table::new(vec![
+            Column::new("NAME", 6),
+            Column::new("DIGEST", 8).max(7),
+            Column::new("SIZE", 6).right(),
+            Column::new("NOTE", 0),
+        ]);
+        table.row(["a", "sha256:abcdef", "1 B", "-"]);
+        table.row(["longer-name", "x"]);
+        assert_eq!(
+            table.out,
+            "NAME   DIGEST     SIZE NOTE\n\
+             a      sha256:     1 B -\n\
+             longer-name x\n"
+        );
+    }
+
+    #[test]
+    fn column_specs_take_an_optional_width() {
+        let sized: ColumnSpec<Col> = "size:12".parse().unwrap();
+        assert_eq!((sized.column, sized.width), (Col::Size, Some(12)));
+        let named: ColumnSpec<Col> = "NAME".parse().unwrap();
+        assert_eq!((named.column, named.width), (Col::Name, None));
+        let err = "disk".parse::<ColumnSpec<Col>>().unwrap_err();
+        assert!(err.contains("one of name, size"), "{err}");
+        assert!("size:wide".parse::<ColumnSpec<Col>>().is_err());
+    }
+}
@@ -0,0 +1,106 @@
+//! Keystrokes from the local terminal during a foreground `bux run`.
+
+use std::io::{IsTerminal, Read};
+
+use nix::sys::termios::{
+    InputFlags, LocalFlags, SetArg, SpecialCharacterIndices, Termios, tcgetattr, tcsetattr,
+};
+use tokio::sync::mpsc;
+
+/// Puts the terminal on stdin into character-at-a-time mode for as long as
+/// it lives, restoring the previous settings on drop.
+///
+/// Echo, line buffering and flow control (which would swallow Ctrl-Q) are
+/// turned off; signal keys stay on, so Ctrl-C still reaches `bux` as
+/// `SIGINT`, and output processing is untouched so the console renders as
+/// before.
+pub struct RawInput {
+    saved: Termios,
+}
+
+impl RawInput {
+    /// Switches stdin's terminal over, or returns `None` when stdin is not a
+    /// terminal.
+    pub fn enable() -> Option<Self> {
+        let stdin = std::io::stdin();
+        if !stdin.is_terminal() {
+            return None;
+        }
+        let saved = tcgetattr(&stdin).ok()?;
+        let mut raw = saved.clone();
+        raw.local_flags
+            .remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::IEXTEN);
+        raw.input_flags.remove(InputFlags::IXON | InputFlags::ICRNL);
+        raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+        raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+        tcsetattr(&stdin, SetArg::TCSANOW, &raw).ok()?;
+        Some(Self { saved })
+    }
+}
+
+impl Drop for RawInput {
+    fn drop(&mut self) {
+        tcsetattr(std::io::stdin(), SetArg::TCSANOW, &self.saved).ok();
+    }
+}
+
+/// Reads stdin on a plain thread and hands over what arrives.
+///
+/// Not `tokio::io::stdin`: its blocked read would hold up runtime shutdown
+/// after a detach, whereas this thread simply ends with the process.
+pub fn input() -> mpsc::Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel(16);
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        let mut buf = [0u8; 64];
+        while let Ok(n @ 1..) = stdin.read(&mut buf) {
+            if tx.blocking_send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Matches a detach key sequence against keystrokes as they arrive.
+pub struct DetachMatcher<'a> {
+    keys: &'a [u8],
+    matched: usize,
+}
+
+impl<'a> DetachMatcher<'a> {
+    pub const fn new(keys: &'a [u8]) -> Self {
+        Self { keys, matched: 0 }
+    }
+
+    /// Feeds `input`; true once the whole sequence has been typed.
+    pub fn feed(&mut self, input: &[u8]) -> bool {
+        for &byte in input {
+            if self.keys.get(self.matched) == Some(&byte) {
+                self.matched += 1;
+            } else {
+                self.matched = usize::from(self.keys.first() == Some(&byte));
+            }
+            if self.matched == self.keys.len() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detach_sequence_must_be_typed_in_a_row() {
+        let mut m = DetachMatcher::new(b"\x10\x11");
+        assert!(!m.feed(b"ls\x10"));
+        assert!(m.feed(b"\x11"));
+
+        let mut broken = DetachMatcher::new(b"\x10\x11");
+        assert!(!broken.feed(b"\x10x\x11"));
+        assert!(broken.feed(b"\x10\x10\x11"));
+    }
+}
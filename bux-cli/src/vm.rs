@@ -2,7 +2,10 @@
 
 use anyhow::{Context, Result};
 
-use crate::OutputFormat;
+use crate::report::Reporter;
+#[cfg(unix)]
+use crate::table::{Column, Table};
+use crate::{OutputFormat, StoreOpts};
 
 /// Arguments for `bux exec`.
 ///
@@ -34,10 +37,22 @@ pub struct ExecArgs {
     #[arg(short = 'w', long)]
     pub workdir: Option<String>,
 
-    /// User (format: uid[:gid]).
+    /// Create the working directory if it does not exist.
+    #[arg(long, requires = "workdir")]
+    pub mkdir_workdir: bool,
+
+    /// User, resolved in the guest (format: uid[:gid] or name[:group]).
     #[arg(short = 'u', long = "user")]
     pub user: Option<String>,
 
+    /// Drop a Linux capability (e.g. NET_RAW, or ALL).
+    #[arg(long = "cap-drop")]
+    pub cap_drop: Vec<String>,
+
+    /// Prevent the command from gaining privileges (e.g. via setuid binaries).
+    #[arg(long)]
+    pub no_new_privs: bool,
+
     /// VM ID, name, or prefix.
     #[arg(required = true)]
     pub target: String,
@@ -54,11 +69,7 @@ pub struct PsArgs {
     #[arg(short = 'a', long)]
     pub all: bool,
 
-    /// Only display VM IDs.
-    #[arg(short = 'q', long)]
-    pub quiet: bool,
-
-    /// Filter output (e.g. status=running, name=myvm).
+    /// Filter output (e.g. status=running, health=healthy, name=myvm).
     #[arg(short = 'f', long = "filter")]
     pub filter: Vec<String>,
 
@@ -102,19 +113,72 @@ pub struct RmArgs {
     #[arg(short = 'f', long)]
     pub force: bool,
 
+    /// Also delete the VMs' named data disks (`run --data-disk`) that no
+    /// other VM uses.
+    #[arg(short = 'v', long)]
+    pub volumes: bool,
+
     /// VM IDs, names, or prefixes.
     #[arg(required = true, num_args = 1..)]
     pub targets: Vec<String>,
 }
 
+/// Arguments for `bux prune`.
+///
+/// Without scope flags, prunes stopped VMs and unreferenced image blobs.
+#[derive(clap::Args)]
+pub struct PruneArgs {
+    /// Remove stopped VMs.
+    #[arg(long)]
+    pub vms: bool,
+
+    /// Remove unreferenced image blobs and compact the image index.
+    #[arg(long)]
+    pub images: bool,
+
+    /// Remove disk base images no VM is using.
+    #[arg(long)]
+    pub disks: bool,
+
+    /// Prune everything (same as --vms --images --disks).
+    #[arg(short = 'a', long)]
+    pub all: bool,
+
+    /// Do not prompt for confirmation.
+    #[arg(short = 'f', long)]
+    pub force: bool,
+}
+
 /// Arguments for `bux wait`.
 #[derive(clap::Args)]
 pub struct WaitArgs {
+    /// Give up after this many seconds.
+    #[arg(long)]
+    pub timeout: Option<u64>,
+
     /// VM IDs, names, or prefixes.
     #[arg(required = true, num_args = 1..)]
     pub targets: Vec<String>,
 }
 
+/// Arguments for `bux attach`.
+#[derive(clap::Args)]
+pub struct AttachArgs {
+    /// VM ID, name, or prefix.
+    pub target: String,
+}
+
+/// Arguments for `bux diff`.
+#[derive(clap::Args)]
+pub struct DiffArgs {
+    /// Output format.
+    #[arg(long, default_value = "table")]
+    pub format: OutputFormat,
+
+    /// VM ID, name, or prefix.
+    pub target: String,
+}
+
 /// Arguments for `bux inspect`.
 #[derive(clap::Args)]
 pub struct InspectArgs {
@@ -122,6 +186,11 @@ pub struct InspectArgs {
     #[arg(short = 'f', long, default_value = "json")]
     pub format: String,
 
+    /// Also query each running VM's guest agent for the environment that
+    /// exec'd commands start from (`agent_env`).
+    #[arg(long)]
+    pub env: bool,
+
     /// VM IDs, names, or prefixes.
     #[arg(required = true, num_args = 1..)]
     pub targets: Vec<String>,
@@ -130,14 +199,27 @@ pub struct InspectArgs {
 /// Arguments for `bux cp`.
 #[derive(clap::Args)]
 pub struct CpArgs {
-    /// Suppress progress output.
-    #[arg(short = 'q', long)]
-    pub quiet: bool,
+    /// Resume an interrupted host → guest file copy instead of restarting
+    /// it, if the file is unchanged since (same size and modification time).
+    #[arg(long)]
+    pub resume: bool,
 
-    /// Source (host path or `<vm>:<guest_path>`).
+    /// Archive mode: also keep owner and group (mode and mtime are kept by
+    /// default). Ownership the destination refuses to take is skipped.
+    #[arg(short = 'a', long, conflicts_with = "no_preserve")]
+    pub archive: bool,
+
+    /// Keep neither mode nor timestamps: files are written 0644 and
+    /// directories 0755, with the current time.
+    #[arg(long)]
+    pub no_preserve: bool,
+
+    /// Source (host path, `<vm>:<guest_path>`, or `-` for a tar archive
+    /// on stdin).
     pub src: String,
 
-    /// Destination (host path or `<vm>:<guest_path>`).
+    /// Destination (host path, `<vm>:<guest_path>`, or `-` to write a tar
+    /// archive to stdout).
     pub dst: String,
 }
 
@@ -160,8 +242,34 @@ pub fn open_runtime() -> Result<bux::Runtime> {
     Ok(bux::Runtime::open(data_dir)?)
 }
 
+/// Manifest digests of the images VMs on record were created from, whose
+/// rootfs image pruning must keep.
+///
+/// Reads the state database directly: unlike [`open_runtime`] this does not
+/// take the runtime lock, which a foreground `bux run` holds.
 #[cfg(unix)]
-pub fn ps(args: &PsArgs) -> Result<()> {
+pub fn pinned_images() -> Result<Vec<String>> {
+    let db_path = dirs::data_dir()
+        .context("no platform data directory")?
+        .join("bux/bux.db");
+    if !db_path.exists() {
+        return Ok(Vec::new());
+    }
+    let db = bux::StateDb::open(db_path)?;
+    Ok(db
+        .list()?
+        .into_iter()
+        .filter_map(|vm| vm.image_digest)
+        .collect())
+}
+
+#[cfg(not(unix))]
+pub fn pinned_images() -> Result<Vec<String>> {
+    Ok(Vec::new())
+}
+
+#[cfg(unix)]
+pub fn ps(args: &PsArgs, report: Reporter) -> Result<()> {
     let rt = open_runtime()?;
     let vms = rt.list()?;
 
@@ -193,15 +301,18 @@ pub fn ps(args: &PsArgs) -> Result<()> {
                 };
                 s == value
             }
+            "health" => vm.health.is_some_and(|h| h.as_str() == value),
             "name" => vm.name.as_deref() == Some(value),
             "id" => vm.id.starts_with(value),
-            "image" => vm.image.as_deref() == Some(value),
+            "image" => {
+                vm.image.as_deref() == Some(value) || vm.image_digest.as_deref() == Some(value)
+            }
             _ => true,
         });
     }
 
     // Quiet mode: IDs only.
-    if args.quiet {
+    if report.is_quiet() {
         for vm in &filtered {
             println!("{}", vm.id);
         }
@@ -209,32 +320,47 @@ pub fn ps(args: &PsArgs) -> Result<()> {
     }
 
     if matches!(args.format, OutputFormat::Json) {
-        println!("{}", serde_json::to_string_pretty(&filtered)?);
+        let shown: Vec<_> = filtered.iter().map(bux::VmState::redacted).collect();
+        println!("{}", serde_json::to_string_pretty(&shown)?);
         return Ok(());
     }
 
     if filtered.is_empty() {
         return Ok(());
     }
-    println!(
-        "{:<14} {:<16} {:<8} {:<10} IMAGE",
-        "ID", "NAME", "PID", "STATUS"
-    );
+    let mut table = Table::new(vec![
+        Column::new("ID", 14),
+        Column::new("NAME", 16),
+        Column::new("PID", 8),
+        Column::new("STATUS", 19),
+        Column::new("DIGEST", 19).max(19),
+        Column::new("IMAGE", 0),
+    ]);
     for vm in &filtered {
         let name = vm.name.as_deref().unwrap_or("-");
         let image = vm.image.as_deref().unwrap_or("-");
-        let status = match vm.status {
+        let digest = vm.image_digest.as_deref().unwrap_or("-");
+        let lifecycle = match vm.status {
             bux::Status::Creating => "creating",
             bux::Status::Running => "running",
             bux::Status::Paused => "paused",
             bux::Status::Stopped => "stopped",
             _ => "unknown",
         };
-        println!(
-            "{:<14} {:<16} {:<8} {:<10} {}",
-            vm.id, name, vm.pid, status, image
-        );
+        let status = match vm.health {
+            Some(health) => format!("{lifecycle} ({})", health.as_str()),
+            None => lifecycle.to_owned(),
+        };
+        table.row([
+            vm.id.as_str(),
+            name,
+            &vm.pid.to_string(),
+            &status,
+            digest,
+            image,
+        ]);
     }
+    table.print();
     Ok(())
 }
 
@@ -253,7 +379,14 @@ pub async fn stop(args: StopArgs) -> Result<()> {
                     let _ = h.signal(sig);
                 }
                 match h.stop_timeout(timeout).await {
-                    Ok(()) => println!("{target}"),
+                    Ok(bux::StopOutcome::Killed) => {
+                        eprintln!(
+                            "warning: {target}: did not stop within {}s; killed",
+                            args.time
+                        );
+                        println!("{target}");
+                    }
+                    Ok(_) => println!("{target}"),
                     Err(e) => errors.push(format!("{target}: {e}")),
                 }
             }
@@ -292,22 +425,24 @@ pub fn kill(args: &KillArgs) -> Result<()> {
 }
 
 #[cfg(unix)]
-pub fn rm(args: &RmArgs) -> Result<()> {
+pub fn rm(args: &RmArgs, report: Reporter) -> Result<()> {
     let rt = open_runtime()?;
     let mut errors = Vec::new();
 
+    let mut total = 0;
+
     for target in &args.targets {
-        // Force mode: kill before removing.
-        if args.force
-            && let Ok(mut h) = rt.get(target)
-        {
-            let _ = h.kill();
-        }
-        match rt.remove(target) {
-            Ok(()) => println!("{target}"),
+        match rt.remove(target, args.force, args.volumes) {
+            Ok(reclaimed) => {
+                println!("{target}");
+                total += reclaimed.bytes;
+            }
             Err(e) => errors.push(format!("{target}: {e}")),
         }
     }
+    if total > 0 {
+        report.status(format_args!("Reclaimed {}", crate::human_size(total)));
+    }
 
     if errors.is_empty() {
         Ok(())
@@ -316,45 +451,55 @@ pub fn rm(args: &RmArgs) -> Result<()> {
     }
 }
 
+impl ExecArgs {
+    /// Builds the guest exec request from the command and its flags.
+    fn request(&self) -> Result<bux::ExecStart> {
+        let (cmd, cmd_args) = self.command.split_first().context("command required")?;
+        let mut req = bux::ExecStart::new(cmd).args(cmd_args.to_vec());
+
+        // Merge env: -e overrides --env-file.
+        let mut file_env = Vec::new();
+        for path in &self.env_file {
+            file_env.extend(read_env_file(path)?);
+        }
+        let cli_env: Vec<String> = self
+            .env
+            .iter()
+            .filter_map(|e| resolve_env_entry(e))
+            .collect();
+        let env_vars = merge_env([file_env.as_slice(), cli_env.as_slice()]);
+        if !env_vars.is_empty() {
+            req = req.env(env_vars);
+        }
+        if let Some(ref wd) = self.workdir {
+            req = req.cwd(wd);
+            if self.mkdir_workdir {
+                req = req.create_cwd();
+            }
+        }
+        if let Some(ref user_spec) = self.user {
+            req = req.user_spec(user_spec);
+        }
+        if !self.cap_drop.is_empty() {
+            req = req.cap_drop(self.cap_drop.clone());
+        }
+        if self.no_new_privs {
+            req = req.no_new_privs();
+        }
+        Ok(req)
+    }
+}
+
 #[cfg(unix)]
 pub async fn exec(args: ExecArgs) -> Result<()> {
-    use std::io::Write;
-
     let rt = open_runtime()?;
     let handle = rt.get(&args.target)?;
-
-    let (cmd, cmd_args) = args.command.split_first().context("command required")?;
-    let mut req = bux::ExecStart::new(cmd).args(cmd_args.to_vec());
-
-    // Merge env: --env-file first, then -e overrides.
-    let mut env_vars = Vec::new();
-    for path in &args.env_file {
-        env_vars.extend(read_env_file(path)?);
-    }
-    env_vars.extend(args.env);
-    if !env_vars.is_empty() {
-        req = req.env(env_vars);
-    }
-    if let Some(ref wd) = args.workdir {
-        req = req.cwd(wd);
-    }
-    if let Some(ref user_spec) = args.user {
-        let (uid, gid) = crate::run::parse_user(user_spec)?;
-        req = req.user(uid, gid.unwrap_or(uid));
-    }
+    let req = args.request()?;
 
     let output = handle
         .exec(req)
         .await?
-        .stream(|msg| match msg {
-            bux_proto::ExecOut::Stdout(d) => {
-                let _ = std::io::stdout().write_all(d);
-            }
-            bux_proto::ExecOut::Stderr(d) => {
-                let _ = std::io::stderr().write_all(d);
-            }
-            _ => {}
-        })
+        .copy_output(&mut tokio::io::stdout(), &mut tokio::io::stderr())
         .await?;
 
     if output.code != 0 {
@@ -364,13 +509,32 @@ pub async fn exec(args: ExecArgs) -> Result<()> {
 }
 
 #[cfg(unix)]
-pub fn inspect(args: &InspectArgs) -> Result<()> {
+pub async fn inspect(args: InspectArgs) -> Result<()> {
     let rt = open_runtime()?;
-    let states: Vec<_> = args
-        .targets
-        .iter()
-        .map(|t| rt.get(t).map(|h| h.state().clone()))
-        .collect::<std::result::Result<_, _>>()?;
+    let mut states = Vec::with_capacity(args.targets.len());
+    for target in &args.targets {
+        let handle = rt.get(target)?;
+        let mut state = serde_json::to_value(handle.state().redacted())?;
+        if args.env {
+            let agent = handle
+                .client()
+                .ok()
+                .filter(|_| handle.state().status == bux::Status::Running);
+            let env = if let Some(client) = agent {
+                if supports(client, bux::feature::ENV, target).await? {
+                    Some(client.env().await?)
+                } else {
+                    None
+                }
+            } else {
+                None
+            };
+            if let Some(obj) = state.as_object_mut() {
+                obj.insert("agent_env".into(), serde_json::to_value(env)?);
+            }
+        }
+        states.push(state);
+    }
 
     if states.len() == 1 {
         println!("{}", serde_json::to_string_pretty(&states[0])?);
@@ -380,96 +544,627 @@ pub fn inspect(args: &InspectArgs) -> Result<()> {
     Ok(())
 }
 
-/// Parses `vm:path` guest reference. Returns `(vm, guest_path)`.
+/// Whether the guest agent of `target` handles `feature`; warns if not,
+/// naming the agent version so the user knows the image needs updating.
 #[cfg(unix)]
-fn parse_guest_ref(s: &str) -> Option<(&str, &str)> {
-    let colon = s.find(':')?;
-    if colon == 0 {
+async fn supports(client: &bux::Client, feature: &str, target: &str) -> Result<bool> {
+    let info = client.info().await?;
+    if info.supports(feature) {
+        return Ok(true);
+    }
+    eprintln!(
+        "warning: {target}: guest agent {} does not support {feature}; \
+         rebuild the image with a newer bux-guest",
+        info.agent_version
+    );
+    Ok(false)
+}
+
+/// Streams a VM's console log from the start until the VM stops; Ctrl-C
+/// detaches and leaves the VM running.
+///
+/// Read-only: keystrokes are not forwarded (the console is a log file, not
+/// a terminal), so the local terminal is never switched to raw mode.
+#[cfg(unix)]
+pub async fn attach(args: &AttachArgs) -> Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let rt = open_runtime()?;
+    let handle = rt.get(&args.target)?;
+    let path = handle
+        .state()
+        .config
+        .console_output
+        .clone()
+        .with_context(|| {
+            format!(
+                "{} has no console log; start it with `bux run -d` or --console-output",
+                args.target
+            )
+        })?;
+    let mut log = tokio::fs::File::open(&path)
+        .await
+        .with_context(|| format!("opening {path}"))?;
+    let mut stdout = tokio::io::stdout();
+    let mut buf = vec![0; 64 << 10];
+    loop {
+        // Sample liveness before draining so output written just before
+        // the VM exits is still printed.
+        let alive = handle.is_alive();
+        loop {
+            let n = log.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            stdout.write_all(&buf[..n]).await?;
+        }
+        stdout.flush().await?;
+        if !alive {
+            return Ok(());
+        }
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            () = tokio::time::sleep(std::time::Duration::from_millis(100)) => {}
+        }
+    }
+}
+
+/// Prints root filesystem changes since boot as `A`/`C`/`D` lines.
+#[cfg(unix)]
+pub async fn diff(args: &DiffArgs) -> Result<()> {
+    let rt = open_runtime()?;
+    let handle = rt.get(&args.target)?;
+    if handle.state().status != bux::Status::Running {
+        anyhow::bail!("{} is not running", args.target);
+    }
+    if !handle.state().config.track_changes {
+        anyhow::bail!(
+            "{} does not track changes (start it with --track-changes)",
+            args.target
+        );
+    }
+    let client = handle.client()?;
+    if !supports(client, bux::feature::DIFF, &args.target).await? {
+        anyhow::bail!("{}: the guest agent is too old for diff", args.target);
+    }
+    let changes = client.diff().await?;
+
+    if matches!(args.format, OutputFormat::Json) {
+        println!("{}", serde_json::to_string_pretty(&changes)?);
+    } else {
+        for change in &changes {
+            println!("{} {}", change.kind.code(), change.path);
+        }
+    }
+    Ok(())
+}
+
+/// Parses a `vm:path` guest reference. Returns `(vm, guest_path)`.
+///
+/// The part before the first `:` only counts as a VM if `is_vm` knows it;
+/// anything else (`C:\foo`, `./a:b`, `/abs:path`) is a host path.
+#[cfg(unix)]
+fn parse_guest_ref(s: &str, is_vm: impl Fn(&str) -> bool) -> Option<(&str, &str)> {
+    let (vm, path) = s.split_once(':')?;
+    // VM IDs and names never contain path separators.
+    if vm.is_empty() || vm.contains(['/', '\\']) || !is_vm(vm) {
         return None;
     }
-    Some((&s[..colon], &s[colon + 1..]))
+    Some((vm, path))
 }
 
 #[cfg(unix)]
-pub async fn cp(args: CpArgs) -> Result<()> {
+pub async fn cp(args: CpArgs, report: Reporter) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    use crate::progress::Tracked;
+
     let rt = open_runtime()?;
     let (src, dst) = (args.src.as_str(), args.dst.as_str());
-
-    match (parse_guest_ref(src), parse_guest_ref(dst)) {
+    let is_vm = |vm: &str| rt.get(vm).is_ok();
+
+    match (parse_guest_ref(src, is_vm), parse_guest_ref(dst, is_vm)) {
+        // guest → stdout, as the agent's archive: a directory's contents
+        // under `./`, or a single file under its name.
+        (Some((id, guest_path)), None) if dst == "-" => {
+            if args.resume {
+                anyhow::bail!("--resume only applies to host → guest file copies");
+            }
+            let handle = rt.get(id)?;
+            let mut progress = report.progress(src, None);
+            let mut stdout = tokio::io::stdout();
+            handle
+                .copy_out_to_writer(
+                    guest_path,
+                    false,
+                    &mut Tracked::new(&mut stdout, &mut progress),
+                )
+                .await?;
+            stdout.flush().await?;
+            progress.finish();
+        }
         // guest → host
         (Some((id, guest_path)), None) => {
+            if args.resume {
+                anyhow::bail!("--resume only applies to host → guest file copies");
+            }
             let handle = rt.get(id)?;
-            let tar_data = handle.copy_out(guest_path).await?;
+            let stat = handle.stat(guest_path).await?;
+            // A single file arrives as a one-entry archive under its name
+            // (the agent falls back to `file`).
+            let name = guest_path.rsplit('/').find(|n| !n.is_empty());
+            let total = (!stat.is_dir).then(|| {
+                tar_entry_len(name.unwrap_or("file").len(), 0, stat.size) + TAR_TRAILER_LEN
+            });
+            let mut progress = report.progress(src, total);
+
+            // Spool the archive to disk so large copies never sit in memory.
+            let spool = spool_file()?;
+            let mut file = tokio::fs::File::from_std(spool.try_clone()?);
+            handle
+                .copy_out_to_writer(
+                    guest_path,
+                    false,
+                    &mut Tracked::new(&mut file, &mut progress),
+                )
+                .await?;
+            file.flush().await?;
+            progress.finish();
             std::fs::create_dir_all(dst)?;
-            let cursor = std::io::Cursor::new(tar_data);
-            let mut archive = tar::Archive::new(cursor);
-            archive.unpack(dst)?;
+            unpack_preserving(spool, std::path::Path::new(dst), &args)?;
+        }
+        // stdin → guest: a tar archive unpacked under the guest path,
+        // streamed in chunks as it is read.
+        (None, Some((id, guest_path))) if src == "-" => {
+            if args.resume {
+                anyhow::bail!("--resume does not apply to copies from stdin");
+            }
+            let handle = rt.get(id)?;
+            let mut progress = report.progress("stdin", None);
+            let mut stdin = tokio::io::stdin();
+            handle
+                .copy_in_from_reader_opts(
+                    guest_path,
+                    args.archive,
+                    &mut Tracked::new(&mut stdin, &mut progress),
+                )
+                .await?;
+            progress.finish();
         }
         // host → guest
         (None, Some((id, guest_path))) => {
             let handle = rt.get(id)?;
             let meta = std::fs::metadata(src)?;
             if meta.is_dir() {
-                let mut buf = Vec::new();
-                {
-                    let mut ar = tar::Builder::new(&mut buf);
-                    ar.append_dir_all(".", src)?;
-                    ar.finish()?;
+                if args.resume {
+                    anyhow::bail!("--resume only applies to single files");
+                }
+                // Build the archive on a blocking thread and stream it
+                // through a pipe, so it never sits whole in memory or on disk.
+                let tree = std::path::PathBuf::from(src);
+                let plain = args.no_preserve.then(unix_now);
+                let total = tree_archive_len(&tree, plain.is_none())?;
+                let (rx, tx) = std::io::pipe()?;
+                let builder = tokio::task::spawn_blocking(move || {
+                    let mut ar = tar::Builder::new(tx);
+                    append_tree(&mut ar, &tree, plain)?;
+                    ar.into_inner().map(drop)
+                });
+                let mut progress = report.progress(src, Some(total));
+                let mut reader =
+                    tokio::fs::File::from_std(std::fs::File::from(std::os::fd::OwnedFd::from(rx)));
+                let sent = handle
+                    .copy_in_from_reader_opts(
+                        guest_path,
+                        args.archive,
+                        &mut Tracked::new(&mut reader, &mut progress),
+                    )
+                    .await;
+                // Closing the read end unblocks the builder if the copy failed.
+                drop(reader);
+                match (sent, builder.await?) {
+                    (Err(e), Err(built)) if built.kind() == std::io::ErrorKind::BrokenPipe => {
+                        return Err(e.into());
+                    }
+                    (_, Err(built)) => return Err(built.into()),
+                    (copied, Ok(())) => copied?,
                 }
-                handle.copy_in(guest_path, &buf).await?;
+                progress.finish();
             } else {
-                let data = std::fs::read(src)?;
-                handle.write_file(guest_path, &data, 0o644).await?;
+                use std::os::unix::fs::MetadataExt;
+
+                let mode = if args.no_preserve {
+                    0o644
+                } else {
+                    meta.mode() & 0o7777
+                };
+                // A partial upload is only kept for the same file content.
+                let source = args
+                    .resume
+                    .then(|| format!("{}:{}.{}", meta.len(), meta.mtime(), meta.mtime_nsec()));
+                let mut progress = report.progress(src, Some(meta.len()));
+                let mut file = tokio::fs::File::open(src).await?;
+                handle
+                    .write_file_from_reader(
+                        guest_path,
+                        mode,
+                        meta.len(),
+                        source.as_deref(),
+                        &mut Tracked::new(&mut file, &mut progress),
+                    )
+                    .await?;
+                progress.finish();
+                if args.archive {
+                    handle
+                        .chown(guest_path, Some(meta.uid()), Some(meta.gid()))
+                        .await?;
+                }
+                if !args.no_preserve {
+                    handle
+                        .utimes(guest_path, meta.atime(), meta.mtime())
+                        .await?;
+                }
             }
         }
-        _ => anyhow::bail!("exactly one of src/dst must use <vm>:<path> format"),
+        _ => anyhow::bail!("exactly one of src/dst must be <vm>:<path> naming an existing VM"),
     }
     Ok(())
 }
 
+/// Unpacks a `bux cp` archive from the guest into `dst` as `args` asks:
+/// mode and mtime by default, owner too with `-a` (where the host allows
+/// it), neither with `--no-preserve`.
+#[cfg(unix)]
+fn unpack_preserving(
+    mut archive: std::fs::File,
+    dst: &std::path::Path,
+    args: &CpArgs,
+) -> Result<()> {
+    use std::io::Seek;
+    use std::os::unix::fs::PermissionsExt;
+
+    archive.rewind()?;
+    let mut ar = tar::Archive::new(archive);
+    ar.set_preserve_mtime(!args.no_preserve);
+    ar.set_preserve_permissions(args.archive);
+    for raw_entry in ar.entries()? {
+        let mut entry = raw_entry?;
+        let header = entry.header();
+        let kind = header.entry_type();
+        let owner = (header.uid()?, header.gid()?);
+        // The path `unpack_in` writes to: only normal components survive.
+        let target: std::path::PathBuf = dst
+            .components()
+            .chain(
+                entry
+                    .path()?
+                    .components()
+                    .filter(|c| matches!(c, std::path::Component::Normal(_))),
+            )
+            .collect();
+        if !entry.unpack_in(dst)? {
+            continue;
+        }
+        if args.no_preserve && !kind.is_symlink() {
+            let mode = if kind.is_dir() { 0o755 } else { 0o644 };
+            std::fs::set_permissions(&target, std::fs::Permissions::from_mode(mode))?;
+        }
+        if args.archive {
+            let uid = u32::try_from(owner.0).ok();
+            let gid = u32::try_from(owner.1).ok();
+            // Only root may give files away; like `cp -a`, skip on refusal.
+            let _ = std::os::unix::fs::lchown(&target, uid, gid);
+        }
+    }
+    Ok(())
+}
+
+/// Creates an empty spool file for `bux cp`.
+///
+/// The file is created exclusively (`O_EXCL`, mode 0600) under a fresh name
+/// in the temp directory and unlinked at once, so nothing else can open or
+/// replace it by path; it disappears when the last handle closes.
+#[cfg(unix)]
+fn spool_file() -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let dir = std::env::temp_dir();
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    let mut attempt = 0u32;
+    loop {
+        let path = dir.join(format!(
+            "bux-cp-{}-{nanos:08x}-{attempt}.tar",
+            std::process::id()
+        ));
+        match std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+        {
+            Ok(file) => {
+                std::fs::remove_file(&path)?;
+                return Ok(file);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Length of the zero blocks [`tar::Builder::finish`] ends an archive with.
+#[cfg(unix)]
+const TAR_TRAILER_LEN: u64 = 1024;
+
+/// Bytes [`tar::Builder`] writes for one entry: a GNU long-name record for
+/// a path (and one for a link target) over the 100-byte header field, the
+/// header, then `size` bytes of data padded to a 512-byte block.
+#[cfg(unix)]
+const fn tar_entry_len(path_len: usize, link_len: usize, size: u64) -> u64 {
+    const fn long(len: usize) -> u64 {
+        if len > 100 {
+            512 + (len as u64 + 1).next_multiple_of(512)
+        } else {
+            0
+        }
+    }
+    long(path_len) + long(link_len) + 512 + size.next_multiple_of(512)
+}
+
+/// Calls `f` with the source path, archive name and metadata of every
+/// directory, symlink and regular file under `dir`, parents first. Other
+/// file types are skipped.
+#[cfg(unix)]
+fn walk_tree(
+    dir: &std::path::Path,
+    name: &std::path::Path,
+    f: &mut impl FnMut(&std::path::Path, &std::path::Path, &std::fs::Metadata) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    for raw_entry in std::fs::read_dir(dir)? {
+        let entry = raw_entry?;
+        let path = entry.path();
+        let rel = name.join(entry.file_name());
+        let meta = std::fs::symlink_metadata(&path)?;
+        if meta.is_dir() {
+            f(&path, &rel, &meta)?;
+            walk_tree(&path, &rel, f)?;
+        } else if meta.is_symlink() || meta.is_file() {
+            f(&path, &rel, &meta)?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends the tree under `dir` for `bux cp`, including `dir` itself as
+/// `.` unless `plain` is set.
+///
+/// With `plain` (an mtime), entries get default modes (0644 files, 0755
+/// directories), that mtime and no ownership, for `--no-preserve`;
+/// otherwise they keep their mode, owner and mtime.
+#[cfg(unix)]
+fn append_tree<W: std::io::Write>(
+    ar: &mut tar::Builder<W>,
+    dir: &std::path::Path,
+    plain: Option<u64>,
+) -> std::io::Result<()> {
+    let header_for = |meta: &std::fs::Metadata| {
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(meta);
+        if let Some(mtime) = plain {
+            header.set_mtime(mtime);
+            header.set_uid(0);
+            header.set_gid(0);
+            let mode = if meta.is_dir() {
+                0o755
+            } else if meta.is_symlink() {
+                0o777
+            } else {
+                0o644
+            };
+            header.set_mode(mode);
+        }
+        header
+    };
+    let mut append = |path: &std::path::Path, rel: &std::path::Path, meta: &std::fs::Metadata| {
+        let mut header = header_for(meta);
+        if meta.is_symlink() {
+            ar.append_link(&mut header, rel, std::fs::read_link(path)?)
+        } else if meta.is_dir() {
+            ar.append_data(&mut header, rel, std::io::empty())
+        } else {
+            ar.append_data(&mut header, rel, std::fs::File::open(path)?)
+        }
+    };
+    if plain.is_none() {
+        append(dir, std::path::Path::new("."), &std::fs::metadata(dir)?)?;
+    }
+    walk_tree(dir, std::path::Path::new(""), &mut append)?;
+    ar.finish()
+}
+
+/// Exact length of the archive [`append_tree`] builds for `dir`, so
+/// progress can be drawn against it.
+#[cfg(unix)]
+fn tree_archive_len(dir: &std::path::Path, with_root: bool) -> std::io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut total = TAR_TRAILER_LEN;
+    if with_root {
+        total += tar_entry_len(1, 0, 0);
+    }
+    walk_tree(dir, std::path::Path::new(""), &mut |path, rel, meta| {
+        let path_len = rel.as_os_str().as_bytes().len();
+        total += if meta.is_symlink() {
+            tar_entry_len(path_len, std::fs::read_link(path)?.as_os_str().len(), 0)
+        } else if meta.is_dir() {
+            tar_entry_len(path_len, 0, 0)
+        } else {
+            tar_entry_len(path_len, 0, meta.len())
+        };
+        Ok(())
+    })?;
+    Ok(total)
+}
+
+/// Seconds since the Unix epoch.
+#[cfg(unix)]
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
 #[cfg(unix)]
 pub async fn wait(args: WaitArgs) -> Result<()> {
     let rt = open_runtime()?;
+    let deadline = args
+        .timeout
+        .map(|secs| tokio::time::Instant::now() + std::time::Duration::from_secs(secs));
     let mut errors = Vec::new();
-
-    for target in &args.targets {
-        match rt.get(target) {
-            Ok(mut h) => match h.wait().await {
-                Ok(()) => println!("{target}"),
-                Err(e) => errors.push(format!("{target}: {e}")),
+    let mut codes = vec![None; args.targets.len()];
+
+    // All targets are waited on at once, so the timeout bounds the whole
+    // command rather than each VM in turn. Handles share the state database
+    // connection, which is not `Sync`, so the waits run on this thread.
+    let local = tokio::task::LocalSet::new();
+    let mut waits = tokio::task::JoinSet::new();
+    for (i, target) in args.targets.iter().enumerate() {
+        let mut h = match rt.get(target) {
+            Ok(h) => h,
+            Err(e) => {
+                errors.push(format!("{target}: {e}"));
+                continue;
+            }
+        };
+        waits.spawn_local_on(
+            async move {
+                let result = match deadline {
+                    Some(at) => tokio::time::timeout_at(at, h.wait()).await,
+                    None => Ok(h.wait().await),
+                };
+                (i, result)
             },
-            Err(e) => errors.push(format!("{target}: {e}")),
-        }
+            &local,
+        );
     }
 
-    if errors.is_empty() {
-        Ok(())
-    } else {
-        anyhow::bail!("{}", errors.join("\n"))
+    // Prints each VM's exit code as it stops, or `unknown` when the code
+    // could not be observed.
+    local
+        .run_until(async {
+            while let Some(joined) = waits.join_next().await {
+                let (i, result) = joined?;
+                let target = &args.targets[i];
+                match result {
+                    Ok(Ok(code)) => {
+                        match code {
+                            Some(c) => println!("{target}: {c}"),
+                            None => println!("{target}: unknown"),
+                        }
+                        codes[i] = code;
+                    }
+                    Ok(Err(e)) => errors.push(format!("{target}: {e}")),
+                    Err(_) => errors.push(format!("{target}: timed out waiting for VM to stop")),
+                }
+            }
+            anyhow::Ok(())
+        })
+        .await?;
+
+    if !errors.is_empty() {
+        anyhow::bail!("{}", errors.join("\n"));
+    }
+    // The first failure in argument order decides the exit status.
+    match codes.into_iter().flatten().find(|&c| c != 0) {
+        Some(code) => std::process::exit(code),
+        None => Ok(()),
     }
 }
 
 #[cfg(unix)]
-pub fn prune() -> Result<()> {
-    let rt = open_runtime()?;
-    let vms = rt.list()?;
-    let mut count = 0u32;
-
-    for vm in &vms {
-        if vm.status == bux::Status::Stopped {
-            match rt.remove(&vm.id) {
-                Ok(()) => {
-                    println!("{}", vm.id);
-                    count += 1;
+pub fn prune(args: &PruneArgs, store: &StoreOpts, report: Reporter) -> Result<()> {
+    let default = !(args.vms || args.images || args.disks || args.all);
+    let vms = default || args.vms || args.all;
+    let blobs = default || args.images || args.all;
+    let compact = args.images || args.all;
+    let disks = args.disks || args.all;
+
+    if !args.force {
+        let mut scope = Vec::new();
+        if vms {
+            scope.push("stopped VMs");
+        }
+        if blobs {
+            scope.push("unreferenced image blobs");
+        }
+        if disks {
+            scope.push("unused disk bases");
+        }
+        eprint!("This will remove {}. Continue? [y/N] ", scope.join(", "));
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            return Ok(());
+        }
+    }
+
+    let mut total = 0;
+    if vms {
+        let rt = open_runtime()?;
+        let mut count = 0u32;
+        let mut bytes = 0;
+        for vm in rt.list()? {
+            if vm.status == bux::Status::Stopped {
+                match rt.remove(&vm.id, false, false) {
+                    Ok(reclaimed) => {
+                        println!("{}", vm.id);
+                        count += 1;
+                        bytes += reclaimed.bytes;
+                    }
+                    Err(e) => eprintln!("warning: {}: {e}", vm.id),
                 }
-                Err(e) => eprintln!("warning: {}: {e}", vm.id),
             }
         }
+        report.status(format_args!(
+            "VMs:    {count} removed, {}",
+            crate::human_size(bytes)
+        ));
+        total += bytes;
+    }
+    if blobs {
+        let oci = crate::open_oci(store)?;
+        let mut bytes = oci.prune(&pinned_images()?)?;
+        if compact {
+            bytes += oci.maintain()?;
+        }
+        report.status(format_args!("Images: {}", crate::human_size(bytes)));
+        total += bytes;
+    }
+    if disks {
+        let data_dir = dirs::data_dir()
+            .context("no platform data directory")?
+            .join("bux");
+        let bytes = bux::DiskManager::open(data_dir)?.prune_bases()?;
+        report.status(format_args!("Disks:  {}", crate::human_size(bytes)));
+        total += bytes;
     }
-    eprintln!("Total reclaimed VMs: {count}");
+    report.status(format_args!(
+        "Total reclaimed space: {}",
+        crate::human_size(total)
+    ));
     Ok(())
 }
 
+#[cfg(not(unix))]
+pub fn prune(_args: &PruneArgs, _store: &StoreOpts, _report: Reporter) -> Result<()> {
+    anyhow::bail!("VM management requires Linux or macOS")
+}
+
 #[cfg(unix)]
 pub fn rename(args: &RenameArgs) -> Result<()> {
     let rt = open_runtime()?;
@@ -501,24 +1196,60 @@ fn parse_signal(name: &str) -> Result<i32> {
     }
 }
 
-/// Reads environment variables from a file (one `KEY=VALUE` per line).
-/// Blank lines and lines starting with `#` are skipped.
+/// Reads environment variables from a dotenv-style file.
+///
+/// See [`parse_env_lines`] for the accepted syntax.
 pub fn read_env_file(path: &str) -> Result<Vec<String>> {
     let content =
         std::fs::read_to_string(path).with_context(|| format!("cannot read env file: {path}"))?;
-    Ok(content
+    Ok(parse_env_lines(&content))
+}
+
+/// Parses `KEY=VALUE` lines, skipping blank lines and `#` comments.
+///
+/// A bare `KEY` passes the host's value through and is dropped when the host
+/// does not define it.
+pub fn parse_env_lines(content: &str) -> Vec<String> {
+    content
         .lines()
         .map(str::trim)
         .filter(|l| !l.is_empty() && !l.starts_with('#'))
-        .map(String::from)
-        .collect())
+        .filter_map(resolve_env_entry)
+        .collect()
+}
+
+/// Resolves a single `KEY=VALUE` entry, or a bare `KEY` from the host environment.
+pub fn resolve_env_entry(entry: &str) -> Option<String> {
+    if entry.contains('=') {
+        return Some(entry.to_owned());
+    }
+    std::env::var(entry).ok().map(|v| format!("{entry}={v}"))
+}
+
+/// Merges environment layers, de-duplicating by key.
+///
+/// Later layers win (e.g. image, then `--env-file`, then `-e`); each key keeps
+/// the position of its first occurrence.
+pub fn merge_env<const N: usize>(layers: [&[String]; N]) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::new();
+    let mut index: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+    for entry in layers.into_iter().flatten() {
+        let key = entry.split_once('=').map_or(entry.as_str(), |(k, _)| k);
+        if let Some(&i) = index.get(key) {
+            merged[i].clone_from(entry);
+        } else {
+            index.insert(key, merged.len());
+            merged.push(entry.clone());
+        }
+    }
+    merged
 }
 
 #[cfg(not(unix))]
 macro_rules! unix_only_stub {
     (sync: $($name:ident($($arg:ident: $ty:ty),*));+ $(;)?) => {
         $(
-            pub fn $name($(_: &$ty),*) -> Result<()> {
+            pub fn $name($(_: $ty),*) -> Result<()> {
                 anyhow::bail!("VM management requires Linux or macOS")
             }
         )+
@@ -535,19 +1266,202 @@ pub async fn $name($(_: $ty),*) -> Result<()> {
 #[cfg(not(unix))]
 unix_only_stub! {
     sync:
-    ps(args: PsArgs);
-    kill(args: KillArgs);
-    rm(args: RmArgs);
-    inspect(args: InspectArgs);
-    prune();
-    rename(args: RenameArgs);
+    ps(args: &PsArgs, report: Reporter);
+    kill(args: &KillArgs);
+    rm(args: &RmArgs, report: Reporter);
+    rename(args: &RenameArgs);
 }
 
 #[cfg(not(unix))]
 unix_only_stub! {
     async:
     stop(args: StopArgs);
+    inspect(args: InspectArgs);
     exec(args: ExecArgs);
-    cp(args: CpArgs);
+    cp(args: CpArgs, report: Reporter);
     wait(args: WaitArgs);
+    diff(args: &DiffArgs);
+    attach(args: &AttachArgs);
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_env_later_layers_win() {
+        let image = vec!["PATH=/usr/bin".to_owned(), "LANG=C".to_owned()];
+        let file = vec!["LANG=en_US.UTF-8".to_owned(), "DEBUG=0".to_owned()];
+        let cli = vec!["DEBUG=1".to_owned(), "NEW=x".to_owned()];
+
+        let merged = merge_env([image.as_slice(), file.as_slice(), cli.as_slice()]);
+        assert_eq!(
+            merged,
+            ["PATH=/usr/bin", "LANG=en_US.UTF-8", "DEBUG=1", "NEW=x"]
+        );
+    }
+
+    /// Parses `bux exec` arguments as the CLI would.
+    fn exec_args(argv: &[&str]) -> Result<ExecArgs, clap::Error> {
+        #[derive(clap::Parser)]
+        struct Exec {
+            #[command(flatten)]
+            args: ExecArgs,
+        }
+        <Exec as clap::Parser>::try_parse_from(std::iter::once("exec").chain(argv.iter().copied()))
+            .map(|exec| exec.args)
+    }
+
+    #[test]
+    fn exec_flags_reach_the_request() {
+        let req = exec_args(&[
+            "-w", "/srv", "-e", "A=1", "--env", "B=x=y", "-u", "1000:100", "web", "sh", "-c", "id",
+        ])
+        .unwrap()
+        .request()
+        .unwrap();
+        assert_eq!(req.cmd, "sh");
+        assert_eq!(req.args, ["-c", "id"]);
+        assert_eq!(req.cwd.as_deref(), Some("/srv"));
+        assert!(!req.create_cwd);
+        assert_eq!(req.env, ["A=1", "B=x=y"]);
+        assert_eq!(req.user.as_deref(), Some("1000:100"));
+
+        // Flags after the VM belong to the command, not to `bux exec`.
+        let passthrough = exec_args(&["web", "env", "-u", "HOME"])
+            .unwrap()
+            .request()
+            .unwrap();
+        assert_eq!(passthrough.args, ["-u", "HOME"]);
+        assert!(passthrough.user.is_none() && passthrough.cwd.is_none());
+        assert!(passthrough.env.is_empty());
+    }
+
+    #[test]
+    fn exec_env_flags_override_env_files() {
+        let path = std::env::temp_dir().join(format!("bux_exec_env_{}", std::process::id()));
+        std::fs::write(&path, "MODE=file\nKEEP=1\n").unwrap();
+        let req = exec_args(&[
+            "--env-file",
+            path.to_str().unwrap(),
+            "-e",
+            "MODE=cli",
+            "web",
+            "true",
+        ])
+        .unwrap()
+        .request()
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(req.env, ["MODE=cli", "KEEP=1"]);
+
+        assert!(exec_args(&["--mkdir-workdir", "web", "true"]).is_err());
+    }
+
+    #[test]
+    fn parse_env_lines_skips_comments_and_passes_through_host_vars() {
+        let parsed =
+            parse_env_lines("# comment\n\nFOO=bar\n  BAZ=a=b  \nPATH\nBUX_TEST_UNSET_VAR\n");
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[0], "FOO=bar");
+        assert_eq!(parsed[1], "BAZ=a=b");
+        assert!(parsed[2].starts_with("PATH="));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn guest_refs_need_a_known_vm() {
+        let is_vm = |vm: &str| matches!(vm, "3f9a2c" | "web");
+
+        assert_eq!(
+            parse_guest_ref("3f9a2c:/etc/hosts", is_vm),
+            Some(("3f9a2c", "/etc/hosts"))
+        );
+        assert_eq!(parse_guest_ref("web:/srv", is_vm), Some(("web", "/srv")));
+        assert_eq!(parse_guest_ref("/abs:path", is_vm), None);
+        assert_eq!(parse_guest_ref("C:\\foo", is_vm), None);
+        assert_eq!(parse_guest_ref("db:/srv", is_vm), None);
+        assert_eq!(parse_guest_ref(":/srv", |_| true), None);
+        assert_eq!(parse_guest_ref("plain", is_vm), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_preserve_archive_uses_default_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("bux_cp_plain_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        std::fs::write(dir.join("bin/tool"), b"#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(dir.join("bin/tool"), std::fs::Permissions::from_mode(0o700))
+            .unwrap();
+
+        let mut ar = tar::Builder::new(Vec::new());
+        append_tree(&mut ar, &dir, Some(42)).unwrap();
+        let data = ar.into_inner().unwrap();
+        let mut entries: Vec<_> = tar::Archive::new(data.as_slice())
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let h = e.unwrap().header().clone();
+                (
+                    h.path().unwrap().into_owned(),
+                    h.mode().unwrap(),
+                    h.mtime().unwrap(),
+                )
+            })
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [("bin".into(), 0o755, 42), ("bin/tool".into(), 0o644, 42)]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn archive_len_matches_the_streamed_archive() {
+        let dir = std::env::temp_dir().join(format!("bux_cp_len_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let deep = dir.join("a".repeat(60)).join("b".repeat(60));
+        std::fs::create_dir_all(&deep).unwrap();
+        std::fs::write(deep.join("long.txt"), vec![7u8; 1000]).unwrap();
+        std::fs::write(dir.join("empty"), b"").unwrap();
+        std::fs::write(dir.join("block"), vec![1u8; 512]).unwrap();
+        std::os::unix::fs::symlink("c".repeat(150), dir.join("link")).unwrap();
+
+        for plain in [None, Some(42)] {
+            let mut ar = tar::Builder::new(Vec::new());
+            append_tree(&mut ar, &dir, plain).unwrap();
+            let data = ar.into_inner().unwrap();
+            assert_eq!(
+                tree_archive_len(&dir, plain.is_none()).unwrap(),
+                data.len() as u64
+            );
+        }
+        assert_eq!(tar_entry_len(8, 0, 5) + TAR_TRAILER_LEN, 2048);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn spool_files_are_private_and_unlinked() {
+        use std::io::{Read, Seek, Write};
+        use std::os::unix::fs::MetadataExt;
+
+        let mut a = spool_file().unwrap();
+        let b = spool_file().unwrap();
+        let meta = a.metadata().unwrap();
+        assert_eq!(meta.nlink(), 0);
+        assert_eq!(meta.mode() & 0o777, 0o600);
+        assert_ne!(meta.ino(), b.metadata().unwrap().ino());
+        a.write_all(b"spooled").unwrap();
+        a.rewind().unwrap();
+        let mut back = String::new();
+        a.read_to_string(&mut back).unwrap();
+        assert_eq!(back, "spooled");
+    }
 }
@@ -129,6 +129,7 @@ fn generate_bindings(headers_dir: &Path, out_dir: &Path) {
         // Inode operations
         .allowlist_function("ext2fs_mkdir")
         .allowlist_function("ext2fs_link")
+        .allowlist_function("ext2fs_lookup")
         .allowlist_function("ext2fs_new_inode")
         .allowlist_function("ext2fs_write_new_inode")
         .allowlist_function("ext2fs_read_inode")
@@ -136,6 +137,10 @@ fn generate_bindings(headers_dir: &Path, out_dir: &Path) {
         .allowlist_function("ext2fs_read_inode_full")
         .allowlist_function("ext2fs_write_inode_full")
         .allowlist_function("ext2fs_inode_alloc_stats2")
+        // File I/O
+        .allowlist_function("ext2fs_file_open")
+        .allowlist_function("ext2fs_file_write")
+        .allowlist_function("ext2fs_file_close")
         // Block operations
         .allowlist_function("ext2fs_new_block2")
         .allowlist_function("ext2fs_block_alloc_stats2")
@@ -157,6 +162,7 @@ fn generate_bindings(headers_dir: &Path, out_dir: &Path) {
         .allowlist_type("ext2_inode")
         .allowlist_type("ext2_inode_large")
         .allowlist_type("errcode_t")
+        .allowlist_type("ext2_file_t")
         .allowlist_type("io_manager")
         .allowlist_type("hdlinks_s")
         .allowlist_type("hdlink_s")
@@ -169,6 +175,7 @@ fn generate_bindings(headers_dir: &Path, out_dir: &Path) {
         .allowlist_var("EXT2_FT_.*")
         .allowlist_var("POPULATE_FS_.*")
         .allowlist_var("LINUX_S_IF.*")
+        .allowlist_var("EXT2_FILE_WRITE")
         .derive_debug(true)
         .derive_default(true)
         .derive_eq(true)
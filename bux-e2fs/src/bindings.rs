@@ -50,6 +50,7 @@
 pub const LINUX_S_IFDIR: u32 = 16384;
 pub const LINUX_S_IFCHR: u32 = 8192;
 pub const LINUX_S_IFIFO: u32 = 4096;
+pub const EXT2_FILE_WRITE: u32 = 1;
 pub const EXT2_FLAG_FLUSH_NO_SYNC: u32 = 1;
 pub type __dev_t = ::core::ffi::c_ulong;
 pub type __uid_t = ::core::ffi::c_uint;
@@ -1509,6 +1510,41 @@ pub fn ext2fs_link(
         flags: ::core::ffi::c_int,
     ) -> errcode_t;
 }
+unsafe extern "C" {
+    pub fn ext2fs_lookup(
+        fs: ext2_filsys,
+        dir: ext2_ino_t,
+        name: *const ::core::ffi::c_char,
+        namelen: ::core::ffi::c_int,
+        buf: *mut ::core::ffi::c_char,
+        inode: *mut ext2_ino_t,
+    ) -> errcode_t;
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ext2_file {
+    _unused: [u8; 0],
+}
+pub type ext2_file_t = *mut ext2_file;
+unsafe extern "C" {
+    pub fn ext2fs_file_open(
+        fs: ext2_filsys,
+        ino: ext2_ino_t,
+        flags: ::core::ffi::c_int,
+        ret: *mut ext2_file_t,
+    ) -> errcode_t;
+}
+unsafe extern "C" {
+    pub fn ext2fs_file_write(
+        file: ext2_file_t,
+        buf: *const ::core::ffi::c_void,
+        nbytes: ::core::ffi::c_uint,
+        written: *mut ::core::ffi::c_uint,
+    ) -> errcode_t;
+}
+unsafe extern "C" {
+    pub fn ext2fs_file_close(file: ext2_file_t) -> errcode_t;
+}
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub struct stat {
@@ -17,6 +17,15 @@ pub enum Error {
     #[error("invalid path: {0}")]
     InvalidPath(String),
 
+    /// A hard link in a layer points at a path no layer holds.
+    #[error("hard link {link} points at missing {target}")]
+    BrokenHardLink {
+        /// Path of the link.
+        link: String,
+        /// Path it points at.
+        target: String,
+    },
+
     /// An I/O error occurred outside of libext2fs.
     #[error(transparent)]
     Io(#[from] std::io::Error),
@@ -16,14 +16,17 @@
 )]
 
 use std::ffi::CString;
-use std::path::Path;
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 
 use crate::error::{Error, Result};
+use crate::ignore::IgnoreRules;
 use crate::sys;
 
 /// Block size for an ext4 filesystem.
 #[non_exhaustive]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 #[repr(u32)]
 pub enum BlockSize {
     /// 1024 bytes.
@@ -31,6 +34,7 @@ pub enum BlockSize {
     /// 2048 bytes.
     B2048 = 1,
     /// 4096 bytes (default, recommended).
+    #[default]
     B4096 = 2,
 }
 
@@ -46,6 +50,17 @@ pub const fn bytes(self) -> u32 {
     }
 }
 
+/// `EXT2_FEATURE_COMPAT_EXT_ATTR`, required by `inline_data`.
+const FEATURE_COMPAT_EXT_ATTR: u32 = 0x0008;
+/// `EXT4_FEATURE_INCOMPAT_INLINE_DATA`.
+const FEATURE_INCOMPAT_INLINE_DATA: u32 = 0x8000;
+/// Inode size used when `inline_data` is enabled.
+const INLINE_INODE_SIZE: u16 = 256;
+
+/// Largest file stored inside a 256-byte inode under `inline_data`: 60
+/// bytes in `i_block` plus 68 in the in-inode `system.data` attribute.
+pub const INLINE_DATA_MAX: u64 = 128;
+
 /// Options for creating a new ext4 filesystem.
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy)]
@@ -54,6 +69,9 @@ pub struct CreateOptions {
     pub block_size: BlockSize,
     /// Reserved block percentage, 0–50 (default: 0 for containers).
     pub reserved_ratio: u8,
+    /// Store files of up to [`INLINE_DATA_MAX`] bytes inside their inode
+    /// (default: off). Uses 256-byte inodes.
+    pub inline_data: bool,
 }
 
 impl Default for CreateOptions {
@@ -61,6 +79,43 @@ fn default() -> Self {
         Self {
             block_size: BlockSize::B4096,
             reserved_ratio: 0,
+            inline_data: false,
+        }
+    }
+}
+
+/// How [`estimate_image_size_with`] charges files smaller than a block.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SmallFilePolicy {
+    /// Every non-empty file takes at least one whole block. Exact for
+    /// images created without `inline_data`.
+    #[default]
+    FullBlock,
+    /// Files of up to [`INLINE_DATA_MAX`] bytes take no data block.
+    /// Matches images created with [`CreateOptions::inline_data`].
+    Inline,
+}
+
+/// Options for [`estimate_image_size_with`].
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EstimateOptions {
+    /// Block size the image will be created with (default: 4096).
+    pub block_size: BlockSize,
+    /// Rounding applied to sub-block files.
+    pub small_files: SmallFilePolicy,
+}
+
+impl From<&CreateOptions> for EstimateOptions {
+    fn from(opts: &CreateOptions) -> Self {
+        Self {
+            block_size: opts.block_size,
+            small_files: if opts.inline_data {
+                SmallFilePolicy::Inline
+            } else {
+                SmallFilePolicy::FullBlock
+            },
         }
     }
 }
@@ -151,6 +206,11 @@ pub fn create(path: &Path, size_bytes: u64, opts: &CreateOptions) -> Result<Self
             param.s_log_block_size = bs as u32;
             param.s_rev_level = sys::EXT2_DYNAMIC_REV;
             param.s_r_blocks_count = reserved as u32;
+            if opts.inline_data {
+                param.s_inode_size = INLINE_INODE_SIZE;
+                param.s_feature_compat |= FEATURE_COMPAT_EXT_ATTR;
+                param.s_feature_incompat |= FEATURE_INCOMPAT_INLINE_DATA;
+            }
 
             check(
                 "ext2fs_initialize",
@@ -293,6 +353,161 @@ pub fn link(&mut self, dir: u32, name: &str, ino: u32, file_type: FileType) -> R
         }
     }
 
+    /// Looks up `name` in directory `dir`, returning its inode number.
+    pub fn lookup(&self, dir: u32, name: &str) -> Result<u32> {
+        let mut ino: sys::ext2_ino_t = 0;
+        unsafe {
+            check(
+                "ext2fs_lookup",
+                sys::ext2fs_lookup(
+                    self.inner,
+                    dir,
+                    name.as_ptr().cast(),
+                    name.len() as i32,
+                    std::ptr::null_mut(),
+                    &raw mut ino,
+                ),
+            )?;
+        }
+        Ok(ino)
+    }
+
+    /// Creates directory `name` in directory `parent` and returns its inode
+    /// number. Mode 0755, owned by root; see [`set_attrs`](Self::set_attrs).
+    pub fn mkdir_at(&mut self, parent: u32, name: &str) -> Result<u32> {
+        let c_name = str_to_cstring(name)?;
+        unsafe {
+            check(
+                "do_mkdir_internal",
+                sys::do_mkdir_internal(self.inner, parent, c_name.as_ptr(), sys::EXT2_ROOT_INO),
+            )?;
+        }
+        self.lookup(parent, name)
+    }
+
+    /// Creates symlink `name` to `target` in directory `parent` and returns
+    /// its inode number.
+    pub fn symlink_at(&mut self, parent: u32, name: &str, target: &[u8]) -> Result<u32> {
+        let c_name = str_to_cstring(name)?;
+        let c_target = CString::new(target).map_err(|e| Error::InvalidPath(e.to_string()))?;
+        unsafe {
+            check(
+                "do_symlink_internal",
+                sys::do_symlink_internal(
+                    self.inner,
+                    parent,
+                    c_name.as_ptr(),
+                    c_target.as_ptr().cast_mut(),
+                    sys::EXT2_ROOT_INO,
+                ),
+            )?;
+        }
+        self.lookup(parent, name)
+    }
+
+    /// Creates regular file `name` in directory `parent` holding everything
+    /// read from `data`, and returns its inode number. Mode 0644, owned by
+    /// root; see [`set_attrs`](Self::set_attrs).
+    ///
+    /// Unlike [`write_file`](Self::write_file), the content needs no host
+    /// file, so it can come straight out of an archive.
+    pub fn write_bytes(&mut self, parent: u32, name: &str, mut data: impl Read) -> Result<u32> {
+        let ino = self.new_node(parent, name, sys::LINUX_S_IFREG as u16 | 0o644, [0, 0])?;
+        unsafe {
+            let mut file: sys::ext2_file_t = std::ptr::null_mut();
+            check(
+                "ext2fs_file_open",
+                sys::ext2fs_file_open(self.inner, ino, sys::EXT2_FILE_WRITE as i32, &raw mut file),
+            )?;
+            let written = copy_into(file, &mut data);
+            let closed = check("ext2fs_file_close", sys::ext2fs_file_close(file));
+            written.and(closed)?;
+        }
+        Ok(ino)
+    }
+
+    /// Creates a device node or FIFO `name` in directory `parent` and
+    /// returns its inode number. `mode` carries the file type bits;
+    /// `major` and `minor` are ignored for FIFOs.
+    pub fn mknod_at(
+        &mut self,
+        parent: u32,
+        name: &str,
+        mode: u32,
+        major: u32,
+        minor: u32,
+    ) -> Result<u32> {
+        // Old 8:8 encoding in the first block slot, else the new one in
+        // the second, as the kernel reads them.
+        let device = if major < 256 && minor < 256 {
+            [major * 256 + minor, 0]
+        } else {
+            [0, (minor & 0xff) | (major << 8) | ((minor & !0xff) << 12)]
+        };
+        self.new_node(parent, name, mode as u16, device)
+    }
+
+    /// Adds `name` in directory `parent` as another link to inode `ino`.
+    pub fn hard_link(&mut self, parent: u32, name: &str, ino: u32) -> Result<()> {
+        let c_name = str_to_cstring(name)?;
+        unsafe {
+            check(
+                "add_link",
+                sys::add_link(self.inner, parent, ino, c_name.as_ptr()),
+            )
+        }
+    }
+
+    /// Sets permission bits (including setuid, setgid and sticky), owner
+    /// and modification time of inode `ino`; the file type is kept.
+    pub fn set_attrs(&mut self, ino: u32, mode: u32, uid: u32, gid: u32, mtime: i64) -> Result<()> {
+        let time = sys::timespec {
+            tv_sec: mtime,
+            tv_nsec: 0,
+        };
+        let st = sys::stat {
+            st_mode: mode & 0o7777,
+            st_uid: uid,
+            st_gid: gid,
+            st_atim: time,
+            st_mtim: time,
+            st_ctim: time,
+            ..sys::stat::default()
+        };
+        unsafe {
+            check(
+                "set_inode_extra",
+                sys::set_inode_extra(self.inner, ino, &raw const st),
+            )
+        }
+    }
+
+    /// Allocates an inode of `mode` with the two leading block slots set to
+    /// `block` and links it as `name` in `parent`.
+    fn new_node(&mut self, parent: u32, name: &str, mode: u16, block: [u32; 2]) -> Result<u32> {
+        let c_name = str_to_cstring(name)?;
+        let ino = self.alloc_inode(parent, mode)?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as u32);
+        unsafe {
+            let mut inode: sys::ext2_inode = std::mem::zeroed();
+            inode.i_mode = mode;
+            inode.i_atime = now;
+            inode.i_ctime = now;
+            inode.i_mtime = now;
+            inode.i_block[0] = block[0];
+            inode.i_block[1] = block[1];
+            // `add_link` counts the link it makes.
+            self.write_new_inode(ino, &inode)?;
+            check(
+                "add_link",
+                sys::add_link(self.inner, parent, ino, c_name.as_ptr()),
+            )?;
+        }
+        Ok(ino)
+    }
+
     /// Reads the on-disk inode structure for the given inode number.
     pub fn read_inode(&self, ino: u32) -> Result<sys::ext2_inode> {
         unsafe {
@@ -387,6 +602,34 @@ pub fn create_from_dir(source_dir: &Path, output: &Path, size_bytes: u64) -> Res
     Ok(())
 }
 
+/// Like [`create_from_dir`], but skips entries matched by `ignore`.
+///
+/// `populate_fs` copies a whole directory in one call, so the filtered tree
+/// is first staged next to `output` (hard links where possible, copies
+/// otherwise) and removed once the image is written.
+pub fn create_from_dir_filtered(
+    source_dir: &Path,
+    output: &Path,
+    size_bytes: u64,
+    ignore: &IgnoreRules,
+) -> Result<()> {
+    if ignore.is_empty() {
+        return create_from_dir(source_dir, output, size_bytes);
+    }
+
+    let mut name = output.as_os_str().to_owned();
+    name.push(".stage");
+    let staging = PathBuf::from(name);
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+
+    let result = stage_filtered(source_dir, &staging, Path::new(""), ignore)
+        .and_then(|_| create_from_dir(&staging, output, size_bytes));
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
 /// Injects a single host file into an existing ext4 image.
 ///
 /// Equivalent to `debugfs -w -R "write <host_file> <guest_path>" <image>`.
@@ -399,28 +642,107 @@ pub fn inject_file(image: &Path, host_file: &Path, guest_path: &str) -> Result<(
 ///
 /// Accounts for file content, inode overhead, ext4 metadata, and journal.
 /// Returns the recommended image size in bytes (minimum 256 MiB).
+///
+/// Assumes 4 KiB blocks and no `inline_data`, matching [`create_from_dir`];
+/// see [`estimate_image_size_with`] for other layouts.
 pub fn estimate_image_size(dir: &Path) -> Result<u64> {
-    let mut total_bytes: u64 = 0;
-    let mut inode_count: u64 = 0;
+    estimate_image_size_with(dir, EstimateOptions::default())
+}
+
+/// Like [`estimate_image_size`], for an image created with the layout
+/// described by `opts`.
+pub fn estimate_image_size_with(dir: &Path, opts: EstimateOptions) -> Result<u64> {
+    let (data_bytes, inode_count) = tree_usage(dir, opts)?;
+    Ok(image_size_for(data_bytes, inode_count))
+}
 
+/// Image size for a tree already known to need `data_bytes` of data
+/// blocks and `inode_count` inodes, e.g. counted while it was written,
+/// sparing the walk [`estimate_image_size`] does.
+pub fn image_size_for(data_bytes: u64, inode_count: u64) -> u64 {
+    // 256 bytes per inode + 10% metadata overhead + 64 MiB journal.
+    let raw = data_bytes + inode_count * 256;
+    let sized = raw * 11 / 10 + 64 * 1024 * 1024;
+    sized.max(256 * 1024 * 1024)
+}
+
+/// Returns the data block bytes and inode count `dir` needs under `opts`.
+fn tree_usage(dir: &Path, opts: EstimateOptions) -> Result<(u64, u64)> {
+    let block = u64::from(opts.block_size.bytes());
+    let round_up = |len: u64| len.div_ceil(block) * block;
+    let inline = opts.small_files == SmallFilePolicy::Inline;
+
+    let mut data_bytes: u64 = 0;
+    let mut inode_count: u64 = 0;
     walk(dir, &mut |meta| {
         inode_count += 1;
         if meta.is_file() {
-            // Round up to 4 KiB block boundary.
-            total_bytes += (meta.len() + 4095) & !4095;
+            if !(inline && meta.len() <= INLINE_DATA_MAX) {
+                data_bytes += round_up(meta.len());
+            }
         } else if meta.is_dir() {
-            total_bytes += 4096;
+            data_bytes += block;
         } else if meta.is_symlink() && meta.len() > 60 {
             // Symlink targets <= 60 bytes are stored inline in the inode.
             // Longer targets need a data block.
-            total_bytes += 4096;
+            data_bytes += block;
         }
     })?;
+    Ok((data_bytes, inode_count))
+}
 
-    // 256 bytes per inode + 10% metadata overhead + 64 MiB journal.
-    let raw = total_bytes + inode_count * 256;
-    let sized = raw * 11 / 10 + 64 * 1024 * 1024;
-    Ok(sized.max(256 * 1024 * 1024))
+/// Mirrors the non-ignored part of `src` into `dst`, preserving ownership,
+/// modes and timestamps. Returns whether anything was staged.
+fn stage_filtered(src: &Path, dst: &Path, rel: &Path, ignore: &IgnoreRules) -> Result<bool> {
+    let meta = src.symlink_metadata()?;
+    std::fs::create_dir(dst)?;
+
+    let mut staged_any = false;
+    for dirent in std::fs::read_dir(src)? {
+        let entry = dirent?;
+        let child_rel = rel.join(entry.file_name());
+        let from = entry.path();
+        let to = dst.join(entry.file_name());
+        let child = from.symlink_metadata()?;
+        let ignored = ignore.is_ignored(&child_rel);
+
+        if child.is_dir() {
+            // Ignored directories are only walked when an exception could
+            // re-include something below them.
+            if ignored && !ignore.has_exceptions() {
+                continue;
+            }
+            if stage_filtered(&from, &to, &child_rel, ignore)? || !ignored {
+                staged_any = true;
+            } else {
+                std::fs::remove_dir(&to)?;
+            }
+            continue;
+        }
+        if ignored {
+            continue;
+        }
+
+        if child.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(&from)?, &to)?;
+            let _ = std::os::unix::fs::lchown(&to, Some(child.uid()), Some(child.gid()));
+        } else if std::fs::hard_link(&from, &to).is_err() {
+            std::fs::copy(&from, &to)?;
+            let _ = std::os::unix::fs::lchown(&to, Some(child.uid()), Some(child.gid()));
+            std::fs::File::options()
+                .write(true)
+                .open(&to)?
+                .set_modified(child.modified()?)?;
+        }
+        staged_any = true;
+    }
+
+    let _ = std::os::unix::fs::lchown(dst, Some(meta.uid()), Some(meta.gid()));
+    std::fs::set_permissions(dst, meta.permissions())?;
+    if let Ok(dir) = std::fs::File::open(dst) {
+        let _ = dir.set_modified(meta.modified()?);
+    }
+    Ok(staged_any)
 }
 
 /// Checks a libext2fs `errcode_t`, converting non-zero values to [`Error::Ext2fs`].
@@ -432,6 +754,42 @@ const fn check(op: &'static str, code: sys::errcode_t) -> Result<()> {
     }
 }
 
+/// Writes everything read from `data` to the open `file`.
+///
+/// # Safety
+///
+/// `file` must be open for writing.
+unsafe fn copy_into(file: sys::ext2_file_t, data: &mut impl Read) -> Result<()> {
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = match data.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let mut done = 0;
+        while done < n {
+            let mut written: u32 = 0;
+            unsafe {
+                check(
+                    "ext2fs_file_write",
+                    sys::ext2fs_file_write(
+                        file,
+                        buf[done..n].as_ptr().cast(),
+                        (n - done) as u32,
+                        &raw mut written,
+                    ),
+                )?;
+            }
+            if written == 0 {
+                return Err(Error::Io(std::io::ErrorKind::WriteZero.into()));
+            }
+            done += written as usize;
+        }
+    }
+}
+
 /// Converts a [`Path`] to a [`CString`].
 fn to_cstring(path: &Path) -> Result<CString> {
     let s = path
@@ -459,3 +817,77 @@ fn walk(dir: &Path, f: &mut impl FnMut(&std::fs::Metadata)) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    /// Writes `count` files of `len` bytes under `root/<dir>`.
+    fn fill(root: &Path, dir: &str, count: usize, len: usize) {
+        let path = root.join(dir);
+        std::fs::create_dir_all(&path).unwrap();
+        for i in 0..count {
+            std::fs::write(path.join(format!("f{i}")), vec![b'x'; len]).unwrap();
+        }
+    }
+
+    fn tiny_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("bux_e2fs_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for d in 0..10 {
+            fill(&root, &format!("d{d}"), 100, 100);
+        }
+        fill(&root, "big", 1, 10_000);
+        root
+    }
+
+    #[test]
+    fn inline_policy_skips_tiny_files() {
+        let root = tiny_tree("policy");
+        let full = tree_usage(&root, EstimateOptions::default()).unwrap();
+        let inline = tree_usage(
+            &root,
+            EstimateOptions {
+                small_files: SmallFilePolicy::Inline,
+                ..EstimateOptions::default()
+            },
+        )
+        .unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+
+        // 11 directories and the 10 KB file need blocks either way.
+        let shared = 11 * 4096 + 3 * 4096;
+        assert_eq!(full, (shared + 1000 * 4096, 1012));
+        assert_eq!(inline, (shared, 1012));
+    }
+
+    #[test]
+    #[ignore = "builds a real image; needs the full libext2fs"]
+    fn estimate_tracks_actual_usage_of_tiny_files() {
+        let root = tiny_tree("actual");
+        let image = root.with_extension("raw");
+        let opts = CreateOptions {
+            inline_data: true,
+            ..CreateOptions::default()
+        };
+        let size = estimate_image_size_with(&root, EstimateOptions::from(&opts)).unwrap();
+        let free =
+            |fs: &Filesystem| unsafe { u64::from((*(*fs.inner).super_).s_free_blocks_count) };
+
+        let mut fs = Filesystem::create(&image, size, &opts).unwrap();
+        let before = free(&fs);
+        fs.populate(&root).unwrap();
+        let used = (before - free(&fs)) * 4096;
+        drop(fs);
+        let (estimated, _) = tree_usage(&root, EstimateOptions::from(&opts)).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        let _ = std::fs::remove_file(&image);
+
+        assert!(used <= estimated, "used {used} > estimated {estimated}");
+        assert!(
+            estimated <= used * 2,
+            "estimated {estimated} vs used {used}"
+        );
+    }
+}
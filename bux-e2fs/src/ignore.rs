@@ -0,0 +1,183 @@
+//! `.dockerignore`-style path filtering for image population.
+//!
+//! Supported syntax, one pattern per line:
+//!
+//! - `#` starts a comment; blank lines are ignored.
+//! - `*` matches any run of characters within one path component, `?` a
+//!   single character, and `[a-z]` / `[!a-z]` a character class.
+//! - `**` matches any number of path components.
+//! - A leading `!` re-includes paths excluded by an earlier pattern.
+//! - Leading `/` and trailing `/` are ignored; patterns are relative to the
+//!   source root.
+//!
+//! A pattern that matches a directory excludes everything below it. As in
+//! Docker, the last matching pattern wins.
+
+use std::path::{Component, Path};
+
+use crate::error::Result;
+
+/// One parsed ignore pattern.
+#[derive(Debug, Clone)]
+struct Rule {
+    /// Pattern split into path components.
+    segments: Vec<String>,
+    /// `!pattern` — re-include instead of exclude.
+    negate: bool,
+}
+
+/// A parsed set of ignore patterns.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    /// Patterns in file order.
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    /// Parses ignore patterns from the contents of an ignore file.
+    #[must_use]
+    pub fn parse(text: &str) -> Self {
+        let rules = text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|line| {
+                let (negate, pattern) = line
+                    .strip_prefix('!')
+                    .map_or((false, line), |rest| (true, rest.trim()));
+                let segments: Vec<String> = pattern
+                    .split('/')
+                    .filter(|s| !s.is_empty() && *s != ".")
+                    .map(ToOwned::to_owned)
+                    .collect();
+                (!segments.is_empty()).then_some(Rule { segments, negate })
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Reads and parses an ignore file.
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    /// Returns `true` if there are no patterns.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns `true` if any pattern re-includes paths (`!pattern`).
+    ///
+    /// Excluded directories must then still be walked, since a descendant
+    /// may be re-included.
+    #[must_use]
+    pub fn has_exceptions(&self) -> bool {
+        self.rules.iter().any(|r| r.negate)
+    }
+
+    /// Returns `true` if `rel` (relative to the source root) is excluded.
+    #[must_use]
+    pub fn is_ignored(&self, rel: &Path) -> bool {
+        let components: Vec<&str> = rel
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(s) => s.to_str(),
+                _ => None,
+            })
+            .collect();
+
+        let mut ignored = false;
+        for rule in &self.rules {
+            // A match on the path itself or any parent directory counts.
+            if (1..=components.len()).any(|n| match_segments(&rule.segments, &components[..n])) {
+                ignored = !rule.negate;
+            }
+        }
+        ignored
+    }
+}
+
+/// Matches pattern components against path components, expanding `**`.
+fn match_segments(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            match_segments(rest, path)
+                || path
+                    .split_first()
+                    .is_some_and(|(_, tail)| match_segments(pattern, tail))
+        }
+        Some((first, rest)) => path.split_first().is_some_and(|(name, tail)| {
+            glob(first.as_bytes(), name.as_bytes()) && match_segments(rest, tail)
+        }),
+    }
+}
+
+/// Matches a single path component against `*`, `?` and `[...]` wildcards.
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| glob(rest, &name[i..])),
+        Some((b'?', rest)) => !name.is_empty() && glob(rest, &name[1..]),
+        Some((b'[', rest)) => {
+            let Some(close) = rest.iter().position(|&c| c == b']') else {
+                return name.first() == Some(&b'[') && glob(rest, &name[1..]);
+            };
+            let Some((&c, tail)) = name.split_first() else {
+                return false;
+            };
+            class_matches(&rest[..close], c) && glob(&rest[close + 1..], tail)
+        }
+        Some((&p, rest)) => name.first() == Some(&p) && glob(rest, &name[1..]),
+    }
+}
+
+/// Matches a byte against a bracket expression body such as `a-z` or `!0-9`.
+fn class_matches(class: &[u8], c: u8) -> bool {
+    let (negate, body) = match class.split_first() {
+        Some((b'!' | b'^', body)) => (true, body),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < body.len() {
+        if i + 2 < body.len() && body[i + 1] == b'-' {
+            found |= (body[i]..=body[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= body[i] == c;
+            i += 1;
+        }
+    }
+    found != negate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excludes_directories_and_globs() {
+        let rules = IgnoreRules::parse("# comment\n.git\n*.log\n/target/\nsrc/**/*.tmp\n");
+        assert!(rules.is_ignored(Path::new(".git")));
+        assert!(rules.is_ignored(Path::new(".git/objects/ab")));
+        assert!(rules.is_ignored(Path::new("debug.log")));
+        assert!(!rules.is_ignored(Path::new("logs/debug.log")));
+        assert!(rules.is_ignored(Path::new("target/release/bux")));
+        assert!(rules.is_ignored(Path::new("src/a/b/c.tmp")));
+        assert!(rules.is_ignored(Path::new("src/c.tmp")));
+        assert!(!rules.is_ignored(Path::new("src/main.rs")));
+    }
+
+    #[test]
+    fn last_match_wins_with_exceptions() {
+        let rules = IgnoreRules::parse("docs\n!docs/README.md\n*.[ch]\n!keep.c\n");
+        assert!(rules.has_exceptions());
+        assert!(rules.is_ignored(Path::new("docs/guide.md")));
+        assert!(!rules.is_ignored(Path::new("docs/README.md")));
+        assert!(rules.is_ignored(Path::new("main.c")));
+        assert!(!rules.is_ignored(Path::new("keep.c")));
+        assert!(!rules.is_ignored(Path::new("main.rs")));
+    }
+}
@@ -0,0 +1,636 @@
+//! Ext4 images written straight from OCI layer tarballs, with no extracted
+//! rootfs directory in between.
+//!
+//! libext2fs offers no cheap way to delete, so layers are walked top (last)
+//! to bottom (first) and an entry is written only if no upper layer
+//! replaced it, removed it with a `.wh.<name>` whiteout, or hid it behind
+//! an opaque directory (`.wh..wh..opq`). Each surviving entry is therefore
+//! written exactly once and never touched again, except for directories an
+//! upper layer needed before the layer defining them was reached: those are
+//! created with default attributes and given their own once it is.
+//!
+//! A hard link whose target lives in a lower layer is only resolved once
+//! that layer is reached: it is linked to the target if the target made it
+//! into the image, and given a copy of the target otherwise.
+
+#![allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+
+use crate::error::{Error, Result};
+use crate::ext4::{CreateOptions, Filesystem, image_size_for};
+use crate::sys;
+
+/// Block size the images are created with.
+const BLOCK: u64 = 4096;
+
+/// Symlink targets up to this length live in the inode.
+const INLINE_SYMLINK_MAX: u64 = 60;
+
+/// Creates an ext4 image at `output` holding the filesystem the OCI layer
+/// tarballs in `layers` (bottom first, gzip-compressed or plain) stack up
+/// to, whiteouts applied.
+///
+/// Files keep the owners, modes and modification times of their tar
+/// headers, whoever runs this. The layers are read twice: once to size the
+/// image, once to write it. Hard links into lower layers see the target as
+/// it was below them, even if an upper layer replaced or removed it; a link
+/// whose target no layer holds fails the whole image.
+pub fn create_from_layers(layers: &[impl AsRef<Path>], output: &Path) -> Result<()> {
+    let mut usage = Usage::default();
+    walk_layers(layers, &mut usage)?;
+    let size = image_size_for(usage.data_bytes, usage.inodes);
+
+    let mut fs = Filesystem::create(output, size, &CreateOptions::default())?;
+    walk_layers(
+        layers,
+        &mut Writer {
+            fs: &mut fs,
+            inos: HashMap::from([(PathBuf::new(), sys::EXT2_ROOT_INO)]),
+            layer: HashMap::new(),
+        },
+    )?;
+    fs.add_journal()
+}
+
+/// Receives the entries that make up the final tree, parents first.
+trait Sink {
+    /// Adds `path` from `entry`.
+    fn create<R: Read>(&mut self, path: &Path, entry: &mut tar::Entry<'_, R>) -> Result<()>;
+    /// Adds directory `path`, which an upper layer needs but has no entry for.
+    fn implicit_dir(&mut self, path: &Path) -> Result<()>;
+    /// Gives directory `path`, added by [`implicit_dir`](Self::implicit_dir),
+    /// the attributes in `header`.
+    fn dir_attrs(&mut self, path: &Path, header: &tar::Header) -> Result<()>;
+    /// Adds `path` as a hard link to `target`, added before.
+    fn link(&mut self, path: &Path, target: &Path) -> Result<()>;
+    /// Adds `path`, a hard link from an upper layer whose target upper
+    /// layers hid, as a copy of the target `entry`.
+    fn copy<R: Read>(&mut self, path: &Path, entry: &mut tar::Entry<'_, R>) -> Result<()>;
+    /// Called once a layer has been walked.
+    fn end_layer(&mut self) {}
+}
+
+/// What upper layers left at a path.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Present {
+    /// A directory with attributes from a layer entry.
+    Dir,
+    /// A directory created only because something below it was written.
+    ImplicitDir,
+    /// Anything else.
+    Other,
+}
+
+/// The tree as seen from the layer being walked.
+#[derive(Default)]
+struct Overlay {
+    /// Paths already written, by this or an upper layer.
+    present: HashMap<PathBuf, Present>,
+    /// Paths upper layers removed with a whiteout.
+    removed: HashSet<PathBuf>,
+    /// Directories whose lower contents upper layers hid.
+    opaque: HashSet<PathBuf>,
+    /// This layer's whiteouts, which only apply to the layers below it.
+    pending_removed: Vec<PathBuf>,
+    /// This layer's opaque directories.
+    pending_opaque: Vec<PathBuf>,
+    /// Paths this layer has entries for, hidden or not.
+    layer_paths: HashSet<PathBuf>,
+    /// Hard links waiting for their target in a lower layer, by target.
+    links: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl Overlay {
+    /// Whether upper layers removed or replaced `path` or a parent of it.
+    fn hidden(&self, path: &Path) -> bool {
+        if self.removed.contains(path) {
+            return true;
+        }
+        path.ancestors().skip(1).any(|dir| {
+            self.removed.contains(dir)
+                || self.opaque.contains(dir)
+                || self.present.get(dir) == Some(&Present::Other)
+        })
+    }
+
+    /// Adds the missing parents of `path`, outermost first.
+    fn add_parents(&mut self, path: &Path, sink: &mut impl Sink) -> Result<()> {
+        let mut missing: Vec<&Path> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| !self.present.contains_key(*dir))
+            .collect();
+        missing.reverse();
+        for dir in missing {
+            sink.implicit_dir(dir)?;
+            self.present.insert(dir.to_path_buf(), Present::ImplicitDir);
+        }
+        Ok(())
+    }
+
+    /// Applies one layer's entries below those already walked.
+    fn apply<R: Read>(
+        &mut self,
+        archive: &mut tar::Archive<R>,
+        sink: &mut impl Sink,
+    ) -> Result<()> {
+        for raw_entry in archive.entries()? {
+            let mut entry = raw_entry?;
+            let path = normalize(&entry.path()?);
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+
+            if name == ".wh..wh..opq" {
+                let Some(dir) = path.parent() else { continue };
+                if !self.hidden(dir) {
+                    // The directory itself belongs to this layer.
+                    self.add_parents(&path, sink)?;
+                    self.pending_opaque.push(dir.to_path_buf());
+                }
+                continue;
+            }
+            if let Some(target) = name.strip_prefix(".wh.") {
+                if let Some(dir) = path.parent() {
+                    self.pending_removed.push(dir.join(target));
+                }
+                continue;
+            }
+            let waiting = self.links.remove(&path);
+            let mut created = false;
+            if !self.hidden(&path) {
+                let is_dir = entry.header().entry_type().is_dir();
+                match self.present.get(&path) {
+                    Some(Present::ImplicitDir) if is_dir => {
+                        sink.dir_attrs(&path, entry.header())?;
+                        self.present.insert(path.clone(), Present::Dir);
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.add_parents(&path, sink)?;
+                        if let Some(target) = self.lower_link_target(&entry)? {
+                            self.links.entry(target).or_default().push(path.clone());
+                        } else {
+                            sink.create(&path, &mut entry)?;
+                            created = true;
+                        }
+                        let present = if is_dir { Present::Dir } else { Present::Other };
+                        self.present.insert(path.clone(), present);
+                    }
+                }
+            }
+            if let Some(links) = waiting {
+                self.resolve_links(&path, created, links, &mut entry, sink)?;
+            }
+            self.layer_paths.insert(path);
+        }
+
+        self.removed.extend(self.pending_removed.drain(..));
+        self.opaque.extend(self.pending_opaque.drain(..));
+        self.layer_paths.clear();
+        sink.end_layer();
+        Ok(())
+    }
+
+    /// The target of `entry` if it is a hard link into a lower layer.
+    fn lower_link_target<R: Read>(&self, entry: &tar::Entry<'_, R>) -> Result<Option<PathBuf>> {
+        if !entry.header().entry_type().is_hard_link() {
+            return Ok(None);
+        }
+        let target = entry.link_name()?.map(|link| normalize(&link));
+        Ok(target.filter(|t| !self.layer_paths.contains(t)))
+    }
+
+    /// Gives upper layer hard `links` their target, `entry` at `path`: a
+    /// link to it if it was `created`, a copy of it otherwise.
+    fn resolve_links<R: Read>(
+        &mut self,
+        path: &Path,
+        created: bool,
+        links: Vec<PathBuf>,
+        entry: &mut tar::Entry<'_, R>,
+        sink: &mut impl Sink,
+    ) -> Result<()> {
+        if created {
+            for link in &links {
+                sink.link(link, path)?;
+            }
+        } else if entry.header().entry_type().is_hard_link() {
+            // The target is a link itself; follow it further down.
+            let Some(target) = self.lower_link_target(entry)? else {
+                return Err(broken_link(&links[0], path));
+            };
+            self.links.entry(target).or_default().extend(links);
+        } else {
+            sink.copy(&links[0], entry)?;
+            for link in &links[1..] {
+                sink.link(link, &links[0])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if a hard link is still waiting once every layer was walked.
+    fn finish(self) -> Result<()> {
+        match self.links.into_iter().next() {
+            Some((target, links)) => Err(broken_link(&links[0], &target)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The error for a hard link at `link` whose `target` no layer holds.
+fn broken_link(link: &Path, target: &Path) -> Error {
+    Error::BrokenHardLink {
+        link: link.display().to_string(),
+        target: target.display().to_string(),
+    }
+}
+
+/// Feeds the final tree of `layers` (bottom first) to `sink`.
+fn walk_layers(layers: &[impl AsRef<Path>], sink: &mut impl Sink) -> Result<()> {
+    let mut overlay = Overlay::default();
+    overlay.present.insert(PathBuf::new(), Present::ImplicitDir);
+    for layer in layers.iter().rev() {
+        let mut archive = tar::Archive::new(open_layer(layer.as_ref())?);
+        overlay.apply(&mut archive, sink)?;
+    }
+    overlay.finish()
+}
+
+/// Opens a layer tarball, decompressing it if it starts with the gzip magic.
+fn open_layer(path: &Path) -> Result<Box<dyn Read>> {
+    let mut file = BufReader::new(File::open(path)?);
+    if file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(GzDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Strips `.`, `..` and leading `/` from a path inside a layer; the root is
+/// the empty path.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => out.push(name),
+            Component::ParentDir => {
+                out.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    out
+}
+
+/// Blocks and inodes the final tree needs, as counted by
+/// [`estimate_image_size`](crate::estimate_image_size).
+#[derive(Default)]
+struct Usage {
+    /// Data blocks, in bytes.
+    data_bytes: u64,
+    /// Inodes, the root excepted.
+    inodes: u64,
+}
+
+impl Sink for Usage {
+    fn create<R: Read>(&mut self, _path: &Path, entry: &mut tar::Entry<'_, R>) -> Result<()> {
+        let header = entry.header();
+        let kind = header.entry_type();
+        if kind.is_hard_link() {
+            return Ok(());
+        }
+        self.inodes += 1;
+        if kind.is_dir() {
+            self.data_bytes += BLOCK;
+        } else if kind.is_symlink() {
+            let len = header.link_name_bytes().map_or(0, |name| name.len() as u64);
+            if len > INLINE_SYMLINK_MAX {
+                self.data_bytes += BLOCK;
+            }
+        } else if kind.is_file() || kind == tar::EntryType::Continuous {
+            self.data_bytes += header.size()?.div_ceil(BLOCK) * BLOCK;
+        }
+        Ok(())
+    }
+
+    fn implicit_dir(&mut self, _path: &Path) -> Result<()> {
+        self.inodes += 1;
+        self.data_bytes += BLOCK;
+        Ok(())
+    }
+
+    fn dir_attrs(&mut self, _path: &Path, _header: &tar::Header) -> Result<()> {
+        Ok(())
+    }
+
+    fn link(&mut self, _path: &Path, _target: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn copy<R: Read>(&mut self, path: &Path, entry: &mut tar::Entry<'_, R>) -> Result<()> {
+        self.create(path, entry)
+    }
+}
+
+/// Writes the tree into an ext4 image.
+struct Writer<'a> {
+    /// Image being written.
+    fs: &'a mut Filesystem,
+    /// Inode of every path written so far.
+    inos: HashMap<PathBuf, u32>,
+    /// Inodes written from the current layer, the only ones its hard links
+    /// may point at.
+    layer: HashMap<PathBuf, u32>,
+}
+
+impl Writer<'_> {
+    /// The parent directory inode and file name of `path`.
+    fn place<'p>(&self, path: &'p Path) -> Result<(u32, &'p str)> {
+        let invalid = || Error::InvalidPath(path.display().to_string());
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(invalid)?;
+        let parent = path
+            .parent()
+            .and_then(|dir| self.inos.get(dir))
+            .ok_or_else(invalid)?;
+        Ok((*parent, name))
+    }
+
+    /// Applies the mode, owner and mtime from `header` to `ino`.
+    fn set_attrs(&mut self, ino: u32, header: &tar::Header) -> Result<()> {
+        self.fs.set_attrs(
+            ino,
+            header.mode()?,
+            header.uid().unwrap_or(0) as u32,
+            header.gid().unwrap_or(0) as u32,
+            header.mtime().unwrap_or(0) as i64,
+        )
+    }
+
+    /// Writes `entry` at `path`, returning its inode; `None` for entries
+    /// that add nothing. Hard links are not handled here.
+    fn write<R: Read>(
+        &mut self,
+        path: &Path,
+        entry: &mut tar::Entry<'_, R>,
+    ) -> Result<Option<u32>> {
+        let (parent, name) = self.place(path)?;
+        let header = entry.header().clone();
+        let kind = header.entry_type();
+
+        let ino = if kind.is_dir() {
+            self.fs.mkdir_at(parent, name)?
+        } else if kind.is_symlink() {
+            let target = header.link_name_bytes().unwrap_or_default();
+            self.fs.symlink_at(parent, name, &target)?
+        } else if kind.is_file() || kind == tar::EntryType::Continuous {
+            self.fs.write_bytes(parent, name, entry)?
+        } else {
+            let file_type = match kind {
+                tar::EntryType::Char => sys::LINUX_S_IFCHR,
+                tar::EntryType::Block => sys::LINUX_S_IFBLK,
+                tar::EntryType::Fifo => sys::LINUX_S_IFIFO,
+                // Metadata-only entries the tar reader did not fold in.
+                _ => return Ok(None),
+            };
+            let major = header.device_major()?.unwrap_or(0);
+            let minor = header.device_minor()?.unwrap_or(0);
+            self.fs
+                .mknod_at(parent, name, file_type | header.mode()?, major, minor)?
+        };
+        self.set_attrs(ino, &header)?;
+        self.inos.insert(path.to_path_buf(), ino);
+        Ok(Some(ino))
+    }
+}
+
+impl Sink for Writer<'_> {
+    fn create<R: Read>(&mut self, path: &Path, entry: &mut tar::Entry<'_, R>) -> Result<()> {
+        if !entry.header().entry_type().is_hard_link() {
+            if let Some(ino) = self.write(path, entry)? {
+                self.layer.insert(path.to_path_buf(), ino);
+            }
+            return Ok(());
+        }
+        // Links into lower layers never get here; the target is in this
+        // layer, but an upper layer may have kept it out of the image.
+        let target = entry.link_name()?.map(|link| normalize(&link));
+        let Some(&ino) = target.as_ref().and_then(|t| self.layer.get(t)) else {
+            return Err(broken_link(path, &target.unwrap_or_default()));
+        };
+        let (parent, name) = self.place(path)?;
+        self.fs.hard_link(parent, name, ino)?;
+        self.inos.insert(path.to_path_buf(), ino);
+        Ok(())
+    }
+
+    fn implicit_dir(&mut self, path: &Path) -> Result<()> {
+        let (parent, name) = self.place(path)?;
+        let ino = self.fs.mkdir_at(parent, name)?;
+        self.inos.insert(path.to_path_buf(), ino);
+        Ok(())
+    }
+
+    fn dir_attrs(&mut self, path: &Path, header: &tar::Header) -> Result<()> {
+        let Some(&ino) = self.inos.get(path) else {
+            return Ok(());
+        };
+        self.set_attrs(ino, header)
+    }
+
+    fn link(&mut self, path: &Path, target: &Path) -> Result<()> {
+        let Some(&ino) = self.inos.get(target) else {
+            return Err(broken_link(path, target));
+        };
+        let (parent, name) = self.place(path)?;
+        self.fs.hard_link(parent, name, ino)?;
+        self.inos.insert(path.to_path_buf(), ino);
+        Ok(())
+    }
+
+    fn copy<R: Read>(&mut self, path: &Path, entry: &mut tar::Entry<'_, R>) -> Result<()> {
+        self.write(path, entry).map(drop)
+    }
+
+    fn end_layer(&mut self) {
+        self.layer.clear();
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    /// Records what reaches the sink.
+    #[derive(Default)]
+    struct Recorder {
+        /// Created paths and the content of files.
+        created: Vec<(String, String)>,
+        /// Directories made for lower entries.
+        implicit: Vec<String>,
+        /// Directories given attributes afterwards.
+        attrs: Vec<String>,
+        /// Hard links and their targets.
+        links: Vec<(String, String)>,
+    }
+
+    impl Sink for Recorder {
+        fn create<R: Read>(&mut self, path: &Path, entry: &mut tar::Entry<'_, R>) -> Result<()> {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            self.created.push((path.display().to_string(), content));
+            Ok(())
+        }
+
+        fn implicit_dir(&mut self, path: &Path) -> Result<()> {
+            self.implicit.push(path.display().to_string());
+            Ok(())
+        }
+
+        fn dir_attrs(&mut self, path: &Path, _header: &tar::Header) -> Result<()> {
+            self.attrs.push(path.display().to_string());
+            Ok(())
+        }
+
+        fn link(&mut self, path: &Path, target: &Path) -> Result<()> {
+            self.links
+                .push((path.display().to_string(), target.display().to_string()));
+            Ok(())
+        }
+
+        fn copy<R: Read>(&mut self, path: &Path, entry: &mut tar::Entry<'_, R>) -> Result<()> {
+            self.create(path, entry)
+        }
+    }
+
+    /// Walks `layers` (bottom first) into a [`Recorder`].
+    fn record(layers: &[Vec<u8>]) -> Result<Recorder> {
+        let mut recorder = Recorder::default();
+        let mut overlay = Overlay::default();
+        overlay.present.insert(PathBuf::new(), Present::ImplicitDir);
+        for data in layers.iter().rev() {
+            overlay.apply(&mut tar::Archive::new(data.as_slice()), &mut recorder)?;
+        }
+        overlay.finish()?;
+        Ok(recorder)
+    }
+
+    /// The created paths and contents of `recorder`.
+    fn created(recorder: &Recorder) -> Vec<(&str, &str)> {
+        recorder
+            .created
+            .iter()
+            .map(|(path, content)| (path.as_str(), content.as_str()))
+            .collect()
+    }
+
+    /// Builds a layer of files (`name` → content), directories (`name/`)
+    /// and hard links (`name` → `=target`).
+    fn layer(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            if let Some(target) = content.strip_prefix('=') {
+                header.set_entry_type(tar::EntryType::Link);
+                header.set_size(0);
+                builder.append_link(&mut header, name, target).unwrap();
+                continue;
+            }
+            if name.ends_with('/') {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o755);
+                header.set_size(0);
+            } else {
+                header.set_mode(0o644);
+                header.set_size(content.len() as u64);
+            }
+            builder
+                .append_data(&mut header, name, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn upper_layers_win_and_whiteouts_hide_lower_entries() {
+        let layers = [
+            layer(&[
+                ("etc/", ""),
+                ("etc/passwd", "base"),
+                ("etc/gone", "base"),
+                ("opt/", ""),
+                ("opt/old", "base"),
+                ("usr/", ""),
+                ("usr/bin/", ""),
+                ("usr/bin/sh", "base"),
+            ]),
+            layer(&[
+                ("etc/.wh.gone", ""),
+                ("opt/.wh..wh..opq", ""),
+                ("opt/new", "mid"),
+                ("usr/bin/tool", "mid"),
+            ]),
+            layer(&[("etc/passwd", "top")]),
+        ];
+
+        let recorder = record(&layers).unwrap();
+        assert_eq!(
+            created(&recorder),
+            [
+                ("etc/passwd", "top"),
+                ("opt/new", "mid"),
+                ("usr/bin/tool", "mid"),
+                ("usr/bin/sh", "base"),
+            ]
+        );
+        assert_eq!(recorder.implicit, ["etc", "opt", "usr", "usr/bin"]);
+        // The base layer's entries describe the directories upper layers made.
+        assert_eq!(recorder.attrs, ["etc", "opt", "usr", "usr/bin"]);
+    }
+
+    #[test]
+    fn hard_links_into_lower_layers_keep_the_lower_content() {
+        let layers = [
+            layer(&[
+                ("bin/", ""),
+                ("bin/busybox", "base"),
+                ("bin/tool", "base tool"),
+            ]),
+            // Links into the base layer, one of them to a file the next
+            // layer replaces.
+            layer(&[("bin/sh", "=bin/busybox"), ("bin/tool2", "=bin/tool")]),
+            layer(&[("bin/tool", "top tool"), ("bin/tool3", "=bin/tool")]),
+        ];
+
+        let recorder = record(&layers).unwrap();
+        // `bin/tool3` links within its layer, which the sink resolves.
+        assert_eq!(
+            created(&recorder),
+            [
+                ("bin/tool", "top tool"),
+                ("bin/tool3", ""),
+                ("bin/busybox", "base"),
+                ("bin/tool2", "base tool"),
+            ]
+        );
+        assert_eq!(
+            recorder.links,
+            [("bin/sh".to_owned(), "bin/busybox".to_owned())]
+        );
+
+        let dangling = [layer(&[("bin/", "")]), layer(&[("bin/sh", "=bin/busybox")])];
+        assert!(matches!(
+            record(&dangling),
+            Err(Error::BrokenHardLink { .. })
+        ));
+    }
+}
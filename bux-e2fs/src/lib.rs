@@ -5,6 +5,8 @@
 //! - **[`sys`]** — Raw FFI bindings (auto-generated by `bindgen`).
 //! - **[`Filesystem`]** — RAII wrapper around `ext2_filsys` with safe operations.
 //! - **[`create_from_dir`]** / **[`inject_file`]** — Convenience functions for common tasks.
+//! - **[`create_from_layers`]** — An image straight from OCI layer tarballs, no rootfs directory.
+//! - **[`IgnoreRules`]** — `.dockerignore`-style filtering for [`create_from_dir_filtered`].
 //!
 //! # Quick Start
 //!
@@ -27,9 +29,14 @@
 
 mod error;
 mod ext4;
+mod ignore;
+mod layers;
 
 pub use error::{Error, Result};
 pub use ext4::{
-    BlockSize, CreateOptions, FileType, Filesystem, create_from_dir, estimate_image_size,
-    inject_file,
+    BlockSize, CreateOptions, EstimateOptions, FileType, Filesystem, INLINE_DATA_MAX,
+    SmallFilePolicy, create_from_dir, create_from_dir_filtered, estimate_image_size,
+    estimate_image_size_with, image_size_for, inject_file,
 };
+pub use ignore::IgnoreRules;
+pub use layers::create_from_layers;
@@ -1,12 +1,18 @@
-//! Control channel handler: ping, shutdown, quiesce, thaw.
+//! Control channel handler: ping, shutdown, quiesce, thaw, env, user lookup,
+//! filesystem diff, agent info, signals to the primary process, RNG seeds.
 
 use std::io;
 use std::path::PathBuf;
 use std::sync::Mutex;
 
-use bux_proto::{ControlReq, ControlResp};
+use bux_proto::{AgentInfo, ControlReq, ControlResp, ErrorCode, ErrorInfo};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
+use crate::diff;
+use crate::entropy;
+use crate::env;
+use crate::exec;
+use crate::init;
 use crate::mounts;
 use crate::server;
 
@@ -40,7 +46,7 @@ pub async fn handle(
             ControlReq::Shutdown => {
                 bux_proto::send(w, &ControlResp::ShutdownOk).await?;
                 w.flush().await?;
-                graceful_shutdown();
+                graceful_shutdown(0);
             }
             ControlReq::Quiesce => {
                 let frozen = mounts::freeze_filesystems();
@@ -74,6 +80,61 @@ pub async fn handle(
                 .await?;
                 w.flush().await?;
             }
+            ControlReq::Env => {
+                bux_proto::send(w, &ControlResp::Env(env::vars())).await?;
+                w.flush().await?;
+            }
+            ControlReq::SetEnv { vars } => {
+                let resp = match env::set(&vars) {
+                    Ok(()) => ControlResp::Env(env::vars()),
+                    Err(e) => ControlResp::Error(e),
+                };
+                bux_proto::send(w, &resp).await?;
+                w.flush().await?;
+            }
+            ControlReq::ResolveUser { spec } => {
+                let resp = match exec::resolve_user(&spec) {
+                    Ok((uid, gid)) => ControlResp::User { uid, gid },
+                    Err(e) => ControlResp::Error(e),
+                };
+                bux_proto::send(w, &resp).await?;
+                w.flush().await?;
+            }
+            ControlReq::Diff => {
+                let resp = match tokio::task::spawn_blocking(diff::changes).await {
+                    Ok(Ok(changes)) => ControlResp::Diff(changes),
+                    Ok(Err(e)) => ControlResp::Error(e),
+                    Err(e) => {
+                        ControlResp::Error(ErrorInfo::new(ErrorCode::Internal, e.to_string()))
+                    }
+                };
+                bux_proto::send(w, &resp).await?;
+                w.flush().await?;
+            }
+            ControlReq::Info => {
+                let info = AgentInfo::new(
+                    env!("CARGO_PKG_VERSION"),
+                    server::FEATURES.iter().map(|&f| f.to_owned()).collect(),
+                );
+                bux_proto::send(w, &ControlResp::Info(info)).await?;
+                w.flush().await?;
+            }
+            ControlReq::Signal { signal } => {
+                let resp = match init::signal_main(signal) {
+                    Ok(()) => ControlResp::SignalOk,
+                    Err(e) => ControlResp::Error(e),
+                };
+                bux_proto::send(w, &resp).await?;
+                w.flush().await?;
+            }
+            ControlReq::SeedRng { seed } => {
+                let resp = match entropy::credit(&seed) {
+                    Ok(()) => ControlResp::Seeded,
+                    Err(e) => ControlResp::Error(e),
+                };
+                bux_proto::send(w, &resp).await?;
+                w.flush().await?;
+            }
         }
     }
 }
@@ -81,8 +142,27 @@ pub async fn handle(
 /// Three-step graceful shutdown:
 /// 1. SIGTERM all children → wait briefly → SIGKILL survivors.
 /// 2. Sync filesystems.
-/// 3. Exit.
-fn graceful_shutdown() -> ! {
+/// 3. Exit with `code`.
+///
+/// After a PID 1 handoff the VM ends with the init instead: it gets
+/// `SIGTERM` (to pass on to the command) along with every other process,
+/// and once the filesystems are synced, survivors are killed.
+pub fn graceful_shutdown(code: i32) -> ! {
+    if init::handed_off() {
+        // -1 reaches every process but PID 1 and this one.
+        unsafe {
+            libc::kill(1, libc::SIGTERM);
+            libc::kill(-1, libc::SIGTERM);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        // Sync first: the init exits as soon as its command is killed.
+        unsafe {
+            libc::sync();
+            libc::kill(-1, libc::SIGKILL);
+        }
+        std::process::exit(code);
+    }
+
     // Step 1: signal all children (we are PID 1).
     // SIGTERM to process group 0 hits all children but not us (PID 1 is immune).
     unsafe { libc::kill(0, libc::SIGTERM) };
@@ -97,5 +177,5 @@ fn graceful_shutdown() -> ! {
     unsafe { libc::sync() };
 
     // Step 3: exit.
-    std::process::exit(0);
+    std::process::exit(code);
 }
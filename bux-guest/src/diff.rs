@@ -0,0 +1,150 @@
+//! Root filesystem change tracking against a snapshot taken at boot, when
+//! the host asks for it with [`DIFF_ENV`].
+//!
+//! The snapshot records each path's ctime, which the kernel bumps on any
+//! content or metadata change and which userspace cannot set back, so a
+//! differing ctime means "modified" regardless of clock skew. The walk stays
+//! on the root device, skipping `/proc`, `/sys`, tmpfs mounts and shares.
+
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bux_proto::{Change, ChangeKind, DIFF_ENV, ErrorInfo};
+
+/// Path → `(ctime_sec, ctime_nsec)` for everything on the root device.
+type Snapshot = HashMap<PathBuf, (i64, i64)>;
+
+/// Snapshot taken by [`start_snapshot`].
+static BASELINE: OnceLock<Snapshot> = OnceLock::new();
+
+/// Set once [`start_snapshot`] has started the walk.
+static TRACKING: AtomicBool = AtomicBool::new(false);
+
+/// Snapshots `/` on a background thread so boot is not delayed, if
+/// [`DIFF_ENV`] is `1`.
+///
+/// Changes made while the walk is still running may be attributed to the
+/// baseline.
+pub fn start_snapshot() {
+    if std::env::var_os(DIFF_ENV).is_none_or(|v| v != "1") {
+        return;
+    }
+    TRACKING.store(true, Ordering::Relaxed);
+    std::thread::spawn(|| {
+        let snapshot = walk(Path::new("/"));
+        eprintln!(
+            "[bux-guest] T+{}ms: snapshot of {} paths taken",
+            crate::server::uptime_ms(),
+            snapshot.len()
+        );
+        BASELINE.set(snapshot).ok();
+    });
+}
+
+/// Changes under `/` since boot, waiting for the snapshot if needed.
+///
+/// Fails without waiting if tracking is off, as the snapshot never comes.
+pub fn changes() -> Result<Vec<Change>, ErrorInfo> {
+    if !TRACKING.load(Ordering::Relaxed) {
+        return Err(ErrorInfo::invalid_request(
+            "change tracking is off; the VM must boot with it enabled",
+        ));
+    }
+    Ok(compare(BASELINE.wait(), &walk(Path::new("/"))))
+}
+
+/// Records every path under `root` that lives on the same device.
+fn walk(root: &Path) -> Snapshot {
+    let mut out = Snapshot::new();
+    let Ok(dev) = root.symlink_metadata().map(|m| m.dev()) else {
+        return out;
+    };
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(meta) = path.symlink_metadata() else {
+                continue;
+            };
+            if meta.dev() != dev {
+                continue;
+            }
+            if meta.is_dir() {
+                stack.push(path.clone());
+            }
+            out.insert(path, (meta.ctime(), meta.ctime_nsec()));
+        }
+    }
+    out
+}
+
+/// Diffs two snapshots, sorted by path.
+fn compare(base: &Snapshot, now: &Snapshot) -> Vec<Change> {
+    let mut changes: Vec<Change> = now
+        .iter()
+        .filter_map(|(path, ctime)| {
+            let kind = match base.get(path) {
+                None => ChangeKind::Added,
+                Some(old) if old != ctime => ChangeKind::Modified,
+                Some(_) => return None,
+            };
+            Some((path, kind))
+        })
+        .chain(
+            base.keys()
+                .filter(|path| !now.contains_key(*path))
+                .map(|path| (path, ChangeKind::Deleted)),
+        )
+        .map(|(path, kind)| Change::new(path.to_string_lossy(), kind))
+        .collect();
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_added_modified_and_deleted_paths() {
+        let root = std::env::temp_dir().join(format!("bux_diff_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        std::fs::write(root.join("etc/hosts"), "a").unwrap();
+        std::fs::write(root.join("etc/motd"), "b").unwrap();
+        std::fs::write(root.join("keep"), "c").unwrap();
+        let base = walk(&root);
+
+        // ctime has nanosecond resolution, but coarse clocks can tick slower.
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(root.join("etc/hosts"), "changed").unwrap();
+        std::fs::remove_file(root.join("etc/motd")).unwrap();
+        std::fs::write(root.join("new"), "d").unwrap();
+
+        let summary: Vec<(String, ChangeKind)> = compare(&base, &walk(&root))
+            .into_iter()
+            .map(|c| {
+                (
+                    c.path.trim_start_matches(root.to_str().unwrap()).to_owned(),
+                    c.kind,
+                )
+            })
+            .collect();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            summary,
+            [
+                ("/etc".to_owned(), ChangeKind::Modified),
+                ("/etc/hosts".to_owned(), ChangeKind::Modified),
+                ("/etc/motd".to_owned(), ChangeKind::Deleted),
+                ("/new".to_owned(), ChangeKind::Added),
+            ]
+        );
+    }
+}
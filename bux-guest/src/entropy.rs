@@ -0,0 +1,69 @@
+//! Credits the host's seed to the kernel entropy pool.
+
+use std::io;
+use std::os::fd::AsRawFd;
+
+use bux_proto::{ErrorInfo, RNG_SEED_LEN};
+
+// Defined in include/uapi/linux/random.h:
+//   #define RNDADDENTROPY  _IOW('R', 0x03, int[2])  = 0x40085203
+/// `RNDADDENTROPY` ioctl — mix bytes into the pool and credit them.
+const RNDADDENTROPY: libc::c_ulong = 0x4008_5203;
+
+/// `struct rand_pool_info` with room for one seed.
+#[repr(C)]
+struct RandPoolInfo {
+    /// Entropy credited, in bits.
+    entropy_count: libc::c_int,
+    /// Bytes in `buf`.
+    buf_size: libc::c_int,
+    /// The seed.
+    buf: [u8; RNG_SEED_LEN],
+}
+
+/// Mixes `seed` into the kernel pool and credits all of it as entropy,
+/// which unblocks `getrandom` callers waiting for the pool to initialize.
+///
+/// Crediting is sound because the seed reached the agent over its vsock
+/// connection, which code inside the guest cannot read.
+pub fn credit(seed: &[u8]) -> Result<(), ErrorInfo> {
+    let buf: [u8; RNG_SEED_LEN] = seed.try_into().map_err(|_| {
+        ErrorInfo::invalid_request(format!(
+            "RNG seed must be {RNG_SEED_LEN} bytes, got {}",
+            seed.len()
+        ))
+    })?;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let info = RandPoolInfo {
+        entropy_count: (RNG_SEED_LEN * 8) as libc::c_int,
+        buf_size: RNG_SEED_LEN as libc::c_int,
+        buf,
+    };
+    let urandom = std::fs::File::open("/dev/urandom")
+        .map_err(|e| ErrorInfo::internal(format!("/dev/urandom: {e}")))?;
+    // SAFETY: `info` is a complete `rand_pool_info` that outlives the call.
+    if unsafe { libc::ioctl(urandom.as_raw_fd(), RNDADDENTROPY, &raw const info) } == 0 {
+        eprintln!(
+            "[bux-guest] T+{}ms: kernel RNG seeded",
+            crate::server::uptime_ms()
+        );
+        Ok(())
+    } else {
+        let e = io::Error::last_os_error();
+        Err(ErrorInfo::internal(format!("crediting RNG seed: {e}")))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_of_the_wrong_length_are_refused() {
+        for len in [0, RNG_SEED_LEN - 1, RNG_SEED_LEN + 1] {
+            let err = credit(&vec![0; len]).unwrap_err();
+            assert_eq!(err.code, bux_proto::ErrorCode::InvalidRequest);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! The agent's environment as commands see it: the process environment
+//! without the boot variables, plus changes pushed by the host.
+//!
+//! The host's changes are kept here and applied to each command rather
+//! than written into the process environment, which is not safe to modify
+//! while other threads may read it.
+
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+
+use bux_proto::ErrorInfo;
+
+/// Variables a change may not leave empty or unset.
+const REQUIRED: &[&str] = &["PATH"];
+
+/// Changes from [`set`]: a new value, or `None` for a removed variable.
+static CHANGES: Mutex<BTreeMap<String, Option<String>>> = Mutex::new(BTreeMap::new());
+
+/// The environment every exec starts from, as `KEY=VALUE` entries.
+pub fn vars() -> Vec<String> {
+    let changes = changes();
+    let mut vars: Vec<String> = std::env::vars_os()
+        .filter_map(|(k, v)| {
+            let key = k.to_string_lossy().into_owned();
+            if bux_proto::BOOT_ENVS.contains(&key.as_str()) {
+                return None;
+            }
+            match changes.get(&key) {
+                Some(Some(value)) => Some(format!("{key}={value}")),
+                Some(None) => None,
+                None => Some(format!("{key}={}", v.to_string_lossy())),
+            }
+        })
+        .collect();
+    vars.extend(
+        changes
+            .iter()
+            .filter(|(key, _)| std::env::var_os(key).is_none())
+            .filter_map(|(key, value)| Some(format!("{key}={}", value.as_ref()?))),
+    );
+    vars
+}
+
+/// Applies `KEY=VALUE` (set) and bare `KEY` (remove) entries, in order,
+/// to the environment later commands start with.
+///
+/// Nothing changes if an entry is malformed, names a boot variable, or
+/// leaves a [`REQUIRED`] variable such as `PATH` empty or unset: removing
+/// one needs a replacement later in the same call.
+pub fn set(entries: &[String]) -> Result<(), ErrorInfo> {
+    let mut changes = CHANGES.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut next = changes.clone();
+    for entry in entries {
+        let (key, value) = match entry.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_owned())),
+            None => (entry.as_str(), None),
+        };
+        if key.is_empty() || key.contains('\0') || value.as_ref().is_some_and(|v| v.contains('\0'))
+        {
+            return Err(ErrorInfo::invalid_request(format!(
+                "malformed variable {entry:?}"
+            )));
+        }
+        if bux_proto::BOOT_ENVS.contains(&key) {
+            return Err(ErrorInfo::invalid_request(format!(
+                "{key} is reserved for the agent's boot"
+            )));
+        }
+        next.insert(key.to_owned(), value);
+    }
+    for key in REQUIRED {
+        let Some(value) = next
+            .get(*key)
+            .filter(|_| changes.get(*key) != next.get(*key))
+        else {
+            continue;
+        };
+        if value.as_ref().is_none_or(String::is_empty) {
+            return Err(ErrorInfo::invalid_request(format!(
+                "{key} cannot be left empty; set a replacement"
+            )));
+        }
+    }
+    *changes = next;
+    Ok(())
+}
+
+/// The changes to apply to a command, in key order.
+pub fn changes() -> BTreeMap<String, Option<String>> {
+    CHANGES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_apply_in_order_and_keep_path_set() {
+        let owned = |entries: &[&str]| entries.iter().map(|e| (*e).to_owned()).collect::<Vec<_>>();
+
+        set(&owned(&["GREETING=hello", "EMPTY="])).unwrap();
+        let set_vars = vars();
+        assert!(set_vars.iter().any(|v| v == "GREETING=hello"));
+        assert!(set_vars.iter().any(|v| v == "EMPTY="));
+
+        for rejected in [
+            &["PATH"][..],
+            &["PATH="],
+            &["PATH=/bin", "PATH"],
+            &["GREETING", "BUX_AUTH_TOKEN=x"],
+            &["=x"],
+        ] {
+            assert!(set(&owned(rejected)).is_err(), "{rejected:?}");
+        }
+        // Rejected calls change nothing.
+        assert_eq!(changes().get("GREETING"), Some(&Some("hello".to_owned())));
+
+        set(&owned(&["PATH", "PATH=/sbin:/bin", "GREETING"])).unwrap();
+        let replaced = vars();
+        assert!(replaced.iter().any(|v| v == "PATH=/sbin:/bin"));
+        assert!(!replaced.iter().any(|v| v.starts_with("GREETING=")));
+    }
+}
@@ -0,0 +1,231 @@
+//! Linux capability dropping for exec'd processes.
+//!
+//! Runs inside `pre_exec`, so everything here is async-signal-safe: raw
+//! `prctl`/`capget`/`capset` syscalls and no allocation.
+
+use std::io;
+
+/// Capability names indexed by number, as in `<linux/capability.h>`.
+const NAMES: [&str; 41] = [
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "DAC_READ_SEARCH",
+    "FOWNER",
+    "FSETID",
+    "KILL",
+    "SETGID",
+    "SETUID",
+    "SETPCAP",
+    "LINUX_IMMUTABLE",
+    "NET_BIND_SERVICE",
+    "NET_BROADCAST",
+    "NET_ADMIN",
+    "NET_RAW",
+    "IPC_LOCK",
+    "IPC_OWNER",
+    "SYS_MODULE",
+    "SYS_RAWIO",
+    "SYS_CHROOT",
+    "SYS_PTRACE",
+    "SYS_PACCT",
+    "SYS_ADMIN",
+    "SYS_BOOT",
+    "SYS_NICE",
+    "SYS_RESOURCE",
+    "SYS_TIME",
+    "SYS_TTY_CONFIG",
+    "MKNOD",
+    "LEASE",
+    "AUDIT_WRITE",
+    "AUDIT_CONTROL",
+    "SETFCAP",
+    "MAC_OVERRIDE",
+    "MAC_ADMIN",
+    "SYSLOG",
+    "WAKE_ALARM",
+    "BLOCK_SUSPEND",
+    "AUDIT_READ",
+    "PERFMON",
+    "BPF",
+    "CHECKPOINT_RESTORE",
+];
+
+/// `_LINUX_CAPABILITY_VERSION_3` (64-bit capability sets).
+const CAP_VERSION_3: u32 = 0x2008_0522;
+
+/// `struct __user_cap_header_struct`.
+#[repr(C)]
+struct CapHeader {
+    /// Capability ABI version.
+    version: u32,
+    /// Target thread (`0` = caller).
+    pid: i32,
+}
+
+/// `struct __user_cap_data_struct`; version 3 uses two of these.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    /// Capabilities currently in effect.
+    effective: u32,
+    /// Capabilities the thread may assume.
+    permitted: u32,
+    /// Capabilities preserved across `execve`.
+    inheritable: u32,
+}
+
+/// Parses capability names into a bitmask.
+///
+/// Accepts `CAP_NET_RAW`, `NET_RAW` or `net_raw`, and `ALL` for every
+/// known capability.
+pub(super) fn parse(names: &[String]) -> Result<u64, String> {
+    let mut mask = 0u64;
+    for name in names {
+        let upper = name.to_ascii_uppercase();
+        let bare = upper.strip_prefix("CAP_").unwrap_or(&upper);
+        if bare == "ALL" {
+            mask |= (1 << NAMES.len()) - 1;
+            continue;
+        }
+        let Some(bit) = NAMES.iter().position(|n| *n == bare) else {
+            return Err(format!("unknown capability: {name}"));
+        };
+        mask |= 1 << bit;
+    }
+    Ok(mask)
+}
+
+/// Removes `mask` from the bounding set so an `execve` as root cannot
+/// regain the capabilities. Needs `CAP_SETPCAP`, so it must run before any
+/// uid change.
+pub(super) fn drop_bounding(mask: u64) -> io::Result<()> {
+    for cap in members(mask) {
+        // SAFETY: PR_CAPBSET_DROP takes a capability number and no pointers.
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) } != 0 {
+            let err = io::Error::last_os_error();
+            // Older kernels do not know the newest capabilities.
+            if err.raw_os_error() != Some(libc::EINVAL) {
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The capability numbers set in `mask`.
+fn members(mask: u64) -> impl Iterator<Item = usize> {
+    (0..NAMES.len()).filter(move |&c| mask & (1 << c) != 0)
+}
+
+/// The bits of the `word`th 32-bit capability set to keep when dropping
+/// `mask`.
+#[allow(clippy::cast_possible_truncation)]
+const fn kept(mask: u64, word: usize) -> u32 {
+    !((mask >> (32 * word)) as u32)
+}
+
+/// Removes `mask` from the effective, permitted and inheritable sets.
+pub(super) fn drop_current(mask: u64) -> io::Result<()> {
+    let mut header = CapHeader {
+        version: CAP_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+    // SAFETY: header and data match the kernel's v3 layout and outlive the call.
+    if unsafe { libc::syscall(libc::SYS_capget, &raw mut header, data.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    for (i, set) in data.iter_mut().enumerate() {
+        let keep = kept(mask, i);
+        set.effective &= keep;
+        set.permitted &= keep;
+        set.inheritable &= keep;
+    }
+    // SAFETY: same layout as above.
+    if unsafe { libc::syscall(libc::SYS_capset, &raw mut header, data.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    /// Parses `names` given as string literals.
+    fn parse_strs(names: &[&str]) -> Result<u64, String> {
+        parse(&names.iter().map(ToString::to_string).collect::<Vec<_>>())
+    }
+
+    /// The thread's current capability sets.
+    fn current() -> [CapData; 2] {
+        let mut header = CapHeader {
+            version: CAP_VERSION_3,
+            pid: 0,
+        };
+        let mut data = [CapData::default(); 2];
+        // SAFETY: header and data match the kernel's v3 layout.
+        let rc = unsafe { libc::syscall(libc::SYS_capget, &raw mut header, data.as_mut_ptr()) };
+        assert_eq!(rc, 0);
+        data
+    }
+
+    #[test]
+    fn names_parse_in_any_spelling() {
+        assert_eq!(parse_strs(&[]), Ok(0));
+        assert_eq!(parse_strs(&["CHOWN"]), Ok(1));
+        assert_eq!(parse_strs(&["cap_net_raw"]), Ok(1 << 13));
+        assert_eq!(parse_strs(&["CAP_SYS_ADMIN", "kill"]), Ok(1 << 21 | 1 << 5));
+        assert_eq!(parse_strs(&["checkpoint_restore"]), Ok(1 << 40));
+        assert_eq!(parse_strs(&["all"]), Ok((1 << 41) - 1));
+        assert_eq!(parse_strs(&["CAP_ALL", "CHOWN"]), Ok((1 << 41) - 1));
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        assert_eq!(
+            parse_strs(&["NET_RAW", "CAP_FLY"]),
+            Err("unknown capability: CAP_FLY".to_owned())
+        );
+        assert!(parse_strs(&[""]).is_err());
+        assert!(parse_strs(&["CAP_"]).is_err());
+    }
+
+    #[test]
+    fn masks_split_into_capability_numbers_and_words() {
+        assert_eq!(members(0).count(), 0);
+        assert_eq!(members(1 << 13 | 1 << 40).collect::<Vec<_>>(), [13, 40]);
+        // Bits beyond the known capabilities are ignored.
+        assert_eq!(members(u64::MAX).count(), NAMES.len());
+
+        let mask = 1 << 13 | 1 << 40;
+        assert_eq!(kept(mask, 0), !(1 << 13));
+        assert_eq!(kept(mask, 1), !(1 << 8));
+        assert_eq!(kept(0, 1), u32::MAX);
+    }
+
+    #[test]
+    fn dropped_capabilities_leave_every_set() {
+        // Capabilities are per thread; keep the test's own untouched.
+        std::thread::spawn(|| {
+            let mask = parse_strs(&["CHOWN", "CHECKPOINT_RESTORE"]).unwrap();
+            let setpcap = current()[0].effective & 1 << 8 != 0;
+            if setpcap {
+                drop_bounding(mask).unwrap();
+                // SAFETY: PR_CAPBSET_READ takes a capability number only.
+                assert_eq!(unsafe { libc::prctl(libc::PR_CAPBSET_READ, 0, 0, 0, 0) }, 0);
+            }
+            drop_current(mask).unwrap();
+            let after = current();
+            for set in [after[0].effective, after[0].permitted, after[0].inheritable] {
+                assert_eq!(set & 1, 0);
+            }
+            for set in [after[1].effective, after[1].permitted, after[1].inheritable] {
+                assert_eq!(set & 1 << 8, 0);
+            }
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -1,14 +1,18 @@
 //! Command execution with PTY support and timeout management.
 
+mod caps;
 mod pty;
 
 use std::io;
 use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::Instant;
 
-use bux_proto::{ErrorCode, ErrorInfo, ExecIn, ExecOut, ExecStart, HelloAck};
+use bux_proto::{
+    EXEC_OUTPUT_WINDOW, ErrorCode, ErrorInfo, ExecIn, ExecOut, ExecStart, FrameReader, HelloAck,
+};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// Monotonic counter for generating unique execution IDs.
@@ -18,11 +22,32 @@
 pub async fn handle(
     r: &mut (impl AsyncRead + Unpin),
     w: &mut (impl AsyncWrite + Unpin),
-    req: ExecStart,
+    mut req: ExecStart,
 ) -> io::Result<()> {
     let exec_id = format!("exec-{}", EXEC_SEQ.fetch_add(1, Ordering::Relaxed));
     let spawn_t0 = Instant::now();
 
+    if let Some(spec) = req.user.take() {
+        match resolve_user(&spec) {
+            Ok((uid, gid)) => {
+                req.uid = Some(uid);
+                req.gid = Some(gid);
+            }
+            Err(err) => {
+                bux_proto::send(w, &HelloAck::Error(err)).await?;
+                return w.flush().await;
+            }
+        }
+    }
+    if let Err(err) = prepare_cwd(&req) {
+        bux_proto::send(w, &HelloAck::Error(err)).await?;
+        return w.flush().await;
+    }
+    if let Err(msg) = caps::parse(&req.cap_drop) {
+        bux_proto::send(w, &HelloAck::Error(ErrorInfo::invalid_request(msg))).await?;
+        return w.flush().await;
+    }
+
     if req.tty.is_some() {
         handle_pty(r, w, req, &exec_id, spawn_t0).await
     } else {
@@ -30,6 +55,54 @@ pub async fn handle(
     }
 }
 
+/// Resolves a `user[:group]` spec against the guest's `/etc/passwd` and
+/// `/etc/group`.
+pub fn resolve_user(spec: &str) -> Result<(u32, u32), ErrorInfo> {
+    let read = |path| std::fs::read_to_string(path).unwrap_or_default();
+    bux_proto::resolve_user(spec, &read("/etc/passwd"), &read("/etc/group"))
+        .map_err(ErrorInfo::not_found)
+}
+
+/// Validates the requested working directory, creating it when asked.
+///
+/// Reports a missing directory by name instead of as a raw spawn errno.
+fn prepare_cwd(req: &ExecStart) -> Result<(), ErrorInfo> {
+    let Some(ref cwd) = req.cwd else {
+        return Ok(());
+    };
+    match std::fs::metadata(cwd) {
+        Ok(meta) if meta.is_dir() => Ok(()),
+        Ok(_) => Err(ErrorInfo::invalid_request(format!(
+            "working directory {cwd} is not a directory"
+        ))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && req.create_cwd => {
+            create_owned_dirs(Path::new(cwd), req.uid, req.gid).map_err(|err| {
+                ErrorInfo::internal(format!("cannot create working directory {cwd}: {err}"))
+            })
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(ErrorInfo::not_found(format!(
+            "working directory {cwd} does not exist"
+        ))),
+        Err(e) => Err(ErrorInfo::internal(format!("working directory {cwd}: {e}"))),
+    }
+}
+
+/// Creates `dir` and any missing parents, handing each directory it
+/// creates (not just the last) to `uid`/`gid`, as if that user had run
+/// `mkdir -p`. Existing directories keep their owner.
+fn create_owned_dirs(dir: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    let missing: Vec<&Path> = dir.ancestors().take_while(|p| !p.exists()).collect();
+    for created in missing.into_iter().rev() {
+        match std::fs::create_dir(created) {
+            Ok(()) => std::os::unix::fs::chown(created, uid, gid)?,
+            // Raced with another creator; leave its owner alone.
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
 /// Pipe-mode execution: stdout and stderr are separate streams.
 async fn handle_pipe(
     r: &mut (impl AsyncRead + Unpin),
@@ -98,15 +171,22 @@ async fn handle_pipe(
     let mut stderr_done = false;
     let mut stdout_buf = [0u8; 4096];
     let mut stderr_buf = [0u8; 4096];
+    // Output sent but not yet acknowledged; pipes are left unread (so the
+    // child blocks on write) while this exceeds the window.
+    let mut in_flight: u64 = 0;
+    // Set once the host half-closes; no further input or acks will arrive.
+    let mut host_done = false;
+    let mut host = FrameReader::new(r);
 
     loop {
         // Exit the I/O loop once both output streams are done.
         if stdout_done && stderr_done {
             break;
         }
+        let window_open = host_done || in_flight < EXEC_OUTPUT_WINDOW;
 
         tokio::select! {
-            host_msg = bux_proto::recv::<ExecIn>(r) => {
+            host_msg = host.recv::<ExecIn>(), if !host_done => {
                 match host_msg {
                     Ok(ExecIn::Stdin(data)) => {
                         if let Some(ref mut stdin) = child_stdin {
@@ -120,6 +200,12 @@ async fn handle_pipe(
                         let _ = unsafe { libc::kill(pid, sig) };
                     }
                     Ok(ExecIn::ResizeTty(_)) => {}
+                    Ok(ExecIn::Ack(n)) => in_flight = in_flight.saturating_sub(n),
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                        // Host half-closed: treat as stdin EOF, keep streaming output.
+                        child_stdin = None;
+                        host_done = true;
+                    }
                     Err(_) => {
                         // Host disconnected — kill child and collect exit status.
                         let _ = unsafe { libc::kill(pid, libc::SIGKILL) };
@@ -127,19 +213,21 @@ async fn handle_pipe(
                     }
                 }
             }
-            n = stdout.read(&mut stdout_buf), if !stdout_done => {
+            n = stdout.read(&mut stdout_buf), if !stdout_done && window_open => {
                 match n {
                     Ok(0) | Err(_) => stdout_done = true,
                     Ok(len) => {
-                        bux_proto::send(w, &ExecOut::Stdout(stdout_buf[..len].to_vec())).await?;
+                        send_output(w, pid, ExecOut::Stdout(stdout_buf[..len].to_vec())).await?;
+                        in_flight += len as u64;
                     }
                 }
             }
-            n = stderr.read(&mut stderr_buf), if !stderr_done => {
+            n = stderr.read(&mut stderr_buf), if !stderr_done && window_open => {
                 match n {
                     Ok(0) | Err(_) => stderr_done = true,
                     Ok(len) => {
-                        bux_proto::send(w, &ExecOut::Stderr(stderr_buf[..len].to_vec())).await?;
+                        send_output(w, pid, ExecOut::Stderr(stderr_buf[..len].to_vec())).await?;
+                        in_flight += len as u64;
                     }
                 }
             }
@@ -192,10 +280,15 @@ async fn handle_pty(
     }
 
     let mut pty_buf = [0u8; 4096];
+    let mut in_flight: u64 = 0;
+    let mut host_done = false;
+    let mut host = FrameReader::new(r);
 
     loop {
+        let window_open = host_done || in_flight < EXEC_OUTPUT_WINDOW;
+
         tokio::select! {
-            host_msg = bux_proto::recv::<ExecIn>(r) => {
+            host_msg = host.recv::<ExecIn>(), if !host_done => {
                 match host_msg {
                     Ok(ExecIn::Stdin(data)) => {
                         let _ = pty_handle.master_write.write_all(&data).await;
@@ -209,17 +302,20 @@ async fn handle_pty(
                     Ok(ExecIn::ResizeTty(config)) => {
                         pty_handle.resize(&config);
                     }
+                    Ok(ExecIn::Ack(n)) => in_flight = in_flight.saturating_sub(n),
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => host_done = true,
                     Err(_) => {
                         let _ = unsafe { libc::kill(pid, libc::SIGKILL) };
                         break;
                     }
                 }
             }
-            n = pty_handle.master_read.read(&mut pty_buf) => {
+            n = pty_handle.master_read.read(&mut pty_buf), if window_open => {
                 match n {
                     Ok(0) | Err(_) => break,
                     Ok(len) => {
-                        bux_proto::send(w, &ExecOut::Stdout(pty_buf[..len].to_vec())).await?;
+                        send_output(w, pid, ExecOut::Stdout(pty_buf[..len].to_vec())).await?;
+                        in_flight += len as u64;
                     }
                 }
             }
@@ -229,6 +325,17 @@ async fn handle_pty(
     send_exit_by_pid(w, pid, spawn_t0, &timed_out).await
 }
 
+/// Sends an output chunk, killing the child if the host is gone.
+///
+/// After a half-close this is the only way to notice that the host went away.
+async fn send_output(w: &mut (impl AsyncWrite + Unpin), pid: i32, msg: ExecOut) -> io::Result<()> {
+    let result = bux_proto::send(w, &msg).await;
+    if result.is_err() {
+        let _ = unsafe { libc::kill(pid, libc::SIGKILL) };
+    }
+    result
+}
+
 /// Waits for a `tokio::process::Child` and sends `ExecOut::Exit`.
 async fn send_exit(
     w: &mut (impl AsyncWrite + Unpin),
@@ -237,7 +344,7 @@ async fn send_exit(
     timed_out: &AtomicBool,
 ) -> io::Result<()> {
     let status = child.wait().await?;
-    let code = status.code().unwrap_or(-1);
+    let code = status.code();
     let signal = status.signal();
 
     #[allow(clippy::cast_possible_truncation)]
@@ -271,11 +378,11 @@ async fn send_exit_by_pid(
         .map_err(io::Error::other)?;
 
     let (code, signal) = match wait_result {
-        Ok(WaitStatus::Exited(_, c)) => (c, None),
-        Ok(WaitStatus::Signaled(_, sig, _)) => (0, Some(sig as i32)),
-        // ECHILD: already reaped (SIG_IGN on SIGCHLD).
-        Err(nix::errno::Errno::ECHILD) => (0, None),
-        Ok(_) | Err(_) => (-1, None),
+        Ok(WaitStatus::Exited(_, c)) => (Some(c), None),
+        Ok(WaitStatus::Signaled(_, sig, _)) => (None, Some(sig as i32)),
+        // ECHILD: already reaped (SIG_IGN on SIGCHLD), so the status is
+        // lost; reporting neither beats claiming success.
+        Ok(_) | Err(_) => (None, None),
     };
 
     #[allow(clippy::cast_possible_truncation)]
@@ -294,20 +401,44 @@ async fn send_exit_by_pid(
     .await
 }
 
-/// Applies common exec options (cwd, env, uid, gid) to a command.
+/// Applies common exec options (cwd, env, uid, gid) to a command on top
+/// of the agent's environment ([`crate::env`]), which leaves out the boot
+/// variables ([`bux_proto::BOOT_ENVS`]).
 ///
 /// Works with both `std::process::Command` and `tokio::process::Command`
-/// since they share the same method signatures for env/cwd/pre_exec.
+/// since they share the same method signatures for arg0/env/cwd/pre_exec.
 macro_rules! apply_exec_options {
     ($cmd:expr, $req:expr) => {{
+        if let Some(ref arg0) = $req.arg0 {
+            $cmd.arg0(arg0);
+        }
         if let Some(ref cwd) = $req.cwd {
             $cmd.current_dir(cwd);
         }
+        // The agent's token, boot seed and mount table are not the
+        // command's business.
+        for var in bux_proto::BOOT_ENVS {
+            $cmd.env_remove(var);
+        }
+        for (k, v) in $crate::env::changes() {
+            match v {
+                Some(v) => $cmd.env(k, v),
+                None => $cmd.env_remove(k),
+            };
+        }
         for pair in &$req.env {
             if let Some((k, v)) = pair.split_once('=') {
                 $cmd.env(k, v);
             }
         }
+        // Names were validated in `handle` before spawning.
+        let cap_mask = $crate::exec::caps::parse(&$req.cap_drop).unwrap_or_default();
+        // The bounding set needs CAP_SETPCAP, so shrink it before setuid.
+        if cap_mask != 0 {
+            unsafe {
+                $cmd.pre_exec(move || $crate::exec::caps::drop_bounding(cap_mask));
+            }
+        }
         // Apply gid before uid — setuid would drop privilege to change gid.
         if let Some(gid) = $req.gid {
             unsafe {
@@ -329,6 +460,86 @@ macro_rules! apply_exec_options {
                 });
             }
         }
+        if cap_mask != 0 {
+            unsafe {
+                $cmd.pre_exec(move || $crate::exec::caps::drop_current(cap_mask));
+            }
+        }
+        if $req.no_new_privs {
+            unsafe {
+                $cmd.pre_exec(|| {
+                    if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
     }};
 }
 pub(crate) use apply_exec_options;
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::os::unix::process::CommandExt;
+
+    use super::*;
+
+    #[test]
+    fn arg0_reaches_symlinked_binary() {
+        // Busybox-style: a symlink whose behaviour would key off argv[0].
+        let dir = std::env::temp_dir().join(format!("bux_arg0_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let applet = dir.join("applet");
+        std::os::unix::fs::symlink("/bin/sh", &applet).unwrap();
+
+        let req = ExecStart::new(applet.to_string_lossy())
+            .args(vec![
+                "-c".into(),
+                "tr '\\0' '\\n' < /proc/$$/cmdline".into(),
+            ])
+            .arg0("-applet");
+        let mut cmd = std::process::Command::new(&req.cmd);
+        cmd.args(&req.args);
+        apply_exec_options!(&mut cmd, &req);
+        let out = cmd.output().unwrap();
+
+        let stdout = String::from_utf8(out.stdout).unwrap();
+        assert_eq!(stdout.lines().next(), Some("-applet"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn created_workdir_parents_belong_to_the_user() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = std::env::temp_dir().join(format!("bux_cwd_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let owner = |p: &Path| std::fs::metadata(p).map(|m| (m.uid(), m.gid())).unwrap();
+        // `dir` was just made, so it belongs to the test's own user. Only
+        // root may give directories away; otherwise keep that owner.
+        let before = owner(&dir);
+        let (uid, gid) = if before.0 == 0 { (4242, 4343) } else { before };
+
+        let cwd = dir.join("srv/app/data");
+        let req = ExecStart::new("/bin/true")
+            .cwd(cwd.to_string_lossy())
+            .create_cwd()
+            .user(uid, gid);
+        prepare_cwd(&req).unwrap();
+        assert!(cwd.is_dir());
+        for created in ["srv", "srv/app", "srv/app/data"] {
+            assert_eq!(owner(&dir.join(created)), (uid, gid), "{created}");
+        }
+        assert_eq!(owner(&dir), before);
+
+        // Without create_cwd a missing directory is reported, not made.
+        let missing = ExecStart::new("/bin/true").cwd(dir.join("nope").to_string_lossy());
+        assert!(prepare_cwd(&missing).is_err());
+        assert!(!dir.join("nope").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,17 +1,21 @@
-//! File transfer handlers: single-file read/write and tar-based copy.
+//! File handlers: single-file read/write, tar-based copy, and metadata operations.
 
 use std::io;
 use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering};
 
-use bux_proto::{Download, ErrorCode, ErrorInfo, STREAM_CHUNK_SIZE, UploadResult};
+use bux_proto::{Download, ErrorCode, ErrorInfo, FileSpec, HelloAck, UploadResult};
 use tokio::io::{AsyncRead, AsyncWrite};
 
 /// Monotonic counter for unique temp file names (avoids PID-only collision).
 static TEMP_SEQ: AtomicU64 = AtomicU64::new(0);
 
 /// Streams a file's contents back as [`Download`] chunks.
-pub async fn handle_read(w: &mut (impl AsyncWrite + Unpin), path: &str) -> io::Result<()> {
+pub async fn handle_read(
+    w: &mut (impl AsyncWrite + Unpin),
+    path: &str,
+    chunk_size: usize,
+) -> io::Result<()> {
     let mut file = match tokio::fs::File::open(path).await {
         Ok(f) => f,
         Err(e) => {
@@ -22,7 +26,7 @@ pub async fn handle_read(w: &mut (impl AsyncWrite + Unpin), path: &str) -> io::R
             .await;
         }
     };
-    bux_proto::send_download_from_reader(w, &mut file, STREAM_CHUNK_SIZE).await?;
+    bux_proto::send_download_from_reader(w, &mut file, chunk_size).await?;
     Ok(())
 }
 
@@ -71,6 +75,139 @@ pub async fn handle_write(
     }
 }
 
+/// Receives a file of known size into a persistent partial file, resuming
+/// from whatever an interrupted earlier upload of the same `source` left
+/// behind.
+///
+/// The partial file lives next to `path` and survives dropped connections;
+/// it is renamed into place once all `size` bytes have arrived. Its source
+/// is recorded beside it, and without a matching one it starts over.
+pub async fn handle_file_upload(
+    r: &mut (impl AsyncRead + Unpin),
+    w: &mut (impl AsyncWrite + Unpin),
+    path: &str,
+    mode: u32,
+    size: u64,
+    source: Option<&str>,
+) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tokio::io::AsyncSeekExt;
+
+    let dest = Path::new(path);
+    let Some(partial) = partial_path(dest) else {
+        let err = ErrorInfo::invalid_request(format!("{path}: not a file path"));
+        return bux_proto::send(w, &HelloAck::Error(err)).await;
+    };
+    let recorded_source = partial.with_extension("bux-source");
+
+    let opened = async {
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&partial)
+            .await?;
+        let mut offset = file.metadata().await?.len();
+        let recorded = tokio::fs::read_to_string(&recorded_source).await.ok();
+        // A partial larger than the declared size cannot be from this source.
+        if source.is_none() || recorded.as_deref() != source || offset > size {
+            file.set_len(0).await?;
+            offset = 0;
+            if let Some(tag) = source {
+                tokio::fs::write(&recorded_source, tag).await?;
+            } else if let Err(e) = tokio::fs::remove_file(&recorded_source).await
+                && e.kind() != io::ErrorKind::NotFound
+            {
+                return Err(e);
+            }
+        }
+        file.seek(io::SeekFrom::Start(offset)).await?;
+        io::Result::Ok((file, offset))
+    }
+    .await;
+    let (mut file, offset) = match opened {
+        Ok(v) => v,
+        Err(e) => return bux_proto::send(w, &HelloAck::Error(io_error_info(&e))).await,
+    };
+    bux_proto::send(w, &HelloAck::Resume { offset }).await?;
+
+    // On a broken stream the partial file is kept for the next attempt.
+    let remaining = size - offset;
+    if let Err(e) = bux_proto::recv_upload_to_writer(r, &mut file, remaining).await {
+        return bux_proto::send(w, &UploadResult::Error(io_error_info(&e))).await;
+    }
+
+    let result = async {
+        let len = file.metadata().await?.len();
+        if len != size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("expected {size} bytes, have {len}"),
+            ));
+        }
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::set_permissions(&partial, std::fs::Permissions::from_mode(mode)).await?;
+        tokio::fs::rename(&partial, dest).await?;
+        let _ = tokio::fs::remove_file(&recorded_source).await;
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => bux_proto::send(w, &UploadResult::Ok).await,
+        Err(e) => bux_proto::send(w, &UploadResult::Error(io_error_info(&e))).await,
+    }
+}
+
+/// Streams an upload into `path` at `offset` (or its end, with `append`)
+/// without truncating, replying with the bytes written.
+pub async fn handle_write_at(
+    r: &mut (impl AsyncRead + Unpin),
+    w: &mut (impl AsyncWrite + Unpin),
+    path: &str,
+    offset: u64,
+    append: bool,
+    mode: u32,
+) -> io::Result<()> {
+    use tokio::io::AsyncSeekExt;
+
+    let opened = async {
+        if let Some(parent) = Path::new(path).parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .append(append)
+            .create(true)
+            .truncate(false)
+            .mode(mode)
+            .open(path)
+            .await?;
+        if !append {
+            file.seek(io::SeekFrom::Start(offset)).await?;
+        }
+        io::Result::Ok(file)
+    }
+    .await;
+    let mut file = match opened {
+        Ok(f) => f,
+        Err(e) => return bux_proto::send(w, &HelloAck::Error(io_error_info(&e))).await,
+    };
+    bux_proto::send(w, &HelloAck::Ready).await?;
+
+    let reply =
+        match bux_proto::recv_upload_to_writer(r, &mut file, bux_proto::MAX_UPLOAD_BYTES).await {
+            Ok(written) => UploadResult::Written(written),
+            Err(e) => UploadResult::Error(io_error_info(&e)),
+        };
+    bux_proto::send(w, &reply).await
+}
+
 /// Receives a tar archive from the host and extracts it into `dest`.
 ///
 /// Validates each entry to reject path-traversal attacks.
@@ -78,6 +215,7 @@ pub async fn handle_copy_in(
     r: &mut (impl AsyncRead + Unpin),
     w: &mut (impl AsyncWrite + Unpin),
     dest: &str,
+    same_owner: bool,
 ) -> io::Result<()> {
     let temp_path = match recv_upload_to_file(r).await {
         Ok(p) => p,
@@ -100,6 +238,7 @@ pub async fn handle_copy_in(
         let file = std::fs::File::open(&tp)?;
         let mut archive = tar::Archive::new(file);
         archive.set_preserve_permissions(true);
+        archive.set_preserve_ownerships(same_owner);
         for raw_entry in archive.entries()? {
             let mut entry = raw_entry?;
             let path = entry.path()?.into_owned();
@@ -134,11 +273,167 @@ pub async fn handle_copy_in(
     }
 }
 
+/// Receives a batch of files as one upload stream and applies it as a unit.
+///
+/// Every file is staged next to its destination first; only when all are
+/// staged are they renamed into place. If a rename fails, files already
+/// replaced are restored, so the batch lands entirely or not at all. Errors
+/// name the file that failed.
+pub async fn handle_write_files(
+    r: &mut (impl AsyncRead + Unpin),
+    w: &mut (impl AsyncWrite + Unpin),
+    files: Vec<FileSpec>,
+) -> io::Result<()> {
+    let temp_path = match recv_upload_to_file(r).await {
+        Ok(p) => p,
+        Err(e) => {
+            return bux_proto::send(
+                w,
+                &UploadResult::Error(ErrorInfo::new(ErrorCode::Internal, e.to_string())),
+            )
+            .await;
+        }
+    };
+
+    let tp = temp_path.clone();
+    let result = tokio::task::spawn_blocking(move || stage_and_commit(&tp, &files))
+        .await
+        .map_err(io::Error::other)?;
+
+    let _ = tokio::fs::remove_file(&temp_path).await;
+
+    match result {
+        Ok(()) => bux_proto::send(w, &UploadResult::Ok).await,
+        Err(info) => bux_proto::send(w, &UploadResult::Error(info)).await,
+    }
+}
+
+/// Splits the concatenated upload at `src` into staged files, then renames
+/// them all into place. Staged files are removed if any step fails, and
+/// destinations already replaced are rolled back.
+fn stage_and_commit(src: &Path, files: &[FileSpec]) -> Result<(), ErrorInfo> {
+    use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
+
+    let fail = |path: &str, e: &io::Error| ErrorInfo::new(io_error_code(e), format!("{path}: {e}"));
+
+    let total = files.iter().map(|f| f.len).sum::<u64>();
+    let mut input = std::fs::File::open(src).map_err(|e| ErrorInfo::internal(e.to_string()))?;
+    let received = input
+        .metadata()
+        .map_err(|e| ErrorInfo::internal(e.to_string()))?
+        .len();
+    if received != total {
+        return Err(ErrorInfo::invalid_request(format!(
+            "upload size mismatch: headers declare {total} bytes, received {received}"
+        )));
+    }
+
+    let mut staged: Vec<(std::path::PathBuf, &str)> = Vec::with_capacity(files.len());
+    let cleanup = |batch: &[(std::path::PathBuf, &str)]| {
+        for (tmp, _) in batch {
+            let _ = std::fs::remove_file(tmp);
+        }
+    };
+
+    for spec in files {
+        let dest = Path::new(&spec.path);
+        let Some(staged_path) = staging_path(dest) else {
+            cleanup(&staged);
+            return Err(ErrorInfo::invalid_request(format!(
+                "{}: not a file path",
+                spec.path
+            )));
+        };
+        let result = (|| -> io::Result<()> {
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut out = std::fs::File::create(&staged_path)?;
+            let copied = io::copy(&mut (&mut input).take(spec.len), &mut out)?;
+            if copied != spec.len {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "upload stream ended early",
+                ));
+            }
+            out.set_permissions(std::fs::Permissions::from_mode(spec.mode))?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&staged_path);
+            cleanup(&staged);
+            return Err(fail(&spec.path, &e));
+        }
+        staged.push((staged_path, spec.path.as_str()));
+    }
+
+    // Each replaced destination is hard-linked aside first, so the old
+    // contents stay reachable until the whole batch is in place.
+    let mut committed: Vec<(&str, Option<std::path::PathBuf>)> = Vec::with_capacity(staged.len());
+    for (i, (tmp, path)) in staged.iter().enumerate() {
+        let result = backup(Path::new(path)).and_then(|kept| match std::fs::rename(tmp, path) {
+            Ok(()) => Ok(kept),
+            Err(e) => {
+                if let Some(ref aside) = kept {
+                    let _ = std::fs::remove_file(aside);
+                }
+                Err(e)
+            }
+        });
+        match result {
+            Ok(kept) => committed.push((path, kept)),
+            Err(e) => {
+                cleanup(&staged[i..]);
+                for (done, kept) in committed.into_iter().rev() {
+                    let _ = match kept {
+                        Some(aside) => std::fs::rename(aside, done),
+                        None => std::fs::remove_file(done),
+                    };
+                }
+                return Err(fail(path, &e));
+            }
+        }
+    }
+    for aside in committed.into_iter().filter_map(|(_, kept)| kept) {
+        let _ = std::fs::remove_file(aside);
+    }
+    Ok(())
+}
+
+/// Hard-links an existing file at `dest` to a hidden backup path beside it.
+/// Returns `None` if there is nothing to keep.
+fn backup(dest: &Path) -> io::Result<Option<std::path::PathBuf>> {
+    let Some(old) = staging_path(dest) else {
+        return Ok(None);
+    };
+    match std::fs::hard_link(dest, &old) {
+        Ok(()) => Ok(Some(old)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns a hidden staging path in the same directory as `dest`, so the
+/// final rename stays on one filesystem.
+fn staging_path(dest: &Path) -> Option<std::path::PathBuf> {
+    let name = dest.file_name()?.to_string_lossy();
+    let seq = TEMP_SEQ.fetch_add(1, Ordering::Relaxed);
+    Some(dest.with_file_name(format!(".{name}.bux-{}-{seq}", std::process::id())))
+}
+
+/// Returns the resumable partial-file path for `dest`, in the same directory.
+fn partial_path(dest: &Path) -> Option<std::path::PathBuf> {
+    let name = dest.file_name()?.to_string_lossy();
+    Some(dest.with_file_name(format!(".{name}.bux-partial")))
+}
+
 /// Packs a path into a tar archive and streams it as [`Download`] chunks.
 pub async fn handle_copy_out(
     w: &mut (impl AsyncWrite + Unpin),
     path: &str,
     follow_symlinks: bool,
+    chunk_size: usize,
 ) -> io::Result<()> {
     let owned_path = path.to_owned();
     let temp_path = temp_file_path("download");
@@ -171,8 +466,7 @@ pub async fn handle_copy_out(
         Ok(()) => {
             // Stream from file — O(chunk_size) memory instead of loading entire tar.
             let mut file = tokio::fs::File::open(&temp_path).await?;
-            let send_result =
-                bux_proto::send_download_from_reader(w, &mut file, STREAM_CHUNK_SIZE).await;
+            let send_result = bux_proto::send_download_from_reader(w, &mut file, chunk_size).await;
             let _ = tokio::fs::remove_file(&temp_path).await;
             send_result.map(|_| ())
         }
@@ -187,6 +481,227 @@ pub async fn handle_copy_out(
     }
 }
 
+/// Replies with metadata for `path` (following symlinks).
+pub async fn handle_stat(w: &mut (impl AsyncWrite + Unpin), path: &str) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let ack = match tokio::fs::metadata(path).await {
+        Ok(meta) => HelloAck::Stat {
+            size: meta.len(),
+            mode: meta.mode(),
+            is_dir: meta.is_dir(),
+            uid: meta.uid(),
+            gid: meta.gid(),
+            mtime: meta.mtime(),
+        },
+        Err(e) => HelloAck::Error(io_error_info(&e)),
+    };
+    bux_proto::send(w, &ack).await
+}
+
+/// Changes the permission bits of `path`.
+pub async fn handle_chmod(
+    w: &mut (impl AsyncWrite + Unpin),
+    path: &str,
+    mode: u32,
+) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let result = tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await;
+    send_done(w, result).await
+}
+
+/// Changes the owner and/or group of `path` (without following symlinks).
+pub async fn handle_chown(
+    w: &mut (impl AsyncWrite + Unpin),
+    path: &str,
+    uid: Option<u32>,
+    gid: Option<u32>,
+) -> io::Result<()> {
+    let owned = path.to_owned();
+    let result = tokio::task::spawn_blocking(move || std::os::unix::fs::lchown(owned, uid, gid))
+        .await
+        .map_err(io::Error::other)?;
+    send_done(w, result).await
+}
+
+/// Sets the access and modification times of `path` (following symlinks).
+pub async fn handle_utimes(
+    w: &mut (impl AsyncWrite + Unpin),
+    path: &str,
+    atime: i64,
+    mtime: i64,
+) -> io::Result<()> {
+    let owned = path.to_owned();
+    let result = tokio::task::spawn_blocking(move || {
+        let times = std::fs::FileTimes::new()
+            .set_accessed(epoch_secs(atime))
+            .set_modified(epoch_secs(mtime));
+        std::fs::File::open(owned)?.set_times(times)
+    })
+    .await
+    .map_err(io::Error::other)?;
+    send_done(w, result).await
+}
+
+/// Converts (possibly negative) seconds since the Unix epoch to a time.
+fn epoch_secs(secs: i64) -> std::time::SystemTime {
+    let offset = std::time::Duration::from_secs(secs.unsigned_abs());
+    if secs < 0 {
+        std::time::UNIX_EPOCH - offset
+    } else {
+        std::time::UNIX_EPOCH + offset
+    }
+}
+
+/// Creates a directory, optionally with missing parents.
+pub async fn handle_mkdir(
+    w: &mut (impl AsyncWrite + Unpin),
+    path: &str,
+    mode: u32,
+    parents: bool,
+) -> io::Result<()> {
+    let result = tokio::fs::DirBuilder::new()
+        .recursive(parents)
+        .mode(mode)
+        .create(path)
+        .await;
+    send_done(w, result).await
+}
+
+/// Moves `from` to `to`, copying and removing when they are on different
+/// filesystems.
+pub async fn handle_rename(
+    w: &mut (impl AsyncWrite + Unpin),
+    from: &str,
+    to: &str,
+) -> io::Result<()> {
+    let result = match tokio::fs::symlink_metadata(from).await {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("rename source {from} does not exist"),
+        )),
+        Err(e) => Err(e),
+        Ok(_) => match tokio::fs::rename(from, to).await {
+            Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+                move_across_filesystems(Path::new(from), Path::new(to)).await
+            }
+            other => other,
+        },
+    };
+    send_done(w, result).await
+}
+
+/// Creates a symlink at `link` pointing to `target`.
+pub async fn handle_symlink(
+    w: &mut (impl AsyncWrite + Unpin),
+    target: &str,
+    link: &str,
+) -> io::Result<()> {
+    send_done(w, tokio::fs::symlink(target, link).await).await
+}
+
+/// Replies with the target of the symlink at `path`.
+pub async fn handle_readlink(w: &mut (impl AsyncWrite + Unpin), path: &str) -> io::Result<()> {
+    let ack = match tokio::fs::read_link(path).await {
+        Ok(target) => HelloAck::Path(target.to_string_lossy().into_owned()),
+        Err(e) => HelloAck::Error(io_error_info(&e)),
+    };
+    bux_proto::send(w, &ack).await
+}
+
+/// Copies `from` to a temporary name beside `to`, renames it into place,
+/// then removes `from`.
+async fn move_across_filesystems(from: &Path, to: &Path) -> io::Result<()> {
+    let Some(staging) = staging_path(to) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: not a file path", to.display()),
+        ));
+    };
+    let (source, target) = (from.to_path_buf(), to.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) =
+            copy_tree(&source, &staging).and_then(|()| std::fs::rename(&staging, &target))
+        {
+            let _ = remove_tree(&staging);
+            return Err(e);
+        }
+        remove_tree(&source)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Copies a file, symlink or directory tree, keeping owners and modes.
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let meta = std::fs::symlink_metadata(from)?;
+    let file_type = meta.file_type();
+    if file_type.is_symlink() {
+        std::os::unix::fs::symlink(std::fs::read_link(from)?, to)?;
+    } else if file_type.is_dir() {
+        std::fs::create_dir(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let child = entry?;
+            copy_tree(&child.path(), &to.join(child.file_name()))?;
+        }
+    } else if file_type.is_file() {
+        std::fs::copy(from, to)?;
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "cannot move special file {} across filesystems",
+                from.display()
+            ),
+        ));
+    }
+    // Owner first: changing it clears the setuid and setgid bits.
+    std::os::unix::fs::lchown(to, Some(meta.uid()), Some(meta.gid()))?;
+    if !file_type.is_symlink() {
+        std::fs::set_permissions(to, std::fs::Permissions::from_mode(meta.mode() & 0o7777))?;
+    }
+    Ok(())
+}
+
+/// Removes a file, symlink or directory tree.
+fn remove_tree(path: &Path) -> io::Result<()> {
+    if std::fs::symlink_metadata(path)?.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+/// Replies to a single-shot operation with [`HelloAck::Done`] or the mapped error.
+pub async fn send_done(
+    w: &mut (impl AsyncWrite + Unpin),
+    result: io::Result<()>,
+) -> io::Result<()> {
+    let ack = match result {
+        Ok(()) => HelloAck::Done,
+        Err(e) => HelloAck::Error(io_error_info(&e)),
+    };
+    bux_proto::send(w, &ack).await
+}
+
+/// Wraps an I/O error as [`ErrorInfo`] with the closest protocol code.
+fn io_error_info(e: &io::Error) -> ErrorInfo {
+    ErrorInfo::new(io_error_code(e), e.to_string())
+}
+
+/// Maps an I/O error onto the closest protocol [`ErrorCode`].
+fn io_error_code(e: &io::Error) -> ErrorCode {
+    match e.kind() {
+        io::ErrorKind::NotFound => ErrorCode::NotFound,
+        io::ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+        io::ErrorKind::InvalidInput | io::ErrorKind::AlreadyExists => ErrorCode::InvalidRequest,
+        _ => ErrorCode::Internal,
+    }
+}
+
 /// Receives [`Upload`] chunks and streams them directly to a temp file.
 ///
 /// Uses `recv_upload_to_writer` so memory usage is O(chunk_size) regardless
@@ -208,3 +723,261 @@ fn temp_file_path(tag: &str) -> std::path::PathBuf {
     let seq = TEMP_SEQ.fetch_add(1, Ordering::Relaxed);
     Path::new("/tmp").join(format!("bux-{tag}-{}-{seq}", std::process::id()))
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    /// Runs [`handle_write_at`] on `data` and returns the guest's final reply.
+    async fn write_at(path: &Path, offset: u64, append: bool, data: &[u8]) -> UploadResult {
+        let mut upload = Vec::new();
+        bux_proto::send_upload(&mut upload, data, 4).await.unwrap();
+        let mut wire = Vec::new();
+        let guest_path = path.to_str().unwrap();
+        handle_write_at(
+            &mut upload.as_slice(),
+            &mut wire,
+            guest_path,
+            offset,
+            append,
+            0o600,
+        )
+        .await
+        .unwrap();
+
+        let mut reply = wire.as_slice();
+        let ack: HelloAck = bux_proto::recv(&mut reply).await.unwrap();
+        assert!(matches!(ack, HelloAck::Ready));
+        bux_proto::recv(&mut reply).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn writes_at_offsets_and_appends_in_place() {
+        let dir = std::env::temp_dir().join(format!("bux_write_at_{}", std::process::id()));
+        let path = dir.join("sub/app.log");
+
+        let created = write_at(&path, 0, false, b"hello world\n").await;
+        assert!(matches!(created, UploadResult::Written(12)));
+        let patched = write_at(&path, 6, false, b"there").await;
+        assert!(matches!(patched, UploadResult::Written(5)));
+        let appended = write_at(&path, 0, true, b"bye\n").await;
+        assert!(matches!(appended, UploadResult::Written(4)));
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello there\nbye\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Runs [`handle_file_upload`] of a `size`-byte file with the host
+    /// streaming `sent`, and returns the guest's offset and final reply.
+    async fn upload(
+        path: &Path,
+        size: u64,
+        source: Option<&str>,
+        sent: &[u8],
+    ) -> (u64, UploadResult) {
+        let mut stream = Vec::new();
+        bux_proto::send_upload(&mut stream, sent, 4).await.unwrap();
+        let mut wire = Vec::new();
+        let guest_path = path.to_str().unwrap();
+        handle_file_upload(
+            &mut stream.as_slice(),
+            &mut wire,
+            guest_path,
+            0o644,
+            size,
+            source,
+        )
+        .await
+        .unwrap();
+
+        let mut reply = wire.as_slice();
+        let HelloAck::Resume { offset } = bux_proto::recv(&mut reply).await.unwrap() else {
+            unreachable!("expected HelloAck::Resume");
+        };
+        (offset, bux_proto::recv(&mut reply).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn uploads_resume_only_from_the_same_source() {
+        let dir = std::env::temp_dir().join(format!("bux_upload_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.bin");
+        let partial = partial_path(&path).unwrap();
+        let recorded = partial.with_extension("bux-source");
+
+        // Bytes of unknown origin are dropped.
+        std::fs::write(&partial, b"HELLO").unwrap();
+        let (offset, done) = upload(&path, 10, Some("v1"), b"helloworld").await;
+        assert_eq!(offset, 0);
+        assert!(matches!(done, UploadResult::Ok));
+        assert_eq!(std::fs::read(&path).unwrap(), b"helloworld");
+        assert!(!partial.exists() && !recorded.exists());
+
+        std::fs::write(&partial, b"hello").unwrap();
+        std::fs::write(&recorded, "v1").unwrap();
+        let (resumed, finished) = upload(&path, 10, Some("v1"), b"WORLD").await;
+        assert_eq!(resumed, 5);
+        assert!(matches!(finished, UploadResult::Ok));
+        assert_eq!(std::fs::read(&path).unwrap(), b"helloWORLD");
+
+        // Another source, or none, starts over.
+        for source in [Some("v2"), None] {
+            std::fs::write(&partial, b"HELLO").unwrap();
+            std::fs::write(&recorded, "v1").unwrap();
+            let (restarted, replaced) = upload(&path, 10, source, b"helloworld").await;
+            assert_eq!(restarted, 0);
+            assert!(matches!(replaced, UploadResult::Ok));
+            assert_eq!(std::fs::read(&path).unwrap(), b"helloworld");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_files_rolls_back_a_failed_batch() {
+        let dir = std::env::temp_dir().join(format!("bux_write_files_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let first = dir.join("app.conf");
+        let added = dir.join("new.conf");
+        std::fs::write(&first, b"old").unwrap();
+        let upload = dir.join("upload");
+        std::fs::write(&upload, b"newnew2dir").unwrap();
+        let spec = |path: &Path, len| FileSpec::new(path.to_str().unwrap(), 0o644, len);
+
+        // The last destination is a directory, so its rename fails after
+        // the first two files were already replaced.
+        let files = [
+            spec(&first, 3),
+            spec(&added, 4),
+            spec(&dir.join("conf.d"), 3),
+        ];
+        let err = stage_and_commit(&upload, &files).unwrap_err();
+        assert!(err.message.contains("conf.d"));
+        assert_eq!(std::fs::read(&first).unwrap(), b"old");
+        assert!(!added.exists());
+        let mut left: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["app.conf", "conf.d", "upload"]);
+
+        // A batch that fits lands whole and leaves no backups behind.
+        std::fs::write(&upload, b"newnew2").unwrap();
+        stage_and_commit(&upload, &files[..2]).unwrap();
+        assert_eq!(std::fs::read(&first).unwrap(), b"new");
+        assert_eq!(std::fs::read(&added).unwrap(), b"new2");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 4);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Runs [`handle_rename`] and returns the guest's reply.
+    async fn rename(from: &Path, to: &Path) -> HelloAck {
+        let mut wire = Vec::new();
+        handle_rename(&mut wire, from.to_str().unwrap(), to.to_str().unwrap())
+            .await
+            .unwrap();
+        bux_proto::recv(&mut wire.as_slice()).await.unwrap()
+    }
+
+    /// Builds `app/` with a private subdirectory, a setgid file and a
+    /// symlink under `dir`.
+    fn make_tree(dir: &Path) -> std::path::PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let app = dir.join("app");
+        std::fs::create_dir_all(app.join("conf")).unwrap();
+        std::fs::write(app.join("conf/app.toml"), b"port = 80").unwrap();
+        std::os::unix::fs::symlink("conf/app.toml", app.join("current")).unwrap();
+        let mode = |path: &Path, mode| {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+        };
+        mode(&app.join("conf/app.toml"), 0o2640);
+        mode(&app.join("conf"), 0o750);
+        app
+    }
+
+    /// The owner new files get, as `(uid, gid)`.
+    fn own_ids(dir: &Path) -> (u32, u32) {
+        use std::os::unix::fs::MetadataExt;
+
+        let meta = std::fs::metadata(dir).unwrap();
+        (meta.uid(), meta.gid())
+    }
+
+    /// Checks that `app` holds what [`make_tree`] built, owned by `owner`.
+    fn assert_tree(app: &Path, owner: (u32, u32)) {
+        use std::os::unix::fs::MetadataExt;
+
+        let meta = |path: &str| std::fs::symlink_metadata(app.join(path)).unwrap();
+        assert_eq!(std::fs::read(app.join("current")).unwrap(), b"port = 80");
+        assert_eq!(
+            std::fs::read_link(app.join("current")).unwrap(),
+            Path::new("conf/app.toml")
+        );
+        assert_eq!(meta("conf").mode() & 0o7777, 0o750);
+        let file = meta("conf/app.toml");
+        assert_eq!(file.mode() & 0o7777, 0o2640);
+        assert_eq!((file.uid(), file.gid()), owner);
+    }
+
+    #[tokio::test]
+    async fn rename_moves_trees_within_a_filesystem() {
+        let dir = std::env::temp_dir().join(format!("bux_rename_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let app = make_tree(&dir);
+        let owner = own_ids(&app);
+
+        let moved = dir.join("moved");
+        assert!(matches!(rename(&app, &moved).await, HelloAck::Done));
+        assert!(!app.exists());
+        assert_tree(&moved, owner);
+        assert!(matches!(
+            rename(&app, &moved).await,
+            HelloAck::Error(e) if e.code == ErrorCode::NotFound
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rename_copies_trees_across_filesystems() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = std::env::temp_dir().join(format!("bux_rename_xdev_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let app = make_tree(&dir);
+        // Root can show that owners other than its own survive.
+        let owner = if own_ids(&app).0 == 0 {
+            std::os::unix::fs::lchown(app.join("conf/app.toml"), Some(1234), Some(5678)).unwrap();
+            std::fs::set_permissions(
+                app.join("conf/app.toml"),
+                std::os::unix::fs::PermissionsExt::from_mode(0o2640),
+            )
+            .unwrap();
+            (1234, 5678)
+        } else {
+            own_ids(&app)
+        };
+
+        // The fallback itself, which works on one filesystem too.
+        let copied = dir.join("copied");
+        move_across_filesystems(&app, &copied).await.unwrap();
+        assert!(!app.exists());
+        assert_tree(&copied, owner);
+        let leftovers = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(leftovers, 1, "staging copy left behind");
+
+        // A real cross-device move, where a tmpfs is at hand.
+        let shm = Path::new("/dev/shm").join(format!("bux_rename_{}", std::process::id()));
+        let other_fs = std::fs::metadata("/dev/shm")
+            .is_ok_and(|m| m.dev() != std::fs::metadata(&dir).unwrap().dev());
+        if other_fs {
+            assert!(matches!(rename(&copied, &shm).await, HelloAck::Done));
+            assert!(!copied.exists());
+            assert_tree(&shm, owner);
+            std::fs::remove_dir_all(&shm).unwrap();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
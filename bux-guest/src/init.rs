@@ -0,0 +1,219 @@
+//! User init command run once at boot, before the agent serves requests,
+//! and the workload started once it does, or handed to the init that takes
+//! over PID 1.
+
+use std::ffi::CString;
+use std::io;
+use std::sync::OnceLock;
+
+use bux_proto::{ErrorInfo, GuestInit, INIT_ENV, MAIN_ENV, PID1_ENV};
+use nix::unistd::ForkResult;
+
+/// Argument the agent re-executes itself with after handing PID 1 over.
+const HANDED_OFF_ARG: &str = "--pid1-handed-off";
+
+/// PID of the command started by [`spawn_main`].
+static MAIN_PID: OnceLock<i32> = OnceLock::new();
+
+/// Runs the command in [`INIT_ENV`] to completion, if one was given.
+///
+/// Its stdio is inherited, so output goes to the VM console. A failure is
+/// returned (aborting boot) unless the host marked the command as allowed
+/// to fail.
+pub async fn run() -> io::Result<()> {
+    let Ok(value) = std::env::var(INIT_ENV) else {
+        return Ok(());
+    };
+    let Some(init) = GuestInit::decode(&value) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("malformed {INIT_ENV}: {value}"),
+        ));
+    };
+    let Some((program, args)) = init.argv.split_first() else {
+        return Ok(());
+    };
+
+    eprintln!("[bux-guest] running init: {}", init.argv.join(" "));
+    let result = command(program)
+        .args(args)
+        .status()
+        .await
+        .and_then(|status| {
+            if status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::other(format!("init {program} {status}")))
+            }
+        });
+    match result {
+        Err(e) if init.may_fail => {
+            eprintln!("[bux-guest] {e}; continuing boot");
+            Ok(())
+        }
+        other => other,
+    }
+}
+
+/// Starts the command in [`MAIN_ENV`], if one was given, and shuts the VM
+/// down with its exit status once it ends.
+///
+/// Set when the host boots the agent in place of the VM's own command, so
+/// the VM lives exactly as long as that command would have.
+pub fn spawn_main() -> io::Result<()> {
+    let Ok(value) = std::env::var(MAIN_ENV) else {
+        return Ok(());
+    };
+    let Some(mut args) = bux_proto::decode_argv(&value) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("malformed {MAIN_ENV}: {value}"),
+        ));
+    };
+    // `decode_argv` never returns an empty vector.
+    let program = args.remove(0);
+
+    eprintln!("[bux-guest] starting {program}");
+    let mut child = command(&program).args(&args).spawn()?;
+    if let Some(pid) = child.id() {
+        #[allow(clippy::cast_possible_wrap)]
+        MAIN_PID.set(pid as i32).ok();
+    }
+    tokio::spawn(async move {
+        let code = match child.wait().await {
+            Ok(status) => status
+                .code()
+                .or_else(|| {
+                    use std::os::unix::process::ExitStatusExt;
+                    status.signal().map(|sig| 128 + sig)
+                })
+                .unwrap_or(1),
+            Err(e) => {
+                eprintln!("[bux-guest] waiting for {program}: {e}");
+                1
+            }
+        };
+        eprintln!("[bux-guest] {program} exited with {code}; shutting down");
+        crate::control::graceful_shutdown(code);
+    });
+    Ok(())
+}
+
+/// Whether this agent serves beside an init it handed PID 1 to.
+pub fn handed_off() -> bool {
+    /// Read once from the command line.
+    static HANDED_OFF: OnceLock<bool> = OnceLock::new();
+    *HANDED_OFF.get_or_init(|| std::env::args().any(|arg| arg == HANDED_OFF_ARG))
+}
+
+/// Hands PID 1 to the init in [`PID1_ENV`], if one was given.
+///
+/// Forks: this process (PID 1) execs the init with the [`MAIN_ENV`]
+/// command appended, and the child re-executes the agent, which skips the
+/// boot steps already done and serves. Returns when there is nothing to
+/// hand off, or with the error that kept PID 1 from being handed over.
+/// Must run before the agent starts any threads.
+pub fn hand_off_pid1() -> io::Result<()> {
+    let Ok(value) = std::env::var(PID1_ENV) else {
+        return Ok(());
+    };
+    let malformed = |var: &str, bad: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("malformed {var}: {bad}"),
+        )
+    };
+    let mut argv = bux_proto::decode_argv(&value).ok_or_else(|| malformed(PID1_ENV, &value))?;
+    if let Ok(main) = std::env::var(MAIN_ENV) {
+        argv.extend(bux_proto::decode_argv(&main).ok_or_else(|| malformed(MAIN_ENV, &main))?);
+    }
+
+    // Everything is prepared up front: between fork and exec the child
+    // only calls `execve`.
+    let init_argv = c_strings(&argv)?;
+    let init_env = env_without(&bux_proto::BOOT_ENVS)?;
+    let agent_argv = c_strings(["bux-guest", HANDED_OFF_ARG])?;
+    let agent_env = env_without(&[INIT_ENV, MAIN_ENV, PID1_ENV, bux_proto::MOUNTS_ENV])?;
+
+    eprintln!("[bux-guest] handing PID 1 to {}", argv.join(" "));
+    // SAFETY: the agent is still single-threaded, and the child only execs.
+    match unsafe { nix::unistd::fork() }? {
+        ForkResult::Child => {
+            let _ = nix::unistd::execve(c"/proc/self/exe", &agent_argv, &agent_env);
+            unsafe { libc::_exit(127) }
+        }
+        ForkResult::Parent { .. } => {
+            let Err(e) = nix::unistd::execvpe(&init_argv[0], &init_argv, &init_env);
+            Err(io::Error::new(
+                io::Error::from(e).kind(),
+                format!("exec {}: {e}", argv[0]),
+            ))
+        }
+    }
+}
+
+/// Converts `args` for `exec`.
+fn c_strings(args: impl IntoIterator<Item = impl AsRef<str>>) -> io::Result<Vec<CString>> {
+    args.into_iter()
+        .map(|arg| {
+            CString::new(arg.as_ref()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        })
+        .collect()
+}
+
+/// A command for `program` that inherits the agent's environment and
+/// working directory, minus the boot variables ([`bux_proto::BOOT_ENVS`]).
+fn command(program: &str) -> tokio::process::Command {
+    let mut cmd = tokio::process::Command::new(program);
+    for var in bux_proto::BOOT_ENVS {
+        cmd.env_remove(var);
+    }
+    cmd
+}
+
+/// The agent's environment as `KEY=VALUE` entries, leaving out `remove`.
+fn env_without(remove: &[&str]) -> io::Result<Vec<CString>> {
+    let vars: Vec<String> = std::env::vars()
+        .filter(|(key, _)| !remove.contains(&key.as_str()))
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+    c_strings(vars)
+}
+
+/// Delivers `signal` to the command started by [`spawn_main`], or after a
+/// handoff to the init on PID 1, which forwards it to the command.
+pub fn signal_main(signal: i32) -> Result<(), ErrorInfo> {
+    let pid = if handed_off() {
+        1
+    } else if let Some(&pid) = MAIN_PID.get() {
+        pid
+    } else {
+        return Err(ErrorInfo::not_found("no primary process"));
+    };
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        Ok(())
+    } else {
+        let e = io::Error::last_os_error();
+        Err(ErrorInfo::invalid_request(format!("signal {signal}: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn started_commands_leave_out_every_boot_variable() {
+        let cmd = command("true");
+        let removed: Vec<_> = cmd
+            .as_std()
+            .get_envs()
+            .filter(|(_, value)| value.is_none())
+            .map(|(key, _)| key.to_string_lossy().into_owned())
+            .collect();
+        for var in bux_proto::BOOT_ENVS {
+            assert!(removed.iter().any(|k| k == var), "{var}");
+        }
+        assert_eq!(cmd.as_std().get_current_dir(), None);
+    }
+}
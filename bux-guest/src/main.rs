@@ -12,10 +12,18 @@ fn main() {
 #[cfg(target_os = "linux")]
 mod control;
 #[cfg(target_os = "linux")]
+mod diff;
+#[cfg(target_os = "linux")]
+mod entropy;
+#[cfg(target_os = "linux")]
+mod env;
+#[cfg(target_os = "linux")]
 mod exec;
 #[cfg(target_os = "linux")]
 mod files;
 #[cfg(target_os = "linux")]
+mod init;
+#[cfg(target_os = "linux")]
 mod mounts;
 #[cfg(target_os = "linux")]
 mod server;
@@ -1,9 +1,13 @@
-//! Essential tmpfs mounts and filesystem freeze/thaw operations.
+//! Essential tmpfs mounts, runtime mount/unmount requests, and filesystem
+//! freeze/thaw operations.
 
 use std::fs;
+use std::io;
 use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io::AsRawFd;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use tokio::io::AsyncWrite;
 
 /// Tmpfs mount specification.
 struct TmpfsMount {
@@ -57,6 +61,33 @@ struct TmpfsMount {
     "virtiofs",
 ];
 
+/// Trees [`Hello::Mount`](bux_proto::Hello::Mount) refuses to mount
+/// anywhere in (and [`Hello::Unmount`](bux_proto::Hello::Unmount) to
+/// unmount from): the agent, the guest's programs and their configuration
+/// live there, and the kernel manages `/proc`, `/sys` and `/dev`. `/`
+/// itself is protected too, but not the rest of the tree under it.
+const PROTECTED_TREES: &[&str] = &[
+    "/bin", "/boot", "/dev", "/etc", "/lib", "/lib64", "/proc", "/run", "/sbin", "/sys", "/tmp",
+    "/usr", "/var",
+];
+
+/// Mount options applied as `mount(2)` flags rather than passed on to the
+/// filesystem.
+const MOUNT_FLAGS: &[(&str, libc::c_ulong)] = &[
+    ("ro", libc::MS_RDONLY),
+    ("rw", 0),
+    ("defaults", 0),
+    ("nosuid", libc::MS_NOSUID),
+    ("nodev", libc::MS_NODEV),
+    ("noexec", libc::MS_NOEXEC),
+    ("noatime", libc::MS_NOATIME),
+    ("nodiratime", libc::MS_NODIRATIME),
+    ("relatime", libc::MS_RELATIME),
+    ("sync", libc::MS_SYNCHRONOUS),
+    ("bind", libc::MS_BIND),
+    ("rbind", libc::MS_BIND | libc::MS_REC),
+];
+
 // Linux ioctl constants for filesystem freeze/thaw.
 // Defined in include/uapi/linux/fs.h:
 //   #define FIFREEZE  _IOWR('X', 119, int)  = 0xC0045877
@@ -69,7 +100,7 @@ struct TmpfsMount {
 /// Mounts essential tmpfs directories early during boot.
 pub fn mount_essential_tmpfs() {
     for m in TMPFS_MOUNTS {
-        let path = std::path::Path::new(m.path);
+        let path = Path::new(m.path);
 
         // Skip if already tmpfs.
         if is_tmpfs(m.path) {
@@ -102,6 +133,195 @@ pub fn mount_essential_tmpfs() {
     }
 }
 
+/// Mounts the virtio-fs shares listed in [`bux_proto::MOUNTS_ENV`].
+///
+/// Failures are logged and skipped so one bad share does not keep the
+/// agent from coming up.
+pub fn mount_shares() {
+    let Ok(table) = std::env::var(bux_proto::MOUNTS_ENV) else {
+        return;
+    };
+    for share in bux_proto::ShareMount::decode(&table) {
+        if let Err(e) = mount_share(&share) {
+            eprintln!("[bux-guest] mount {} at {}: {e}", share.tag, share.path);
+        }
+    }
+}
+
+/// Re-enters the working directory by path.
+///
+/// The VM's workdir is entered before the agent mounts anything, so when
+/// a tmpfs or share lands on it (`-v ./src:/src -w /src`) the agent would
+/// otherwise keep the directory underneath, and so would the commands it
+/// starts without a `cwd` of their own.
+pub fn reenter_workdir() {
+    if let Err(e) = std::env::current_dir().and_then(std::env::set_current_dir) {
+        eprintln!("[bux-guest] re-entering workdir: {e}");
+    }
+}
+
+/// Creates the mount point and mounts one virtio-fs share.
+fn mount_share(share: &bux_proto::ShareMount) -> io::Result<()> {
+    fs::create_dir_all(&share.path)?;
+    let tag = std::ffi::CString::new(share.tag.as_str())?;
+    let target = std::ffi::CString::new(share.path.as_str())?;
+    let flags = if share.read_only { libc::MS_RDONLY } else { 0 };
+
+    // SAFETY: all pointers are valid NUL-terminated strings for the call.
+    let ret = unsafe {
+        libc::mount(
+            tag.as_ptr(),
+            target.as_ptr(),
+            c"virtiofs".as_ptr(),
+            flags,
+            std::ptr::null(),
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Handles [`Hello::Mount`](bux_proto::Hello::Mount).
+pub async fn handle_mount(
+    w: &mut (impl AsyncWrite + Unpin),
+    source: String,
+    target: String,
+    fstype: String,
+    options: Vec<String>,
+) -> io::Result<()> {
+    // Mounting may wait on the device or the host side of a share.
+    let result = tokio::task::spawn_blocking(move || mount(&source, &target, &fstype, &options))
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)));
+    crate::files::send_done(w, result).await
+}
+
+/// Handles [`Hello::Unmount`](bux_proto::Hello::Unmount).
+pub async fn handle_unmount(w: &mut (impl AsyncWrite + Unpin), target: String) -> io::Result<()> {
+    let result = tokio::task::spawn_blocking(move || unmount(&target))
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)));
+    crate::files::send_done(w, result).await
+}
+
+/// Mounts `source` at `target`, creating the mount point if missing.
+fn mount(source: &str, target: &str, fstype: &str, options: &[String]) -> io::Result<()> {
+    let planned = resolve_target(Path::new(target))?;
+    fs::create_dir_all(&planned)?;
+    // Something may have swapped in a symlink since.
+    let mount_point = fs::canonicalize(&planned)?;
+    check_target(&mount_point)?;
+
+    let (flags, data) = parse_options(options);
+    let c_source = std::ffi::CString::new(source)?;
+    let c_target = std::ffi::CString::new(mount_point.as_os_str().as_encoded_bytes())?;
+    let c_fstype = std::ffi::CString::new(fstype)?;
+    let c_data = std::ffi::CString::new(data)?;
+
+    // SAFETY: all pointers are valid NUL-terminated strings for the call.
+    let ret = unsafe {
+        libc::mount(
+            c_source.as_ptr(),
+            c_target.as_ptr(),
+            c_fstype.as_ptr(),
+            flags,
+            c_data.as_ptr().cast(),
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Unmounts the filesystem at `target`.
+fn unmount(target: &str) -> io::Result<()> {
+    check_target(Path::new(target))?;
+    let mount_point = fs::canonicalize(target)?;
+    check_target(&mount_point)?;
+
+    let path = std::ffi::CString::new(mount_point.as_os_str().as_encoded_bytes())?;
+    // SAFETY: `path` is a valid NUL-terminated string for the call.
+    if unsafe { libc::umount2(path.as_ptr(), 0) } == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EINVAL) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a mount point", mount_point.display()),
+        ));
+    }
+    Err(err)
+}
+
+/// Resolves the part of `target` that exists and checks where the mount
+/// point would end up, before any directory is created: a symlink on the
+/// way must not get a protected tree's subdirectories created.
+fn resolve_target(target: &Path) -> io::Result<PathBuf> {
+    check_target(target)?;
+    let existing = target
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or_else(|| Path::new("/"));
+    let missing = target.strip_prefix(existing).map_err(io::Error::other)?;
+    if !missing
+        .components()
+        .all(|c| matches!(c, std::path::Component::Normal(_)))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "mount point {} climbs out of a missing directory",
+                target.display()
+            ),
+        ));
+    }
+    let resolved = fs::canonicalize(existing)?.join(missing);
+    check_target(&resolved)?;
+    Ok(resolved)
+}
+
+/// Rejects relative and [protected](is_protected) mount points.
+fn check_target(target: &Path) -> io::Result<()> {
+    if !target.is_absolute() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("mount point {} is not absolute", target.display()),
+        ));
+    }
+    if is_protected(target) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("refusing to change the mount at {}", target.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns `true` for mount points a runtime mount must not touch.
+fn is_protected(target: &Path) -> bool {
+    target == Path::new("/") || PROTECTED_TREES.iter().any(|p| target.starts_with(p))
+}
+
+/// Splits `mount -o` style options into `mount(2)` flags and the
+/// comma-joined filesystem data.
+fn parse_options(options: &[String]) -> (libc::c_ulong, String) {
+    let mut flags = 0;
+    let mut data = Vec::new();
+    for opt in options {
+        match MOUNT_FLAGS.iter().find(|(name, _)| name == opt) {
+            Some((_, flag)) => flags |= flag,
+            None => data.push(opt.as_str()),
+        }
+    }
+    (flags, data.join(","))
+}
+
 /// Returns `true` if `path` is already mounted as tmpfs.
 fn is_tmpfs(path: &str) -> bool {
     let Ok(mounts) = fs::read_to_string("/proc/mounts") else {
@@ -151,7 +371,7 @@ pub fn freeze_filesystems() -> Vec<PathBuf> {
         if ret == 0 {
             frozen.push(PathBuf::from(mount_point));
         } else {
-            let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
+            let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
             // EBUSY = already frozen → count as success.
             if errno == libc::EBUSY {
                 frozen.push(PathBuf::from(mount_point));
@@ -180,3 +400,64 @@ pub fn thaw_frozen(frozen: &[PathBuf]) -> u32 {
 
     thawed
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount_points_are_checked_through_symlinks_before_creation() {
+        // Outside /tmp, which is itself protected.
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join(format!(".bux_mount_target_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let base = fs::canonicalize(&dir).unwrap();
+        std::os::unix::fs::symlink("/etc", dir.join("etc")).unwrap();
+
+        assert_eq!(
+            resolve_target(&dir.join("data/new")).unwrap(),
+            base.join("data/new")
+        );
+        let err = resolve_target(&dir.join("etc/bux-new")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!Path::new("/etc/bux-new").exists());
+        let climb = resolve_target(&dir.join("missing/../../etc")).unwrap_err();
+        assert_eq!(climb.kind(), io::ErrorKind::InvalidInput);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn runtime_mounts_avoid_system_paths() {
+        for path in [
+            "/",
+            "/usr",
+            "/etc",
+            "/proc",
+            "/proc/sys",
+            "/dev/shm",
+            "/sys/fs/cgroup",
+            "/usr/local/share",
+            "/etc/ssl",
+            "/var/lib/app",
+            "/lib/modules",
+        ] {
+            assert!(is_protected(Path::new(path)), "{path}");
+        }
+        for path in ["/mnt/data", "/srv", "/opt/app", "/home/user", "/usrdata"] {
+            assert!(!is_protected(Path::new(path)), "{path}");
+        }
+        assert!(check_target(Path::new("mnt/data")).is_err());
+        assert!(check_target(Path::new("/mnt/data")).is_ok());
+
+        let options = ["ro", "nosuid", "size=64m", "mode=0755"].map(str::to_owned);
+        assert_eq!(
+            parse_options(&options),
+            (
+                libc::MS_RDONLY | libc::MS_NOSUID,
+                "size=64m,mode=0755".to_owned()
+            )
+        );
+    }
+}
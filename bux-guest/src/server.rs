@@ -4,15 +4,53 @@
 use std::sync::OnceLock;
 use std::time::Instant;
 
-use bux_proto::{AGENT_PORT, Hello, HelloAck, PROTOCOL_VERSION};
-use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+use bux_proto::{AGENT_PORT, Hello, HelloAck, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, feature};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter, ReadHalf, WriteHalf};
 use tokio_vsock::VsockListener;
 
 use crate::control;
+use crate::diff;
 use crate::exec;
 use crate::files;
+use crate::init;
 use crate::mounts;
 
+/// Operations this agent handles, reported by `ControlReq::Info`: one per
+/// arm of [`session`] and [`control::handle`] beyond the basics every agent
+/// has (ping, shutdown, info).
+pub const FEATURES: &[&str] = &[
+    feature::EXEC,
+    feature::PTY,
+    feature::FILE_READ,
+    feature::FILE_WRITE,
+    feature::FILE_WRITE_AT,
+    feature::FILE_UPLOAD,
+    feature::RESUMABLE_UPLOAD,
+    feature::WRITE_FILES,
+    feature::COPY_IN,
+    feature::COPY_OUT,
+    feature::STAT,
+    feature::CHMOD,
+    feature::CHOWN,
+    feature::UTIMES,
+    feature::MKDIR,
+    feature::RENAME,
+    feature::SYMLINK,
+    feature::MOUNT,
+    feature::QUIESCE,
+    feature::ENV,
+    feature::RESOLVE_USER,
+    feature::DIFF,
+    feature::SIGNAL,
+    feature::SET_ENV,
+    feature::SEED_RNG,
+    #[cfg(feature = "json")]
+    feature::JSON_CODEC,
+];
+
+/// Token every connection must present first, from [`bux_proto::AUTH_ENV`].
+static AUTH_TOKEN: OnceLock<Option<String>> = OnceLock::new();
+
 /// Boot timestamp, set once at agent startup.
 pub static BOOT_T0: OnceLock<Instant> = OnceLock::new();
 
@@ -22,7 +60,8 @@ pub fn uptime_ms() -> u64 {
     BOOT_T0.get().map_or(0, |t| t.elapsed().as_millis() as u64)
 }
 
-/// Entry point: mounts tmpfs, binds vsock, accepts connections.
+/// Entry point: mounts tmpfs, runs the user init, binds vsock, accepts
+/// connections.
 pub async fn run() -> io::Result<()> {
     BOOT_T0.set(Instant::now()).ok();
     eprintln!("[bux-guest] T+0ms: starting");
@@ -30,8 +69,15 @@ pub async fn run() -> io::Result<()> {
     // PID 1 duty: auto-reap zombie children.
     unsafe { libc::signal(libc::SIGCHLD, libc::SIG_IGN) };
 
+    AUTH_TOKEN.set(std::env::var(bux_proto::AUTH_ENV).ok()).ok();
+
     mounts::mount_essential_tmpfs();
     eprintln!("[bux-guest] T+{}ms: tmpfs mounted", uptime_ms());
+    mounts::mount_shares();
+    mounts::reenter_workdir();
+    init::run().await?;
+    init::hand_off_pid1()?;
+    diff::start_snapshot();
 
     let addr = tokio_vsock::VsockAddr::new(libc::VMADDR_CID_ANY, AGENT_PORT);
     let listener =
@@ -40,6 +86,7 @@ pub async fn run() -> io::Result<()> {
         "[bux-guest] T+{}ms: listening on vsock port {AGENT_PORT}",
         uptime_ms()
     );
+    init::spawn_main()?;
 
     loop {
         let (stream, _addr) = listener.accept().await?;
@@ -51,23 +98,53 @@ pub async fn run() -> io::Result<()> {
     }
 }
 
-/// Dispatches a single connection based on its [`Hello`] message.
+/// Serves a single connection in the codec its first byte picks.
 async fn session(stream: tokio_vsock::VsockStream) -> io::Result<()> {
     let (reader, writer) = tokio::io::split(stream);
     let mut r = BufReader::new(reader);
-    let mut w = BufWriter::new(writer);
+    let w = BufWriter::new(writer);
+    let Some(&first) = r.fill_buf().await?.first() else {
+        return Ok(());
+    };
+    bux_proto::Codec::detect(first)
+        .scope(Box::pin(dispatch(r, w)))
+        .await
+}
 
-    let hello: Hello = match bux_proto::recv(&mut r).await {
-        Ok(h) => h,
-        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
-        Err(e) => return Err(e),
+/// Dispatches a connection based on its [`Hello`] message.
+async fn dispatch(
+    mut r: BufReader<ReadHalf<tokio_vsock::VsockStream>>,
+    mut w: BufWriter<WriteHalf<tokio_vsock::VsockStream>>,
+) -> io::Result<()> {
+    let Some(mut hello) = recv_hello(&mut r).await? else {
+        return Ok(());
     };
+    if let Some(expected) = AUTH_TOKEN.get().and_then(Option::as_deref) {
+        let authorized =
+            matches!(&hello, Hello::Auth { token } if bux_proto::token_matches(expected, token));
+        if !authorized {
+            let err = bux_proto::ErrorInfo::permission_denied("unauthenticated");
+            bux_proto::send(&mut w, &HelloAck::Error(err)).await?;
+            return w.flush().await;
+        }
+    }
+    if matches!(hello, Hello::Auth { .. }) {
+        bux_proto::send(&mut w, &HelloAck::Ready).await?;
+        w.flush().await?;
+        let Some(next) = recv_hello(&mut r).await? else {
+            return Ok(());
+        };
+        hello = next;
+    }
 
     match hello {
         Hello::Control { version } => {
-            if version != PROTOCOL_VERSION {
+            // The host offers the oldest version it speaks; anything from
+            // our own minimum up to our version decodes the same.
+            if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
                 let err = bux_proto::ErrorInfo::version_mismatch(format!(
-                    "host protocol v{version}, guest protocol v{PROTOCOL_VERSION}"
+                    "host protocol v{version}+, guest protocol \
+                     v{MIN_PROTOCOL_VERSION}..=v{PROTOCOL_VERSION}"
                 ));
                 bux_proto::send(&mut w, &HelloAck::Error(err)).await?;
                 return w.flush().await;
@@ -83,28 +160,84 @@ async fn session(stream: tokio_vsock::VsockStream) -> io::Result<()> {
             control::handle(&mut r, &mut w).await
         }
         Hello::Exec(req) => exec::handle(&mut r, &mut w, req).await,
-        Hello::FileRead { path } => {
+        Hello::FileRead { path, chunk_size } => {
             bux_proto::send(&mut w, &HelloAck::Ready).await?;
             w.flush().await?;
-            files::handle_read(&mut w, &path).await
+            files::handle_read(&mut w, &path, chunk_size as usize).await
         }
         Hello::FileWrite { path, mode } => {
             bux_proto::send(&mut w, &HelloAck::Ready).await?;
             w.flush().await?;
             files::handle_write(&mut r, &mut w, &path, mode).await
         }
-        Hello::CopyIn { dest } => {
+        Hello::CopyIn { dest, same_owner } => {
             bux_proto::send(&mut w, &HelloAck::Ready).await?;
             w.flush().await?;
-            files::handle_copy_in(&mut r, &mut w, &dest).await
+            files::handle_copy_in(&mut r, &mut w, &dest, same_owner).await
         }
         Hello::CopyOut {
             path,
             follow_symlinks,
+            chunk_size,
         } => {
             bux_proto::send(&mut w, &HelloAck::Ready).await?;
             w.flush().await?;
-            files::handle_copy_out(&mut w, &path, follow_symlinks).await
+            files::handle_copy_out(&mut w, &path, follow_symlinks, chunk_size as usize).await
         }
+        Hello::FileWriteAt {
+            path,
+            offset,
+            append,
+            mode,
+        } => files::handle_write_at(&mut r, &mut w, &path, offset, append, mode).await,
+        Hello::FileUpload {
+            path, mode, size, ..
+        } => files::handle_file_upload(&mut r, &mut w, &path, mode, size, None).await,
+        Hello::Stat { path } => files::handle_stat(&mut w, &path).await,
+        Hello::WriteFiles { files: specs } => {
+            bux_proto::send(&mut w, &HelloAck::Ready).await?;
+            w.flush().await?;
+            files::handle_write_files(&mut r, &mut w, specs).await
+        }
+        Hello::Chmod { path, mode } => files::handle_chmod(&mut w, &path, mode).await,
+        Hello::Chown { path, uid, gid } => files::handle_chown(&mut w, &path, uid, gid).await,
+        Hello::Utimes { path, atime, mtime } => {
+            files::handle_utimes(&mut w, &path, atime, mtime).await
+        }
+        Hello::Mkdir {
+            path,
+            mode,
+            parents,
+        } => files::handle_mkdir(&mut w, &path, mode, parents).await,
+        Hello::Rename { from, to } => files::handle_rename(&mut w, &from, &to).await,
+        Hello::Symlink { target, link } => files::handle_symlink(&mut w, &target, &link).await,
+        Hello::Readlink { path } => files::handle_readlink(&mut w, &path).await,
+        Hello::ResumableUpload {
+            path,
+            mode,
+            size,
+            source,
+        } => files::handle_file_upload(&mut r, &mut w, &path, mode, size, Some(&source)).await,
+        Hello::Mount {
+            source,
+            target,
+            fstype,
+            options,
+        } => mounts::handle_mount(&mut w, source, target, fstype, options).await,
+        Hello::Unmount { target } => mounts::handle_unmount(&mut w, target).await,
+        Hello::Auth { .. } => {
+            let err = bux_proto::ErrorInfo::invalid_request("already authenticated");
+            bux_proto::send(&mut w, &HelloAck::Error(err)).await?;
+            w.flush().await
+        }
+    }
+}
+
+/// Reads the next [`Hello`]; `None` if the host closed the connection.
+async fn recv_hello(r: &mut (impl tokio::io::AsyncRead + Unpin)) -> io::Result<Option<Hello>> {
+    match bux_proto::recv(r).await {
+        Ok(h) => Ok(Some(h)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
     }
 }
@@ -2,7 +2,8 @@
 //!
 //! 1. Locates or downloads the pre-built `libkrun` dynamic library.
 //! 2. Optionally runs `bindgen` to regenerate Rust bindings (feature `regenerate`).
-//! 3. Configures the linker for dynamic linking.
+//! 3. Configures the linker for dynamic linking, or with feature `dynamic`
+//!    generates `dlopen` wrappers for every `krun_*` function instead.
 //!
 //! # Environment variables
 //!
@@ -37,32 +38,121 @@ fn main() {
     println!("cargo:rerun-if-env-changed=BUX_UPDATE_BINDINGS");
     println!("cargo:rerun-if-env-changed=DOCS_RS");
 
-    // docs.rs: no network, no native libs — pre-generated bindings suffice.
-    if env::var("DOCS_RS").is_ok() {
-        return;
-    }
-
-    let target = env::var("TARGET").expect("TARGET not set");
     let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR not set"));
+    let dynamic = env::var_os("CARGO_FEATURE_DYNAMIC").is_some();
 
     // Optionally regenerate bindings from the remote header.
     #[cfg(feature = "regenerate")]
-    {
+    if env::var("DOCS_RS").is_err() {
         let header = download_header(&out_dir);
         generate_bindings(&header, &out_dir);
     }
 
+    if dynamic {
+        let bindings = if cfg!(feature = "regenerate") && env::var("DOCS_RS").is_err() {
+            out_dir.join("bindings.rs")
+        } else {
+            PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set"))
+                .join("src/bindings.rs")
+        };
+        println!("cargo:rerun-if-changed={}", bindings.display());
+        generate_dynamic(&bindings, &out_dir.join("dynamic.rs"));
+    }
+
+    // docs.rs: no network, no native libs — pre-generated bindings suffice.
+    if env::var("DOCS_RS").is_ok() {
+        return;
+    }
+
+    let target = env::var("TARGET").expect("TARGET not set");
+
     // Only link on supported platforms.
     if !is_supported_target(&target) {
         return;
     }
 
     let lib_dir = obtain_libraries(&target, &out_dir);
-    println!("cargo:rustc-link-search=native={}", lib_dir.display());
-    println!("cargo:rustc-link-lib=dylib=krun");
+    if dynamic {
+        // Searched at runtime after the standard locations.
+        println!("cargo:rustc-env=BUX_KRUN_LIB_DIR={}", lib_dir.display());
+    } else {
+        println!("cargo:rustc-link-search=native={}", lib_dir.display());
+        println!("cargo:rustc-link-lib=dylib=krun");
+    }
     println!("cargo:LIB_DIR={}", lib_dir.display());
 }
 
+/// Write `dlopen`-based wrappers for every `pub fn krun_*` declared in
+/// `bindings` to `dest`.
+///
+/// Each wrapper has the extern declaration's signature and docs, calls
+/// through a function table resolved when the library is loaded, and
+/// returns `-ENOSYS` if the library or the symbol is missing. All libkrun
+/// functions return an `int` status, which makes that fallback uniform.
+fn generate_dynamic(bindings: &Path, dest: &Path) {
+    let source = fs::read_to_string(bindings)
+        .unwrap_or_else(|e| panic!("failed to read {}: {e}", bindings.display()));
+
+    let mut fields = String::new();
+    let mut resolve = String::new();
+    let mut wrappers = String::new();
+    for block in source.split("unsafe extern \"C\" {").skip(1) {
+        let block = block.split("\n}").next().unwrap_or_default();
+        let Some((attrs, decl)) = block.split_once("pub fn ") else {
+            continue;
+        };
+        let decl = decl.trim_end().trim_end_matches(';');
+        let (name, rest) = decl.split_once('(').expect("malformed extern fn");
+        let (params, ret) = rest.rsplit_once(')').expect("malformed extern fn");
+        assert!(
+            ret.contains("i32") || ret.contains("c_int"),
+            "{name}: non-int return type{ret}"
+        );
+        let params: Vec<(&str, &str)> = params
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(|p| p.split_once(':').expect("unnamed parameter"))
+            .map(|(n, t)| (n.trim(), t.trim()))
+            .collect();
+        let types: Vec<&str> = params.iter().map(|(_, t)| *t).collect();
+        let sig = format!("unsafe extern \"C\" fn({}){ret}", types.join(", "));
+        let decl_params: Vec<String> = params.iter().map(|(n, t)| format!("{n}: {t}")).collect();
+        let names: Vec<&str> = params.iter().map(|(n, _)| *n).collect();
+        let docs: String = attrs
+            .lines()
+            .map(str::trim)
+            .filter(|l| l.starts_with("#[doc"))
+            .map(|l| format!("{l}\n"))
+            .collect();
+
+        fields.push_str(&format!("    {name}: Option<{sig}>,\n"));
+        resolve.push_str(&format!(
+            "            {name}: symbol(handle, c\"{name}\").map(|p| unsafe {{ \
+             ::core::mem::transmute::<*mut ::core::ffi::c_void, {sig}>(p) }}),\n"
+        ));
+        wrappers.push_str(&format!(
+            "{docs}pub unsafe fn {name}({}){ret} {{\n    \
+             match api().and_then(|api| api.{name}) {{\n        \
+             Some(f) => unsafe {{ f({}) }},\n        \
+             None => -ENOSYS,\n    }}\n}}\n\n",
+            decl_params.join(", "),
+            names.join(", "),
+        ));
+    }
+
+    let code = format!(
+        "// Generated by build.rs from the extern declarations in the bindings.\n\n\
+         /// Function table resolved from a loaded libkrun.\n\
+         struct Api {{\n{fields}}}\n\n\
+         impl Api {{\n    \
+         /// Looks up every function in `handle`; missing ones stay `None`.\n    \
+         unsafe fn resolve(handle: *mut ::core::ffi::c_void) -> Self {{\n        \
+         Self {{\n{resolve}        }}\n    }}\n}}\n\n{wrappers}"
+    );
+    fs::write(dest, code).unwrap_or_else(|e| panic!("failed to write {}: {e}", dest.display()));
+}
+
 /// Download `libkrun.h` from the pinned fork into `$OUT_DIR`.
 #[cfg(feature = "regenerate")]
 fn download_header(out_dir: &Path) -> PathBuf {
@@ -0,0 +1,109 @@
+//! libkrun loaded with `dlopen` instead of linked (feature `dynamic`).
+//!
+//! Every `krun_*` function of the crate root has a same-named wrapper here.
+//! Call [`load`] before anything else to learn whether the library is
+//! available: while it is not, the wrappers return `-ENOSYS`, as does any
+//! function the loaded library does not export.
+
+use std::ffi::{CStr, CString, c_void};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use libc::ENOSYS;
+
+use super::*;
+
+/// Environment variable naming the library file to load.
+pub const LIBRARY_ENV: &str = "BUX_LIBKRUN";
+
+/// Outcome of the first [`load`], shared by all later calls.
+static API: OnceLock<Result<Api, String>> = OnceLock::new();
+
+/// Loads libkrun on first call; returns why it could not be loaded.
+///
+/// Candidates are tried in order: `$BUX_LIBKRUN`, the executable's
+/// directory and its `../lib`, the directory the library was found in at
+/// build time, then the bare name through the dynamic loader's search path.
+pub fn load() -> Result<(), String> {
+    match API.get_or_init(open) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.clone()),
+    }
+}
+
+/// The loaded function table, if [`load`] succeeded.
+fn api() -> Option<&'static Api> {
+    API.get_or_init(open).as_ref().ok()
+}
+
+/// File names of the library, most specific first.
+#[cfg(target_os = "macos")]
+const NAMES: [&str; 2] = ["libkrun.1.dylib", "libkrun.dylib"];
+#[cfg(not(target_os = "macos"))]
+const NAMES: [&str; 2] = ["libkrun.so.1", "libkrun.so"];
+
+/// Paths to try, in order; bare names go through the loader's search path.
+fn candidates() -> Vec<PathBuf> {
+    if let Some(path) = std::env::var_os(LIBRARY_ENV).filter(|p| !p.is_empty()) {
+        return vec![PathBuf::from(path)];
+    }
+    let mut dirs = Vec::new();
+    if let Some(exe_dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(PathBuf::from))
+    {
+        dirs.push(exe_dir.join("../lib"));
+        dirs.insert(0, exe_dir);
+    }
+    if let Some(dir) = option_env!("BUX_KRUN_LIB_DIR") {
+        dirs.push(PathBuf::from(dir));
+    }
+    let mut out: Vec<PathBuf> = dirs
+        .iter()
+        .flat_map(|dir| NAMES.iter().map(|name| dir.join(name)))
+        .filter(|path| path.exists())
+        .collect();
+    out.extend(NAMES.iter().map(PathBuf::from));
+    out
+}
+
+/// Opens the first loadable candidate and resolves its functions.
+fn open() -> Result<Api, String> {
+    let mut last = String::from("no candidate paths");
+    for path in candidates() {
+        let Ok(cpath) = CString::new(path.as_os_str().as_encoded_bytes()) else {
+            continue;
+        };
+        // SAFETY: `cpath` is a valid C string; the handle is never closed,
+        // so resolved function pointers stay valid for the process lifetime.
+        let handle = unsafe { libc::dlopen(cpath.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            last = dlerror().unwrap_or_else(|| format!("cannot load {}", path.display()));
+            continue;
+        }
+        // SAFETY: `handle` was just returned by a successful `dlopen`.
+        return Ok(unsafe { Api::resolve(handle) });
+    }
+    Err(last)
+}
+
+/// Looks up `name` in `handle`.
+fn symbol(handle: *mut c_void, name: &CStr) -> Option<*mut c_void> {
+    // SAFETY: `handle` is a live `dlopen` handle and `name` a C string.
+    let ptr = unsafe { libc::dlsym(handle, name.as_ptr()) };
+    (!ptr.is_null()).then_some(ptr)
+}
+
+/// The most recent `dlerror` message, if any.
+fn dlerror() -> Option<String> {
+    // SAFETY: `dlerror` returns null or a C string valid until the next call.
+    let msg = unsafe { libc::dlerror() };
+    // SAFETY: checked non-null just above.
+    (!msg.is_null()).then(|| {
+        unsafe { CStr::from_ptr(msg) }
+            .to_string_lossy()
+            .into_owned()
+    })
+}
+
+include!(concat!(env!("OUT_DIR"), "/dynamic.rs"));
@@ -10,6 +10,9 @@
 //!    (or uses a local path via `BUX_DEPS_DIR`).
 //! 2. Configures the linker for dynamic linking.
 //!
+//! With feature `dynamic` nothing is linked; the [`dynamic`] module instead
+//! offers the same functions, resolved with `dlopen` at runtime.
+//!
 //! For local development, set `BUX_DEPS_DIR` to point at a directory
 //! containing the pre-built `libkrun` dynamic library.
 //!
@@ -33,3 +36,6 @@
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 #[cfg(not(feature = "regenerate"))]
 include!("bindings.rs");
+
+#[cfg(feature = "dynamic")]
+pub mod dynamic;
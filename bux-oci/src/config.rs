@@ -0,0 +1,286 @@
+//! Assembles OCI image config blobs for images bux writes itself.
+//!
+//! The output is a full `application/vnd.oci.image.config.v1+json` document
+//! (with `created`, `architecture`, `os`, `rootfs` and `history`), so images
+//! produced by `commit` or `export` load in other OCI tools unchanged.
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use oci_client::manifest::{IMAGE_CONFIG_MEDIA_TYPE, OciDescriptor};
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::ImageConfig;
+
+/// Manifest annotation recording when the image was created.
+pub const ANNOTATION_CREATED: &str = "org.opencontainers.image.created";
+
+/// Builds a spec-compliant image config from an [`ImageConfig`] plus
+/// image metadata.
+///
+/// Defaults: `created` is now, `os` is `linux` (the guest OS, whatever the
+/// host), and `architecture` is the host's in Go naming (`amd64`, `arm64`).
+#[derive(Debug, Clone)]
+#[must_use = "a ConfigBuilder does nothing until .build() is called"]
+pub struct ConfigBuilder {
+    config: ImageConfig,
+    created: SystemTime,
+    author: Option<String>,
+    architecture: String,
+    os: String,
+    diff_ids: Vec<String>,
+    history: Vec<Value>,
+    annotations: BTreeMap<String, String>,
+}
+
+impl ConfigBuilder {
+    /// Starts a config carrying the runtime defaults in `config`.
+    pub fn new(config: ImageConfig) -> Self {
+        Self {
+            config,
+            created: SystemTime::now(),
+            author: None,
+            architecture: host_architecture().to_owned(),
+            os: "linux".to_owned(),
+            diff_ids: Vec::new(),
+            history: Vec::new(),
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the creation time (default: now).
+    pub const fn created(mut self, at: SystemTime) -> Self {
+        self.created = at;
+        self
+    }
+
+    /// Sets the image author.
+    pub fn author(mut self, author: impl Into<String>) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    /// Overrides the CPU architecture, in Go naming (`amd64`, `arm64`).
+    pub fn architecture(mut self, arch: impl Into<String>) -> Self {
+        self.architecture = arch.into();
+        self
+    }
+
+    /// Overrides the operating system (default `linux`).
+    pub fn os(mut self, os: impl Into<String>) -> Self {
+        self.os = os.into();
+        self
+    }
+
+    /// Appends a layer by its uncompressed digest (`diff_id`), with a
+    /// history entry describing how it was made.
+    pub fn layer(mut self, diff_id: impl Into<String>, created_by: impl Into<String>) -> Self {
+        self.diff_ids.push(diff_id.into());
+        self.history
+            .push(self.history_entry(&created_by.into(), false));
+        self
+    }
+
+    /// Appends a history entry for a step that produced no layer (e.g. a
+    /// config change).
+    pub fn empty_layer(mut self, created_by: impl Into<String>) -> Self {
+        self.history
+            .push(self.history_entry(&created_by.into(), true));
+        self
+    }
+
+    /// Adds a manifest annotation returned with the built config.
+    pub fn annotation(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.annotations.insert(key.into(), value.into());
+        self
+    }
+
+    /// Serializes the config and computes its digest.
+    pub fn build(self) -> ConfigBlob {
+        let created = rfc3339(self.created);
+        let mut doc = Map::new();
+        doc.insert("created".into(), json!(created));
+        if let Some(author) = self.author {
+            doc.insert("author".into(), json!(author));
+        }
+        doc.insert("architecture".into(), json!(self.architecture));
+        doc.insert("os".into(), json!(self.os));
+        doc.insert("config".into(), runtime_config(&self.config));
+        doc.insert(
+            "rootfs".into(),
+            json!({ "type": "layers", "diff_ids": self.diff_ids }),
+        );
+        doc.insert("history".into(), Value::Array(self.history));
+
+        let data = Value::Object(doc).to_string().into_bytes();
+        let digest = format!("sha256:{:x}", Sha256::digest(&data));
+        let mut annotations = self.annotations;
+        annotations
+            .entry(ANNOTATION_CREATED.to_owned())
+            .or_insert(created);
+        ConfigBlob {
+            data,
+            digest,
+            annotations,
+        }
+    }
+
+    /// One `history` item stamped with this config's creation time.
+    fn history_entry(&self, created_by: &str, empty_layer: bool) -> Value {
+        let mut entry = json!({ "created": rfc3339(self.created), "created_by": created_by });
+        if empty_layer {
+            entry["empty_layer"] = json!(true);
+        }
+        entry
+    }
+}
+
+/// A serialized image config, ready to store or push.
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct ConfigBlob {
+    /// Config JSON bytes; the digest covers exactly these.
+    pub data: Vec<u8>,
+    /// `sha256:` digest of `data`.
+    pub digest: String,
+    /// Annotations for the manifest that references this config, including
+    /// [`ANNOTATION_CREATED`].
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl ConfigBlob {
+    /// Descriptor for the manifest's `config` field.
+    pub fn descriptor(&self) -> OciDescriptor {
+        OciDescriptor {
+            media_type: IMAGE_CONFIG_MEDIA_TYPE.to_owned(),
+            digest: self.digest.clone(),
+            size: i64::try_from(self.data.len()).unwrap_or(i64::MAX),
+            urls: None,
+            annotations: None,
+        }
+    }
+}
+
+/// Maps an [`ImageConfig`] onto the OCI `config` object's field names,
+/// leaving out unset fields.
+fn runtime_config(config: &ImageConfig) -> Value {
+    let mut out = Map::new();
+    let mut put = |key: &str, value: Option<Value>| {
+        if let Some(v) = value {
+            out.insert(key.to_owned(), v);
+        }
+    };
+    put("User", config.user.clone().map(Value::from));
+    put("Env", config.env.clone().map(Value::from));
+    put("Entrypoint", config.entrypoint.clone().map(Value::from));
+    put("Cmd", config.cmd.clone().map(Value::from));
+    put("WorkingDir", config.working_dir.clone().map(Value::from));
+    put("ExposedPorts", config.exposed_ports.clone());
+    put("Labels", config.labels.clone().map(Value::Object));
+    Value::Object(out)
+}
+
+/// Host CPU architecture in the Go naming OCI uses.
+fn host_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        other => other,
+    }
+}
+
+/// Formats a time as RFC 3339 UTC with second precision.
+fn rfc3339(at: SystemTime) -> String {
+    let secs = at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let days = i64::try_from(secs / 86_400).unwrap_or(0);
+    let rem = secs % 86_400;
+
+    // Howard Hinnant's civil-from-days algorithm.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::time::Duration;
+
+    use oci_client::config::ConfigFile;
+    use oci_client::manifest::OciImageManifest;
+
+    use super::*;
+
+    #[test]
+    fn config_round_trips_through_oci_types() {
+        let runtime: ImageConfig = serde_json::from_str(
+            r#"{"Cmd": ["/bin/sh"], "Env": ["PATH=/bin"], "WorkingDir": "/app",
+                "ExposedPorts": {"80/tcp": {}}, "Labels": {"stage": "dev"}}"#,
+        )
+        .unwrap();
+        let blob = ConfigBuilder::new(runtime)
+            .created(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            .architecture("arm64")
+            .layer("sha256:aaaa", "bux commit")
+            .empty_layer("bux commit --change CMD")
+            .build();
+
+        assert_eq!(
+            blob.digest,
+            format!("sha256:{:x}", Sha256::digest(&blob.data))
+        );
+        assert_eq!(blob.annotations[ANNOTATION_CREATED], "2023-11-14T22:13:20Z");
+
+        let file: ConfigFile = serde_json::from_slice(&blob.data).unwrap();
+        assert_eq!(file.created.unwrap().timestamp(), 1_700_000_000);
+        assert_eq!(serde_json::to_value(&file.architecture).unwrap(), "arm64");
+        assert_eq!(serde_json::to_value(&file.os).unwrap(), "linux");
+        assert_eq!(file.rootfs.diff_ids, ["sha256:aaaa"]);
+        let history = file.history.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].empty_layer, Some(true));
+        let config = file.config.unwrap();
+        assert_eq!(config.cmd.unwrap(), ["/bin/sh"]);
+        assert_eq!(config.working_dir.as_deref(), Some("/app"));
+
+        let manifest = OciImageManifest {
+            config: blob.descriptor(),
+            annotations: Some(blob.annotations.clone()),
+            ..OciImageManifest::default()
+        };
+        let parsed: OciImageManifest =
+            serde_json::from_str(&serde_json::to_string(&manifest).unwrap()).unwrap();
+        assert_eq!(parsed.config.media_type, IMAGE_CONFIG_MEDIA_TYPE);
+        assert_eq!(parsed.config.digest, blob.digest);
+        assert_eq!(
+            usize::try_from(parsed.config.size).unwrap(),
+            blob.data.len()
+        );
+
+        let ours = crate::parse_image_config(std::str::from_utf8(&blob.data).unwrap()).unwrap();
+        assert_eq!(ours.exposed_ports_parsed().len(), 1);
+    }
+
+    #[test]
+    fn rfc3339_handles_leap_years() {
+        let at = |secs| rfc3339(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(4_107_542_399), "2100-02-28T23:59:59Z");
+    }
+}
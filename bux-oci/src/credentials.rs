@@ -0,0 +1,313 @@
+//! Registry credentials from a Docker `config.json`.
+//!
+//! Each registry is looked up the way `docker` does it: a `credHelpers`
+//! entry for its host, else the `credsStore` helper, else a static `auths`
+//! entry. A helper is the `docker-credential-<name>` program on `PATH`, run
+//! as `docker-credential-<name> get` with the registry on stdin; it prints
+//! `{"Username": ..., "Secret": ...}`. Answers are cached for the life of
+//! the [`Credentials`], so short-lived tokens (ECR, GCR, ACR) are fetched
+//! once per process rather than once per request.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+use base64::Engine;
+use oci_client::secrets::RegistryAuth;
+
+/// Key Docker files Docker Hub credentials under.
+const DOCKER_HUB: &str = "https://index.docker.io/v1/";
+
+/// What a helper prints when it has nothing for the registry.
+const NOT_FOUND: &str = "credentials not found";
+
+/// The parts of `config.json` that hold credentials.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DockerConfig {
+    /// Static credentials keyed by registry (`host` or `https://host/...`).
+    #[serde(default)]
+    auths: HashMap<String, AuthEntry>,
+    /// Helper name per registry host.
+    #[serde(default)]
+    cred_helpers: HashMap<String, String>,
+    /// Helper for every registry without a `credHelpers` entry.
+    #[serde(default)]
+    creds_store: Option<String>,
+}
+
+/// One `auths` entry: base64 `user:password`, or the two spelled out.
+#[derive(Debug, Default, serde::Deserialize)]
+struct AuthEntry {
+    /// Base64 of `user:password`.
+    #[serde(default)]
+    auth: Option<String>,
+    /// User name, with `password`.
+    #[serde(default)]
+    username: Option<String>,
+    /// Password, with `username`.
+    #[serde(default)]
+    password: Option<String>,
+}
+
+/// A helper's answer to `get`.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperReply {
+    /// User name; registries such as ECR use a fixed one.
+    username: String,
+    /// Password or token.
+    secret: String,
+}
+
+/// Credentials from one `config.json`, resolved per registry on demand.
+#[derive(Debug)]
+pub struct Credentials {
+    /// Location of `config.json`.
+    path: PathBuf,
+    /// Parsed on first use, so a broken file only fails registry access.
+    config: OnceLock<Result<DockerConfig, String>>,
+    /// Helper programs are this followed by the helper name.
+    helper_prefix: PathBuf,
+    /// Resolved credentials by registry.
+    cache: Mutex<HashMap<String, RegistryAuth>>,
+}
+
+impl Credentials {
+    /// Uses the Docker config at `path`, read when first needed. A
+    /// missing file yields no credentials.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            config: OnceLock::new(),
+            helper_prefix: PathBuf::from("docker-credential-"),
+            cache: Mutex::default(),
+        }
+    }
+
+    /// The parsed config.
+    fn config(&self) -> crate::Result<&DockerConfig> {
+        self.config
+            .get_or_init(|| match fs::read(&self.path) {
+                Ok(data) => serde_json::from_slice(&data).map_err(|e| e.to_string()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(DockerConfig::default()),
+                Err(e) => Err(e.to_string()),
+            })
+            .as_ref()
+            .map_err(|e| crate::Error::Credentials(format!("{}: {e}", self.path.display())))
+    }
+
+    /// Returns the credentials for `registry` (`host[:port]`), or
+    /// [`RegistryAuth::Anonymous`] if the config has none.
+    ///
+    /// Blocking: may run a helper program.
+    pub fn resolve(&self, registry: &str) -> crate::Result<RegistryAuth> {
+        if let Some(auth) = self.cached(registry) {
+            return Ok(auth);
+        }
+        let config = self.config()?;
+        let key = config_key(registry);
+        let helper = config.cred_helpers.get(key).or(config.creds_store.as_ref());
+        let auth = match helper {
+            Some(name) => self.run_helper(name, key)?,
+            None => static_auth(config, key)?,
+        };
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(registry.to_owned(), auth.clone());
+        Ok(auth)
+    }
+
+    /// Credentials already resolved for `registry`.
+    fn cached(&self, registry: &str) -> Option<RegistryAuth> {
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(registry)
+            .cloned()
+    }
+
+    /// Asks helper `name` for the credentials filed under `key`.
+    fn run_helper(&self, name: &str, key: &str) -> crate::Result<RegistryAuth> {
+        let mut program = self.helper_prefix.clone().into_os_string();
+        program.push(name);
+        let failed = |detail: String| {
+            crate::Error::Credentials(format!("{}: {detail}", Path::new(&program).display()))
+        };
+
+        let mut child = Command::new(&program)
+            .arg("get")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| failed(e.to_string()))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(key.as_bytes())
+                .map_err(|e| failed(e.to_string()))?;
+        }
+        let output = child
+            .wait_with_output()
+            .map_err(|e| failed(e.to_string()))?;
+
+        if !output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            if stdout.contains(NOT_FOUND) {
+                return Ok(RegistryAuth::Anonymous);
+            }
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = if stderr.trim().is_empty() {
+                stdout
+            } else {
+                stderr
+            };
+            return Err(failed(format!("{}: {}", output.status, message.trim())));
+        }
+        let reply: HelperReply =
+            serde_json::from_slice(&output.stdout).map_err(|e| failed(e.to_string()))?;
+        Ok(RegistryAuth::Basic(reply.username, reply.secret))
+    }
+}
+
+/// The `auths` entry of `config` for `key`, matched on its host.
+fn static_auth(config: &DockerConfig, key: &str) -> crate::Result<RegistryAuth> {
+    let Some(entry) = config
+        .auths
+        .iter()
+        .find(|(name, _)| host_of(name) == host_of(key))
+        .map(|(_, entry)| entry)
+    else {
+        return Ok(RegistryAuth::Anonymous);
+    };
+    if let (Some(user), Some(password)) = (&entry.username, &entry.password) {
+        return Ok(RegistryAuth::Basic(user.clone(), password.clone()));
+    }
+    let Some(encoded) = entry.auth.as_deref().filter(|a| !a.is_empty()) else {
+        return Ok(RegistryAuth::Anonymous);
+    };
+    let invalid = || crate::Error::Credentials(format!("auths entry for {key} is malformed"));
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|_| invalid())?;
+    let text = String::from_utf8(decoded).map_err(|_| invalid())?;
+    let (user, password) = text.split_once(':').ok_or_else(invalid)?;
+    Ok(RegistryAuth::Basic(user.to_owned(), password.to_owned()))
+}
+
+/// Where `config.json` files credentials for `registry`: Docker Hub under
+/// its legacy URL, everything else under the bare host.
+fn config_key(registry: &str) -> &str {
+    match registry {
+        "docker.io" | "index.docker.io" | "registry-1.docker.io" => DOCKER_HUB,
+        other => other,
+    }
+}
+
+/// `host[:port]` of an `auths` key, which may be a URL.
+fn host_of(key: &str) -> &str {
+    let rest = key.split_once("://").map_or(key, |(_, rest)| rest);
+    rest.split('/').next().unwrap_or(rest)
+}
+
+/// The Docker client's config file: `$DOCKER_CONFIG/config.json`, else
+/// `~/.docker/config.json`.
+pub fn default_config_path() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("DOCKER_CONFIG") {
+        return Some(PathBuf::from(dir).join("config.json"));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".docker/config.json"))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn helpers_and_static_auths_resolve_per_registry() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("bux_oci_creds_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        // Answers for the host it is asked about and counts its runs.
+        let helper = root.join("docker-credential-fake");
+        let script = format!(
+            "#!/bin/sh\nread host\necho run >> {runs}\n\
+             case \"$host\" in\n\
+             *.ecr.*) echo '{{\"Username\":\"AWS\",\"Secret\":\"tok-'\"$host\"'\"}}' ;;\n\
+             *) echo '{NOT_FOUND} in native keychain'; exit 1 ;;\n\
+             esac\n",
+            runs = root.join("runs").display()
+        );
+        fs::write(&helper, script).unwrap();
+        fs::set_permissions(&helper, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let config = root.join("config.json");
+        fs::write(
+            &config,
+            r#"{
+                "auths": {
+                    "https://index.docker.io/v1/": {"auth": "aHViOnNlY3JldA=="},
+                    "ghcr.io": {"username": "me", "password": "pat"}
+                },
+                "credHelpers": {"1.dkr.ecr.eu-west-1.amazonaws.com": "fake", "gone.io": "fake"}
+            }"#,
+        )
+        .unwrap();
+        let mut creds = Credentials::new(&config);
+        creds.helper_prefix = root.join("docker-credential-");
+
+        let ecr = "1.dkr.ecr.eu-west-1.amazonaws.com";
+        let expected = RegistryAuth::Basic("AWS".into(), format!("tok-{ecr}"));
+        assert_eq!(creds.resolve(ecr).unwrap(), expected);
+        assert_eq!(creds.resolve(ecr).unwrap(), expected);
+        assert_eq!(
+            fs::read_to_string(root.join("runs"))
+                .unwrap()
+                .lines()
+                .count(),
+            1
+        );
+        assert_eq!(creds.resolve("gone.io").unwrap(), RegistryAuth::Anonymous);
+
+        assert_eq!(
+            creds.resolve("docker.io").unwrap(),
+            RegistryAuth::Basic("hub".into(), "secret".into())
+        );
+        assert_eq!(
+            creds.resolve("ghcr.io").unwrap(),
+            RegistryAuth::Basic("me".into(), "pat".into())
+        );
+        assert_eq!(creds.resolve("quay.io").unwrap(), RegistryAuth::Anonymous);
+
+        // A store helper that is not installed is an error, not anonymous.
+        fs::write(&config, r#"{"credsStore": "missing"}"#).unwrap();
+        let mut store = Credentials::new(&config);
+        store.helper_prefix = root.join("docker-credential-");
+        assert!(matches!(
+            store.resolve("quay.io"),
+            Err(crate::Error::Credentials(_))
+        ));
+
+        assert_eq!(
+            Credentials::new(root.join("absent.json"))
+                .resolve("quay.io")
+                .unwrap(),
+            RegistryAuth::Anonymous
+        );
+        fs::write(&config, "{").unwrap();
+        assert!(matches!(
+            Credentials::new(&config).resolve("quay.io"),
+            Err(crate::Error::Credentials(_))
+        ));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
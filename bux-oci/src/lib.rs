@@ -10,22 +10,43 @@
 //!  ├── Store (SQLite index + content-addressed blob storage)
 //!  │    ├── layers/   — sha256-addressed layer tarballs
 //!  │    ├── configs/  — sha256-addressed config blobs
+//!  │    ├── manifests/ — raw manifests, byte-for-byte as pulled
 //!  │    └── rootfs/   — extracted rootfs directories
 //!  └── oci_client::Client (registry communication)
 //! ```
 
 #![allow(clippy::missing_docs_in_private_items)]
 
+mod config;
+mod credentials;
 mod extract;
+pub mod metrics;
+mod progress;
+mod signature;
 mod store;
 
+use std::collections::{HashMap, VecDeque};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError, Weak};
+use std::time::{Duration, Instant};
 
+use futures_util::StreamExt;
+
+pub use config::{ANNOTATION_CREATED, ConfigBlob, ConfigBuilder};
+pub use extract::RootfsUsage;
 use oci_client::Reference;
-use oci_client::client::ClientConfig;
-use oci_client::secrets::RegistryAuth;
-pub use store::ImageMeta;
+use oci_client::client::{
+    BlobResponse, Certificate, CertificateEncoding, ClientConfig, ClientProtocol,
+};
+use oci_client::manifest::{IMAGE_MANIFEST_MEDIA_TYPE, OCI_IMAGE_MEDIA_TYPE, OciDescriptor};
+pub use oci_client::secrets::RegistryAuth;
+use progress::Tracker;
+pub use progress::{PullObserver, PullProgress};
+pub use signature::{NoopVerifier, SignatureVerifier};
 use store::Store;
+pub use store::{ImageFilter, ImageList, ImageMeta, LayerInfo, LayerRefs};
+use tokio::io::AsyncWriteExt;
 
 /// Result type for bux-oci operations.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -38,10 +59,24 @@ pub enum Error {
     #[error("invalid image reference: {0}")]
     InvalidReference(String),
 
-    /// The image was not found locally.
+    /// An image filter was not of the form `label=KEY[=VALUE]` or
+    /// `reference=PATTERN`.
+    #[error("invalid image filter: {0}")]
+    InvalidFilter(String),
+
+    /// The image was not found locally, or the repository is unknown to
+    /// its registry.
     #[error("image not found: {0}")]
     NotFound(String),
 
+    /// A digest prefix matched more than one stored image.
+    #[error("{0}")]
+    Ambiguous(String),
+
+    /// The registry requires credentials that were missing or rejected.
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+
     /// Local store / database error.
     #[error("db: {0}")]
     Db(String),
@@ -50,6 +85,22 @@ pub enum Error {
     #[error("registry: {0}")]
     Registry(String),
 
+    /// [`OciConfig::signature_verifier`] rejected the image.
+    #[error("signature verification failed: {0}")]
+    SignatureVerificationFailed(String),
+
+    /// A pull exceeded [`OciConfig::pull_timeout`].
+    #[error("pull timed out after {0:?}")]
+    Timeout(Duration),
+
+    /// A CA certificate file could not be read or parsed.
+    #[error("certificate: {0}")]
+    Certificate(String),
+
+    /// The Docker config could not be parsed or a credential helper failed.
+    #[error("credentials: {0}")]
+    Credentials(String),
+
     /// Filesystem I/O error.
     #[error(transparent)]
     Io(#[from] std::io::Error),
@@ -65,8 +116,66 @@ pub enum Error {
 pub struct OciConfig {
     /// Root directory for the image store. Defaults to `<platform_data_dir>/bux`.
     pub store_dir: PathBuf,
-    /// Registry authentication. Defaults to anonymous.
+    /// Directory for in-progress downloads and extractions, e.g. fast local
+    /// disk when the store sits on slow or network storage. Finished files
+    /// move into the store, copied if it is another filesystem. `None`
+    /// stages inside the store. Defaults to `BUX_STAGING_DIR`.
+    pub staging_dir: Option<PathBuf>,
+    /// Registry authentication for every registry. Defaults to anonymous,
+    /// which defers to [`docker_config`](Self::docker_config).
     pub auth: RegistryAuth,
+    /// Docker `config.json` consulted per registry while
+    /// [`auth`](Self::auth) is anonymous: `credHelpers`, then `credsStore`,
+    /// then static `auths`. Helpers (`docker-credential-<name>`, e.g.
+    /// `ecr-login`, `gcloud`) run once per registry per [`Oci`]. A missing
+    /// file means no credentials. Defaults to `$DOCKER_CONFIG/config.json`,
+    /// else `~/.docker/config.json`.
+    pub docker_config: Option<PathBuf>,
+    /// Proxy for `http://` registries. Defaults to `HTTP_PROXY`.
+    pub http_proxy: Option<String>,
+    /// Proxy for `https://` registries. Defaults to `HTTPS_PROXY`.
+    pub https_proxy: Option<String>,
+    /// Comma-separated hosts that bypass the proxies. Defaults to `NO_PROXY`.
+    pub no_proxy: Option<String>,
+    /// PEM or DER CA certificates trusted in addition to the built-in roots,
+    /// e.g. a corporate TLS-intercepting proxy. PEM files may hold a bundle.
+    /// Defaults to the paths in `BUX_EXTRA_CA_CERTS` (`PATH`-style list).
+    pub extra_ca_certs: Vec<PathBuf>,
+    /// Skip TLS certificate verification entirely.
+    ///
+    /// **Dangerous**: any party on the network path can impersonate the
+    /// registry and serve arbitrary images. Prefer
+    /// [`extra_ca_certs`](Self::extra_ca_certs). Defaults to
+    /// `BUX_ACCEPT_INVALID_CERTS=1`.
+    pub accept_invalid_certs: bool,
+    /// Registries (`host[:port]`) reached without certificate verification,
+    /// falling back to plain HTTP. A bare `host` matches every port. All
+    /// other registries stay strict. Defaults to the comma-separated
+    /// `BUX_INSECURE_REGISTRIES`.
+    pub insecure_registries: Vec<String>,
+    /// `User-Agent` sent to registries. `None` (the default) sends
+    /// `bux/<version> (<os>/<arch>)`.
+    pub user_agent: Option<String>,
+    /// Upper bound on a whole [`Oci::pull`] (manifest, layers and
+    /// extraction). `None` (the default) waits indefinitely.
+    pub pull_timeout: Option<Duration>,
+    /// Checks each manifest [`Oci::pull`] fetches before any layer is
+    /// downloaded. `None` (the default) trusts every image.
+    pub signature_verifier: Option<Arc<dyn SignatureVerifier>>,
+    /// Extract layers into the rootfs as they download instead of storing
+    /// every layer first and extracting afterwards. Saves a full read of
+    /// each layer, but an interrupted pull starts its layers over. Defaults
+    /// to `BUX_EXTRACT_STREAMING=1`.
+    pub extract_streaming: bool,
+    /// With [`extract_streaming`](Self::extract_streaming), also store the
+    /// layers for later pulls and [`Oci::verify`]. Turning this off keeps
+    /// only the extracted rootfs, for one-shot runs; such images verify
+    /// with missing layers until [`Oci::repair`]ed. Defaults to `true`.
+    pub cache_streamed_layers: bool,
+    /// How long store operations wait for another process (or another task
+    /// of this one) to release the SQLite index before failing with
+    /// [`Error::Db`]. Defaults to 5 seconds.
+    pub busy_timeout: Duration,
 }
 
 impl Default for OciConfig {
@@ -74,7 +183,33 @@ fn default() -> Self {
         let store_dir = dirs_default_store();
         Self {
             store_dir,
+            staging_dir: std::env::var_os("BUX_STAGING_DIR").map(PathBuf::from),
             auth: RegistryAuth::Anonymous,
+            docker_config: credentials::default_config_path(),
+            http_proxy: env_any(&["HTTP_PROXY", "http_proxy"]),
+            https_proxy: env_any(&["HTTPS_PROXY", "https_proxy"]),
+            no_proxy: env_any(&["NO_PROXY", "no_proxy"]),
+            extra_ca_certs: std::env::var_os("BUX_EXTRA_CA_CERTS")
+                .map(|v| std::env::split_paths(&v).collect())
+                .unwrap_or_default(),
+            accept_invalid_certs: env_any(&["BUX_ACCEPT_INVALID_CERTS"])
+                .is_some_and(|v| v == "1" || v.eq_ignore_ascii_case("true")),
+            insecure_registries: env_any(&["BUX_INSECURE_REGISTRIES"])
+                .map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|r| !r.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            user_agent: None,
+            pull_timeout: None,
+            signature_verifier: None,
+            extract_streaming: env_any(&["BUX_EXTRACT_STREAMING"])
+                .is_some_and(|v| v == "1" || v.eq_ignore_ascii_case("true")),
+            cache_streamed_layers: true,
+            busy_timeout: store::DEFAULT_BUSY_TIMEOUT,
         }
     }
 }
@@ -106,7 +241,68 @@ pub struct ImageConfig {
     pub labels: Option<serde_json::Map<String, serde_json::Value>>,
 }
 
+/// Transport protocol of an exposed port.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Proto {
+    /// TCP (the default when no protocol is given).
+    Tcp,
+    /// UDP.
+    Udp,
+    /// SCTP.
+    Sctp,
+}
+
+impl Proto {
+    /// Lowercase name as used in `ExposedPorts` keys.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+            Self::Sctp => "sctp",
+        }
+    }
+}
+
+/// A port from the image's `EXPOSE` directive.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
+pub struct PortSpec {
+    /// Port number inside the guest.
+    pub port: u16,
+    /// Transport protocol.
+    pub proto: Proto,
+}
+
+impl PortSpec {
+    /// Parses an `ExposedPorts` key: `port[/tcp|udp|sctp]`, TCP by default.
+    fn parse(s: &str) -> Option<Self> {
+        let (number, name) = s.split_once('/').unwrap_or((s, "tcp"));
+        let proto = match name.to_ascii_lowercase().as_str() {
+            "tcp" => Proto::Tcp,
+            "udp" => Proto::Udp,
+            "sctp" => Proto::Sctp,
+            _ => return None,
+        };
+        let port = number.parse().ok().filter(|p| *p != 0)?;
+        Some(Self { port, proto })
+    }
+}
+
 impl ImageConfig {
+    /// Parses [`exposed_ports`](Self::exposed_ports) (`{"80/tcp": {}}`),
+    /// skipping malformed entries. Sorted by port, then protocol.
+    pub fn exposed_ports_parsed(&self) -> Vec<PortSpec> {
+        let Some(serde_json::Value::Object(map)) = &self.exposed_ports else {
+            return Vec::new();
+        };
+        let mut ports: Vec<PortSpec> = map.keys().filter_map(|k| PortSpec::parse(k)).collect();
+        ports.sort_unstable();
+        ports.dedup();
+        ports
+    }
+
     /// Returns the combined entrypoint + cmd as the final execution command.
     pub fn command(&self) -> Vec<String> {
         let mut parts = Vec::new();
@@ -130,21 +326,269 @@ pub struct PullResult {
     pub digest: String,
     /// Path to the extracted rootfs directory.
     pub rootfs: PathBuf,
+    /// Total compressed size of all layers in bytes.
+    pub size: u64,
+    /// Layer digests, bottom layer first.
+    pub layers: Vec<String>,
     /// Image configuration (Cmd, Env, WorkingDir, etc.).
     pub config: Option<ImageConfig>,
+    /// Space the rootfs needs on a disk image, counted while this call
+    /// extracted it. `None` if the rootfs was already in the store; size
+    /// it by walking [`rootfs`](Self::rootfs) then.
+    pub rootfs_usage: Option<RootfsUsage>,
+}
+
+/// When [`Oci::ensure_with_policy`] contacts the registry.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PullPolicy {
+    /// Use the cached image if its rootfs is complete, else pull.
+    #[default]
+    IfNotPresent,
+    /// Always resolve the reference against the registry; layers and rootfs
+    /// are fetched again only if the remote digest changed.
+    Always,
+    /// Only use the cache; fail with [`Error::NotFound`] if the image is
+    /// not there.
+    Never,
+}
+
+/// Integrity of one stored layer blob.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerStatus {
+    /// The blob hashes to its digest.
+    Ok,
+    /// The blob exists but its content does not match the digest.
+    Corrupt,
+    /// The blob is not on disk.
+    Missing,
+}
+
+impl LayerStatus {
+    /// Lowercase name for display.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Corrupt => "corrupt",
+            Self::Missing => "missing",
+        }
+    }
+}
+
+/// Verification result for one layer.
+#[non_exhaustive]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LayerCheck {
+    /// Layer digest.
+    pub digest: String,
+    /// What re-hashing the blob found.
+    pub status: LayerStatus,
+}
+
+/// Everything the local store knows about one image, from [`Oci::inspect`].
+#[non_exhaustive]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ImageInspect {
+    /// Index entry: reference, manifest digest, size and pull time.
+    #[serde(flatten)]
+    pub meta: ImageMeta,
+    /// Parsed runtime config, if the image has one.
+    pub config: Option<ImageConfig>,
+    /// Extracted rootfs directory.
+    pub rootfs: PathBuf,
+    /// Layers, bottom layer first.
+    pub layers: Vec<LayerInfo>,
+}
+
+/// One step of an image's build, from [`Oci::history`].
+///
+/// Steps that produced a layer carry its digest and size; metadata-only
+/// steps (`ENV`, `CMD`, ...) have neither.
+#[non_exhaustive]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HistoryEntry {
+    /// Layer digest, or `None` for an empty-layer step.
+    pub digest: Option<String>,
+    /// Compressed layer size in bytes, or `None` for an empty-layer step.
+    pub size: Option<u64>,
+    /// Creation timestamp as recorded in the config (RFC 3339).
+    pub created: Option<String>,
+    /// Command that created the step.
+    pub created_by: Option<String>,
+    /// Free-form comment recorded with the step.
+    pub comment: Option<String>,
+}
+
+/// Result of [`Oci::verify`].
+#[non_exhaustive]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct VerifyReport {
+    /// Canonical image reference string.
+    pub reference: String,
+    /// Manifest content digest.
+    pub digest: String,
+    /// Per-layer results, bottom layer first.
+    pub layers: Vec<LayerCheck>,
+    /// Whether the extracted rootfs is in place.
+    pub rootfs_complete: bool,
 }
 
+impl VerifyReport {
+    /// Returns `true` if every layer is intact and the rootfs is complete.
+    pub fn is_ok(&self) -> bool {
+        self.rootfs_complete && self.layers.iter().all(|l| l.status == LayerStatus::Ok)
+    }
+}
+
+/// Single-platform manifest types accepted when fetching a manifest by
+/// digest.
+const MANIFEST_MEDIA_TYPES: &[&str] = &[OCI_IMAGE_MEDIA_TYPE, IMAGE_MANIFEST_MEDIA_TYPE];
+
 /// OCI image manager backed by a content-addressed store.
 ///
 /// All methods take `&self` — the underlying store uses SQLite (which serializes
 /// writes internally) and content-addressed blobs (immutable files).
+///
+/// The synchronous methods block the calling thread on SQLite and, for
+/// [`verify`](Self::verify) and [`prune`](Self::prune), on filesystem
+/// walks. That is fine for a CLI; async servers should use the `*_async`
+/// variants, which run the same work on tokio's blocking pool.
 pub struct Oci {
     /// Content-addressed image store.
     store: Store,
     /// OCI registry HTTP client.
     client: oci_client::Client,
+    /// Clients for [`OciConfig::insecure_registries`], if any are listed.
+    insecure: Option<Insecure>,
     /// Registry authentication credentials.
     auth: RegistryAuth,
+    /// Per-registry credentials, when [`auth`](Self::auth) is anonymous.
+    credentials: Option<Arc<credentials::Credentials>>,
+    /// See [`OciConfig::pull_timeout`].
+    pull_timeout: Option<Duration>,
+    /// See [`OciConfig::signature_verifier`].
+    signature_verifier: Option<Arc<dyn SignatureVerifier>>,
+    /// See [`OciConfig::extract_streaming`].
+    extract_streaming: bool,
+    /// See [`OciConfig::cache_streamed_layers`].
+    cache_streamed_layers: bool,
+    /// Serializes pulls of the same reference within this process.
+    pull_locks: PullLocks,
+    /// Parsed configs of recently used references.
+    configs: ConfigCache,
+}
+
+/// Sets the flag when dropped, e.g. when a pull times out or is cancelled.
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// One async lock per image reference being pulled.
+///
+/// Entries are weak so a reference nobody is pulling holds no lock; dead
+/// entries are swept whenever a lock is handed out.
+#[derive(Default)]
+struct PullLocks(std::sync::Mutex<HashMap<String, Weak<tokio::sync::Mutex<()>>>>);
+
+impl PullLocks {
+    /// Returns the lock for `reference`, shared with every caller that
+    /// still holds it.
+    fn get(&self, reference: &str) -> Arc<tokio::sync::Mutex<()>> {
+        let mut locks = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        locks.retain(|_, lock| lock.strong_count() > 0);
+        if let Some(lock) = locks.get(reference).and_then(Weak::upgrade) {
+            return lock;
+        }
+        let lock = Arc::default();
+        locks.insert(reference.to_owned(), Arc::downgrade(&lock));
+        lock
+    }
+}
+
+/// The parsed configs of the most recently used references, so repeated
+/// [`ensure`](Oci::ensure) calls skip parsing the stored JSON.
+///
+/// Each entry remembers the manifest digest it was parsed for and is only
+/// used while the reference still points there, so changes made through
+/// another `Oci` on the same store are picked up too.
+#[derive(Default)]
+struct ConfigCache(std::sync::Mutex<VecDeque<ConfigEntry>>);
+
+/// One [`ConfigCache`] entry.
+struct ConfigEntry {
+    /// Image reference.
+    reference: String,
+    /// Manifest digest the config belongs to.
+    digest: String,
+    /// The parsed config, `None` if it was missing or malformed.
+    config: Option<ImageConfig>,
+}
+
+impl ConfigCache {
+    /// Entries kept; the least recently used is dropped beyond this.
+    const CAPACITY: usize = 64;
+
+    /// Returns the config of `reference` at `digest`, parsing it with
+    /// `load` on a miss.
+    fn get_or_load(
+        &self,
+        reference: &str,
+        digest: &str,
+        load: impl FnOnce() -> Result<Option<ImageConfig>>,
+    ) -> Result<Option<ImageConfig>> {
+        {
+            let mut entries = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(at) = entries
+                .iter()
+                .position(|e| e.reference == reference && e.digest == digest)
+            {
+                entries.make_contiguous()[..=at].rotate_right(1);
+                return Ok(entries.front().and_then(|e| e.config.clone()));
+            }
+        }
+        let config = load()?;
+        let mut entries = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.retain(|e| e.reference != reference);
+        entries.truncate(Self::CAPACITY - 1);
+        entries.push_front(ConfigEntry {
+            reference: reference.to_owned(),
+            digest: digest.to_owned(),
+            config: config.clone(),
+        });
+        Ok(config)
+    }
+
+    /// Forgets `reference`, e.g. after it was pulled again or removed.
+    fn invalidate(&self, reference: &str) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|e| e.reference != reference);
+    }
+}
+
+/// Clients used only for registries listed as insecure.
+struct Insecure {
+    /// `host[:port]` entries from the configuration.
+    registries: Vec<String>,
+    /// HTTPS without certificate verification (tried first).
+    tls: oci_client::Client,
+    /// Plain HTTP (fallback).
+    http: oci_client::Client,
+}
+
+impl Insecure {
+    /// Returns `true` if `registry` (`host[:port]`) is listed.
+    fn matches(&self, registry: &str) -> bool {
+        let host = registry.rsplit_once(':').map_or(registry, |(h, _)| h);
+        self.registries.iter().any(|r| r == registry || r == host)
+    }
 }
 
 impl std::fmt::Debug for Oci {
@@ -163,12 +607,56 @@ pub fn open() -> Result<Self> {
 
     /// Opens the OCI manager with explicit configuration.
     pub fn open_with(config: OciConfig) -> Result<Self> {
-        let store = Store::open(&config.store_dir)?;
-        let client = oci_client::Client::new(ClientConfig::default());
+        let client = build_client(client_config(&config)?)?;
+        Self::with_client(config, client)
+    }
+
+    /// Opens the OCI manager with a pre-built registry client, e.g. one
+    /// with a custom user agent, TLS setup or shared connection pool.
+    ///
+    /// `client` talks to every registry not listed in
+    /// [`OciConfig::insecure_registries`]; the proxy and certificate fields
+    /// of `config` only apply to the clients built for those. The client is
+    /// `Send + Sync` and its clones share connections and cached registry
+    /// tokens, so one may serve several `Oci` instances across threads.
+    pub fn with_client(config: OciConfig, client: oci_client::Client) -> Result<Self> {
+        let mut store = Store::open(&config.store_dir)?.with_busy_timeout(config.busy_timeout)?;
+        if let Some(dir) = &config.staging_dir {
+            store = store.with_staging_dir(dir)?;
+        }
+        let insecure = if config.insecure_registries.is_empty() {
+            None
+        } else {
+            Some(Insecure {
+                registries: config.insecure_registries.clone(),
+                tls: build_client(ClientConfig {
+                    accept_invalid_certificates: true,
+                    ..client_config(&config)?
+                })?,
+                http: build_client(ClientConfig {
+                    protocol: ClientProtocol::Http,
+                    ..client_config(&config)?
+                })?,
+            })
+        };
+        let credentials = match (&config.auth, &config.docker_config) {
+            (RegistryAuth::Anonymous, Some(path)) => {
+                Some(Arc::new(credentials::Credentials::new(path)))
+            }
+            _ => None,
+        };
         Ok(Self {
             store,
             client,
+            insecure,
             auth: config.auth,
+            credentials,
+            pull_timeout: config.pull_timeout,
+            signature_verifier: config.signature_verifier,
+            extract_streaming: config.extract_streaming,
+            cache_streamed_layers: config.cache_streamed_layers,
+            pull_locks: PullLocks::default(),
+            configs: ConfigCache::default(),
         })
     }
 
@@ -182,81 +670,158 @@ pub fn open_at(store_dir: &Path) -> Result<Self> {
 
     /// Pulls an image from a registry, caches layers, extracts rootfs.
     ///
-    /// Uses streaming downloads — each layer is written directly to disk
-    /// via `pull_blob`, keeping memory usage at O(chunk_size) instead of
-    /// O(total_image_size). `on_status` receives human-readable progress.
-    pub async fn pull(&self, image: &str, on_status: impl Fn(&str)) -> Result<PullResult> {
+    /// Uses streaming downloads — each layer is written directly to disk,
+    /// keeping memory usage at O(chunk_size) instead of O(total_image_size).
+    /// `on_status` receives human-readable progress and, through
+    /// [`PullObserver::progress`], the download rate and ETA.
+    ///
+    /// Fails with [`Error::SignatureVerificationFailed`] if
+    /// [`OciConfig::signature_verifier`] rejects the manifest, and with
+    /// [`Error::Timeout`] once [`OciConfig::pull_timeout`] elapses.
+    /// Dropping the future cancels the pull the same way (e.g. racing it
+    /// against a signal): a running extraction stops and removes its staging
+    /// directory, while partially downloaded layers stay on disk and the next
+    /// pull resumes them.
+    ///
+    /// Concurrent pulls of the same reference through one `Oci` run one
+    /// after another; the later ones find the layers and rootfs in place.
+    pub async fn pull(&self, image: &str, on_status: impl PullObserver) -> Result<PullResult> {
+        self.with_pull_timeout(async {
+            let lock = self.pull_locks.get(&parse_reference(image)?.to_string());
+            let _pulling = lock.lock().await;
+            Box::pin(metrics::recorded(self.pull_locked(image, &on_status))).await
+        })
+        .await
+    }
+
+    /// Applies [`OciConfig::pull_timeout`] to `fut`.
+    async fn with_pull_timeout<T>(&self, fut: impl Future<Output = Result<T>>) -> Result<T> {
+        match self.pull_timeout {
+            Some(limit) => tokio::time::timeout(limit, fut).await.map_err(|_| {
+                let timeout = Error::Timeout(limit);
+                metrics::pull_failed(&timeout);
+                timeout
+            })?,
+            None => fut.await,
+        }
+    }
+
+    /// Body of [`pull`](Self::pull); the caller holds the reference's pull
+    /// lock.
+    async fn pull_locked(&self, image: &str, on_status: &impl PullObserver) -> Result<PullResult> {
         let reference = parse_reference(image)?;
         let ref_str = reference.to_string();
 
         // 1. Pull manifest + config (small, OK in memory).
-        on_status(&format!("Pulling {ref_str}..."));
-        let (manifest, manifest_digest, config_json) = self
-            .client
-            .pull_manifest_and_config(&reference, &self.auth)
-            .await
-            .map_err(|e| Error::Registry(e.to_string()))?;
+        on_status.status(&format!("Pulling {ref_str}..."));
+        let auth = self.auth_for(reference.registry()).await?;
+        let (client, (manifest, manifest_digest, config_json)) =
+            self.pull_manifest(&reference, &auth).await?;
 
-        // 2. Stream each layer to disk — O(chunk) memory per layer.
-        let layer_count = manifest.layers.len();
-        let mut total_size: u64 = 0;
-        for (i, layer) in manifest.layers.iter().enumerate() {
-            let digest = &layer.digest;
-            let size = u64::try_from(layer.size).unwrap_or(0);
+        // 2. Keep the exact manifest bytes, which the parsed manifest has
+        // lost (fetched again by digest), and check its signature before
+        // any layer is downloaded.
+        let raw_manifest = if let Some(raw) = self.store.load_manifest(&manifest_digest)? {
+            raw
+        } else {
+            let pinned = Reference::with_digest(
+                reference.registry().to_owned(),
+                reference.repository().to_owned(),
+                manifest_digest.clone(),
+            );
+            let (raw, _) = client
+                .pull_manifest_raw(&pinned, &auth, MANIFEST_MEDIA_TYPES)
+                .await
+                .map_err(|e| Error::Registry(e.to_string()))?;
+            raw.to_vec()
+        };
+        if let Some(verifier) = &self.signature_verifier {
+            on_status.status(&format!("Verifying signature of {manifest_digest}..."));
+            verifier.verify(&ref_str, &manifest_digest, &raw_manifest)?;
+        }
+        self.store.save_manifest(&manifest_digest, &raw_manifest)?;
 
-            if self.store.has_layer(digest) {
-                on_status(&format!("Layer {}/{} cached", i + 1, layer_count));
-            } else {
-                on_status(&format!(
-                    "Downloading layer {}/{} ({size} bytes)...",
-                    i + 1,
-                    layer_count
-                ));
-                let staging = self.store.layer_staging_path(digest);
-                let mut file = tokio::fs::File::create(&staging).await?;
-                self.client
-                    .pull_blob(&reference, layer, &mut file)
-                    .await
-                    .map_err(|e| Error::Registry(e.to_string()))?;
-                self.store.commit_layer(digest, &layer.media_type, size)?;
+        // 3. Stream each layer to disk — O(chunk) memory per layer — or,
+        // when streaming extraction, straight into the rootfs.
+        let total_size: u64 = manifest
+            .layers
+            .iter()
+            .map(|l| u64::try_from(l.size).unwrap_or(0))
+            .sum();
+        let streamed = self.extract_streaming && !self.store.rootfs_complete(&manifest_digest);
+        let mut tracker = Tracker::new(
+            manifest.layers.len(),
+            manifest
+                .layers
+                .iter()
+                .filter(|l| !self.store.has_layer(&l.digest))
+                .map(|l| l.size),
+        );
+        let streamed_tally = if streamed {
+            let started = Instant::now();
+            let tally = self
+                .pull_streaming(
+                    client,
+                    &reference,
+                    &manifest,
+                    &manifest_digest,
+                    &mut tracker,
+                    on_status,
+                )
+                .await?;
+            metrics::extracted(started.elapsed());
+            Some(tally)
+        } else {
+            let layer_count = manifest.layers.len();
+            for (i, layer) in manifest.layers.iter().enumerate() {
+                let digest = &layer.digest;
+                let size = u64::try_from(layer.size).unwrap_or(0);
+
+                if self.store.has_layer(digest) {
+                    on_status.status(&format!("Layer {}/{} cached", i + 1, layer_count));
+                } else {
+                    on_status.status(&format!(
+                        "Downloading layer {}/{} ({size} bytes)...",
+                        i + 1,
+                        layer_count
+                    ));
+                    self.download_layer(client, &reference, layer, i + 1, &mut tracker, on_status)
+                        .await?;
+                    self.store
+                        .commit_layer(&ref_str, digest, &layer.media_type, size)?;
+                }
             }
-            total_size += size;
-        }
+            None
+        };
 
-        // 3. Save config blob.
+        // 4. Save config blob.
         let config_digest = &manifest.config.digest;
         self.store.save_config(config_digest, &config_json)?;
         let config = parse_image_config(&config_json);
 
-        // 4. Extract rootfs atomically (staging dir → rename).
+        // 5. Extract rootfs atomically (staging dir → rename).
         let rootfs = self.store.rootfs_path(&manifest_digest);
-        if !self.store.rootfs_complete(&manifest_digest) {
-            on_status("Extracting rootfs...");
+        let extracted = if !streamed && !self.store.rootfs_complete(&manifest_digest) {
+            on_status.status("Extracting rootfs...");
             let layer_files: Vec<(PathBuf, String)> = manifest
                 .layers
                 .iter()
                 .map(|l| (self.store.layer_path(&l.digest), l.media_type.clone()))
                 .collect();
-
-            // Clean up any stale staging dir from a previous interrupted run.
-            let staging = self.store.rootfs_staging_path(&manifest_digest);
-            if staging.exists() {
-                std::fs::remove_dir_all(&staging)?;
-            }
-
-            // Run extraction in a blocking task (CPU-bound tar I/O).
-            let staging_clone = staging.clone();
-            tokio::task::spawn_blocking(move || {
-                extract::extract_layer_files(&layer_files, &staging_clone)
-            })
-            .await
-            .map_err(|e| Error::Io(std::io::Error::other(e)))??;
-
-            self.store.commit_rootfs(&manifest_digest)?;
+            let started = Instant::now();
+            let tally = self.extract_rootfs(&manifest_digest, layer_files).await?;
+            metrics::extracted(started.elapsed());
+            Some(tally)
+        } else {
+            streamed_tally
+        };
+        if let Some(tally) = &extracted {
+            warn_unowned(tally.unowned(), on_status);
         }
 
-        // 5. Update SQLite index.
+        // 6. Update SQLite index.
         let layer_digests: Vec<String> = manifest.layers.iter().map(|l| l.digest.clone()).collect();
+        self.configs.invalidate(&ref_str);
         self.store.upsert_image(
             &ref_str,
             &manifest_digest,
@@ -265,55 +830,1027 @@ pub async fn pull(&self, image: &str, on_status: impl Fn(&str)) -> Result<PullRe
             &layer_digests,
         )?;
 
-        on_status("Done.");
+        on_status.status("Done.");
         Ok(PullResult {
             reference: ref_str,
             digest: manifest_digest,
             rootfs,
+            size: total_size,
+            layers: layer_digests,
             config,
+            rootfs_usage: extracted.map(|tally| tally.usage()),
+        })
+    }
+
+    /// Extracts `layer_files` into a staging directory and installs it as
+    /// the rootfs for `manifest_digest`. Returns what was extracted.
+    async fn extract_rootfs(
+        &self,
+        manifest_digest: &str,
+        layer_files: Vec<(PathBuf, String)>,
+    ) -> Result<extract::Tally> {
+        // Clean up any stale staging dir from a previous interrupted run.
+        let staging = self.store.rootfs_staging_path(manifest_digest);
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+
+        // Run extraction in a blocking task (CPU-bound tar I/O). If this
+        // future is dropped, the guard tells the task to stop; the task
+        // then removes its own partial tree.
+        let cancel = CancelOnDrop(Arc::new(AtomicBool::new(false)));
+        let flag = Arc::clone(&cancel.0);
+        let tally = tokio::task::spawn_blocking(move || {
+            let mut tally = extract::Tally::default();
+            let result = extract::extract_layer_files(&layer_files, &staging, &mut tally, &flag);
+            if result.is_err() {
+                std::fs::remove_dir_all(&staging).ok();
+            }
+            result.map(|()| tally)
         })
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))??;
+
+        self.store.commit_rootfs(manifest_digest)?;
+        Ok(tally)
+    }
+
+    /// Builds the rootfs for `manifest_digest` layer by layer, extracting
+    /// uncached layers as they download, and installs it.
+    ///
+    /// Layers are applied strictly in order, so whiteouts behave as in
+    /// [`extract_rootfs`](Self::extract_rootfs). A failed pull removes the
+    /// staging tree; a dropped one leaves it for the next pull to clear.
+    /// Returns what was extracted.
+    async fn pull_streaming(
+        &self,
+        client: &oci_client::Client,
+        reference: &Reference,
+        manifest: &oci_client::manifest::OciImageManifest,
+        manifest_digest: &str,
+        tracker: &mut Tracker,
+        on_status: &impl PullObserver,
+    ) -> Result<extract::Tally> {
+        let staging = self.store.rootfs_staging_path(manifest_digest);
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+        std::fs::create_dir_all(&staging)?;
+
+        let cancel = CancelOnDrop(Arc::new(AtomicBool::new(false)));
+        let layer_count = manifest.layers.len();
+        // Shared with each layer's blocking task in turn.
+        let tally = Arc::new(std::sync::Mutex::new(extract::Tally::default()));
+        for (i, layer) in manifest.layers.iter().enumerate() {
+            let applied = if self.store.has_layer(&layer.digest) {
+                on_status.status(&format!(
+                    "Extracting cached layer {}/{layer_count}...",
+                    i + 1
+                ));
+                let path = self.store.layer_path(&layer.digest);
+                let media_type = layer.media_type.clone();
+                let (rootfs, flag) = (staging.clone(), Arc::clone(&cancel.0));
+                let shared = Arc::clone(&tally);
+                tokio::task::spawn_blocking(move || {
+                    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+                    let mut applied = shared.lock().unwrap_or_else(PoisonError::into_inner);
+                    extract::apply_layer(file, &media_type, &rootfs, &mut applied, &flag)
+                })
+                .await
+                .map_err(|e| Error::Io(std::io::Error::other(e)))
+                .and_then(|r| r)
+            } else {
+                on_status.status(&format!(
+                    "Downloading and extracting layer {}/{layer_count} ({} bytes)...",
+                    i + 1,
+                    layer.size
+                ));
+                tracker.start_layer(i + 1, layer.size, 0);
+                let mut on_chunk = |bytes| {
+                    if let Some(progress) = tracker.advance(bytes, Instant::now()) {
+                        on_status.progress(&progress);
+                    }
+                };
+                let streamed = self
+                    .stream_layer(
+                        client,
+                        reference,
+                        layer,
+                        &staging,
+                        &tally,
+                        &cancel.0,
+                        &mut on_chunk,
+                    )
+                    .await;
+                if streamed.is_ok() {
+                    on_status.progress(&tracker.finish_layer(Instant::now()));
+                }
+                streamed
+            };
+            if let Err(e) = applied {
+                std::fs::remove_dir_all(&staging).ok();
+                return Err(e);
+            }
+        }
+
+        self.store.commit_rootfs(manifest_digest)?;
+        Ok(std::mem::take(
+            &mut tally.lock().unwrap_or_else(PoisonError::into_inner),
+        ))
+    }
+
+    /// Downloads one layer, applying it to `rootfs` as it arrives and,
+    /// with [`OciConfig::cache_streamed_layers`], storing the blob too.
+    ///
+    /// The digest is checked once the download ends; a mismatch fails the
+    /// pull, so the staging rootfs holding the bad data is never installed.
+    /// What was extracted is recorded in `tally`, and the size of each chunk
+    /// received is passed to `on_chunk`.
+    #[allow(clippy::too_many_arguments)]
+    async fn stream_layer(
+        &self,
+        client: &oci_client::Client,
+        reference: &Reference,
+        layer: &OciDescriptor,
+        rootfs: &Path,
+        tally: &Arc<std::sync::Mutex<extract::Tally>>,
+        cancel: &Arc<AtomicBool>,
+        on_chunk: &mut impl FnMut(usize),
+    ) -> Result<()> {
+        /// Chunks buffered between the download and the extraction.
+        const IN_FLIGHT: usize = 16;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(IN_FLIGHT);
+        let media_type = layer.media_type.clone();
+        let (target, flag) = (rootfs.to_path_buf(), Arc::clone(cancel));
+        let shared = Arc::clone(tally);
+        let apply = tokio::task::spawn_blocking(move || {
+            let mut applied = shared.lock().unwrap_or_else(PoisonError::into_inner);
+            let reader = extract::ChunkReader::new(rx);
+            extract::apply_layer(reader, &media_type, &target, &mut applied, &flag)
+        });
+
+        let staging = self.store.layer_staging_path(&layer.digest);
+        let mut hasher = store::DigestHasher::new(&layer.digest);
+        // Collect the download's outcome instead of returning early: the
+        // extraction must finish before the caller removes its tree.
+        let downloaded = async {
+            let mut stream = client
+                .pull_blob_stream(reference, layer)
+                .await
+                .map_err(|e| Error::Registry(e.to_string()))?
+                .stream;
+            let mut blob = if self.cache_streamed_layers {
+                Some(tokio::fs::File::create(&staging).await?)
+            } else {
+                None
+            };
+            let mut sender = Some(tx);
+            while let Some(next) = stream.next().await {
+                let chunk = next?;
+                metrics::downloaded(chunk.len());
+                on_chunk(chunk.len());
+                hasher.update(&chunk);
+                if let Some(file) = &mut blob {
+                    file.write_all(&chunk).await?;
+                }
+                // The extraction may stop reading before the blob ends
+                // (tar padding, gzip trailer); keep hashing the rest.
+                if let Some(open) = &sender
+                    && open.send(chunk).await.is_err()
+                {
+                    sender = None;
+                }
+            }
+            if let Some(mut file) = blob {
+                file.flush().await?;
+            }
+            Ok::<_, Error>(())
+        }
+        .await;
+        let applied = apply
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+        downloaded?;
+
+        if !hasher.matches(&layer.digest) {
+            tokio::fs::remove_file(&staging).await.ok();
+            return Err(Error::Registry(format!(
+                "layer {} failed digest verification",
+                layer.digest
+            )));
+        }
+        applied?;
+
+        let size = u64::try_from(layer.size).unwrap_or(0);
+        let ref_str = reference.to_string();
+        if self.cache_streamed_layers {
+            self.store
+                .commit_layer(&ref_str, &layer.digest, &layer.media_type, size)
+        } else {
+            self.store
+                .record_layer(&ref_str, &layer.digest, &layer.media_type, size)
+        }
+    }
+
+    /// Streams a layer into its staging file and verifies its digest.
+    ///
+    /// A staging file left by an interrupted pull is resumed with a range
+    /// request; registries that ignore the range send the whole blob, which
+    /// replaces the partial file. Progress is reported as layer `number`
+    /// of those `tracker` follows.
+    async fn download_layer(
+        &self,
+        client: &oci_client::Client,
+        reference: &Reference,
+        layer: &OciDescriptor,
+        number: usize,
+        tracker: &mut Tracker,
+        on_status: &impl PullObserver,
+    ) -> Result<()> {
+        let registry_err =
+            |e: oci_client::errors::OciDistributionError| Error::Registry(e.to_string());
+        let staging = self.store.layer_staging_path(&layer.digest);
+        let expected = u64::try_from(layer.size).unwrap_or(0);
+        let have = tokio::fs::metadata(&staging).await.map_or(0, |m| m.len());
+
+        let (mut stream, append) = if have > 0 && have < expected {
+            on_status.status(&format!("Resuming {} at {have} bytes", layer.digest));
+            match client
+                .pull_blob_stream_partial(reference, layer, have, None)
+                .await
+                .map_err(registry_err)?
+            {
+                BlobResponse::Partial(partial) => (partial.stream, true),
+                BlobResponse::Full(full) => (full.stream, false),
+            }
+        } else if have > 0 && have == expected {
+            // Fully downloaded before the interruption; only verify below.
+            (futures_util::stream::empty().boxed(), true)
+        } else {
+            let full = client
+                .pull_blob_stream(reference, layer)
+                .await
+                .map_err(registry_err)?;
+            (full.stream, false)
+        };
+
+        tracker.start_layer(number, layer.size, if append { have } else { 0 });
+        let mut file = if append {
+            tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(&staging)
+                .await?
+        } else {
+            tokio::fs::File::create(&staging).await?
+        };
+        while let Some(next) = stream.next().await {
+            let chunk = next?;
+            metrics::downloaded(chunk.len());
+            file.write_all(&chunk).await?;
+            if let Some(progress) = tracker.advance(chunk.len(), Instant::now()) {
+                on_status.progress(&progress);
+            }
+        }
+        file.flush().await?;
+        on_status.progress(&tracker.finish_layer(Instant::now()));
+        drop(file);
+
+        let path = staging.clone();
+        let digest = layer.digest.clone();
+        let verified =
+            tokio::task::spawn_blocking(move || store::file_matches_digest(&path, &digest))
+                .await
+                .map_err(|e| Error::Io(std::io::Error::other(e)))??;
+        if !verified {
+            tokio::fs::remove_file(&staging).await.ok();
+            return Err(Error::Registry(format!(
+                "layer {} failed digest verification",
+                layer.digest
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fetches the manifest and config, returning the client that reached
+    /// the registry so layers are pulled the same way.
+    ///
+    /// Insecure registries are tried over HTTPS without verification first,
+    /// then over plain HTTP.
+    async fn pull_manifest(
+        &self,
+        reference: &Reference,
+        auth: &RegistryAuth,
+    ) -> Result<(
+        &oci_client::Client,
+        (oci_client::manifest::OciImageManifest, String, String),
+    )> {
+        let registry_err =
+            |e: oci_client::errors::OciDistributionError| Error::Registry(e.to_string());
+        let Some(insecure) = self
+            .insecure
+            .as_ref()
+            .filter(|i| i.matches(reference.registry()))
+        else {
+            let pulled = self
+                .client
+                .pull_manifest_and_config(reference, auth)
+                .await
+                .map_err(registry_err)?;
+            return Ok((&self.client, pulled));
+        };
+        match insecure.tls.pull_manifest_and_config(reference, auth).await {
+            Ok(pulled) => Ok((&insecure.tls, pulled)),
+            Err(tls_err) => {
+                let pulled = insecure
+                    .http
+                    .pull_manifest_and_config(reference, auth)
+                    .await
+                    .map_err(|e| Error::Registry(format!("https: {tls_err}; http: {e}")))?;
+                Ok((&insecure.http, pulled))
+            }
+        }
     }
 
     /// Returns a cached [`PullResult`] if already present, otherwise pulls.
     ///
+    /// Same as [`ensure_with_policy`](Self::ensure_with_policy) with
+    /// [`PullPolicy::IfNotPresent`].
+    pub async fn ensure(&self, image: &str, on_status: impl PullObserver) -> Result<PullResult> {
+        self.ensure_with_policy(image, PullPolicy::IfNotPresent, on_status)
+            .await
+    }
+
+    /// Returns the image as `policy` allows: from the cache, from the
+    /// registry, or from the cache after checking the registry.
+    ///
     /// This is the preferred entry point for `bux run <image>` — instant when
     /// cached. Uses [`rootfs_complete`](Store::rootfs_complete) to verify the
-    /// extraction finished successfully (crash-safe).
-    pub async fn ensure(&self, image: &str, on_status: impl Fn(&str)) -> Result<PullResult> {
-        let reference = parse_reference(image)?;
-        let ref_str = reference.to_string();
+    /// extraction finished successfully (crash-safe). Concurrent calls for
+    /// the same reference through one `Oci` share a single pull: later
+    /// callers wait for it and return the cached result.
+    pub async fn ensure_with_policy(
+        &self,
+        image: &str,
+        policy: PullPolicy,
+        on_status: impl PullObserver,
+    ) -> Result<PullResult> {
+        let ref_str = self.resolve(image)?;
+        let pull = Box::pin(metrics::recorded(self.pull_locked(&ref_str, &on_status)));
+        self.with_pull_timeout(self.ensure_with(&ref_str, policy, pull))
+            .await
+    }
 
-        // Check if we have a complete cached rootfs for this reference.
-        if let Some(digest) = self.store.get_digest(&ref_str)?
-            && self.store.rootfs_complete(&digest)
+    /// Applies `policy`: returns the cached image, or runs `pull` under the
+    /// reference's pull lock unless another caller cached it while this one
+    /// waited.
+    async fn ensure_with(
+        &self,
+        ref_str: &str,
+        policy: PullPolicy,
+        pull: impl Future<Output = Result<PullResult>>,
+    ) -> Result<PullResult> {
+        match policy {
+            PullPolicy::Never => {
+                return self
+                    .cached(ref_str)?
+                    .ok_or_else(|| Error::NotFound(format!("{ref_str} (pull policy is never)")));
+            }
+            PullPolicy::IfNotPresent => {
+                if let Some(cached) = self.cached(ref_str)? {
+                    return Ok(cached);
+                }
+            }
+            PullPolicy::Always => {}
+        }
+        let lock = self.pull_locks.get(ref_str);
+        let _pulling = lock.lock().await;
+        if policy == PullPolicy::IfNotPresent
+            && let Some(cached) = self.cached(ref_str)?
         {
-            let rootfs = self.store.rootfs_path(&digest);
-            let config = self
+            return Ok(cached);
+        }
+        pull.await
+    }
+
+    /// Builds the [`PullResult`] for `ref_str` if its rootfs is complete,
+    /// recording the use for [`prune_to_size`](Self::prune_to_size).
+    #[allow(clippy::print_stderr)] // the crate has no logger; see `touch` below
+    fn cached(&self, ref_str: &str) -> Result<Option<PullResult>> {
+        let Some(digest) = self.store.get_digest(ref_str)? else {
+            return Ok(None);
+        };
+        if !self.store.rootfs_complete(&digest) {
+            return Ok(None);
+        }
+        // Best effort: a failed LRU update only skews eviction order.
+        if let Err(e) = self.store.touch(ref_str) {
+            eprintln!("[bux-oci] recording use of {ref_str}: {e}");
+        }
+        let config = self.image_config(ref_str, &digest)?;
+        Ok(Some(PullResult {
+            size: self.store.image_size(ref_str)?.unwrap_or(0),
+            layers: self.store.image_layers(ref_str)?,
+            reference: ref_str.to_owned(),
+            rootfs: self.store.rootfs_path(&digest),
+            digest,
+            config,
+            rootfs_usage: None,
+        }))
+    }
+
+    /// The parsed config of `ref_str`, whose manifest digest is `digest`,
+    /// from [`ConfigCache`] or the store.
+    fn image_config(&self, ref_str: &str, digest: &str) -> Result<Option<ImageConfig>> {
+        self.configs.get_or_load(ref_str, digest, || {
+            Ok(self
                 .store
-                .load_image_config(&ref_str)?
-                .and_then(|json| serde_json::from_str(&json).ok());
-            return Ok(PullResult {
-                reference: ref_str,
-                digest,
-                rootfs,
-                config,
+                .load_image_config(ref_str)?
+                .and_then(|json| parse_image_config(&json)))
+        })
+    }
+
+    /// Returns the media type and exact bytes of `image`'s manifest, as
+    /// pulled, for verifying signatures or attestations against it.
+    ///
+    /// For multi-platform images this is the platform manifest that was
+    /// extracted, not the index. Images pulled by bux versions that did not
+    /// keep manifests fail with [`Error::NotFound`] until pulled again.
+    pub fn manifest(&self, image: &str) -> Result<(String, Vec<u8>)> {
+        let ref_str = self.resolve(image)?;
+        let digest = self
+            .store
+            .get_digest(&ref_str)?
+            .ok_or_else(|| Error::NotFound(ref_str.clone()))?;
+        let raw = self
+            .store
+            .load_manifest(&digest)?
+            .ok_or_else(|| Error::NotFound(format!("manifest {digest} of {ref_str}")))?;
+        Ok((manifest_media_type(&raw), raw))
+    }
+
+    /// Where the blob of layer `digest` lives:
+    /// `layers/sha256-<hex>.tar.gz` under the store root, whatever the
+    /// layer's compression.
+    ///
+    /// The store layout is stable (see the crate README), so tools such as
+    /// backup scripts or scanners may read these paths; they must not write
+    /// to them. Only the path is computed: the file exists if the layer was
+    /// downloaded and kept (see [`OciConfig::cache_streamed_layers`]).
+    pub fn layer_path(&self, digest: &str) -> PathBuf {
+        self.store.layer_path(digest)
+    }
+
+    /// Where the config blob `digest` lives: `configs/sha256-<hex>.json`.
+    /// See [`layer_path`](Self::layer_path).
+    pub fn config_path(&self, digest: &str) -> PathBuf {
+        self.store.config_path(digest)
+    }
+
+    /// Where the extracted rootfs of the image with manifest digest
+    /// `manifest_digest` lives: `rootfs/sha256-<hex>/`. The digest of a
+    /// stored reference is [`ImageMeta::digest`], e.g. from
+    /// [`inspect`](Self::inspect). See [`layer_path`](Self::layer_path).
+    pub fn rootfs_path(&self, manifest_digest: &str) -> PathBuf {
+        self.store.rootfs_path(manifest_digest)
+    }
+
+    /// Bytes of file data in the extracted rootfs of `manifest_digest`, or
+    /// 0 if there is none. Walks the whole tree.
+    pub fn rootfs_size(&self, manifest_digest: &str) -> u64 {
+        store::disk_usage(&self.store.rootfs_path(manifest_digest))
+    }
+
+    /// Describes a stored image, including its ordered layer list.
+    pub fn inspect(&self, image: &str) -> Result<ImageInspect> {
+        let ref_str = self.resolve(image)?;
+        let meta = self
+            .store
+            .image_meta(&ref_str)?
+            .ok_or_else(|| Error::NotFound(ref_str.clone()))?;
+        let config = self.image_config(&ref_str, &meta.digest)?;
+        Ok(ImageInspect {
+            rootfs: self.store.rootfs_path(&meta.digest),
+            layers: self.store.layers_for_image(&ref_str)?,
+            config,
+            meta,
+        })
+    }
+
+    /// Lists the tags of `repo` in its registry, in the registry's order.
+    ///
+    /// `repo` is parsed like an image reference; a tag or digest in it is
+    /// ignored. Credentials and insecure registries are handled as for
+    /// [`pull`](Self::pull). Returns [`Error::NotFound`] if the registry
+    /// does not know the repository and [`Error::Unauthorized`] if it
+    /// wants credentials.
+    pub async fn list_tags(&self, repo: &str) -> Result<Vec<String>> {
+        let reference = parse_reference(repo)?;
+        let Some(insecure) = self
+            .insecure
+            .as_ref()
+            .filter(|i| i.matches(reference.registry()))
+        else {
+            return self.fetch_tags(&self.client, &reference).await;
+        };
+        match self.fetch_tags(&insecure.tls, &reference).await {
+            Ok(tags) => Ok(tags),
+            Err(Error::Registry(tls_err)) => self
+                .fetch_tags(&insecure.http, &reference)
+                .await
+                .map_err(|err| match err {
+                    Error::Registry(http_err) => {
+                        Error::Registry(format!("https: {tls_err}; http: {http_err}"))
+                    }
+                    other => other,
+                }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Credentials for `registry`: [`OciConfig::auth`], or what the Docker
+    /// config holds for it.
+    async fn auth_for(&self, registry: &str) -> Result<RegistryAuth> {
+        let Some(credentials) = &self.credentials else {
+            return Ok(self.auth.clone());
+        };
+        let (lookup, host) = (Arc::clone(credentials), registry.to_owned());
+        tokio::task::spawn_blocking(move || lookup.resolve(&host))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))?
+    }
+
+    /// Pages through `tags/list` with `n`/`last` until the registry returns
+    /// an empty page or stops advancing.
+    async fn fetch_tags(
+        &self,
+        client: &oci_client::Client,
+        reference: &Reference,
+    ) -> Result<Vec<String>> {
+        let auth = self.auth_for(reference.registry()).await?;
+        let mut tags = Vec::new();
+        let mut last: Option<String> = None;
+        loop {
+            let page = client
+                .list_tags(reference, &auth, Some(TAGS_PAGE_SIZE), last.as_deref())
+                .await
+                .map_err(|e| tags_error(reference, &e))?;
+            let Some(next) = page.tags.last().cloned() else {
+                break;
+            };
+            if last.as_ref() == Some(&next) {
+                break;
+            }
+            tags.extend(page.tags);
+            last = Some(next);
+        }
+        Ok(tags)
+    }
+
+    /// Lists every stored layer with the images that share it, the most
+    /// shared first, for seeing where disk space goes and what
+    /// [`remove`](Self::remove) would free. Layers no image references any
+    /// more are listed with none until [`prune`](Self::prune)d.
+    pub fn layer_refs(&self) -> Result<Vec<LayerRefs>> {
+        self.store.layer_refs()
+    }
+
+    /// Returns the build steps of a stored image, bottom first.
+    ///
+    /// Pairs each non-empty `history` entry of the image config with the
+    /// next layer. Images whose config has no history get one bare entry
+    /// per layer; layers the history does not account for are appended
+    /// the same way.
+    pub fn history(&self, image: &str) -> Result<Vec<HistoryEntry>> {
+        let ref_str = self.resolve(image)?;
+        if self.store.get_digest(&ref_str)?.is_none() {
+            return Err(Error::NotFound(ref_str));
+        }
+        let config = self.store.load_image_config(&ref_str)?;
+        let layers = self.store.layers_for_image(&ref_str)?;
+        Ok(correlate_history(config.as_deref(), &layers))
+    }
+
+    /// Re-hashes every stored layer of `image` and checks that its rootfs
+    /// extraction completed.
+    ///
+    /// Reads each blob in full; expect this to take a while for large
+    /// images.
+    pub fn verify(&self, image: &str) -> Result<VerifyReport> {
+        let ref_str = self.resolve(image)?;
+        let manifest_digest = self
+            .store
+            .get_digest(&ref_str)?
+            .ok_or_else(|| Error::NotFound(ref_str.clone()))?;
+        let layers = self
+            .store
+            .image_layers(&ref_str)?
+            .into_iter()
+            .map(|digest| {
+                let status = if !self.store.has_layer(&digest) {
+                    LayerStatus::Missing
+                } else if self.store.verify_layer(&digest)? {
+                    LayerStatus::Ok
+                } else {
+                    LayerStatus::Corrupt
+                };
+                Ok(LayerCheck { digest, status })
+            })
+            .collect::<Result<_>>()?;
+        Ok(VerifyReport {
+            rootfs_complete: self.store.rootfs_complete(&manifest_digest),
+            reference: ref_str,
+            digest: manifest_digest,
+            layers,
+        })
+    }
+
+    /// Verifies `image`, re-downloads only its missing or corrupt layers,
+    /// and re-extracts the rootfs if anything was wrong.
+    ///
+    /// Layers are fetched by manifest digest, so a tag that has since moved
+    /// upstream does not change the stored image. The rootfs is replaced in
+    /// place; do not repair an image that running VMs are using. Returns the
+    /// report taken after the repair.
+    pub async fn repair(&self, image: &str, on_status: impl PullObserver) -> Result<VerifyReport> {
+        let ref_str = self.resolve(image)?;
+        let before = self.verify(&ref_str)?;
+        if before.is_ok() {
+            return Ok(before);
+        }
+
+        let bad: Vec<&LayerCheck> = before
+            .layers
+            .iter()
+            .filter(|l| l.status != LayerStatus::Ok)
+            .collect();
+        if !bad.is_empty() {
+            let reference = parse_reference(&ref_str)?;
+            let pinned = Reference::with_digest(
+                reference.registry().to_owned(),
+                reference.repository().to_owned(),
+                before.digest.clone(),
+            );
+            on_status.status(&format!("Fetching manifest {}...", before.digest));
+            let auth = self.auth_for(pinned.registry()).await?;
+            let (client, (manifest, _, _)) = self.pull_manifest(&pinned, &auth).await?;
+            let sizes = bad.iter().map(|check| {
+                manifest
+                    .layers
+                    .iter()
+                    .find(|l| l.digest == check.digest)
+                    .map_or(0, |l| l.size)
             });
+            let mut tracker = Tracker::new(bad.len(), sizes);
+            for (i, check) in bad.into_iter().enumerate() {
+                let layer = manifest
+                    .layers
+                    .iter()
+                    .find(|l| l.digest == check.digest)
+                    .ok_or_else(|| {
+                        Error::Registry(format!(
+                            "manifest {} has no layer {}",
+                            before.digest, check.digest
+                        ))
+                    })?;
+                if check.status == LayerStatus::Corrupt {
+                    std::fs::remove_file(self.store.layer_path(&check.digest))?;
+                }
+                on_status.status(&format!("Re-downloading {}...", check.digest));
+                self.download_layer(client, &pinned, layer, i + 1, &mut tracker, &on_status)
+                    .await?;
+                self.store.restore_layer(&check.digest)?;
+            }
         }
 
-        self.pull(image, on_status).await
+        on_status.status("Re-extracting rootfs...");
+        let rootfs = self.store.rootfs_path(&before.digest);
+        if rootfs.exists() {
+            std::fs::remove_dir_all(&rootfs)?;
+        }
+        let layer_files = before
+            .layers
+            .iter()
+            .map(|l| {
+                let media_type = self
+                    .store
+                    .layer_media_type(&l.digest)?
+                    .ok_or_else(|| Error::NotFound(l.digest.clone()))?;
+                Ok((self.store.layer_path(&l.digest), media_type))
+            })
+            .collect::<Result<_>>()?;
+        let tally = self.extract_rootfs(&before.digest, layer_files).await?;
+        warn_unowned(tally.unowned(), &on_status);
+
+        on_status.status("Done.");
+        self.verify(&ref_str)
     }
 
     /// Lists all locally stored images.
+    ///
+    /// Blocking; see [`images_async`](Self::images_async).
     pub fn images(&self) -> Result<Vec<ImageMeta>> {
         self.store.list_images()
     }
 
+    /// Lists all locally stored images without blocking the runtime.
+    pub async fn images_async(&self) -> Result<Vec<ImageMeta>> {
+        self.blocking(Store::list_images).await
+    }
+
+    /// Lists locally stored images matching all `filters`, leaving out
+    /// unreadable records (see [`image_list`](Self::image_list)).
+    ///
+    /// Blocking; see [`images_filtered_async`](Self::images_filtered_async).
+    pub fn images_filtered(&self, filters: &[ImageFilter]) -> Result<Vec<ImageMeta>> {
+        self.store.list_images_filtered(filters)
+    }
+
+    /// Lists locally stored images matching all `filters` without blocking
+    /// the runtime.
+    pub async fn images_filtered_async(&self, filters: &[ImageFilter]) -> Result<Vec<ImageMeta>> {
+        let owned = filters.to_vec();
+        self.blocking(move |store| store.list_images_filtered(&owned))
+            .await
+    }
+
+    /// Lists locally stored images matching all `filters`, counting the
+    /// index records too damaged to read instead of failing on them.
+    ///
+    /// Blocking; see [`image_list_async`](Self::image_list_async).
+    pub fn image_list(&self, filters: &[ImageFilter]) -> Result<ImageList> {
+        self.store.scan_images(filters)
+    }
+
+    /// Like [`image_list`](Self::image_list), without blocking the runtime.
+    pub async fn image_list_async(&self, filters: &[ImageFilter]) -> Result<ImageList> {
+        let owned = filters.to_vec();
+        self.blocking(move |store| store.scan_images(&owned)).await
+    }
+
+    /// Adds `new_ref` as a local alias for the already-stored `source`.
+    ///
+    /// Both references share blobs and rootfs; removing one leaves the other
+    /// intact.
+    pub fn tag(&self, source: &str, new_ref: &str) -> Result<()> {
+        let from = self.resolve(source)?;
+        let to = parse_reference(new_ref)?.to_string();
+        self.configs.invalidate(&to);
+        self.store.tag_image(&from, &to)
+    }
+
+    /// Copies the stored `image` into the store of `dest` under the same
+    /// reference, as a pull there would leave it: manifest, config and
+    /// layer blobs, the extracted rootfs and the image record.
+    ///
+    /// For promoting images between stores without a registry. Layers
+    /// `dest` already has are not copied; the others are checked against
+    /// their digests on the way. `dest`'s signature verifier, if any,
+    /// checks the manifest first. The image must be fully cached here:
+    /// layers extracted while streaming and not kept (see
+    /// [`OciConfig::cache_streamed_layers`]) fail with [`Error::NotFound`].
+    pub async fn copy_to(&self, image: &str, dest: &Self) -> Result<()> {
+        let ref_str = self.resolve(image)?;
+        let digest = self
+            .store
+            .get_digest(&ref_str)?
+            .ok_or_else(|| Error::NotFound(ref_str.clone()))?;
+        let raw_manifest = self
+            .store
+            .load_manifest(&digest)?
+            .ok_or_else(|| Error::NotFound(format!("manifest {digest} of {ref_str}")))?;
+        let manifest: oci_client::manifest::OciImageManifest =
+            serde_json::from_slice(&raw_manifest)?;
+        let config_digest = &manifest.config.digest;
+        let config_json =
+            std::fs::read_to_string(self.store.config_path(config_digest)).map_err(|e| match e
+                .kind()
+            {
+                std::io::ErrorKind::NotFound => {
+                    Error::NotFound(format!("config {config_digest} of {ref_str}"))
+                }
+                _ => e.into(),
+            })?;
+        if let Some(verifier) = &dest.signature_verifier {
+            verifier.verify(&ref_str, &digest, &raw_manifest)?;
+        }
+
+        // Waits out a pull of the same reference into `dest`.
+        let lock = dest.pull_locks.get(&ref_str);
+        let _guard = lock.lock().await;
+
+        for layer in &manifest.layers {
+            if dest.store.has_layer(&layer.digest) {
+                continue;
+            }
+            let from = self.store.layer_path(&layer.digest);
+            if !from.is_file() {
+                return Err(Error::NotFound(format!(
+                    "layer {} of {ref_str}",
+                    layer.digest
+                )));
+            }
+            let to = dest.store.layer_staging_path(&layer.digest);
+            let layer_digest = layer.digest.clone();
+            tokio::task::spawn_blocking(move || copy_blob(&from, &to, &layer_digest))
+                .await
+                .map_err(|e| Error::Io(std::io::Error::other(e)))??;
+            let size = u64::try_from(layer.size).unwrap_or(0);
+            dest.store
+                .commit_layer(&ref_str, &layer.digest, &layer.media_type, size)?;
+        }
+        dest.store.save_manifest(&digest, &raw_manifest)?;
+        dest.store.save_config(config_digest, &config_json)?;
+
+        if !dest.store.rootfs_complete(&digest) {
+            let layer_files = manifest
+                .layers
+                .iter()
+                .map(|l| (dest.store.layer_path(&l.digest), l.media_type.clone()))
+                .collect();
+            dest.extract_rootfs(&digest, layer_files).await?;
+        }
+
+        let total_size = manifest
+            .layers
+            .iter()
+            .map(|l| u64::try_from(l.size).unwrap_or(0))
+            .sum();
+        let layer_digests: Vec<String> = manifest.layers.iter().map(|l| l.digest.clone()).collect();
+        dest.configs.invalidate(&ref_str);
+        dest.store
+            .upsert_image(&ref_str, &digest, total_size, config_digest, &layer_digests)
+    }
+
     /// Removes a locally stored image and its extracted rootfs.
     ///
-    /// Layer blobs are ref-counted; only orphaned blobs are deleted.
+    /// Layer blobs are ref-counted; only orphaned blobs are deleted. A
+    /// digest prefix removes every reference to that image.
+    /// Blocking; see [`remove_async`](Self::remove_async).
     pub fn remove(&self, image: &str) -> Result<()> {
-        let reference = parse_reference(image)?;
-        self.store.remove_image(&reference.to_string())
+        for reference in self.resolve_all(image)? {
+            self.configs.invalidate(&reference);
+            self.store.remove_image(&reference)?;
+        }
+        Ok(())
+    }
+
+    /// Like [`remove`](Self::remove), without blocking the runtime.
+    pub async fn remove_async(&self, image: &str) -> Result<()> {
+        let references = self.resolve_all(image)?;
+        for reference in &references {
+            self.configs.invalidate(reference);
+        }
+        self.blocking(move |store| {
+            for reference in &references {
+                store.remove_image(reference)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Resolves `image` to one stored reference; see
+    /// [`resolve_all`](Self::resolve_all). Of several references to a
+    /// digest, the most recently used wins.
+    fn resolve(&self, image: &str) -> Result<String> {
+        self.resolve_all(image)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::NotFound(image.to_owned()))
+    }
+
+    /// Resolves `image` the way image commands accept it: a stored
+    /// reference as is, else a manifest digest prefix (`sha256:1a2b3c` or
+    /// `1a2b3c`) to every reference of that image, most recently used
+    /// first, else the normalized reference (which may not be stored yet).
+    fn resolve_all(&self, image: &str) -> Result<Vec<String>> {
+        let parsed = parse_reference(image).map(|r| r.to_string());
+        if let Ok(reference) = &parsed
+            && self.store.get_digest(reference)?.is_some()
+        {
+            return Ok(vec![reference.clone()]);
+        }
+        match self.store.resolve_digest_prefix(image) {
+            Ok(digest) => return self.store.references_to(&digest),
+            Err(Error::NotFound(_) | Error::InvalidReference(_)) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(vec![parsed?])
+    }
+
+    /// Deletes unreferenced layer blobs, rootfs directories, and staging
+    /// leftovers, keeping the rootfs of every manifest digest in `pinned`
+    /// (images VMs were created from). Returns bytes freed. Do not run
+    /// concurrently with a pull.
+    pub fn prune(&self, pinned: &[String]) -> Result<u64> {
+        self.store.prune(pinned)
+    }
+
+    /// Like [`prune`](Self::prune), without blocking the runtime.
+    pub async fn prune_async(&self, pinned: &[String]) -> Result<u64> {
+        let owned = pinned.to_vec();
+        self.blocking(move |store| store.prune(&owned)).await
+    }
+
+    /// Prunes like [`prune`](Self::prune), then removes the least recently
+    /// used images (by pull or [`ensure`](Self::ensure)) until the store
+    /// takes at most `max_bytes` on disk. Images whose digest is in
+    /// `pinned` stay even if that leaves the store over the cap. Returns
+    /// bytes freed. Do not run concurrently with a pull.
+    pub fn prune_to_size(&self, max_bytes: u64, pinned: &[String]) -> Result<u64> {
+        self.store.prune_to_size(max_bytes, pinned)
+    }
+
+    /// Like [`prune_to_size`](Self::prune_to_size), without blocking the
+    /// runtime.
+    pub async fn prune_to_size_async(&self, max_bytes: u64, pinned: &[String]) -> Result<u64> {
+        let owned = pinned.to_vec();
+        self.blocking(move |store| store.prune_to_size(max_bytes, &owned))
+            .await
+    }
+
+    /// Compacts the image index database. Returns bytes reclaimed.
+    ///
+    /// Intended for occasional maintenance, not for every pull.
+    pub fn maintain(&self) -> Result<u64> {
+        self.store.maintain()
+    }
+
+    /// Runs `f` on tokio's blocking pool.
+    ///
+    /// The store's SQLite connection cannot be shared across threads, so
+    /// the task opens its own; WAL mode lets it run alongside this one.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Store) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let root = self.store.root().to_path_buf();
+        let staging = self.store.staging_dir().map(Path::to_path_buf);
+        let busy_timeout = self.store.busy_timeout();
+        tokio::task::spawn_blocking(move || {
+            let mut store = Store::open(&root)?.with_busy_timeout(busy_timeout)?;
+            if let Some(dir) = &staging {
+                store = store.with_staging_dir(dir)?;
+            }
+            f(&store)
+        })
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?
+    }
+}
+
+/// Reports entries whose owner extraction could not apply, typically
+/// because the store is written by a non-root user.
+fn warn_unowned(count: u64, on_status: &impl PullObserver) {
+    if count > 0 {
+        on_status.status(&format!(
+            "warning: {count} file(s) keep the current user as owner instead of \
+             the image's; extract as root to preserve image ownership"
+        ));
+    }
+}
+
+/// Copies the blob at `from` to `to`, removing the copy again unless it
+/// matches `digest`.
+fn copy_blob(from: &Path, to: &Path, digest: &str) -> Result<()> {
+    std::fs::copy(from, to)?;
+    if store::file_matches_digest(to, digest)? {
+        return Ok(());
+    }
+    std::fs::remove_file(to).ok();
+    Err(Error::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("layer {digest} failed digest verification"),
+    )))
+}
+
+/// Tags requested per `tags/list` page. Registries may return fewer.
+const TAGS_PAGE_SIZE: usize = 1000;
+
+/// Maps a `tags/list` failure to [`Error::NotFound`], [`Error::Unauthorized`]
+/// or [`Error::Registry`].
+fn tags_error(reference: &Reference, e: &oci_client::errors::OciDistributionError) -> Error {
+    use oci_client::errors::{OciDistributionError as E, OciErrorCode as Code};
+    let repo = format!("{}/{}", reference.registry(), reference.repository());
+    match e {
+        E::UnauthorizedError { .. }
+        | E::AuthenticationFailure(_)
+        | E::ServerError {
+            code: 401 | 403, ..
+        } => Error::Unauthorized(repo),
+        E::ServerError { code: 404, .. } => Error::NotFound(repo),
+        E::RegistryError { envelope, .. } => {
+            let has = |codes: &[Code]| envelope.errors.iter().any(|err| codes.contains(&err.code));
+            if has(&[Code::Unauthorized, Code::Denied]) {
+                Error::Unauthorized(repo)
+            } else if has(&[Code::NameUnknown, Code::NotFound]) {
+                Error::NotFound(repo)
+            } else {
+                Error::Registry(e.to_string())
+            }
+        }
+        _ => Error::Registry(e.to_string()),
     }
 }
 
@@ -324,8 +1861,116 @@ fn parse_reference(image: &str) -> Result<Reference> {
         .map_err(|e: oci_client::ParseError| Error::InvalidReference(e.to_string()))
 }
 
+/// Builds a registry client from `cfg`.
+fn build_client(cfg: ClientConfig) -> Result<oci_client::Client> {
+    oci_client::Client::try_from(cfg).map_err(|e| Error::Registry(e.to_string()))
+}
+
+/// Builds the registry client configuration (proxies and TLS trust).
+fn client_config(config: &OciConfig) -> Result<ClientConfig> {
+    let mut extra_root_certificates = Vec::new();
+    for path in &config.extra_ca_certs {
+        let data = std::fs::read(path)
+            .map_err(|e| Error::Certificate(format!("{}: {e}", path.display())))?;
+        let certs = parse_certs(&data);
+        if certs.is_empty() {
+            return Err(Error::Certificate(format!(
+                "{}: no PEM certificate found",
+                path.display()
+            )));
+        }
+        extra_root_certificates.extend(certs);
+    }
+    Ok(ClientConfig {
+        http_proxy: config.http_proxy.clone(),
+        https_proxy: config.https_proxy.clone(),
+        no_proxy: config.no_proxy.clone(),
+        extra_root_certificates,
+        accept_invalid_certificates: config.accept_invalid_certs,
+        user_agent: user_agent(config.user_agent.as_deref()),
+        ..ClientConfig::default()
+    })
+}
+
+/// Returns `custom`, or bux's default, as the `'static` string
+/// [`ClientConfig`] wants.
+///
+/// Each distinct value is leaked once and reused, so opening many `Oci`s
+/// does not grow memory.
+fn user_agent(custom: Option<&str>) -> &'static str {
+    static INTERNED: std::sync::Mutex<Vec<&'static str>> = std::sync::Mutex::new(Vec::new());
+
+    let wanted = custom.map_or_else(
+        || {
+            format!(
+                "bux/{} ({}/{})",
+                env!("CARGO_PKG_VERSION"),
+                std::env::consts::OS,
+                std::env::consts::ARCH
+            )
+        },
+        str::to_owned,
+    );
+    let mut interned = INTERNED.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(ua) = interned.iter().find(|ua| **ua == wanted) {
+        return ua;
+    }
+    let ua: &'static str = Box::leak(wanted.into_boxed_str());
+    interned.push(ua);
+    ua
+}
+
+/// Splits a PEM bundle into individual certificates; non-PEM data is taken
+/// as a single DER certificate.
+fn parse_certs(data: &[u8]) -> Vec<Certificate> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let Ok(text) = std::str::from_utf8(data) else {
+        return vec![Certificate {
+            encoding: CertificateEncoding::Der,
+            data: data.to_vec(),
+        }];
+    };
+    let mut certs = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(BEGIN) {
+        let Some(len) = rest[start..].find(END) else {
+            break;
+        };
+        let end = start + len + END.len();
+        certs.push(Certificate {
+            encoding: CertificateEncoding::Pem,
+            data: rest.as_bytes()[start..end].to_vec(),
+        });
+        rest = &rest[end..];
+    }
+    certs
+}
+
+/// Returns the first non-empty environment variable among `names`.
+fn env_any(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|n| std::env::var(*n).ok().filter(|v| !v.is_empty()))
+}
+
 /// Deserializes the raw OCI config JSON blob into our minimal [`ImageConfig`].
 ///
+/// Reads a manifest's `mediaType`, which OCI makes optional; absent means
+/// an OCI image manifest.
+fn manifest_media_type(raw: &[u8]) -> String {
+    #[derive(serde::Deserialize)]
+    struct Versioned {
+        #[serde(rename = "mediaType")]
+        media_type: Option<String>,
+    }
+    serde_json::from_slice::<Versioned>(raw)
+        .ok()
+        .and_then(|v| v.media_type)
+        .unwrap_or_else(|| OCI_IMAGE_MEDIA_TYPE.to_owned())
+}
+
 /// The config blob wraps the actual config under a top-level `"config"` key.
 fn parse_image_config(data: &str) -> Option<ImageConfig> {
     #[derive(serde::Deserialize)]
@@ -335,6 +1980,54 @@ struct TopLevel {
     serde_json::from_str::<TopLevel>(data).ok()?.config
 }
 
+/// Pairs the config's `history` array with `layers` for [`Oci::history`].
+fn correlate_history(config: Option<&str>, layers: &[LayerInfo]) -> Vec<HistoryEntry> {
+    #[derive(Default, serde::Deserialize)]
+    struct TopLevel {
+        #[serde(default)]
+        history: Vec<Step>,
+    }
+    #[derive(serde::Deserialize)]
+    struct Step {
+        created: Option<String>,
+        created_by: Option<String>,
+        comment: Option<String>,
+        #[serde(default)]
+        empty_layer: bool,
+    }
+
+    let steps = config
+        .and_then(|json| serde_json::from_str::<TopLevel>(json).ok())
+        .unwrap_or_default()
+        .history;
+    let mut remaining = layers.iter();
+    let mut entries: Vec<HistoryEntry> = steps
+        .into_iter()
+        .map(|step| {
+            let layer = if step.empty_layer {
+                None
+            } else {
+                remaining.next()
+            };
+            HistoryEntry {
+                digest: layer.map(|l| l.digest.clone()),
+                size: layer.map(|l| l.size),
+                created: step.created,
+                created_by: step.created_by,
+                comment: step.comment,
+            }
+        })
+        .collect();
+    entries.extend(remaining.map(|l| HistoryEntry {
+        digest: Some(l.digest.clone()),
+        size: Some(l.size),
+        created: None,
+        created_by: None,
+        comment: None,
+    }));
+    entries
+}
+
 /// Returns the default store directory: `$BUX_HOME` or `<platform_data_dir>/bux`.
 fn dirs_default_store() -> PathBuf {
     if let Ok(home) = std::env::var("BUX_HOME") {
@@ -364,3 +2057,599 @@ fn dirs_default_store() -> PathBuf {
     }
     PathBuf::from("bux")
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposed_ports_skip_malformed_entries() {
+        let config: ImageConfig = serde_json::from_str(
+            r#"{"ExposedPorts": {"443/tcp": {}, "53/UDP": {}, "8080": {}, "x/tcp": {},
+                "70000/tcp": {}, "0/tcp": {}, "9/quic": {}}}"#,
+        )
+        .unwrap();
+        let port = |port, proto| PortSpec { port, proto };
+        assert_eq!(
+            config.exposed_ports_parsed(),
+            [
+                port(53, Proto::Udp),
+                port(443, Proto::Tcp),
+                port(8080, Proto::Tcp)
+            ]
+        );
+    }
+
+    #[test]
+    fn history_pairs_non_empty_steps_with_layers() {
+        let layer = |digest: &str, size| LayerInfo {
+            digest: digest.into(),
+            media_type: String::new(),
+            size,
+            position: 0,
+            ref_count: 1,
+        };
+        let layers = [layer("sha256:a", 10), layer("sha256:b", 20)];
+        let config = r#"{"history": [
+            {"created": "2024-01-01T00:00:00Z", "created_by": "ADD rootfs.tar /"},
+            {"created_by": "ENV PATH=/bin", "empty_layer": true},
+            {"created_by": "RUN make"}]}"#;
+
+        let entries = correlate_history(Some(config), &layers);
+        let digests: Vec<_> = entries.iter().map(|e| e.digest.as_deref()).collect();
+        assert_eq!(digests, [Some("sha256:a"), None, Some("sha256:b")]);
+        assert_eq!(entries[0].created.as_deref(), Some("2024-01-01T00:00:00Z"));
+        assert_eq!(entries[2].size, Some(20));
+
+        let bare = correlate_history(Some(r#"{"config": {}}"#), &layers);
+        assert_eq!(bare.len(), 2);
+        assert!(
+            bare.iter()
+                .all(|e| e.created_by.is_none() && e.size.is_some())
+        );
+    }
+
+    #[tokio::test]
+    async fn async_listing_sees_sync_writes() {
+        let root = std::env::temp_dir().join(format!("bux_oci_async_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let oci = Oci::open_at(&root).unwrap();
+        oci.store
+            .upsert_image(
+                "docker.io/library/alpine:latest",
+                "sha256:m",
+                1,
+                "sha256:c",
+                &[],
+            )
+            .unwrap();
+
+        let images = oci.images_async().await.unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].digest, "sha256:m");
+
+        oci.remove_async("alpine").await.unwrap();
+        assert!(oci.images().unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn concurrent_ensure_pulls_once() {
+        const REF: &str = "docker.io/library/alpine:latest";
+        let root = std::env::temp_dir().join(format!("bux_oci_ensure_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let oci = Oci::open_at(&root).unwrap();
+        let pulls = std::sync::atomic::AtomicUsize::new(0);
+        let pull = || async {
+            pulls.fetch_add(1, Ordering::Relaxed);
+            // Let the other callers run up to the lock.
+            tokio::task::yield_now().await;
+            std::fs::create_dir_all(oci.store.rootfs_path("sha256:m"))?;
+            oci.store.save_config("sha256:c", "{}")?;
+            oci.store
+                .upsert_image(REF, "sha256:m", 1, "sha256:c", &[])?;
+            Ok(oci.cached(REF)?.unwrap())
+        };
+
+        let results = futures_util::future::join_all(
+            (0..8).map(|_| oci.ensure_with(REF, PullPolicy::IfNotPresent, pull())),
+        )
+        .await;
+        assert_eq!(pulls.load(Ordering::Relaxed), 1);
+        assert!(
+            results
+                .iter()
+                .all(|r| r.as_ref().unwrap().digest == "sha256:m")
+        );
+        assert!(
+            oci.pull_locks
+                .0
+                .lock()
+                .unwrap()
+                .values()
+                .all(|l| l.strong_count() == 0)
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn ensure_follows_the_pull_policy() {
+        const REF: &str = "docker.io/library/alpine:latest";
+        let root = std::env::temp_dir().join(format!("bux_oci_policy_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let oci = Oci::open_at(&root).unwrap();
+        let pulls = std::sync::atomic::AtomicUsize::new(0);
+        // Stands in for a registry whose tag now points at `digest`.
+        let pull = |digest: &'static str| {
+            let (registry, count) = (&oci, &pulls);
+            async move {
+                count.fetch_add(1, Ordering::Relaxed);
+                std::fs::create_dir_all(registry.store.rootfs_path(digest))?;
+                registry.store.save_config("sha256:c", "{}")?;
+                registry
+                    .store
+                    .upsert_image(REF, digest, 1, "sha256:c", &[])?;
+                Ok(registry.cached(REF)?.unwrap())
+            }
+        };
+        let ensure = |policy, digest| oci.ensure_with(REF, policy, pull(digest));
+
+        // Uncached: `Never` fails without pulling, the others pull.
+        let missing = ensure(PullPolicy::Never, "sha256:old").await;
+        assert!(matches!(missing, Err(Error::NotFound(_))));
+        assert_eq!(pulls.load(Ordering::Relaxed), 0);
+        let first = ensure(PullPolicy::IfNotPresent, "sha256:old")
+            .await
+            .unwrap();
+        assert_eq!(
+            (first.digest.as_str(), pulls.load(Ordering::Relaxed)),
+            ("sha256:old", 1)
+        );
+
+        // Cached but stale: only `Always` asks the registry and moves on.
+        let kept = ensure(PullPolicy::IfNotPresent, "sha256:new")
+            .await
+            .unwrap();
+        assert_eq!(kept.digest, "sha256:old");
+        let offline = ensure(PullPolicy::Never, "sha256:new").await.unwrap();
+        assert_eq!(offline.digest, "sha256:old");
+        assert_eq!(pulls.load(Ordering::Relaxed), 1);
+        let fresh = ensure(PullPolicy::Always, "sha256:new").await.unwrap();
+        assert_eq!(
+            (fresh.digest.as_str(), pulls.load(Ordering::Relaxed)),
+            ("sha256:new", 2)
+        );
+        assert_eq!(
+            ensure(PullPolicy::Never, "sha256:x").await.unwrap().digest,
+            "sha256:new"
+        );
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn blob_paths_follow_the_documented_layout() {
+        let root = std::env::temp_dir().join(format!("bux_oci_layout_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let oci = Oci::open_at(&root).unwrap();
+
+        assert_eq!(
+            oci.layer_path("sha256:ab12"),
+            root.join("layers/sha256-ab12.tar.gz")
+        );
+        assert_eq!(
+            oci.config_path("sha256:cd34"),
+            root.join("configs/sha256-cd34.json")
+        );
+        assert_eq!(
+            oci.rootfs_path("sha256:ef56"),
+            root.join("rootfs/sha256-ef56")
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn tag_listing_errors_are_classified() {
+        use oci_client::errors::{OciDistributionError as E, OciEnvelope};
+        let reference = parse_reference("registry.lan/team/app").unwrap();
+        let envelope = |code: &str| E::RegistryError {
+            envelope: serde_json::from_str::<OciEnvelope>(&format!(
+                r#"{{"errors":[{{"code":"{code}","message":""}}]}}"#
+            ))
+            .unwrap(),
+            url: String::new(),
+        };
+        let server = |code| E::ServerError {
+            code,
+            url: String::new(),
+            message: String::new(),
+        };
+
+        for e in [envelope("NAME_UNKNOWN"), server(404)] {
+            assert!(
+                matches!(tags_error(&reference, &e), Error::NotFound(r) if r == "registry.lan/team/app")
+            );
+        }
+        for e in [
+            envelope("DENIED"),
+            server(401),
+            E::UnauthorizedError { url: String::new() },
+        ] {
+            assert!(matches!(tags_error(&reference, &e), Error::Unauthorized(_)));
+        }
+        assert!(matches!(
+            tags_error(&reference, &envelope("UNSUPPORTED")),
+            Error::Registry(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn registry_requests_carry_the_user_agent() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        /// Answers every request with 404, reporting its `User-Agent`.
+        async fn mock_registry(
+            listener: tokio::net::TcpListener,
+            agents: tokio::sync::mpsc::UnboundedSender<String>,
+        ) {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut buf = vec![0; 4096];
+                let n = conn.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let agent = request.lines().find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("user-agent")
+                        .then(|| value.trim().to_owned())
+                });
+                if let Some(ua) = agent {
+                    let _ = agents.send(ua);
+                }
+                let _ = conn
+                    .write_all(
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    )
+                    .await;
+            }
+        }
+
+        let root = std::env::temp_dir().join(format!("bux_oci_ua_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for (custom, expected) in [
+            (None, format!("bux/{} (", env!("CARGO_PKG_VERSION"))),
+            (Some("acme-ci/2.1"), "acme-ci/2.1".to_owned()),
+        ] {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            let server = tokio::spawn(mock_registry(listener, tx));
+            let oci = Oci::open_with(OciConfig {
+                store_dir: root.clone(),
+                insecure_registries: vec!["127.0.0.1".into()],
+                user_agent: custom.map(str::to_owned),
+                http_proxy: None,
+                https_proxy: None,
+                ..OciConfig::default()
+            })
+            .unwrap();
+
+            assert!(
+                oci.list_tags(&format!("127.0.0.1:{port}/app"))
+                    .await
+                    .is_err()
+            );
+            server.abort();
+            let mut agents = Vec::new();
+            while let Ok(ua) = rx.try_recv() {
+                agents.push(ua);
+            }
+            assert!(!agents.is_empty());
+            assert!(
+                agents.iter().all(|ua| ua.starts_with(&expected)),
+                "{agents:?}"
+            );
+        }
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn insecure_registries_match_host_and_port() {
+        let insecure = Insecure {
+            registries: vec!["localhost:5000".into(), "registry.lan".into()],
+            tls: oci_client::Client::default(),
+            http: oci_client::Client::default(),
+        };
+        assert!(insecure.matches("localhost:5000"));
+        assert!(!insecure.matches("localhost:5001"));
+        assert!(insecure.matches("registry.lan"));
+        assert!(insecure.matches("registry.lan:8443"));
+        assert!(!insecure.matches("docker.io"));
+
+        // HTTP registries keep their host:port in the stored reference.
+        let reference = parse_reference("localhost:5000/app:dev").unwrap();
+        assert_eq!(reference.registry(), "localhost:5000");
+        assert_eq!(reference.to_string(), "localhost:5000/app:dev");
+    }
+
+    #[test]
+    fn parse_certs_splits_pem_bundles() {
+        let bundle = "# corp roots\n\
+            -----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n\
+            junk\n\
+            -----BEGIN CERTIFICATE-----\nBBBB\n-----END CERTIFICATE-----\n";
+        let certs = parse_certs(bundle.as_bytes());
+        assert_eq!(certs.len(), 2);
+        assert!(
+            certs[1]
+                .data
+                .starts_with(b"-----BEGIN CERTIFICATE-----\nBBBB")
+        );
+        assert!(matches!(certs[0].encoding, CertificateEncoding::Pem));
+
+        assert!(parse_certs(b"no certs here").is_empty());
+        let der = parse_certs(&[0x30, 0x82, 0xff, 0xfe]);
+        assert!(matches!(der[0].encoding, CertificateEncoding::Der));
+    }
+
+    #[test]
+    fn image_commands_accept_digest_prefixes() {
+        let root = std::env::temp_dir().join(format!("bux_oci_prefix_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let oci = Oci::open_at(&root).unwrap();
+        oci.store.save_config("sha256:c", "{}").unwrap();
+        for (reference, digest) in [
+            ("docker.io/library/alpine:latest", "sha256:c0ffee"),
+            ("docker.io/library/alpine:3", "sha256:c0ffee"),
+            // A stored reference that also reads as hex wins.
+            ("docker.io/library/c0ff:latest", "sha256:99"),
+        ] {
+            oci.store
+                .upsert_image(reference, digest, 1, "sha256:c", &[])
+                .unwrap();
+        }
+
+        assert_eq!(oci.inspect("c0ffe").unwrap().meta.digest, "sha256:c0ffee");
+        assert_eq!(oci.inspect("c0ff").unwrap().meta.digest, "sha256:99");
+        assert!(matches!(oci.inspect("c0ffee1"), Err(Error::NotFound(_))));
+
+        oci.remove("sha256:c0ffe").unwrap();
+        let left: Vec<_> = oci
+            .images()
+            .unwrap()
+            .into_iter()
+            .map(|i| i.reference)
+            .collect();
+        assert_eq!(left, ["docker.io/library/c0ff:latest"]);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn verify_reports_corrupt_and_missing_layers() {
+        let root = std::env::temp_dir().join(format!("bux_oci_verify_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let oci = Oci::open_at(&root).unwrap();
+
+        let hello = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let gone = "sha256:00";
+        for digest in [hello, gone] {
+            std::fs::write(oci.store.layer_staging_path(digest), b"hello").unwrap();
+            oci.store
+                .commit_layer("docker.io/library/alpine:latest", digest, "tar", 5)
+                .unwrap();
+        }
+        let manifest = "sha256:m";
+        let layers = [hello.to_owned(), gone.to_owned()];
+        oci.store
+            .upsert_image(
+                "docker.io/library/alpine:latest",
+                manifest,
+                10,
+                "sha256:c",
+                &layers,
+            )
+            .unwrap();
+        std::fs::remove_file(oci.store.layer_path(gone)).unwrap();
+
+        let report = oci.verify("alpine").unwrap();
+        let statuses: Vec<_> = report.layers.iter().map(|l| l.status).collect();
+        assert_eq!(statuses, [LayerStatus::Ok, LayerStatus::Missing]);
+        assert!(!report.rootfs_complete);
+        assert!(!report.is_ok());
+
+        std::fs::write(oci.store.layer_path(hello), b"tampered").unwrap();
+        std::fs::create_dir_all(oci.store.rootfs_path(manifest)).unwrap();
+        let tampered = oci.verify("alpine").unwrap();
+        assert_eq!(tampered.layers[0].status, LayerStatus::Corrupt);
+        assert!(tampered.rootfs_complete);
+        assert!(matches!(oci.verify("busybox"), Err(Error::NotFound(_))));
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn manifest_returns_stored_bytes_and_media_type() {
+        let root =
+            std::env::temp_dir().join(format!("bux_oci_manifest_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let oci = Oci::open_at(&root).unwrap();
+        let digest = "sha256:m";
+        oci.store
+            .upsert_image(
+                "docker.io/library/alpine:latest",
+                digest,
+                0,
+                "sha256:c",
+                &[],
+            )
+            .unwrap();
+        assert!(matches!(oci.manifest("alpine"), Err(Error::NotFound(_))));
+
+        let oci_manifest = br#"{"schemaVersion":2,"config":{}}"#;
+        oci.store.save_manifest(digest, oci_manifest).unwrap();
+        let (media_type, raw) = oci.manifest("alpine").unwrap();
+        assert_eq!(media_type, OCI_IMAGE_MEDIA_TYPE);
+        assert_eq!(raw, oci_manifest);
+
+        let docker = format!(r#"{{"schemaVersion":2,"mediaType":"{IMAGE_MANIFEST_MEDIA_TYPE}"}}"#);
+        assert_eq!(
+            manifest_media_type(docker.as_bytes()),
+            IMAGE_MANIFEST_MEDIA_TYPE
+        );
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn copy_to_promotes_an_image_between_stores() {
+        use sha2::Digest;
+
+        let root = std::env::temp_dir().join(format!("bux_oci_copy_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let dev = Oci::open_at(&root.join("dev")).unwrap();
+        let prod = Oci::open_at(&root.join("prod")).unwrap();
+
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, "etc/hostname", &b"bux"[..])
+            .unwrap();
+        let layer = tar.into_inner().unwrap();
+        let layer_digest = format!("sha256:{:x}", sha2::Sha256::digest(&layer));
+        let media_type = "application/vnd.oci.image.layer.v1.tar";
+        let manifest = format!(
+            r#"{{"schemaVersion":2,"config":{{"mediaType":"application/vnd.oci.image.config.v1+json","digest":"sha256:c","size":2}},"layers":[{{"mediaType":"{media_type}","digest":"{layer_digest}","size":{}}}]}}"#,
+            layer.len()
+        );
+        let digest = &format!("sha256:{:x}", sha2::Sha256::digest(&manifest));
+        let reference = "docker.io/library/app:1";
+        std::fs::write(dev.store.layer_staging_path(&layer_digest), &layer).unwrap();
+        dev.store
+            .commit_layer(reference, &layer_digest, media_type, layer.len() as u64)
+            .unwrap();
+        dev.store
+            .save_manifest(digest, manifest.as_bytes())
+            .unwrap();
+        dev.store.save_config("sha256:c", "{}").unwrap();
+        dev.store
+            .upsert_image(
+                reference,
+                digest,
+                1,
+                "sha256:c",
+                std::slice::from_ref(&layer_digest),
+            )
+            .unwrap();
+
+        dev.copy_to("app:1", &prod).await.unwrap();
+        assert_eq!(prod.manifest("app:1").unwrap().1, manifest.as_bytes());
+        assert!(prod.verify("app:1").unwrap().is_ok());
+        let hostname = prod.rootfs_path(digest).join("etc/hostname");
+        assert_eq!(std::fs::read(hostname).unwrap(), b"bux");
+        // Copying again finds everything in place.
+        dev.copy_to(&digest[..15], &prod).await.unwrap();
+        assert_eq!(prod.inspect("app:1").unwrap().layers[0].ref_count, 1);
+
+        // A layer that was never kept cannot be copied.
+        std::fs::remove_file(dev.layer_path(&layer_digest)).unwrap();
+        let fresh = Oci::open_at(&root.join("fresh")).unwrap();
+        assert!(matches!(
+            dev.copy_to("app:1", &fresh).await,
+            Err(Error::NotFound(_))
+        ));
+        assert!(fresh.images().unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    /// Stores `reference` at manifest `digest` with a complete rootfs and
+    /// the config blob `config`, the way a pull leaves it.
+    fn store_image(oci: &Oci, reference: &str, digest: &str, config: &str) {
+        use sha2::Digest;
+
+        let config_digest = format!("sha256:{:x}", sha2::Sha256::digest(config));
+        std::fs::create_dir_all(oci.store.rootfs_path(digest)).unwrap();
+        oci.store.save_config(&config_digest, config).unwrap();
+        oci.store
+            .upsert_image(reference, digest, 1, &config_digest, &[])
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn config_cache_follows_the_store_and_stays_bounded() {
+        const REF: &str = "docker.io/library/alpine:latest";
+        let root =
+            std::env::temp_dir().join(format!("bux_oci_config_cache_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let oci = Oci::open_at(&root).unwrap();
+        let cmd = || oci.cached(REF).unwrap().unwrap().config.unwrap().cmd;
+
+        store_image(&oci, REF, "sha256:m1", r#"{"config": {"Cmd": ["sh"]}}"#);
+        assert_eq!(cmd().unwrap(), ["sh"]);
+        assert_eq!(cmd().unwrap(), ["sh"]);
+        // Written behind the cache's back, as by another process.
+        store_image(&oci, REF, "sha256:m2", r#"{"config": {"Cmd": ["nginx"]}}"#);
+        assert_eq!(cmd().unwrap(), ["nginx"]);
+        assert_eq!(
+            oci.inspect(REF).unwrap().config.unwrap().cmd.unwrap(),
+            ["nginx"]
+        );
+
+        for i in 0..=ConfigCache::CAPACITY {
+            let reference = format!("docker.io/library/app{i}:1");
+            store_image(&oci, &reference, "sha256:m1", "{}");
+            oci.cached(&reference).unwrap().unwrap();
+        }
+        let filled = oci.configs.0.lock().unwrap();
+        assert_eq!(filled.len(), ConfigCache::CAPACITY);
+        assert!(filled.iter().all(|e| e.reference != REF));
+        drop(filled);
+
+        oci.cached(REF).unwrap().unwrap();
+        oci.remove(REF).unwrap();
+        let removed = oci.configs.0.lock().unwrap();
+        assert!(removed.iter().all(|e| e.reference != REF));
+        drop(removed);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    #[ignore = "benchmark; run with --ignored --nocapture"]
+    #[allow(clippy::print_stderr)]
+    async fn bench_repeated_ensure() {
+        const REF: &str = "docker.io/library/app:1";
+        const RUNS: u32 = 2000;
+        let root =
+            std::env::temp_dir().join(format!("bux_oci_bench_ensure_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let oci = Oci::open_at(&root).unwrap();
+        // About the size of a typical application image's config.
+        let env: Vec<String> = (0..40).map(|i| format!("VAR_{i}=value-{i}")).collect();
+        let history: Vec<serde_json::Value> = (0..200)
+            .map(|i| serde_json::json!({"created_by": format!("/bin/sh -c step {i}")}))
+            .collect();
+        let config = serde_json::json!({
+            "config": {"Cmd": ["app"], "Env": env, "Labels": {"org.example": "x"}},
+            "history": history,
+        });
+        store_image(&oci, REF, "sha256:m", &config.to_string());
+
+        let mut timings = Vec::new();
+        for cached in [false, true] {
+            let started = Instant::now();
+            for _ in 0..RUNS {
+                if !cached {
+                    oci.configs.invalidate(REF);
+                }
+                oci.ensure(REF, |_: &str| {}).await.unwrap();
+            }
+            timings.push(started.elapsed() / RUNS);
+        }
+        eprintln!(
+            "ensure of a cached image: {:?} parsing the config, {:?} with it cached",
+            timings[0], timings[1]
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,81 @@
+//! Pull metrics, recorded through the [`metrics`](https://docs.rs/metrics)
+//! facade when the `metrics` feature is enabled.
+//!
+//! Without the feature the recording functions compile to nothing; the
+//! names stay available so an embedder can refer to them either way.
+
+// Empty without the feature, which is no reason to make them `const`.
+#![cfg_attr(not(feature = "metrics"), allow(clippy::missing_const_for_fn))]
+
+use std::time::Duration;
+
+use crate::Error;
+
+/// Counter: images pulled successfully.
+pub const IMAGES_PULLED: &str = "bux_oci_images_pulled_total";
+/// Counter: layer bytes received from registries.
+pub const BYTES_DOWNLOADED: &str = "bux_oci_bytes_downloaded_total";
+/// Histogram: seconds spent building a rootfs from its layers. With
+/// streaming extraction this includes downloading the uncached layers.
+pub const EXTRACTION_SECONDS: &str = "bux_oci_extraction_seconds";
+/// Counter: failed pulls, labelled by `kind` ([`error_kind`]).
+pub const PULL_FAILURES: &str = "bux_oci_pull_failures_total";
+
+/// Runs `pull` and records its outcome.
+pub(crate) async fn recorded<T>(pull: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+    let result = pull.await;
+    match &result {
+        Ok(_) => pulled(),
+        Err(e) => pull_failed(e),
+    }
+    result
+}
+
+/// Records a successful pull.
+fn pulled() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(IMAGES_PULLED).increment(1);
+}
+
+/// Records a failed pull.
+pub(crate) fn pull_failed(error: &Error) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(PULL_FAILURES, "kind" => error_kind(error)).increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = error;
+}
+
+/// Records `bytes` of layer data received.
+pub(crate) fn downloaded(bytes: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(BYTES_DOWNLOADED).increment(bytes as u64);
+    #[cfg(not(feature = "metrics"))]
+    let _ = bytes;
+}
+
+/// Records the time a rootfs took to build.
+pub(crate) fn extracted(elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!(EXTRACTION_SECONDS).record(elapsed.as_secs_f64());
+    #[cfg(not(feature = "metrics"))]
+    let _ = elapsed;
+}
+
+/// The `kind` label [`PULL_FAILURES`] carries for `error`.
+pub const fn error_kind(error: &Error) -> &'static str {
+    match error {
+        Error::InvalidReference(_) => "invalid_reference",
+        Error::InvalidFilter(_) => "invalid_filter",
+        Error::NotFound(_) => "not_found",
+        Error::Ambiguous(_) => "ambiguous",
+        Error::Unauthorized(_) => "unauthorized",
+        Error::Db(_) => "db",
+        Error::Registry(_) => "registry",
+        Error::SignatureVerificationFailed(_) => "signature",
+        Error::Timeout(_) => "timeout",
+        Error::Certificate(_) => "certificate",
+        Error::Credentials(_) => "credentials",
+        Error::Io(_) => "io",
+        Error::Json(_) => "json",
+    }
+}
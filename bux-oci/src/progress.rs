@@ -0,0 +1,240 @@
+//! Download progress for pulls: bytes per layer, throughput and ETA.
+//!
+//! The numbers are computed here so every frontend renders the same
+//! figures; [`PullObserver::progress`] receives them while layers download.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Receives what a pull is doing.
+///
+/// Any `Fn(&str)` is an observer that only takes the status messages, so
+/// existing closures keep working (annotate the argument, `|msg: &str|`).
+pub trait PullObserver {
+    /// A human-readable status message ("Pulling ...", "Layer 2/5 cached").
+    fn status(&self, message: &str);
+
+    /// Download progress, at most five times a second per layer plus once
+    /// when a layer completes. Ignored by default.
+    fn progress(&self, progress: &PullProgress) {
+        let _ = progress;
+    }
+}
+
+impl<F: Fn(&str)> PullObserver for F {
+    fn status(&self, message: &str) {
+        self(message);
+    }
+}
+
+/// A snapshot of a pull's downloads.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PullProgress {
+    /// Position of the downloading layer, from 1.
+    pub layer: usize,
+    /// Layers in the image.
+    pub layers: usize,
+    /// Bytes of the current layer on disk, including a resumed prefix.
+    pub layer_bytes: u64,
+    /// Size of the current layer, if the manifest gives one.
+    pub layer_size: Option<u64>,
+    /// Bytes received from the registry so far in this pull.
+    pub downloaded: u64,
+    /// Bytes left to download across all layers, or `None` when a layer
+    /// to download has no size in the manifest.
+    pub remaining: Option<u64>,
+    /// Average throughput over the last few seconds, or `None` until there
+    /// is enough to measure.
+    pub bytes_per_sec: Option<f64>,
+}
+
+impl PullProgress {
+    /// Estimated time until every layer is downloaded, when both the
+    /// remaining bytes and the throughput are known.
+    pub fn eta(&self) -> Option<Duration> {
+        let rate = self.bytes_per_sec.filter(|r| *r > 0.0)?;
+        #[allow(clippy::cast_precision_loss)]
+        let remaining = self.remaining? as f64;
+        Some(Duration::from_secs_f64(remaining / rate))
+    }
+}
+
+/// Tracks the layers a pull downloads and decides when to report.
+#[derive(Debug)]
+pub struct Tracker {
+    layers: usize,
+    /// Bytes still to download, `None` once a layer of unknown size is
+    /// involved.
+    pending: Option<u64>,
+    layer: usize,
+    layer_bytes: u64,
+    layer_size: Option<u64>,
+    downloaded: u64,
+    /// `(time, downloaded)` samples over the last [`Self::WINDOW`].
+    samples: VecDeque<(Instant, u64)>,
+    last_report: Option<Instant>,
+}
+
+impl Tracker {
+    /// Minimum time between two progress reports of a layer.
+    const INTERVAL: Duration = Duration::from_millis(200);
+    /// Span the throughput is averaged over.
+    const WINDOW: Duration = Duration::from_secs(5);
+
+    /// Tracks a pull of `layers` layers, of which those sized
+    /// `to_download` (manifest sizes) need downloading.
+    pub fn new(layers: usize, to_download: impl IntoIterator<Item = i64>) -> Self {
+        let pending = to_download
+            .into_iter()
+            .try_fold(0u64, |sum, size| Some(sum + known_size(size)?));
+        Self {
+            layers,
+            pending,
+            layer: 0,
+            layer_bytes: 0,
+            layer_size: None,
+            downloaded: 0,
+            samples: VecDeque::new(),
+            last_report: None,
+        }
+    }
+
+    /// Starts downloading the layer at position `layer` (from 1) whose
+    /// manifest size is `size`, `already` bytes of which are on disk.
+    pub fn start_layer(&mut self, layer: usize, size: i64, already: u64) {
+        self.layer = layer;
+        self.layer_size = known_size(size);
+        self.layer_bytes = already;
+        self.pending = self.pending.map(|p| p.saturating_sub(already));
+        self.last_report = None;
+    }
+
+    /// Counts `received` bytes at `now`, returning a report when one is due.
+    pub fn advance(&mut self, received: usize, now: Instant) -> Option<PullProgress> {
+        let bytes = received as u64;
+        self.layer_bytes += bytes;
+        self.downloaded += bytes;
+        self.pending = self.pending.map(|p| p.saturating_sub(bytes));
+        if self
+            .last_report
+            .is_some_and(|last| now.duration_since(last) < Self::INTERVAL)
+        {
+            return None;
+        }
+        Some(self.report(now))
+    }
+
+    /// Ends the current layer at `now`, returning its final report.
+    pub fn finish_layer(&mut self, now: Instant) -> PullProgress {
+        // Whatever the manifest promised, this layer needs nothing more.
+        if let Some(size) = self.layer_size {
+            let short = size.saturating_sub(self.layer_bytes);
+            self.pending = self.pending.map(|p| p.saturating_sub(short));
+        }
+        self.report(now)
+    }
+
+    /// Samples the throughput and snapshots the current state.
+    fn report(&mut self, now: Instant) -> PullProgress {
+        self.last_report = Some(now);
+        self.samples.push_back((now, self.downloaded));
+        // Keep one sample at or beyond the window as the baseline.
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|(at, _)| now.duration_since(*at) >= Self::WINDOW)
+        {
+            self.samples.pop_front();
+        }
+        let bytes_per_sec = self.samples.front().and_then(|(at, bytes)| {
+            let elapsed = now.duration_since(*at).as_secs_f64();
+            #[allow(clippy::cast_precision_loss)]
+            (elapsed > 0.0).then(|| (self.downloaded - bytes) as f64 / elapsed)
+        });
+        PullProgress {
+            layer: self.layer,
+            layers: self.layers,
+            layer_bytes: self.layer_bytes,
+            layer_size: self.layer_size,
+            downloaded: self.downloaded,
+            remaining: self.pending,
+            bytes_per_sec,
+        }
+    }
+}
+
+/// A manifest size, which registries may leave at zero or negative when
+/// unknown.
+fn known_size(size: i64) -> Option<u64> {
+    u64::try_from(size).ok().filter(|s| *s > 0)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_rate_remaining_and_eta() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(3, [1000, 3000]);
+        tracker.start_layer(2, 1000, 0);
+
+        let first = tracker.advance(100, start).unwrap();
+        assert_eq!(first.remaining, Some(3900));
+        assert_eq!(first.bytes_per_sec, None);
+        assert_eq!(first.eta(), None);
+
+        // Throttled until the interval has passed.
+        assert!(
+            tracker
+                .advance(100, start + Duration::from_millis(50))
+                .is_none()
+        );
+        let second = tracker
+            .advance(300, start + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!((second.layer, second.layers), (2, 3));
+        assert_eq!(second.layer_bytes, 500);
+        assert_eq!(second.remaining, Some(3500));
+        assert_eq!(second.bytes_per_sec, Some(400.0));
+        assert_eq!(second.eta(), Some(Duration::from_secs_f64(3500.0 / 400.0)));
+    }
+
+    #[test]
+    fn resumed_bytes_count_as_done_but_not_as_throughput() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(1, [1000]);
+        tracker.start_layer(1, 1000, 600);
+        tracker.advance(0, start);
+        let p = tracker
+            .advance(200, start + Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(p.layer_bytes, 800);
+        assert_eq!(p.downloaded, 200);
+        assert_eq!(p.remaining, Some(200));
+        assert_eq!(p.bytes_per_sec, Some(100.0));
+        assert_eq!(
+            tracker
+                .finish_layer(start + Duration::from_secs(3))
+                .remaining,
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn throughput_only_averages_the_recent_window() {
+        let start = Instant::now();
+        let mut tracker = Tracker::new(1, [0]);
+        tracker.start_layer(1, 0, 0);
+        tracker.advance(10_000, start);
+        for second in 1..=10 {
+            tracker.advance(100, start + Duration::from_secs(second));
+        }
+        let p = tracker.finish_layer(start + Duration::from_secs(10));
+        assert_eq!(p.bytes_per_sec, Some(100.0));
+        // A layer without a size leaves the total unknown.
+        assert_eq!((p.remaining, p.eta(), p.layer_size), (None, None, None));
+    }
+}
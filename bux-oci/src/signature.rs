@@ -0,0 +1,35 @@
+//! Integration point for image signature verification (cosign, notation).
+//!
+//! [`Oci::pull`](crate::Oci::pull) hands the manifest to the configured
+//! [`SignatureVerifier`] before downloading any layer, so a rejected image
+//! never reaches the store.
+
+/// Decides whether a pulled manifest is trusted.
+pub trait SignatureVerifier: std::fmt::Debug + Send + Sync {
+    /// Checks the signature of the manifest `manifest_digest` of
+    /// `reference`, whose exact bytes are `raw_manifest`.
+    ///
+    /// `reference` is the normalized image reference (e.g.
+    /// `docker.io/library/alpine:latest`); signatures usually live in the
+    /// same repository. Return
+    /// [`Error::SignatureVerificationFailed`](crate::Error::SignatureVerificationFailed)
+    /// to reject the image; any other error aborts the pull as is.
+    fn verify(
+        &self,
+        reference: &str,
+        manifest_digest: &str,
+        raw_manifest: &[u8],
+    ) -> crate::Result<()>;
+}
+
+/// No-op verifier: accepts every image. Create it with
+/// [`NoopVerifier::default`].
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopVerifier;
+
+impl SignatureVerifier for NoopVerifier {
+    fn verify(&self, _: &str, _: &str, _: &[u8]) -> crate::Result<()> {
+        Ok(())
+    }
+}
@@ -6,14 +6,17 @@
 //!   images.db          — SQLite: image index + layer refs
 //!   layers/            — content-addressed layer tarballs (sha256-{hex}.tar.gz)
 //!   configs/           — image config blobs (sha256-{hex}.json)
+//!   manifests/         — raw image manifests as pulled (sha256-{hex}.json)
 //!   rootfs/{digest}/   — extracted rootfs directories (keyed by manifest digest)
 //! ```
 
+use std::collections::HashSet;
 use std::fs;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use rusqlite::{Connection, params};
+use rusqlite::{Connection, Transaction, TransactionBehavior, params};
 use sha2::{Digest, Sha256};
 
 /// Extension trait to convert `rusqlite::Result` into `crate::Result`.
@@ -27,6 +30,14 @@ fn db(self) -> crate::Result<T> {
     }
 }
 
+/// Whether `e` is SQLite reporting a lock held by another connection.
+fn is_busy(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
+
 /// Metadata for a locally stored image.
 #[non_exhaustive]
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -39,6 +50,98 @@ pub struct ImageMeta {
     pub size: u64,
     /// ISO 8601 timestamp when the image was cached.
     pub created_at: String,
+    /// ISO 8601 timestamp when the image was last pulled or served from
+    /// the cache.
+    #[serde(default)]
+    pub last_used: String,
+}
+
+/// Image records read from the index, as listed by [`Store::scan_images`].
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct ImageList {
+    /// Records that parsed, newest first.
+    pub images: Vec<ImageMeta>,
+    /// Records skipped because a column held the wrong type. [`Store::prune`]
+    /// (`bux image gc`) deletes them.
+    pub skipped: usize,
+}
+
+/// SQL condition matching `images` rows that [`Store::scan_images`] cannot
+/// parse.
+const MALFORMED_IMAGE: &str = "typeof(reference) != 'text' OR typeof(digest) != 'text' \
+                               OR typeof(size) != 'integer'";
+
+/// One layer of a stored image, as listed by [`Store::layers_for_image`].
+#[non_exhaustive]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LayerInfo {
+    /// Compressed blob digest.
+    pub digest: String,
+    /// Blob media type (e.g. `application/vnd.oci.image.layer.v1.tar+gzip`).
+    pub media_type: String,
+    /// Compressed size in bytes.
+    pub size: u64,
+    /// Index in the manifest, `0` being the bottom layer.
+    pub position: u32,
+    /// Number of stored images sharing this blob.
+    pub ref_count: u32,
+}
+
+/// A stored layer and the images that reference it, from
+/// [`Store::layer_refs`].
+#[non_exhaustive]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LayerRefs {
+    /// Compressed blob digest.
+    pub digest: String,
+    /// Blob media type.
+    pub media_type: String,
+    /// Compressed size in bytes.
+    pub size: u64,
+    /// References of the images whose manifests list this layer, sorted.
+    /// Empty for a layer nothing uses any more, which `prune` removes.
+    pub images: Vec<String>,
+}
+
+/// A predicate for [`Store::list_images_filtered`], parsed from
+/// `label=KEY`, `label=KEY=VALUE` or `reference=PATTERN`.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageFilter {
+    /// Image carries label `key`, optionally with exactly `value`.
+    Label {
+        /// Label key.
+        key: String,
+        /// Required value; `None` matches any value.
+        value: Option<String>,
+    },
+    /// Reference matches a glob (`*`, `?`, `[...]`). Docker Hub images also
+    /// match by their short name, e.g. `alpine:*`.
+    Reference(String),
+}
+
+impl std::str::FromStr for ImageFilter {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s.split_once('=') {
+            Some(("label", label)) if !label.is_empty() => Ok(match label.split_once('=') {
+                Some((key, value)) => Self::Label {
+                    key: key.to_owned(),
+                    value: Some(value.to_owned()),
+                },
+                None => Self::Label {
+                    key: label.to_owned(),
+                    value: None,
+                },
+            }),
+            Some(("reference", pattern)) if !pattern.is_empty() => {
+                Ok(Self::Reference(pattern.to_owned()))
+            }
+            _ => Err(crate::Error::InvalidFilter(s.to_owned())),
+        }
+    }
 }
 
 /// Content-addressed OCI image store with SQLite indexing.
@@ -47,18 +150,25 @@ pub struct Store {
     root: PathBuf,
     /// SQLite database connection.
     db: Connection,
+    /// Where downloads and extractions are staged before moving into
+    /// `root` (`None` = next to their final location).
+    staging: Option<PathBuf>,
+    /// How long a statement waits for another connection's lock.
+    busy_timeout: Duration,
 }
 
 impl std::fmt::Debug for Store {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Store")
             .field("root", &self.root)
+            .field("staging", &self.staging)
+            .field("busy_timeout", &self.busy_timeout)
             .field("db", &"<sqlite>")
             .finish()
     }
 }
 
-// SQL schema — single migration for now, extensible via version table.
+// SQL schema; later additions are backfilled in `migrate`.
 const SCHEMA: &str = "\
     CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL);
     INSERT OR IGNORE INTO schema_version VALUES (1);
@@ -81,25 +191,153 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         position    INTEGER NOT NULL,
         PRIMARY KEY (image_ref, layer_digest)
     );
+    CREATE TABLE IF NOT EXISTS layer_claims (
+        image_ref    TEXT NOT NULL,
+        layer_digest TEXT NOT NULL REFERENCES layers(digest) ON DELETE CASCADE,
+        PRIMARY KEY (image_ref, layer_digest)
+    );
+    CREATE TABLE IF NOT EXISTS labels (
+        image_ref TEXT NOT NULL REFERENCES images(reference) ON DELETE CASCADE,
+        key       TEXT NOT NULL,
+        value     TEXT NOT NULL,
+        PRIMARY KEY (image_ref, key)
+    );
 ";
 
+/// Current schema version recorded in `schema_version`.
+const SCHEMA_VERSION: i64 = 3;
+
+/// `images` columns read into an [`ImageMeta`] by [`image_meta_row`].
+const IMAGE_COLUMNS: &str = "reference, digest, size, created, COALESCE(last_used, created)";
+
+/// Default for [`Store::with_busy_timeout`].
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Attempts at taking the write lock in [`Store::write_tx`], each waiting
+/// up to the busy timeout.
+const WRITE_ATTEMPTS: u32 = 3;
+
+/// Sets `ref_count` to the images listing a layer plus the pulls that
+/// stored it for an image not indexed yet (`layer_claims`).
+const RECOUNT_LAYER: &str = "UPDATE layers SET ref_count =
+        (SELECT COUNT(*) FROM image_layers WHERE layer_digest = ?1)
+      + (SELECT COUNT(*) FROM layer_claims WHERE layer_digest = ?1)
+    WHERE digest = ?1";
+
+/// Current time with milliseconds, so uses within one second still order.
+const NOW_MS: &str = "strftime('%Y-%m-%d %H:%M:%f', 'now')";
+
+/// The `last_used` cutoff below which [`Store::touch`] records a new use:
+/// LRU eviction doesn't need finer resolution than an hour.
+const STALE_BEFORE: &str = "strftime('%Y-%m-%d %H:%M:%f', 'now', '-1 hour')";
+
 impl Store {
     /// Opens (or creates) the store at the given root directory.
     pub fn open(root: &Path) -> crate::Result<Self> {
         fs::create_dir_all(root.join("layers"))?;
         fs::create_dir_all(root.join("configs"))?;
+        fs::create_dir_all(root.join("manifests"))?;
         fs::create_dir_all(root.join("rootfs"))?;
 
         let db_path = root.join("images.db");
         let db = Connection::open(&db_path).db()?;
-        db.execute_batch("PRAGMA journal_mode=WAL; PRAGMA foreign_keys=ON;")
-            .db()?;
+        // Wait out other processes' locks from the first statement on:
+        // switching to WAL and creating the schema take them too.
+        db.busy_timeout(DEFAULT_BUSY_TIMEOUT).db()?;
+        // The index is small and mostly read; checkpointing at ~1 MiB of
+        // WAL instead of SQLite's ~4 MiB keeps readers' scans short.
+        db.execute_batch(
+            "PRAGMA journal_mode=WAL; PRAGMA foreign_keys=ON; PRAGMA wal_autocheckpoint=256;",
+        )
+        .db()?;
         db.execute_batch(SCHEMA).db()?;
 
-        Ok(Self {
+        let store = Self {
             root: root.to_path_buf(),
             db,
-        })
+            staging: None,
+            busy_timeout: DEFAULT_BUSY_TIMEOUT,
+        };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    /// Stages layer downloads and rootfs extractions under `dir` instead of
+    /// inside the store; commits move them in, copying across filesystems.
+    pub fn with_staging_dir(mut self, dir: &Path) -> crate::Result<Self> {
+        fs::create_dir_all(dir.join("layers"))?;
+        fs::create_dir_all(dir.join("rootfs"))?;
+        self.staging = Some(dir.to_path_buf());
+        Ok(self)
+    }
+
+    /// Waits up to `timeout` for locks held by other connections (other
+    /// processes, or blocking tasks of this one) before failing with
+    /// `SQLITE_BUSY`. Defaults to [`DEFAULT_BUSY_TIMEOUT`].
+    pub fn with_busy_timeout(mut self, timeout: Duration) -> crate::Result<Self> {
+        self.db.busy_timeout(timeout).db()?;
+        self.busy_timeout = timeout;
+        Ok(self)
+    }
+
+    /// Lock wait set by [`with_busy_timeout`](Self::with_busy_timeout).
+    pub const fn busy_timeout(&self) -> Duration {
+        self.busy_timeout
+    }
+
+    /// Separate staging directory, if one is set.
+    pub fn staging_dir(&self) -> Option<&Path> {
+        self.staging.as_deref()
+    }
+
+    /// Directory holding the `layers/` and `rootfs/` staging areas.
+    fn staging_root(&self) -> &Path {
+        self.staging.as_deref().unwrap_or(&self.root)
+    }
+
+    /// Brings databases created by older versions up to [`SCHEMA_VERSION`].
+    fn migrate(&self) -> crate::Result<()> {
+        let version_of = |db: &Connection| -> crate::Result<i64> {
+            db.query_row("SELECT MAX(version) FROM schema_version", [], |row| {
+                row.get(0)
+            })
+            .db()
+        };
+        if version_of(&self.db)? >= SCHEMA_VERSION {
+            return Ok(());
+        }
+
+        let tx = self.write_tx()?;
+        // Another process may have migrated while this one waited.
+        let version = version_of(&tx)?;
+        if version >= SCHEMA_VERSION {
+            return Ok(());
+        }
+        if version < 2 {
+            // v2: index labels of images pulled before the labels table existed.
+            let images: Vec<(String, Option<String>)> = {
+                let mut stmt = tx.prepare("SELECT reference, config FROM images").db()?;
+                let rows = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                    .db()?;
+                rows.collect::<rusqlite::Result<_>>().db()?
+            };
+            for (reference, config) in &images {
+                insert_labels(&tx, reference, config.as_deref())?;
+            }
+        }
+        if version < 3 {
+            // v3: last use for LRU eviction; `NULL` reads as `created`.
+            tx.execute_batch("ALTER TABLE images ADD COLUMN last_used TEXT;")
+                .db()?;
+        }
+        tx.execute("DELETE FROM schema_version", []).db()?;
+        tx.execute(
+            "INSERT INTO schema_version VALUES (?1)",
+            params![SCHEMA_VERSION],
+        )
+        .db()?;
+        tx.commit().db()
     }
 
     /// Returns the path to a layer tarball on disk.
@@ -114,7 +352,7 @@ pub fn layer_path(&self, digest: &str) -> PathBuf {
     /// atomically move it into place.
     pub fn layer_staging_path(&self, digest: &str) -> PathBuf {
         let filename = digest.replace(':', "-");
-        self.root
+        self.staging_root()
             .join("layers")
             .join(format!("{filename}.tar.gz.tmp"))
     }
@@ -124,42 +362,96 @@ pub fn has_layer(&self, digest: &str) -> bool {
         self.layer_path(digest).exists()
     }
 
-    /// Commits a streamed layer: atomic rename from staging path + DB upsert.
+    /// Commits a streamed layer of the image `reference`: atomic rename
+    /// from staging path + DB upsert.
     ///
     /// The caller must have already written the layer data to the path
-    /// returned by [`layer_staging_path`].
-    pub fn commit_layer(&self, digest: &str, media_type: &str, size: u64) -> crate::Result<()> {
+    /// returned by [`layer_staging_path`]. If a blob matching `digest` is
+    /// already in place (a retried commit, or a concurrent pull that won),
+    /// it is kept and the staging file discarded. The reference is counted
+    /// as in [`record_layer`](Self::record_layer).
+    pub fn commit_layer(
+        &self,
+        reference: &str,
+        digest: &str,
+        media_type: &str,
+        size: u64,
+    ) -> crate::Result<()> {
         let staging = self.layer_staging_path(digest);
         let final_path = self.layer_path(digest);
-        fs::rename(&staging, &final_path)?;
+        if final_path.is_file() && file_matches_digest(&final_path, digest)? {
+            remove_tree(&staging)?;
+        } else {
+            move_into_place(&staging, &final_path, |from, to| fs::rename(from, to))?;
+        }
+        self.record_layer(reference, digest, media_type, size)
+    }
 
-        self.db
-            .execute(
-                "INSERT INTO layers (digest, media_type, size)
-                 VALUES (?1, ?2, ?3)
-                 ON CONFLICT(digest) DO UPDATE SET ref_count = ref_count + 1",
-                params![digest, media_type, i64::try_from(size).unwrap_or(i64::MAX)],
-            )
-            .db()?;
+    /// Counts a reference to a layer from the image `reference` in the
+    /// index without storing its blob, for layers extracted straight from
+    /// the registry and not cached. [`has_layer`](Self::has_layer) stays
+    /// `false` for them.
+    ///
+    /// The reference holds the layer until [`upsert_image`](Self::upsert_image)
+    /// indexes the image; recording the same layer for the same image again
+    /// counts nothing more.
+    pub fn record_layer(
+        &self,
+        reference: &str,
+        digest: &str,
+        media_type: &str,
+        size: u64,
+    ) -> crate::Result<()> {
+        let tx = self.write_tx()?;
+        tx.execute(
+            "INSERT INTO layers (digest, media_type, size) VALUES (?1, ?2, ?3)
+             ON CONFLICT(digest) DO NOTHING",
+            params![digest, media_type, i64::try_from(size).unwrap_or(i64::MAX)],
+        )
+        .db()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO layer_claims (image_ref, layer_digest) VALUES (?1, ?2)",
+            params![reference, digest],
+        )
+        .db()?;
+        tx.execute(RECOUNT_LAYER, params![digest]).db()?;
+        tx.commit().db()
+    }
 
+    /// Moves a re-downloaded layer back into place without touching its
+    /// ref count, for repairing a blob the index already tracks.
+    pub fn restore_layer(&self, digest: &str) -> crate::Result<()> {
+        move_into_place(
+            &self.layer_staging_path(digest),
+            &self.layer_path(digest),
+            |from, to| fs::rename(from, to),
+        )?;
         Ok(())
     }
 
-    /// Verifies layer integrity by recomputing SHA256.
+    /// Returns the recorded media type of a layer.
+    pub fn layer_media_type(&self, digest: &str) -> crate::Result<Option<String>> {
+        match self.db.query_row(
+            "SELECT media_type FROM layers WHERE digest = ?1",
+            params![digest],
+            |row| row.get(0),
+        ) {
+            Ok(media_type) => Ok(Some(media_type)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(crate::Error::Db(e.to_string())),
+        }
+    }
+
+    /// Verifies layer integrity by recomputing its digest.
     ///
     /// Returns `Ok(true)` if the hash matches, `Ok(false)` if it doesn't,
-    /// and `Err` on I/O failure. Not used internally — `oci-client` verifies
-    /// digests during download. Exposed for external callers.
-    #[allow(dead_code)]
+    /// and `Err` on I/O failure.
     pub fn verify_layer(&self, digest: &str) -> crate::Result<bool> {
-        let path = self.layer_path(digest);
-        let data = fs::read(&path)?;
-        let computed = format!("sha256:{:x}", Sha256::digest(&data));
-        Ok(computed == digest)
+        file_matches_digest(&self.layer_path(digest), digest)
     }
 
     /// Path to a config blob on disk.
-    fn config_path(&self, digest: &str) -> PathBuf {
+    pub fn config_path(&self, digest: &str) -> PathBuf {
         let filename = digest.replace(':', "-");
         self.root.join("configs").join(format!("{filename}.json"))
     }
@@ -173,6 +465,35 @@ pub fn save_config(&self, digest: &str, data: &str) -> crate::Result<()> {
         Ok(())
     }
 
+    /// Path to a raw manifest on disk.
+    fn manifest_path(&self, digest: &str) -> PathBuf {
+        let filename = digest.replace(':', "-");
+        self.root.join("manifests").join(format!("{filename}.json"))
+    }
+
+    /// Saves a manifest exactly as the registry served it.
+    pub fn save_manifest(&self, digest: &str, data: &[u8]) -> crate::Result<()> {
+        let path = self.manifest_path(digest);
+        if !path.exists() {
+            atomic_write(&path, data)?;
+        }
+        Ok(())
+    }
+
+    /// Loads a stored manifest; `None` if it was never saved.
+    pub fn load_manifest(&self, digest: &str) -> crate::Result<Option<Vec<u8>>> {
+        match fs::read(self.manifest_path(digest)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Root directory of the store.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
     /// Path to an extracted rootfs directory (keyed by manifest digest).
     pub fn rootfs_path(&self, manifest_digest: &str) -> PathBuf {
         let dirname = manifest_digest.replace(':', "-");
@@ -182,7 +503,9 @@ pub fn rootfs_path(&self, manifest_digest: &str) -> PathBuf {
     /// Returns a staging path for rootfs extraction.
     pub fn rootfs_staging_path(&self, manifest_digest: &str) -> PathBuf {
         let dirname = manifest_digest.replace(':', "-");
-        self.root.join("rootfs").join(format!("{dirname}.tmp"))
+        self.staging_root()
+            .join("rootfs")
+            .join(format!("{dirname}.tmp"))
     }
 
     /// Returns `true` if an extracted rootfs is complete and valid.
@@ -192,9 +515,10 @@ pub fn rootfs_complete(&self, manifest_digest: &str) -> bool {
 
     /// Atomically installs a staged rootfs extraction.
     ///
-    /// Removes any stale staging directory first, then renames the staging
-    /// path into its final location. If the final path already exists
-    /// (e.g. from a concurrent extraction), the staging directory is removed.
+    /// Renames the staging path into its final location, copying across
+    /// filesystems if needed. If the final path already exists (e.g. from a
+    /// concurrent extraction or a retried commit), the staging directory is
+    /// removed instead.
     pub fn commit_rootfs(&self, manifest_digest: &str) -> crate::Result<()> {
         let staging = self.rootfs_staging_path(manifest_digest);
         let final_path = self.rootfs_path(manifest_digest);
@@ -205,11 +529,15 @@ pub fn commit_rootfs(&self, manifest_digest: &str) -> crate::Result<()> {
             return Ok(());
         }
 
-        fs::rename(&staging, &final_path)?;
+        move_into_place(&staging, &final_path, |from, to| fs::rename(from, to))?;
         Ok(())
     }
 
     /// Inserts or updates an image record and its layer associations.
+    ///
+    /// Layers listed by the image, or by its previous version, are
+    /// recounted; claims from [`record_layer`](Self::record_layer) for this
+    /// reference become its associations.
     pub fn upsert_image(
         &self,
         reference: &str,
@@ -218,19 +546,22 @@ pub fn upsert_image(
         config_digest: &str,
         layer_digests: &[String],
     ) -> crate::Result<()> {
-        let tx = self.db.unchecked_transaction().db()?;
+        let tx = self.write_tx()?;
 
         // Load config JSON from blob store for embedding in the DB.
         let config_json = fs::read_to_string(self.config_path(config_digest)).ok();
 
         tx.execute(
-            "INSERT INTO images (reference, digest, size, config)
-             VALUES (?1, ?2, ?3, ?4)
-             ON CONFLICT(reference) DO UPDATE SET
-                digest = excluded.digest,
-                size = excluded.size,
-                config = excluded.config,
-                created = datetime('now')",
+            &format!(
+                "INSERT INTO images (reference, digest, size, config, last_used)
+                 VALUES (?1, ?2, ?3, ?4, {NOW_MS})
+                 ON CONFLICT(reference) DO UPDATE SET
+                    digest = excluded.digest,
+                    size = excluded.size,
+                    config = excluded.config,
+                    created = datetime('now'),
+                    last_used = excluded.last_used"
+            ),
             params![
                 reference,
                 digest,
@@ -240,12 +571,34 @@ pub fn upsert_image(
         )
         .db()?;
 
-        // Clear old layer associations, then insert new ones.
+        tx.execute(
+            "DELETE FROM labels WHERE image_ref = ?1",
+            params![reference],
+        )
+        .db()?;
+        insert_labels(&tx, reference, config_json.as_deref())?;
+
+        // Clear old layer associations and claims, then insert new ones.
+        let mut touched: HashSet<String> = {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT layer_digest FROM image_layers WHERE image_ref = ?1
+                     UNION SELECT layer_digest FROM layer_claims WHERE image_ref = ?1",
+                )
+                .db()?;
+            let rows = stmt.query_map(params![reference], |row| row.get(0)).db()?;
+            rows.collect::<rusqlite::Result<_>>().db()?
+        };
         tx.execute(
             "DELETE FROM image_layers WHERE image_ref = ?1",
             params![reference],
         )
         .db()?;
+        tx.execute(
+            "DELETE FROM layer_claims WHERE image_ref = ?1",
+            params![reference],
+        )
+        .db()?;
 
         for (pos, layer_digest) in layer_digests.iter().enumerate() {
             tx.execute(
@@ -259,6 +612,10 @@ pub fn upsert_image(
             )
             .db()?;
         }
+        touched.extend(layer_digests.iter().cloned());
+        for layer_digest in &touched {
+            tx.execute(RECOUNT_LAYER, params![layer_digest]).db()?;
+        }
 
         tx.commit().db()?;
         Ok(())
@@ -266,27 +623,77 @@ pub fn upsert_image(
 
     /// Lists all stored images.
     pub fn list_images(&self) -> crate::Result<Vec<ImageMeta>> {
-        let mut stmt = self
-            .db
-            .prepare("SELECT reference, digest, size, created FROM images ORDER BY created DESC")
-            .db()?;
+        self.list_images_filtered(&[])
+    }
+
+    /// Lists stored images matching every filter; empty if none match.
+    /// Malformed records are left out; see [`scan_images`](Self::scan_images).
+    pub fn list_images_filtered(&self, filters: &[ImageFilter]) -> crate::Result<Vec<ImageMeta>> {
+        Ok(self.scan_images(filters)?.images)
+    }
+
+    /// Lists stored images matching every filter, skipping and counting
+    /// records that fail to parse instead of failing the whole listing.
+    pub fn scan_images(&self, filters: &[ImageFilter]) -> crate::Result<ImageList> {
+        let mut clauses = Vec::new();
+        let mut args: Vec<&str> = Vec::new();
+        for filter in filters {
+            let n = args.len() + 1;
+            match filter {
+                ImageFilter::Label { key, value: None } => {
+                    clauses.push(format!(
+                        "EXISTS (SELECT 1 FROM labels WHERE image_ref = reference AND key = ?{n})"
+                    ));
+                    args.push(key);
+                }
+                ImageFilter::Label {
+                    key,
+                    value: Some(value),
+                } => {
+                    clauses.push(format!(
+                        "EXISTS (SELECT 1 FROM labels WHERE image_ref = reference \
+                         AND key = ?{n} AND value = ?{})",
+                        n + 1
+                    ));
+                    args.push(key);
+                    args.push(value);
+                }
+                ImageFilter::Reference(pattern) => {
+                    clauses.push(format!(
+                        "(reference GLOB ?{n} OR reference GLOB 'docker.io/' || ?{n} \
+                         OR reference GLOB 'docker.io/library/' || ?{n})"
+                    ));
+                    args.push(pattern);
+                }
+            }
+        }
+
+        let mut sql = format!("SELECT {IMAGE_COLUMNS} FROM images");
+        if !clauses.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&clauses.join(" AND "));
+        }
+        sql.push_str(" ORDER BY created DESC");
 
+        let mut stmt = self.db.prepare(&sql).db()?;
         let rows = stmt
-            .query_map([], |row| {
-                Ok(ImageMeta {
-                    reference: row.get(0)?,
-                    digest: row.get(1)?,
-                    size: u64::try_from(row.get::<_, i64>(2)?).unwrap_or(0),
-                    created_at: row.get::<_, String>(3).unwrap_or_default(),
-                })
-            })
+            .query_map(rusqlite::params_from_iter(args), image_meta_row)
             .db()?;
 
-        let mut images = Vec::new();
+        let mut list = ImageList::default();
         for row in rows {
-            images.push(row.db()?);
+            match row {
+                Ok(meta) => list.images.push(meta),
+                Err(
+                    rusqlite::Error::InvalidColumnType(..)
+                    | rusqlite::Error::FromSqlConversionFailure(..),
+                ) => {
+                    list.skipped += 1;
+                }
+                Err(e) => return Err(crate::Error::Db(e.to_string())),
+            }
         }
-        Ok(images)
+        Ok(list)
     }
 
     /// Loads the stored image config JSON for a reference.
@@ -316,24 +723,270 @@ pub fn get_digest(&self, reference: &str) -> crate::Result<Option<String>> {
         }
     }
 
+    /// Resolves a manifest digest prefix, as `bux images` shows it
+    /// (`sha256:1a2b3c…`, or the hex alone), to the full digest of a stored
+    /// image.
+    ///
+    /// Fails with [`NotFound`](crate::Error::NotFound) when no image
+    /// matches, [`Ambiguous`](crate::Error::Ambiguous) when several
+    /// different images do, and
+    /// [`InvalidReference`](crate::Error::InvalidReference) when `prefix`
+    /// is not hex.
+    pub fn resolve_digest_prefix(&self, prefix: &str) -> crate::Result<String> {
+        let (algorithm, hex) = prefix.split_once(':').unwrap_or(("sha256", prefix));
+        if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(crate::Error::InvalidReference(format!(
+                "'{prefix}' is not a digest prefix"
+            )));
+        }
+        let mut stmt = self
+            .db
+            .prepare("SELECT DISTINCT digest FROM images WHERE digest LIKE ?1")
+            .db()?;
+        let pattern = format!("{algorithm}:{}%", hex.to_ascii_lowercase());
+        let mut matches: Vec<String> = stmt
+            .query_map(params![pattern], |row| row.get(0))
+            .db()?
+            .collect::<rusqlite::Result<_>>()
+            .db()?;
+        match matches.len() {
+            0 => Err(crate::Error::NotFound(format!(
+                "no image matching '{prefix}'"
+            ))),
+            1 => Ok(matches.swap_remove(0)),
+            n => Err(crate::Error::Ambiguous(format!(
+                "prefix '{prefix}' matches {n} images"
+            ))),
+        }
+    }
+
+    /// References to the image with manifest `digest`, most recently used
+    /// first.
+    pub fn references_to(&self, digest: &str) -> crate::Result<Vec<String>> {
+        let mut stmt = self
+            .db
+            .prepare(
+                "SELECT reference FROM images WHERE digest = ?1
+                 ORDER BY COALESCE(last_used, created) DESC",
+            )
+            .db()?;
+        let rows = stmt.query_map(params![digest], |row| row.get(0)).db()?;
+        rows.collect::<rusqlite::Result<_>>().db()
+    }
+
+    /// Looks up the index entry for a reference, if cached.
+    pub fn image_meta(&self, reference: &str) -> crate::Result<Option<ImageMeta>> {
+        match self.db.query_row(
+            &format!("SELECT {IMAGE_COLUMNS} FROM images WHERE reference = ?1"),
+            params![reference],
+            image_meta_row,
+        ) {
+            Ok(meta) => Ok(Some(meta)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(crate::Error::Db(e.to_string())),
+        }
+    }
+
+    /// Records that `reference` was just used, for [`images_by_lru`](Self::images_by_lru).
+    ///
+    /// Only writes when the recorded use is over an hour old, so cache hits
+    /// normally stay read-only and don't contend for the write lock.
+    pub fn touch(&self, reference: &str) -> crate::Result<()> {
+        let stale: bool = self
+            .db
+            .query_row(
+                &format!(
+                    "SELECT EXISTS(SELECT 1 FROM images WHERE reference = ?1
+                        AND (last_used IS NULL OR last_used < {STALE_BEFORE}))"
+                ),
+                params![reference],
+                |row| row.get(0),
+            )
+            .db()?;
+        if stale {
+            self.db
+                .execute(
+                    &format!("UPDATE images SET last_used = {NOW_MS} WHERE reference = ?1"),
+                    params![reference],
+                )
+                .db()?;
+        }
+        Ok(())
+    }
+
+    /// Lists stored images, least recently used first. Malformed records
+    /// are left out.
+    pub fn images_by_lru(&self) -> crate::Result<Vec<ImageMeta>> {
+        let mut stmt = self
+            .db
+            .prepare(&format!(
+                "SELECT {IMAGE_COLUMNS} FROM images WHERE NOT ({MALFORMED_IMAGE})
+                 ORDER BY COALESCE(last_used, created), reference"
+            ))
+            .db()?;
+        let rows = stmt.query_map([], image_meta_row).db()?;
+        rows.collect::<rusqlite::Result<_>>().db()
+    }
+
+    /// Looks up the total layer size recorded for a reference, if cached.
+    pub fn image_size(&self, reference: &str) -> crate::Result<Option<u64>> {
+        match self.db.query_row(
+            "SELECT size FROM images WHERE reference = ?1",
+            params![reference],
+            |row| row.get::<_, i64>(0),
+        ) {
+            Ok(size) => Ok(Some(u64::try_from(size).unwrap_or(0))),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(crate::Error::Db(e.to_string())),
+        }
+    }
+
+    /// Lists an image's layers with their blob metadata, bottom layer first.
+    pub fn layers_for_image(&self, reference: &str) -> crate::Result<Vec<LayerInfo>> {
+        let mut stmt = self
+            .db
+            .prepare(
+                "SELECT l.digest, l.media_type, l.size, il.position, l.ref_count
+                 FROM image_layers il JOIN layers l ON l.digest = il.layer_digest
+                 WHERE il.image_ref = ?1 ORDER BY il.position",
+            )
+            .db()?;
+        let rows = stmt
+            .query_map(params![reference], |row| {
+                Ok(LayerInfo {
+                    digest: row.get(0)?,
+                    media_type: row.get(1)?,
+                    size: u64::try_from(row.get::<_, i64>(2)?).unwrap_or(0),
+                    position: u32::try_from(row.get::<_, i64>(3)?).unwrap_or(u32::MAX),
+                    ref_count: u32::try_from(row.get::<_, i64>(4)?).unwrap_or(0),
+                })
+            })
+            .db()?;
+        rows.collect::<rusqlite::Result<_>>().db()
+    }
+
+    /// Lists every layer in the index with the images referencing it: the
+    /// most shared first, then the largest.
+    pub fn layer_refs(&self) -> crate::Result<Vec<LayerRefs>> {
+        let mut stmt = self
+            .db
+            .prepare(
+                "SELECT l.digest, l.media_type, l.size, il.image_ref
+                 FROM layers l LEFT JOIN image_layers il ON il.layer_digest = l.digest
+                 ORDER BY l.digest, il.image_ref",
+            )
+            .db()?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                ))
+            })
+            .db()?;
+        let mut layers: Vec<LayerRefs> = Vec::new();
+        for row in rows {
+            let (digest, media_type, size, image) = row.db()?;
+            // Rows arrive grouped by layer.
+            if layers.last().is_none_or(|last| last.digest != digest) {
+                layers.push(LayerRefs {
+                    digest,
+                    media_type,
+                    size: u64::try_from(size).unwrap_or(0),
+                    images: Vec::new(),
+                });
+            }
+            if let (Some(layer), Some(reference)) = (layers.last_mut(), image) {
+                layer.images.push(reference);
+            }
+        }
+        layers.sort_by(|a, b| {
+            b.images
+                .len()
+                .cmp(&a.images.len())
+                .then(b.size.cmp(&a.size))
+        });
+        Ok(layers)
+    }
+
+    /// Lists an image's layer digests, bottom layer first.
+    pub fn image_layers(&self, reference: &str) -> crate::Result<Vec<String>> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT layer_digest FROM image_layers WHERE image_ref = ?1 ORDER BY position")
+            .db()?;
+        let rows = stmt.query_map(params![reference], |row| row.get(0)).db()?;
+        rows.collect::<rusqlite::Result<_>>().db()
+    }
+
+    /// Points `new_ref` at the same manifest, config and layers as `source`,
+    /// bumping layer ref counts. An existing `new_ref` is replaced.
+    pub fn tag_image(&self, source: &str, new_ref: &str) -> crate::Result<()> {
+        if self.get_digest(source)?.is_none() {
+            return Err(crate::Error::NotFound(source.to_owned()));
+        }
+        if source == new_ref {
+            return Ok(());
+        }
+        if self.get_digest(new_ref)?.is_some() {
+            self.remove_image(new_ref)?;
+        }
+
+        let tx = self.write_tx()?;
+        tx.execute(
+            "INSERT INTO images (reference, digest, size, config)
+             SELECT ?2, digest, size, config FROM images WHERE reference = ?1",
+            params![source, new_ref],
+        )
+        .db()?;
+        tx.execute(
+            "INSERT INTO image_layers (image_ref, layer_digest, position)
+             SELECT ?2, layer_digest, position FROM image_layers WHERE image_ref = ?1",
+            params![source, new_ref],
+        )
+        .db()?;
+        tx.execute(
+            "UPDATE layers SET ref_count = ref_count + 1
+             WHERE digest IN (SELECT layer_digest FROM image_layers WHERE image_ref = ?1)",
+            params![new_ref],
+        )
+        .db()?;
+        tx.execute(
+            "INSERT INTO labels (image_ref, key, value)
+             SELECT ?2, key, value FROM labels WHERE image_ref = ?1",
+            params![source, new_ref],
+        )
+        .db()?;
+        tx.commit().db()
+    }
+
     /// Removes an image and its rootfs. Layer blobs are ref-counted and only
-    /// deleted when no other image references them.
-    pub fn remove_image(&self, reference: &str) -> crate::Result<()> {
+    /// deleted when no other image references them; the rootfs is kept while
+    /// another reference still resolves to the same manifest digest.
+    /// Returns bytes freed on disk.
+    pub fn remove_image(&self, reference: &str) -> crate::Result<u64> {
+        // Read under the write lock, so a concurrent removal of the same
+        // reference cannot release its layers twice.
+        let tx = self.write_tx()?;
+
         // Look up digest for rootfs cleanup.
         let digest = self.get_digest(reference)?;
 
-        // Decrement layer ref counts and collect orphans.
+        // Decrement layer ref counts, including claims of a pull of this
+        // reference that never finished, and collect orphans.
         let layer_digests: Vec<String> = {
-            let mut stmt = self
-                .db
-                .prepare("SELECT layer_digest FROM image_layers WHERE image_ref = ?1")
+            let mut stmt = tx
+                .prepare(
+                    "SELECT layer_digest FROM image_layers WHERE image_ref = ?1
+                     UNION ALL SELECT layer_digest FROM layer_claims WHERE image_ref = ?1",
+                )
                 .db()?;
             let rows = stmt.query_map(params![reference], |row| row.get(0)).db()?;
             rows.filter_map(Result::ok).collect()
         };
 
-        let tx = self.db.unchecked_transaction().db()?;
-
         for ld in &layer_digests {
             tx.execute(
                 "UPDATE layers SET ref_count = ref_count - 1 WHERE digest = ?1",
@@ -341,6 +994,11 @@ pub fn remove_image(&self, reference: &str) -> crate::Result<()> {
             )
             .db()?;
         }
+        tx.execute(
+            "DELETE FROM layer_claims WHERE image_ref = ?1",
+            params![reference],
+        )
+        .db()?;
 
         // Delete the image (CASCADE deletes image_layers).
         tx.execute(
@@ -357,24 +1015,252 @@ pub fn remove_image(&self, reference: &str) -> crate::Result<()> {
             let rows = stmt.query_map([], |row| row.get(0)).db()?;
             rows.filter_map(Result::ok).collect()
         };
+        let mut freed = 0;
         for orphan in &orphans {
             tx.execute("DELETE FROM layers WHERE digest = ?1", params![orphan])
                 .db()?;
-            fs::remove_file(self.layer_path(orphan)).ok();
+            let path = self.layer_path(orphan);
+            let len = disk_usage(&path);
+            if fs::remove_file(path).is_ok() {
+                freed += len;
+            }
         }
 
+        // Other references (e.g. a second tag) may share this rootfs.
+        let rootfs_orphaned = match digest {
+            Some(ref d) => {
+                tx.query_row(
+                    "SELECT COUNT(*) FROM images WHERE digest = ?1",
+                    params![d],
+                    |row| row.get::<_, i64>(0),
+                )
+                .db()?
+                    == 0
+            }
+            None => false,
+        };
+
         tx.commit().db()?;
 
-        // Remove rootfs directory.
-        if let Some(ref d) = digest {
+        // Remove rootfs directory once its last referent is gone.
+        if let Some(ref d) = digest
+            && rootfs_orphaned
+        {
             let rootfs = self.rootfs_path(d);
             if rootfs.exists() {
+                freed += disk_usage(&rootfs);
                 fs::remove_dir_all(&rootfs)?;
             }
+            let manifest = self.manifest_path(d);
+            let len = disk_usage(&manifest);
+            if fs::remove_file(manifest).is_ok() {
+                freed += len;
+            }
         }
 
-        Ok(())
+        Ok(freed)
+    }
+
+    /// Deletes layer blobs, manifests and rootfs directories that no image
+    /// references, plus staging leftovers from interrupted pulls. Returns
+    /// bytes freed.
+    ///
+    /// Image records [`scan_images`](Self::scan_images) skips are deleted
+    /// first, releasing their layers.
+    ///
+    /// The rootfs and manifest of each digest in `pinned` survive even
+    /// without an image record (e.g. a running VM's image whose tag moved).
+    ///
+    /// Must not run concurrently with a pull, whose in-progress staging
+    /// files would be removed.
+    pub fn prune(&self, pinned: &[String]) -> crate::Result<u64> {
+        self.remove_malformed_images()?;
+        let layers: HashSet<PathBuf> = self
+            .query_strings("SELECT digest FROM layers")?
+            .iter()
+            .map(|d| self.layer_path(d))
+            .collect();
+        let mut images = self.query_strings("SELECT DISTINCT digest FROM images")?;
+        images.extend_from_slice(pinned);
+        let rootfs: HashSet<PathBuf> = images.iter().map(|d| self.rootfs_path(d)).collect();
+        let manifests: HashSet<PathBuf> = images.iter().map(|d| self.manifest_path(d)).collect();
+
+        let mut freed = 0;
+        for (dir, keep) in [
+            ("layers", &layers),
+            ("manifests", &manifests),
+            ("rootfs", &rootfs),
+        ] {
+            for entry in fs::read_dir(self.root.join(dir))? {
+                let path = entry?.path();
+                if keep.contains(&path) {
+                    continue;
+                }
+                freed += disk_usage(&path);
+                if path.is_dir() {
+                    fs::remove_dir_all(&path)?;
+                } else {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+        // A separate staging area only ever holds leftovers.
+        if let Some(staging) = &self.staging {
+            for dir in ["layers", "rootfs"] {
+                for entry in fs::read_dir(staging.join(dir))? {
+                    let path = entry?.path();
+                    freed += disk_usage(&path);
+                    remove_tree(&path)?;
+                }
+            }
+        }
+        Ok(freed)
+    }
+
+    /// Runs [`prune`](Self::prune), then removes the least recently used
+    /// images until the store takes at most `max_bytes` on disk. Returns
+    /// bytes freed.
+    ///
+    /// Images whose digest is in `pinned` are never removed, so the store
+    /// can stay above the cap.
+    pub fn prune_to_size(&self, max_bytes: u64, pinned: &[String]) -> crate::Result<u64> {
+        let mut freed = self.prune(pinned)?;
+        let mut usage = disk_usage(&self.root);
+        for image in self.images_by_lru()? {
+            if usage <= max_bytes {
+                break;
+            }
+            if pinned.contains(&image.digest) {
+                continue;
+            }
+            let bytes = self.remove_image(&image.reference)?;
+            freed += bytes;
+            usage = usage.saturating_sub(bytes);
+        }
+        Ok(freed)
+    }
+
+    /// Deletes unparsable image records and the layer references they
+    /// held, dropping layers nothing else uses.
+    fn remove_malformed_images(&self) -> crate::Result<()> {
+        let tx = self.write_tx()?;
+        tx.execute_batch(&format!(
+            "UPDATE layers SET ref_count = ref_count - (
+                 SELECT COUNT(*) FROM image_layers JOIN images ON image_ref = reference
+                 WHERE layer_digest = layers.digest AND ({MALFORMED_IMAGE}));
+             DELETE FROM images WHERE {MALFORMED_IMAGE};
+             DELETE FROM layers WHERE ref_count <= 0;"
+        ))
+        .db()?;
+        tx.commit().db()
+    }
+
+    /// Compacts the SQLite index: truncates the WAL, then runs `VACUUM` and
+    /// `ANALYZE`. Returns bytes reclaimed from `images.db` and its WAL.
+    ///
+    /// This rewrites the whole database, so run it occasionally (e.g. via
+    /// `bux image gc`), not after every pull.
+    pub fn maintain(&self) -> crate::Result<u64> {
+        let before = self.db_size();
+        self.checkpoint()?;
+        self.db.execute_batch("VACUUM; ANALYZE;").db()?;
+        // In WAL mode VACUUM writes through the WAL; fold it back in.
+        self.checkpoint()?;
+        Ok(before.saturating_sub(self.db_size()))
+    }
+
+    /// Starts a transaction holding the write lock from the outset.
+    ///
+    /// A deferred transaction that reads first and then writes fails with
+    /// `SQLITE_BUSY` at once when another connection wrote in between;
+    /// taking the lock up front waits for it instead. Should the wait
+    /// exceed the busy timeout, taking the lock is retried a few times.
+    fn write_tx(&self) -> crate::Result<Transaction<'_>> {
+        let mut attempt = 1;
+        loop {
+            match Transaction::new_unchecked(&self.db, TransactionBehavior::Immediate) {
+                Err(e) if is_busy(&e) && attempt < WRITE_ATTEMPTS => attempt += 1,
+                result => return result.db(),
+            }
+        }
+    }
+
+    /// Runs `PRAGMA wal_checkpoint(TRUNCATE)`.
+    fn checkpoint(&self) -> crate::Result<()> {
+        self.db
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+            .db()
+    }
+
+    /// Combined on-disk size of `images.db` and its WAL.
+    fn db_size(&self) -> u64 {
+        ["images.db", "images.db-wal"]
+            .iter()
+            .filter_map(|name| fs::metadata(self.root.join(name)).ok())
+            .map(|m| m.len())
+            .sum()
     }
+
+    /// Collects the first column of a query as strings.
+    fn query_strings(&self, sql: &str) -> crate::Result<Vec<String>> {
+        let mut stmt = self.db.prepare(sql).db()?;
+        let rows = stmt.query_map([], |row| row.get(0)).db()?;
+        rows.collect::<rusqlite::Result<_>>().db()
+    }
+}
+
+/// Reads an [`ImageMeta`] from a row selecting [`IMAGE_COLUMNS`].
+fn image_meta_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<ImageMeta> {
+    Ok(ImageMeta {
+        reference: row.get(0)?,
+        digest: row.get(1)?,
+        size: u64::try_from(row.get::<_, i64>(2)?).unwrap_or(0),
+        created_at: row.get::<_, String>(3).unwrap_or_default(),
+        last_used: row.get::<_, String>(4).unwrap_or_default(),
+    })
+}
+
+/// Total size of a file or directory tree, without following symlinks.
+pub fn disk_usage(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    fs::read_dir(path).map_or(0, |entries| {
+        entries
+            .filter_map(Result::ok)
+            .map(|e| disk_usage(&e.path()))
+            .sum()
+    })
+}
+
+/// Indexes the `config.Labels` of an image config blob. Non-string values
+/// are stored as their JSON text.
+fn insert_labels(db: &Connection, reference: &str, config_json: Option<&str>) -> crate::Result<()> {
+    let Some(value) = config_json.and_then(|j| serde_json::from_str::<serde_json::Value>(j).ok())
+    else {
+        return Ok(());
+    };
+    let Some(labels) = value
+        .get("config")
+        .and_then(|c| c.get("Labels"))
+        .and_then(serde_json::Value::as_object)
+    else {
+        return Ok(());
+    };
+    for (key, val) in labels {
+        let text = val
+            .as_str()
+            .map_or_else(|| val.to_string(), ToOwned::to_owned);
+        db.execute(
+            "INSERT OR REPLACE INTO labels (image_ref, key, value) VALUES (?1, ?2, ?3)",
+            params![reference, key, text],
+        )
+        .db()?;
+    }
+    Ok(())
 }
 
 /// Writes data to a file atomically (write to .tmp, then rename).
@@ -386,3 +1272,708 @@ fn atomic_write(path: &Path, data: &[u8]) -> io::Result<()> {
     fs::rename(&tmp, path)?;
     Ok(())
 }
+
+/// Moves the file or directory tree `from` to `to` with `rename`, falling
+/// back to copying when they are on different filesystems (`EXDEV`, e.g.
+/// when `layers/` is a bind mount).
+///
+/// The copy is built and synced at a temporary sibling of `to`, then
+/// renamed over it, so `to` never holds partial data; `from` is removed only
+/// once the copy is in place. `rename` is a parameter so tests can inject
+/// failures.
+fn move_into_place(
+    from: &Path,
+    to: &Path,
+    rename: impl Fn(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    match rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+        result => return result,
+    }
+    let mut tmp_name = to.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".xdev");
+    let tmp = to.with_file_name(tmp_name);
+    remove_tree(&tmp)?;
+    if let Err(e) = copy_synced(from, &tmp) {
+        remove_tree(&tmp).ok();
+        return Err(e);
+    }
+    rename(&tmp, to)?;
+    remove_tree(from)
+}
+
+/// Copies a file, symlink or directory tree, syncing every file and
+/// directory written. Ownership is kept where permitted.
+fn copy_synced(from: &Path, to: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    let ft = meta.file_type();
+    if ft.is_symlink() {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+        #[cfg(not(unix))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("cannot copy symlink {} across filesystems", from.display()),
+        ));
+    } else if ft.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let child = entry?;
+            copy_synced(&child.path(), &to.join(child.file_name()))?;
+        }
+        fs::set_permissions(to, meta.permissions())?;
+        fs::File::open(to)?.sync_all()?;
+    } else if ft.is_file() {
+        fs::copy(from, to)?;
+        fs::File::open(to)?.sync_all()?;
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "cannot copy special file {} across filesystems",
+                from.display()
+            ),
+        ));
+    }
+    // Only root can hand files to other users; others keep their own.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        std::os::unix::fs::lchown(to, Some(meta.uid()), Some(meta.gid())).ok();
+    }
+    Ok(())
+}
+
+/// Removes a file or directory tree; a missing path is not an error.
+fn remove_tree(path: &Path) -> io::Result<()> {
+    let removed = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match removed {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+/// Streams `path` through the hash named by `digest` (`sha256:…` or
+/// `sha512:…`) and compares. Unknown algorithms never match.
+pub fn file_matches_digest(path: &Path, digest: &str) -> crate::Result<bool> {
+    let mut hasher = DigestHasher::new(digest);
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.matches(digest))
+}
+
+/// Incremental hash for checking data against a `sha256:…` or `sha512:…`
+/// digest, fed as it streams by.
+pub enum DigestHasher {
+    /// `sha256:…`
+    Sha256(Sha256),
+    /// `sha512:…`
+    Sha512(sha2::Sha512),
+    /// Any other algorithm; never matches.
+    Unknown,
+}
+
+impl DigestHasher {
+    /// Starts a hash of the algorithm named by `digest`.
+    pub fn new(digest: &str) -> Self {
+        match digest.split_once(':') {
+            Some(("sha256", _)) => Self::Sha256(Sha256::new()),
+            Some(("sha512", _)) => Self::Sha512(sha2::Sha512::new()),
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Adds `data` to the hash.
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+            Self::Unknown => {}
+        }
+    }
+
+    /// Returns `true` if everything hashed so far has `digest`.
+    pub fn matches(self, digest: &str) -> bool {
+        let computed = match self {
+            Self::Sha256(h) => format!("sha256:{:x}", h.finalize()),
+            Self::Sha512(h) => format!("sha512:{:x}", h.finalize()),
+            Self::Unknown => return false,
+        };
+        computed == digest
+    }
+}
+
+impl Write for DigestHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_rootfs_survives_until_last_reference() {
+        let root = std::env::temp_dir().join(format!("bux_oci_store_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Store::open(&root).unwrap();
+
+        let digest = "sha256:abc123";
+        store
+            .upsert_image("alpine:latest", digest, 1, "sha256:cfg", &[])
+            .unwrap();
+        store
+            .upsert_image("alpine:3", digest, 1, "sha256:cfg", &[])
+            .unwrap();
+        fs::create_dir_all(store.rootfs_path(digest)).unwrap();
+        store.save_manifest(digest, b"{}").unwrap();
+
+        store.remove_image("alpine:latest").unwrap();
+        assert!(store.rootfs_complete(digest));
+        assert!(store.load_manifest(digest).unwrap().is_some());
+        assert_eq!(
+            store.get_digest("alpine:3").unwrap().as_deref(),
+            Some(digest)
+        );
+
+        store.remove_image("alpine:3").unwrap();
+        assert!(!store.rootfs_path(digest).exists());
+        assert_eq!(store.load_manifest(digest).unwrap(), None);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn concurrent_writers_wait_instead_of_failing_busy() {
+        let root = std::env::temp_dir().join(format!("bux_oci_store_busy_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let layers = vec!["sha256:shared".to_owned()];
+
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let (dir, shared) = (&root, &layers);
+                scope.spawn(move || {
+                    let store = Store::open(dir).unwrap();
+                    for i in 0..25 {
+                        let reference = format!("img{thread}:{i}");
+                        store
+                            .record_layer(&reference, &shared[0], "application/x-tar", 1)
+                            .unwrap();
+                        store
+                            .upsert_image(&reference, "sha256:m", 1, "sha256:cfg", shared)
+                            .unwrap();
+                        store.remove_image(&reference).unwrap();
+                    }
+                });
+            }
+        });
+
+        let store = Store::open(&root).unwrap();
+        assert!(store.list_images().unwrap().is_empty());
+        // Every reference was released exactly once.
+        assert!(
+            store
+                .query_strings("SELECT digest FROM layers")
+                .unwrap()
+                .is_empty()
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn digest_prefixes_resolve_unique_images_only() {
+        let root =
+            std::env::temp_dir().join(format!("bux_oci_store_prefix_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Store::open(&root).unwrap();
+        store
+            .upsert_image("alpine:latest", "sha256:ab12ef", 1, "sha256:cfg", &[])
+            .unwrap();
+        store
+            .upsert_image("alpine:3", "sha256:ab12ef", 1, "sha256:cfg", &[])
+            .unwrap();
+        store
+            .upsert_image("nginx:latest", "sha256:ab34cd", 1, "sha256:cfg", &[])
+            .unwrap();
+
+        // Unique, with or without the algorithm; two tags are one image.
+        for prefix in ["ab12", "sha256:ab12", "AB12E"] {
+            assert_eq!(
+                store.resolve_digest_prefix(prefix).unwrap(),
+                "sha256:ab12ef"
+            );
+        }
+        let mut references = store.references_to("sha256:ab12ef").unwrap();
+        references.sort();
+        assert_eq!(references, ["alpine:3", "alpine:latest"]);
+
+        assert!(matches!(
+            store.resolve_digest_prefix("ab"),
+            Err(crate::Error::Ambiguous(_))
+        ));
+        assert!(matches!(
+            store.resolve_digest_prefix("ff"),
+            Err(crate::Error::NotFound(_))
+        ));
+        assert!(matches!(
+            store.resolve_digest_prefix("alpine"),
+            Err(crate::Error::InvalidReference(_))
+        ));
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn layer_refs_lists_shared_layers_first() {
+        let root = std::env::temp_dir().join(format!("bux_oci_store_refs_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Store::open(&root).unwrap();
+        for (reference, digest, size) in [
+            ("app:1", "sha256:base", 100),
+            ("app:1", "sha256:app", 500),
+            ("web:1", "sha256:base", 100),
+            ("web:1", "sha256:web", 50),
+        ] {
+            store.record_layer(reference, digest, "tar", size).unwrap();
+        }
+        let (base, app, web) = (
+            "sha256:base".to_owned(),
+            "sha256:app".to_owned(),
+            "sha256:web".to_owned(),
+        );
+        store
+            .upsert_image("app:1", "sha256:m1", 1, "sha256:c", &[base.clone(), app])
+            .unwrap();
+        store
+            .upsert_image("web:1", "sha256:m2", 1, "sha256:c", &[base, web])
+            .unwrap();
+        store
+            .record_layer("gone:1", "sha256:orphan", "tar", 7)
+            .unwrap();
+
+        let refs = store.layer_refs().unwrap();
+        let summary: Vec<_> = refs
+            .iter()
+            .map(|l| (l.digest.as_str(), l.images.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("sha256:base", 2),
+                ("sha256:app", 1),
+                ("sha256:web", 1),
+                ("sha256:orphan", 0)
+            ]
+        );
+        assert_eq!(refs[0].images, ["app:1", "web:1"]);
+        assert_eq!(refs[1].size, 500);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn layers_for_image_lists_blobs_in_manifest_order() {
+        let root = std::env::temp_dir().join(format!("bux_oci_layers_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Store::open(&root).unwrap();
+
+        // Committed once per image that pulled it.
+        for (reference, digest, size) in [
+            ("app:1", "sha256:base", 300),
+            ("app:1", "sha256:app", 20),
+            ("base:1", "sha256:base", 300),
+        ] {
+            fs::write(store.layer_staging_path(digest), b"").unwrap();
+            store
+                .commit_layer(reference, digest, "tar+gzip", size)
+                .unwrap();
+        }
+        let stack = ["sha256:base".to_owned(), "sha256:app".to_owned()];
+        store
+            .upsert_image("app:1", "sha256:m1", 320, "sha256:c", &stack)
+            .unwrap();
+        store
+            .upsert_image("base:1", "sha256:m2", 300, "sha256:c", &stack[..1])
+            .unwrap();
+
+        let layers = store.layers_for_image("app:1").unwrap();
+        let summary: Vec<_> = layers
+            .iter()
+            .map(|l| (l.digest.as_str(), l.position, l.size, l.ref_count))
+            .collect();
+        assert_eq!(
+            summary,
+            [("sha256:base", 0, 300, 2), ("sha256:app", 1, 20, 1)]
+        );
+        assert_eq!(layers[0].media_type, "tar+gzip");
+        assert!(store.layers_for_image("missing:1").unwrap().is_empty());
+        assert_eq!(store.image_meta("app:1").unwrap().unwrap().size, 320);
+        assert!(store.image_meta("missing:1").unwrap().is_none());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn prune_removes_unreferenced_blobs_and_maintain_succeeds() {
+        let root = std::env::temp_dir().join(format!("bux_oci_prune_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Store::open(&root).unwrap();
+
+        store
+            .upsert_image("alpine:latest", "sha256:keep", 1, "sha256:cfg", &[])
+            .unwrap();
+        fs::create_dir_all(store.rootfs_path("sha256:keep")).unwrap();
+        fs::create_dir_all(store.rootfs_path("sha256:stale")).unwrap();
+        fs::write(store.rootfs_path("sha256:stale").join("f"), [0u8; 100]).unwrap();
+        fs::write(store.layer_staging_path("sha256:partial"), [0u8; 50]).unwrap();
+        fs::create_dir_all(store.rootfs_path("sha256:pinned")).unwrap();
+
+        assert_eq!(store.prune(&["sha256:pinned".to_owned()]).unwrap(), 150);
+        assert!(store.rootfs_complete("sha256:keep"));
+        assert!(store.rootfs_path("sha256:pinned").exists());
+        assert!(!store.rootfs_path("sha256:stale").exists());
+        assert!(!store.layer_staging_path("sha256:partial").exists());
+
+        store.maintain().unwrap();
+        assert_eq!(store.list_images().unwrap().len(), 1);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn malformed_image_rows_are_skipped_then_pruned() {
+        let root =
+            std::env::temp_dir().join(format!("bux_oci_corrupt_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Store::open(&root).unwrap();
+
+        // One reference per image using the layer, as pulls record them.
+        for (reference, digest) in [
+            ("alpine:latest", "sha256:shared"),
+            ("broken:latest", "sha256:shared"),
+            ("broken:latest", "sha256:own"),
+        ] {
+            store.record_layer(reference, digest, "tar", 1).unwrap();
+        }
+        store
+            .upsert_image(
+                "alpine:latest",
+                "sha256:ok",
+                1,
+                "sha256:cfg",
+                &["sha256:shared".into()],
+            )
+            .unwrap();
+        store
+            .upsert_image(
+                "broken:latest",
+                "sha256:bad",
+                1,
+                "sha256:cfg",
+                &["sha256:shared".into(), "sha256:own".into()],
+            )
+            .unwrap();
+        store
+            .db
+            .execute_batch(
+                "UPDATE images SET size = 'huge' WHERE reference = 'broken:latest';
+                 INSERT INTO images (reference, digest) VALUES ('blob:latest', x'00');",
+            )
+            .unwrap();
+
+        let list = store.scan_images(&[]).unwrap();
+        assert_eq!(list.skipped, 2);
+        assert_eq!(list.images.len(), 1);
+        assert_eq!(list.images[0].reference, "alpine:latest");
+        assert_eq!(store.list_images().unwrap().len(), 1);
+
+        store.prune(&[]).unwrap();
+        assert_eq!(store.scan_images(&[]).unwrap().skipped, 0);
+        let layers = store.layers_for_image("alpine:latest").unwrap();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].ref_count, 1);
+        assert_eq!(
+            store.query_strings("SELECT digest FROM layers").unwrap(),
+            ["sha256:shared"]
+        );
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn prune_to_size_evicts_least_recently_used_unpinned() {
+        let root = std::env::temp_dir().join(format!("bux_oci_lru_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Store::open(&root).unwrap();
+        let pause = || std::thread::sleep(Duration::from_millis(5));
+
+        for name in ["a", "b", "c"] {
+            let digest = format!("sha256:{name}");
+            store
+                .upsert_image(&format!("{name}:1"), &digest, 1, "sha256:cfg", &[])
+                .unwrap();
+            fs::create_dir_all(store.rootfs_path(&digest)).unwrap();
+            fs::write(store.rootfs_path(&digest).join("f"), [0u8; 1000]).unwrap();
+            pause();
+        }
+        // Touching within the hour doesn't write; age every use past it.
+        store.touch("a:1").unwrap();
+        assert_eq!(store.images_by_lru().unwrap()[0].reference, "a:1");
+        store
+            .db
+            .execute(
+                "UPDATE images SET last_used = strftime('%Y-%m-%d %H:%M:%f', last_used, '-2 hours')",
+                [],
+            )
+            .unwrap();
+        store.touch("a:1").unwrap();
+        let order: Vec<_> = store
+            .images_by_lru()
+            .unwrap()
+            .into_iter()
+            .map(|i| i.reference)
+            .collect();
+        assert_eq!(order, ["b:1", "c:1", "a:1"]);
+
+        // "b" is least recently used but pinned, so "c" goes instead; one
+        // eviction is enough to get under the cap.
+        let cap = disk_usage(&root) - 500;
+        let freed = store.prune_to_size(cap, &["sha256:b".to_owned()]).unwrap();
+        assert_eq!(freed, 1000);
+        let left: Vec<_> = store
+            .images_by_lru()
+            .unwrap()
+            .into_iter()
+            .map(|i| i.reference)
+            .collect();
+        assert_eq!(left, ["b:1", "a:1"]);
+        assert!(!store.rootfs_path("sha256:c").exists());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn list_images_filters_by_label_and_reference() {
+        let root = std::env::temp_dir().join(format!("bux_oci_filter_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Store::open(&root).unwrap();
+
+        store
+            .save_config(
+                "sha256:prod",
+                r#"{"config":{"Labels":{"stage":"prod","org.opencontainers.image.version":"1.2"}}}"#,
+            )
+            .unwrap();
+        store
+            .save_config("sha256:dev", r#"{"config":{"Labels":{"stage":"dev"}}}"#)
+            .unwrap();
+        store
+            .upsert_image(
+                "docker.io/library/alpine:3",
+                "sha256:a",
+                1,
+                "sha256:prod",
+                &[],
+            )
+            .unwrap();
+        store
+            .upsert_image("ghcr.io/acme/tool:1", "sha256:b", 1, "sha256:dev", &[])
+            .unwrap();
+
+        let refs = |filters: &[&str]| -> Vec<String> {
+            let parsed: Vec<ImageFilter> = filters.iter().map(|f| f.parse().unwrap()).collect();
+            let mut refs: Vec<String> = store
+                .list_images_filtered(&parsed)
+                .unwrap()
+                .into_iter()
+                .map(|m| m.reference)
+                .collect();
+            refs.sort();
+            refs
+        };
+        assert_eq!(refs(&["label=stage=prod"]), ["docker.io/library/alpine:3"]);
+        assert_eq!(refs(&["label=stage"]).len(), 2);
+        assert_eq!(
+            refs(&["reference=alpine:*"]),
+            ["docker.io/library/alpine:3"]
+        );
+        assert_eq!(refs(&["reference=ghcr.io/*"]), ["ghcr.io/acme/tool:1"]);
+        assert!(refs(&["label=stage=prod", "reference=ghcr.io/*"]).is_empty());
+        assert!(refs(&["label=missing"]).is_empty());
+        assert!("stage=prod".parse::<ImageFilter>().is_err());
+
+        // Re-pulling under a new config replaces the old labels.
+        store
+            .upsert_image("ghcr.io/acme/tool:1", "sha256:b", 1, "sha256:prod", &[])
+            .unwrap();
+        assert_eq!(refs(&["label=stage=dev"]), Vec::<String>::new());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn tag_shares_blobs_until_last_reference() {
+        let root = std::env::temp_dir().join(format!("bux_oci_tag_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Store::open(&root).unwrap();
+        let digest = "sha256:img";
+        let layer = "sha256:layer";
+
+        fs::write(store.layer_staging_path(layer), b"blob").unwrap();
+        store
+            .commit_layer("ghcr.io/org/app:1", layer, "tar+gzip", 4)
+            .unwrap();
+        store
+            .upsert_image(
+                "ghcr.io/org/app:1",
+                digest,
+                4,
+                "sha256:cfg",
+                &[layer.to_owned()],
+            )
+            .unwrap();
+        fs::create_dir_all(store.rootfs_path(digest)).unwrap();
+
+        store.tag_image("ghcr.io/org/app:1", "app:local").unwrap();
+        assert_eq!(
+            store.get_digest("app:local").unwrap().as_deref(),
+            Some(digest)
+        );
+        assert_eq!(store.image_layers("app:local").unwrap(), [layer]);
+        assert!(matches!(
+            store.tag_image("missing:1", "other:1"),
+            Err(crate::Error::NotFound(_))
+        ));
+
+        store.remove_image("ghcr.io/org/app:1").unwrap();
+        assert!(store.has_layer(layer));
+        assert!(store.rootfs_complete(digest));
+
+        store.remove_image("app:local").unwrap();
+        assert!(!store.has_layer(layer));
+        assert!(!store.rootfs_path(digest).exists());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn commits_are_idempotent() {
+        let root = std::env::temp_dir().join(format!("bux_oci_commit_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Store::open(&root).unwrap();
+
+        // A blob already in place is kept, with or without a new staging file.
+        let digest = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        fs::write(store.layer_path(digest), b"hello").unwrap();
+        store.commit_layer("app:1", digest, "tar+gzip", 5).unwrap();
+        fs::write(store.layer_staging_path(digest), b"hello").unwrap();
+        store.commit_layer("app:1", digest, "tar+gzip", 5).unwrap();
+        assert!(!store.layer_staging_path(digest).exists());
+        assert_eq!(fs::read(store.layer_path(digest)).unwrap(), b"hello");
+
+        // The retry counted the image once; removing it frees the blob.
+        store
+            .upsert_image("app:1", "sha256:m", 5, "sha256:c", &[digest.to_owned()])
+            .unwrap();
+        assert_eq!(store.layers_for_image("app:1").unwrap()[0].ref_count, 1);
+        store.remove_image("app:1").unwrap();
+        assert!(!store.has_layer(digest));
+
+        // A rootfs already in place is kept and the staging tree dropped.
+        let manifest = "sha256:m";
+        fs::create_dir_all(store.rootfs_path(manifest)).unwrap();
+        fs::create_dir_all(store.rootfs_staging_path(manifest)).unwrap();
+        store.commit_rootfs(manifest).unwrap();
+        store.commit_rootfs(manifest).unwrap();
+        assert!(!store.rootfs_staging_path(manifest).exists());
+        assert!(store.rootfs_complete(manifest));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn separate_staging_dir_feeds_the_store() {
+        let root =
+            std::env::temp_dir().join(format!("bux_oci_staging_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let staging = root.join("staging");
+        let store = Store::open(&root.join("store"))
+            .unwrap()
+            .with_staging_dir(&staging)
+            .unwrap();
+
+        let digest = "sha256:layer";
+        assert!(store.layer_staging_path(digest).starts_with(&staging));
+        assert!(store.rootfs_staging_path("sha256:m").starts_with(&staging));
+        fs::write(store.layer_staging_path(digest), b"blob").unwrap();
+        store.commit_layer("app:1", digest, "tar+gzip", 4).unwrap();
+        assert_eq!(fs::read(store.layer_path(digest)).unwrap(), b"blob");
+
+        // Leftovers from an interrupted pull are pruned from the staging dir.
+        fs::write(store.layer_staging_path("sha256:partial"), b"par").unwrap();
+        fs::create_dir_all(store.rootfs_staging_path("sha256:m")).unwrap();
+        store.prune(&[]).unwrap();
+        assert_eq!(fs::read_dir(staging.join("layers")).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(staging.join("rootfs")).unwrap().count(), 0);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn move_into_place_copies_across_devices() {
+        let root = std::env::temp_dir().join(format!("bux_oci_xdev_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let from = root.join("staging");
+        let to = root.join("final");
+        fs::create_dir_all(from.join("etc")).unwrap();
+        fs::write(from.join("etc/hostname"), b"bux").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("etc/hostname", from.join("link")).unwrap();
+
+        // Only the first rename (the cross-device one) fails.
+        let xdev = |src: &Path, dst: &Path| {
+            if src == from {
+                Err(io::Error::from(io::ErrorKind::CrossesDevices))
+            } else {
+                fs::rename(src, dst)
+            }
+        };
+        move_into_place(&from, &to, xdev).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read(to.join("etc/hostname")).unwrap(), b"bux");
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(to.join("link")).unwrap(),
+            Path::new("etc/hostname")
+        );
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+
+        // Other rename failures surface unchanged.
+        fs::create_dir(&from).unwrap();
+        let denied = |_: &Path, _: &Path| Err(io::Error::from(io::ErrorKind::PermissionDenied));
+        let err = move_into_place(&from, &root.join("other"), denied).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(from.exists());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn file_matches_digest_streams_sha256() {
+        let path = std::env::temp_dir().join(format!("bux_oci_digest_test_{}", std::process::id()));
+        fs::write(&path, b"hello").unwrap();
+        let digest = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert!(file_matches_digest(&path, digest).unwrap());
+        assert!(!file_matches_digest(&path, "sha256:00").unwrap());
+        assert!(!file_matches_digest(&path, "md5:5d41402abc4b2a76b9719d911017c592").unwrap());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,56 @@
+//! Shared-secret authentication of host connections.
+//!
+//! When the host sets [`AUTH_ENV`] in the guest environment, the agent
+//! expects every connection to open with [`Hello::Auth`](crate::Hello::Auth)
+//! carrying the same token before the operation's own `Hello`. Like the
+//! mount table, the value travels on the kernel command line, so it must
+//! pass [`is_valid_token`].
+//!
+//! The token keeps other host processes that can reach the vsock socket
+//! out of the VM. It does not hide the secret from code running inside the
+//! guest, which can read the kernel command line.
+
+/// Environment variable carrying the expected token.
+pub const AUTH_ENV: &str = "BUX_AUTH_TOKEN";
+
+/// Longest token accepted, in bytes.
+pub const MAX_TOKEN_LEN: usize = 256;
+
+/// Whether `token` can be handed to the guest: non-empty, at most
+/// [`MAX_TOKEN_LEN`] bytes of printable ASCII, without `"`.
+pub fn is_valid_token(token: &str) -> bool {
+    !token.is_empty()
+        && token.len() <= MAX_TOKEN_LEN
+        && token.bytes().all(|b| b.is_ascii_graphic() && b != b'"')
+}
+
+/// Compares `given` against `expected` in time that depends only on the
+/// length of `expected`.
+pub fn token_matches(expected: &str, given: &str) -> bool {
+    let (want, got) = (expected.as_bytes(), given.as_bytes());
+    let mut diff = u8::from(want.len() != got.len());
+    for (i, &b) in want.iter().enumerate() {
+        diff |= b ^ got.get(i).copied().unwrap_or(!b);
+    }
+    std::hint::black_box(diff) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_compare_exactly() {
+        assert!(token_matches("s3cret", "s3cret"));
+        assert!(!token_matches("s3cret", "s3creT"));
+        assert!(!token_matches("s3cret", "s3cre"));
+        assert!(!token_matches("s3cret", "s3cret!"));
+        assert!(!token_matches("s3cret", ""));
+
+        assert!(is_valid_token("a-Z_0.9+/="));
+        assert!(!is_valid_token(""));
+        assert!(!is_valid_token("two words"));
+        assert!(!is_valid_token("quo\"te"));
+        assert!(!is_valid_token(&"x".repeat(MAX_TOKEN_LEN + 1)));
+    }
+}
@@ -1,8 +1,12 @@
-//! Async length-prefixed frame codec over any [`AsyncRead`]/[`AsyncWrite`] stream.
+//! Async frame codec over any [`AsyncRead`]/[`AsyncWrite`] stream.
 //!
-//! Each frame is: `[u32 big-endian length][postcard payload]`.
+//! Each frame is `[u32 big-endian length][postcard payload]`, or with the
+//! `json` feature and [`Codec::Json`], one line of JSON. [`send_blocking`]
+//! and [`recv_blocking`] frame the same way over blocking std streams.
 
+use std::future::Future;
 use std::io;
+use std::sync::atomic::{AtomicU8, Ordering};
 
 use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
@@ -10,45 +14,253 @@
 /// Maximum allowed frame payload (16 MiB).
 const MAX_FRAME: u32 = 16 * 1024 * 1024;
 
-/// Sends a postcard-serialized message with a 4-byte BE length prefix.
+/// Largest chunk a stream sender puts in one frame.
+///
+/// Leaves room under the 16 MiB frame limit for the [`Upload`](crate::Upload)
+/// / [`Download`](crate::Download) tag and the postcard length prefix.
+/// JSON frames take less; see [`Codec::max_chunk_size`].
+pub const MAX_CHUNK_SIZE: usize = MAX_FRAME as usize - 16;
+
+/// Largest chunk in one JSON frame: each byte becomes up to three digits
+/// and a comma, plus room for the tag.
+#[cfg(feature = "json")]
+const MAX_JSON_CHUNK_SIZE: usize = (MAX_FRAME as usize - 64) / 4;
+
+/// Clamps a requested chunk size to what fits in one frame of the current
+/// codec.
+fn chunk_len(chunk_size: usize) -> usize {
+    chunk_size.clamp(1, Codec::current().max_chunk_size())
+}
+
+/// How frames are encoded on a connection.
+///
+/// A connection keeps one codec throughout, chosen by the side that opens
+/// it: the guest agent looks at the first byte ([`Codec::detect`]) and
+/// answers in kind. [`send`], [`recv`] and [`FrameReader`] use the codec
+/// of the current task ([`Codec::scope`]), else the process default
+/// ([`Codec::set_default`]).
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    /// Length-prefixed postcard: compact, and what production uses.
+    #[default]
+    Postcard,
+    /// Newline-delimited JSON, one message per line, for reading and typing
+    /// frames by hand (e.g. `socat - UNIX-CONNECT:<socket>`) and for third
+    /// party agents. Slower and larger: byte payloads become number arrays.
+    #[cfg(feature = "json")]
+    Json,
+}
+
+tokio::task_local! {
+    /// Codec of the connection the current task serves.
+    static CODEC: Codec;
+}
+
+/// Codec for tasks outside a [`Codec::scope`], as stored by
+/// [`Codec::set_default`].
+static DEFAULT_CODEC: AtomicU8 = AtomicU8::new(0);
+
+impl Codec {
+    /// Picks the codec a connection uses from its first byte.
+    ///
+    /// Postcard frames start with a length byte of 0 or 1 (frames are
+    /// capped at 16 MiB); a JSON frame starts with `{`.
+    pub const fn detect(first: u8) -> Self {
+        match first {
+            #[cfg(feature = "json")]
+            b'{' => Self::Json,
+            _ => Self::Postcard,
+        }
+    }
+
+    /// Runs `fut` with this codec for every frame it sends or receives.
+    pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+        CODEC.scope(self, fut).await
+    }
+
+    /// Makes this the codec outside any [`scope`](Self::scope), for the
+    /// whole process. Meant for debugging; agents that predate the `json`
+    /// feature only speak postcard.
+    pub fn set_default(self) {
+        DEFAULT_CODEC.store(self as u8, Ordering::Relaxed);
+    }
+
+    /// Codec for the current task.
+    pub fn current() -> Self {
+        CODEC.try_with(|codec| *codec).unwrap_or_else(|_| {
+            match DEFAULT_CODEC.load(Ordering::Relaxed) {
+                #[cfg(feature = "json")]
+                1 => Self::Json,
+                _ => Self::Postcard,
+            }
+        })
+    }
+
+    /// Largest chunk a stream sender puts in one frame of this codec:
+    /// [`MAX_CHUNK_SIZE`] for postcard, about a quarter of that for JSON.
+    pub const fn max_chunk_size(self) -> usize {
+        match self {
+            Self::Postcard => MAX_CHUNK_SIZE,
+            #[cfg(feature = "json")]
+            Self::Json => MAX_JSON_CHUNK_SIZE,
+        }
+    }
+
+    /// Encodes `msg` as a complete frame.
+    fn encode(self, msg: &impl Serialize) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Postcard => {
+                let payload = postcard::to_allocvec(msg).map_err(invalid_data)?;
+                let len = u32::try_from(payload.len()).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "frame exceeds u32::MAX")
+                })?;
+                // Pre-assemble frame to minimize syscalls.
+                let mut frame = Vec::with_capacity(4 + payload.len());
+                frame.extend_from_slice(&len.to_be_bytes());
+                frame.extend_from_slice(&payload);
+                Ok(frame)
+            }
+            #[cfg(feature = "json")]
+            Self::Json => {
+                // Strings escape their newlines, so the frame is one line.
+                let mut frame = serde_json::to_vec(msg).map_err(invalid_data)?;
+                frame.push(b'\n');
+                Ok(frame)
+            }
+        }
+    }
+
+    /// Decodes a frame's payload.
+    fn decode<T: for<'de> Deserialize<'de>>(self, payload: &[u8]) -> io::Result<T> {
+        match self {
+            Self::Postcard => postcard::from_bytes(payload).map_err(invalid_data),
+            #[cfg(feature = "json")]
+            Self::Json => serde_json::from_slice(payload).map_err(invalid_data),
+        }
+    }
+
+    /// Locates the first complete frame in `buf`, returning its payload
+    /// range and the frame's end; `Err(needed)` with the bytes the frame
+    /// needs in total (at least) while it is incomplete.
+    fn split(self, buf: &[u8]) -> io::Result<Result<(std::ops::Range<usize>, usize), usize>> {
+        match self {
+            Self::Postcard => {
+                let Some(hdr) = buf.get(..4) else {
+                    return Ok(Err(4));
+                };
+                let len = u32::from_be_bytes([hdr[0], hdr[1], hdr[2], hdr[3]]);
+                if len > MAX_FRAME {
+                    return Err(frame_too_large());
+                }
+                let end = 4 + len as usize;
+                Ok(if buf.len() >= end {
+                    Ok((4..end, end))
+                } else {
+                    Err(end)
+                })
+            }
+            #[cfg(feature = "json")]
+            Self::Json => match buf.iter().position(|&b| b == b'\n') {
+                Some(newline) => Ok(Ok((0..newline, newline + 1))),
+                None if buf.len() > MAX_FRAME as usize => Err(frame_too_large()),
+                None => Ok(Err(buf.len() + 1)),
+            },
+        }
+    }
+}
+
+/// Wraps a serialization error.
+fn invalid_data(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Error for a frame over [`MAX_FRAME`].
+fn frame_too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "frame exceeds 16 MiB limit")
+}
+
+/// Sends a message as one frame in the [current](Codec::current) codec.
 pub async fn send(w: &mut (impl AsyncWrite + Unpin), msg: &impl Serialize) -> io::Result<()> {
-    let payload =
-        postcard::to_allocvec(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    let len = u32::try_from(payload.len())
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "frame exceeds u32::MAX"))?;
-    // Pre-assemble frame to minimize syscalls.
-    let mut frame = Vec::with_capacity(4 + payload.len());
-    frame.extend_from_slice(&len.to_be_bytes());
-    frame.extend_from_slice(&payload);
+    let frame = Codec::current().encode(msg)?;
     w.write_all(&frame).await?;
     w.flush().await
 }
 
-/// Receives and deserializes a length-prefixed postcard message.
+/// Receives and deserializes one frame in the [current](Codec::current)
+/// codec.
 pub async fn recv<T: for<'de> Deserialize<'de>>(r: &mut (impl AsyncRead + Unpin)) -> io::Result<T> {
-    let mut hdr = [0u8; 4];
-    r.read_exact(&mut hdr).await?;
-    let len = u32::from_be_bytes(hdr);
-    if len > MAX_FRAME {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "frame exceeds 16 MiB limit",
-        ));
+    let codec = Codec::current();
+    let mut frame = Vec::new();
+    loop {
+        match codec.split(&frame)? {
+            Ok((payload, _)) => return codec.decode(&frame[payload]),
+            // Never reads past the frame: the rest belongs to the caller.
+            Err(needed) => match codec {
+                Codec::Postcard => {
+                    let have = frame.len();
+                    frame.resize(needed, 0);
+                    r.read_exact(&mut frame[have..]).await?;
+                }
+                // Byte by byte up to the newline, then one scan for it.
+                #[cfg(feature = "json")]
+                Codec::Json => loop {
+                    let byte = r.read_u8().await?;
+                    frame.push(byte);
+                    if byte == b'\n' || frame.len() > MAX_FRAME as usize {
+                        break;
+                    }
+                },
+            },
+        }
+    }
+}
+
+/// Blocking [`send`], for callers without an async runtime.
+pub fn send_blocking(w: &mut impl io::Write, msg: &impl Serialize) -> io::Result<()> {
+    let frame = Codec::current().encode(msg)?;
+    w.write_all(&frame)?;
+    w.flush()
+}
+
+/// Blocking [`recv`], for callers without an async runtime. Set a read
+/// timeout on the stream to bound the wait.
+pub fn recv_blocking<T: for<'de> Deserialize<'de>>(r: &mut impl io::Read) -> io::Result<T> {
+    let codec = Codec::current();
+    let mut frame = Vec::new();
+    loop {
+        match codec.split(&frame)? {
+            Ok((payload, _)) => return codec.decode(&frame[payload]),
+            Err(needed) => match codec {
+                Codec::Postcard => {
+                    let have = frame.len();
+                    frame.resize(needed, 0);
+                    r.read_exact(&mut frame[have..])?;
+                }
+                #[cfg(feature = "json")]
+                Codec::Json => loop {
+                    let mut byte = [0];
+                    r.read_exact(&mut byte)?;
+                    frame.push(byte[0]);
+                    if byte[0] == b'\n' || frame.len() > MAX_FRAME as usize {
+                        break;
+                    }
+                },
+            },
+        }
     }
-    let mut payload = vec![0u8; len as usize];
-    r.read_exact(&mut payload).await?;
-    postcard::from_bytes(&payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
 }
 
 /// Sends `data` as a series of [`Upload::Chunk`] messages followed by
-/// [`Upload::Done`], using the given chunk size.
+/// [`Upload::Done`], using the given chunk size (clamped to
+/// [`Codec::max_chunk_size`]).
 pub async fn send_upload(
     w: &mut (impl AsyncWrite + Unpin),
     data: &[u8],
     chunk_size: usize,
 ) -> io::Result<()> {
     use crate::Upload;
-    for chunk in data.chunks(chunk_size) {
+    for chunk in data.chunks(chunk_len(chunk_size)) {
         send(w, &Upload::Chunk(chunk.to_vec())).await?;
     }
     send(w, &Upload::Done).await
@@ -76,14 +288,15 @@ pub async fn recv_upload(r: &mut (impl AsyncRead + Unpin), max_bytes: u64) -> io
 }
 
 /// Sends `data` as a series of [`Download::Chunk`] messages followed by
-/// [`Download::Done`], using the given chunk size.
+/// [`Download::Done`], using the given chunk size (clamped to
+/// [`Codec::max_chunk_size`]).
 pub async fn send_download(
     w: &mut (impl AsyncWrite + Unpin),
     data: &[u8],
     chunk_size: usize,
 ) -> io::Result<()> {
     use crate::Download;
-    for chunk in data.chunks(chunk_size) {
+    for chunk in data.chunks(chunk_len(chunk_size)) {
         send(w, &Download::Chunk(chunk.to_vec())).await?;
     }
     send(w, &Download::Done).await
@@ -113,7 +326,7 @@ pub async fn send_upload_from_reader(
     chunk_size: usize,
 ) -> io::Result<u64> {
     use crate::Upload;
-    let mut buf = vec![0u8; chunk_size];
+    let mut buf = vec![0u8; chunk_len(chunk_size)];
     let mut total: u64 = 0;
     loop {
         let n = src.read(&mut buf).await?;
@@ -137,7 +350,7 @@ pub async fn send_download_from_reader(
     chunk_size: usize,
 ) -> io::Result<u64> {
     use crate::Download;
-    let mut buf = vec![0u8; chunk_size];
+    let mut buf = vec![0u8; chunk_len(chunk_size)];
     let mut total: u64 = 0;
     loop {
         let n = src.read(&mut buf).await?;
@@ -207,12 +420,80 @@ pub async fn recv_download_to_writer(
     }
 }
 
+/// Cancellation-safe frame reader.
+///
+/// [`recv`] reads the header and payload in separate awaits, so dropping it
+/// mid-frame (e.g. when it loses a `tokio::select!` race) desyncs the stream.
+/// `FrameReader` keeps partial frames in a per-connection buffer instead, so
+/// a cancelled [`recv`](Self::recv) resumes where it left off.
+pub struct FrameReader<R> {
+    /// Underlying byte stream.
+    inner: R,
+    /// Bytes read from `inner` but not yet consumed as complete frames.
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    /// Wraps a byte stream.
+    pub const fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Receives the next message.
+    ///
+    /// Cancellation-safe. A clean EOF between frames (the peer closed or
+    /// half-closed its write side) yields [`io::ErrorKind::UnexpectedEof`];
+    /// EOF inside a frame yields [`io::ErrorKind::InvalidData`].
+    pub async fn recv<T: for<'de> Deserialize<'de>>(&mut self) -> io::Result<T> {
+        let codec = Codec::current();
+        loop {
+            let want = match codec.split(&self.buf)? {
+                Ok((payload, end)) => {
+                    let msg = codec.decode(&self.buf[payload]);
+                    self.buf.drain(..end);
+                    return msg;
+                }
+                Err(needed) => needed,
+            };
+
+            self.buf
+                .reserve(want.saturating_sub(self.buf.len()).max(8 * 1024));
+            // `read_buf` is cancellation-safe: bytes land in `buf` only on completion.
+            if self.inner.read_buf(&mut self.buf).await? == 0 {
+                return Err(if self.buf.is_empty() {
+                    io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")
+                } else {
+                    io::Error::new(io::ErrorKind::InvalidData, "connection closed mid-frame")
+                });
+            }
+        }
+    }
+
+    /// Returns the underlying stream, discarding any buffered bytes.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: std::fmt::Debug> std::fmt::Debug for FrameReader<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FrameReader")
+            .field("inner", &self.inner)
+            .field("buffered", &self.buf.len())
+            .finish()
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
     use crate::{
-        ControlReq, ControlResp, ErrorCode, ErrorInfo, ExecIn, ExecOut, ExecStart, Hello, HelloAck,
-        Upload, UploadResult,
+        AgentInfo, ControlReq, ControlResp, ErrorCode, ErrorInfo, ExecIn, ExecOut, ExecStart,
+        FileSpec, Hello, HelloAck, Upload, UploadResult,
     };
 
     #[tokio::test]
@@ -227,6 +508,7 @@ async fn roundtrip_hello_control() {
     async fn roundtrip_hello_exec() {
         let start = ExecStart::new("/bin/ls")
             .args(vec!["-la".into()])
+            .arg0("ls")
             .env(vec!["PATH=/usr/bin".into()])
             .cwd("/tmp")
             .user(1000, 1000)
@@ -241,6 +523,7 @@ async fn roundtrip_hello_exec() {
             Hello::Exec(e) => {
                 assert_eq!(e.cmd, "/bin/ls");
                 assert_eq!(e.args, vec!["-la"]);
+                assert_eq!(e.arg0.as_deref(), Some("ls"));
                 assert_eq!(e.uid, Some(1000));
                 assert!(e.stdin);
                 assert_eq!(e.tty.unwrap().rows, 24);
@@ -251,6 +534,209 @@ async fn roundtrip_hello_exec() {
         }
     }
 
+    #[tokio::test]
+    async fn roundtrip_hello_metadata_ops() {
+        let (mut c, mut s) = tokio::io::duplex(1024);
+        send(
+            &mut c,
+            &Hello::Chmod {
+                path: "/srv/app".into(),
+                mode: 0o4755,
+            },
+        )
+        .await
+        .unwrap();
+        send(
+            &mut c,
+            &Hello::Chown {
+                path: "/srv".into(),
+                uid: Some(1000),
+                gid: None,
+            },
+        )
+        .await
+        .unwrap();
+        send(
+            &mut c,
+            &Hello::Mkdir {
+                path: "/srv/data".into(),
+                mode: 0o750,
+                parents: true,
+            },
+        )
+        .await
+        .unwrap();
+        send(
+            &mut c,
+            &Hello::Utimes {
+                path: "/srv".into(),
+                atime: 1,
+                mtime: -1,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            recv(&mut s).await.unwrap(),
+            Hello::Chmod { path, mode: 0o4755 } if path == "/srv/app"
+        ));
+        let Hello::Chown {
+            path: owned,
+            uid,
+            gid,
+        } = recv(&mut s).await.unwrap()
+        else {
+            unreachable!("expected Hello::Chown");
+        };
+        assert_eq!(owned, "/srv");
+        assert_eq!(uid, Some(1000));
+        assert_eq!(gid, None);
+        assert!(matches!(
+            recv(&mut s).await.unwrap(),
+            Hello::Mkdir {
+                mode: 0o750,
+                parents: true,
+                ..
+            }
+        ));
+        assert!(matches!(
+            recv(&mut s).await.unwrap(),
+            Hello::Utimes {
+                atime: 1,
+                mtime: -1,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn roundtrip_hello_rename() {
+        let (mut c, mut s) = tokio::io::duplex(1024);
+        let hello = Hello::Rename {
+            from: "/srv/app.tmp".into(),
+            to: "/srv/app".into(),
+        };
+        send(&mut c, &hello).await.unwrap();
+        assert!(matches!(
+            recv(&mut s).await.unwrap(),
+            Hello::Rename { from, to } if from == "/srv/app.tmp" && to == "/srv/app"
+        ));
+    }
+
+    #[tokio::test]
+    async fn roundtrip_hello_mount_and_unmount() {
+        let (mut c, mut s) = tokio::io::duplex(1024);
+        send(
+            &mut c,
+            &Hello::Mount {
+                source: "tmpfs".into(),
+                target: "/mnt/scratch".into(),
+                fstype: "tmpfs".into(),
+                options: vec!["nosuid".into(), "size=64m".into()],
+            },
+        )
+        .await
+        .unwrap();
+        send(
+            &mut c,
+            &Hello::Unmount {
+                target: "/mnt/scratch".into(),
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            recv(&mut s).await.unwrap(),
+            Hello::Mount { source, target, fstype, options }
+                if source == "tmpfs"
+                    && target == "/mnt/scratch"
+                    && fstype == "tmpfs"
+                    && options == ["nosuid", "size=64m"]
+        ));
+        assert!(matches!(
+            recv(&mut s).await.unwrap(),
+            Hello::Unmount { target } if target == "/mnt/scratch"
+        ));
+    }
+
+    #[tokio::test]
+    async fn roundtrip_hello_file_write_at() {
+        let (mut c, mut s) = tokio::io::duplex(1024);
+        let hello = Hello::FileWriteAt {
+            path: "/var/log/app.log".into(),
+            offset: 0,
+            append: true,
+            mode: 0o640,
+        };
+        send(&mut c, &hello).await.unwrap();
+        assert!(matches!(
+            recv(&mut s).await.unwrap(),
+            Hello::FileWriteAt {
+                offset: 0,
+                append: true,
+                mode: 0o640,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn roundtrip_hello_symlink_and_readlink() {
+        let (mut c, mut s) = tokio::io::duplex(1024);
+        send(
+            &mut c,
+            &Hello::Symlink {
+                target: "../lib/app".into(),
+                link: "/srv/bin/app".into(),
+            },
+        )
+        .await
+        .unwrap();
+        send(
+            &mut c,
+            &Hello::Readlink {
+                path: "/srv/bin/app".into(),
+            },
+        )
+        .await
+        .unwrap();
+        send(&mut s, &HelloAck::Path("../lib/app".into()))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            recv(&mut s).await.unwrap(),
+            Hello::Symlink { target, link } if target == "../lib/app" && link == "/srv/bin/app"
+        ));
+        assert!(matches!(
+            recv(&mut s).await.unwrap(),
+            Hello::Readlink { path } if path == "/srv/bin/app"
+        ));
+        assert!(matches!(
+            recv(&mut c).await.unwrap(),
+            HelloAck::Path(target) if target == "../lib/app"
+        ));
+    }
+
+    #[tokio::test]
+    async fn roundtrip_hello_write_files() {
+        let files = vec![
+            FileSpec::new("/etc/app.conf", 0o644, 12),
+            FileSpec::new("/usr/local/bin/app", 0o755, 4096),
+        ];
+        let (mut c, mut s) = tokio::io::duplex(1024);
+        send(&mut c, &Hello::WriteFiles { files }).await.unwrap();
+        let Hello::WriteFiles { files: got } = recv(&mut s).await.unwrap() else {
+            unreachable!("expected Hello::WriteFiles");
+        };
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[1].path, "/usr/local/bin/app");
+        assert_eq!(got[1].mode, 0o755);
+        assert_eq!(got[1].len, 4096);
+    }
+
     #[tokio::test]
     async fn roundtrip_hello_ack_variants() {
         let cases: Vec<HelloAck> = vec![
@@ -260,6 +746,16 @@ async fn roundtrip_hello_ack_variants() {
                 pid: 42,
             },
             HelloAck::Ready,
+            HelloAck::Done,
+            HelloAck::Resume { offset: 1 << 33 },
+            HelloAck::Stat {
+                size: 42,
+                mode: 0o100_644,
+                is_dir: false,
+                uid: 1000,
+                gid: 1000,
+                mtime: 1_700_000_000,
+            },
             HelloAck::Error(ErrorInfo::internal("boom")),
         ];
         for ack in cases {
@@ -293,6 +789,53 @@ async fn roundtrip_control() {
                 ..
             }
         ));
+
+        send(&mut c, &ControlReq::Env).await.unwrap();
+        let env_req: ControlReq = recv(&mut s).await.unwrap();
+        assert!(matches!(env_req, ControlReq::Env));
+        send(&mut s, &ControlResp::Env(vec!["PATH=/bin".into()]))
+            .await
+            .unwrap();
+        let env_resp: ControlResp = recv(&mut c).await.unwrap();
+        assert!(matches!(env_resp, ControlResp::Env(vars) if vars == ["PATH=/bin"]));
+
+        let info = AgentInfo::new(
+            "0.7.0",
+            vec![crate::feature::DIFF.into(), "from-the-future".into()],
+        );
+        send(&mut s, &ControlResp::Info(info.clone()))
+            .await
+            .unwrap();
+        let ControlResp::Info(got) = recv(&mut c).await.unwrap() else {
+            unreachable!("expected ControlResp::Info");
+        };
+        assert_eq!(got, info);
+        assert!(got.supports(crate::feature::DIFF));
+        assert!(!got.supports(crate::feature::PTY));
+    }
+
+    #[tokio::test]
+    async fn roundtrip_seed_rng() {
+        let (mut c, mut s) = tokio::io::duplex(1024);
+        let seed = vec![0xa5; crate::RNG_SEED_LEN];
+        send(&mut c, &ControlReq::SeedRng { seed: seed.clone() })
+            .await
+            .unwrap();
+        let req: ControlReq = recv(&mut s).await.unwrap();
+        assert!(matches!(req, ControlReq::SeedRng { seed: got } if got == seed));
+        send(&mut s, &ControlResp::Seeded).await.unwrap();
+        let resp: ControlResp = recv(&mut c).await.unwrap();
+        assert!(matches!(resp, ControlResp::Seeded));
+    }
+
+    #[test]
+    fn error_replies_keep_their_oldest_wire_index() {
+        // Agents back to MIN_PROTOCOL_VERSION tell replies apart by variant
+        // index, so later variants go after these.
+        let ack = postcard::to_allocvec(&HelloAck::Error(ErrorInfo::internal("boom"))).unwrap();
+        assert_eq!(ack[0], 6);
+        let resp = postcard::to_allocvec(&ControlResp::Error(ErrorInfo::internal("boom"))).unwrap();
+        assert_eq!(resp[0], 8);
     }
 
     #[tokio::test]
@@ -305,6 +848,7 @@ async fn roundtrip_exec_io() {
             .unwrap();
         send(&mut c, &ExecIn::StdinClose).await.unwrap();
         send(&mut c, &ExecIn::Signal(15)).await.unwrap();
+        send(&mut c, &ExecIn::Ack(4096)).await.unwrap();
         send(
             &mut c,
             &ExecIn::ResizeTty(crate::TtyConfig {
@@ -325,7 +869,9 @@ async fn roundtrip_exec_io() {
         let m: ExecIn = recv(&mut s).await.unwrap();
         assert!(matches!(m, ExecIn::Signal(15)));
         let m: ExecIn = recv(&mut s).await.unwrap();
-        assert!(matches!(m, ExecIn::ResizeTty(t) if t.rows == 50 && t.cols == 120));
+        assert!(matches!(m, ExecIn::Ack(4096)));
+        let resize: ExecIn = recv(&mut s).await.unwrap();
+        assert!(matches!(resize, ExecIn::ResizeTty(t) if t.rows == 50 && t.cols == 120));
 
         // Guest sends output
         send(&mut s, &ExecOut::Stdout(b"world".to_vec()))
@@ -337,7 +883,7 @@ async fn roundtrip_exec_io() {
         send(
             &mut s,
             &ExecOut::Exit {
-                code: 0,
+                code: Some(0),
                 signal: None,
                 timed_out: false,
                 duration_ms: 42,
@@ -346,6 +892,18 @@ async fn roundtrip_exec_io() {
         )
         .await
         .unwrap();
+        send(
+            &mut s,
+            &ExecOut::Exit {
+                code: None,
+                signal: Some(9),
+                timed_out: true,
+                duration_ms: 1000,
+                error_message: String::new(),
+            },
+        )
+        .await
+        .unwrap();
 
         let m: ExecOut = recv(&mut c).await.unwrap();
         assert!(matches!(m, ExecOut::Stdout(d) if d == b"world"));
@@ -355,12 +913,22 @@ async fn roundtrip_exec_io() {
         assert!(matches!(
             m,
             ExecOut::Exit {
-                code: 0,
+                code: Some(0),
                 signal: None,
                 timed_out: false,
                 ..
             }
         ));
+        let killed: ExecOut = recv(&mut c).await.unwrap();
+        assert!(matches!(
+            killed,
+            ExecOut::Exit {
+                code: None,
+                signal: Some(9),
+                timed_out: true,
+                ..
+            }
+        ));
     }
 
     #[tokio::test]
@@ -385,6 +953,33 @@ async fn roundtrip_download_stream() {
         assert_eq!(received, data);
     }
 
+    #[tokio::test]
+    async fn oversized_chunk_size_respects_frame_limit() {
+        let data = vec![1u8; MAX_CHUNK_SIZE + 1];
+        let mut wire = Vec::new();
+        send_upload(&mut wire, &data, usize::MAX).await.unwrap();
+
+        let mut cursor = io::Cursor::new(wire);
+        let received = recv_upload(&mut cursor, u64::MAX).await.unwrap();
+        assert_eq!(received, data);
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn oversized_chunk_size_respects_json_frame_limit() {
+        // The widest bytes take three digits and a comma each.
+        let data = vec![255u8; MAX_JSON_CHUNK_SIZE + 1];
+        Codec::Json
+            .scope(async {
+                let mut wire = Vec::new();
+                send_upload(&mut wire, &data, usize::MAX).await.unwrap();
+                let mut cursor = io::Cursor::new(wire);
+                let received = recv_upload(&mut cursor, u64::MAX).await.unwrap();
+                assert_eq!(received, data);
+            })
+            .await;
+    }
+
     #[tokio::test]
     async fn upload_result_roundtrip() {
         let (mut c, mut s) = tokio::io::duplex(1024);
@@ -392,6 +987,10 @@ async fn upload_result_roundtrip() {
         let r: UploadResult = recv(&mut s).await.unwrap();
         assert!(matches!(r, UploadResult::Ok));
 
+        send(&mut s, &UploadResult::Written(42)).await.unwrap();
+        let written: UploadResult = recv(&mut c).await.unwrap();
+        assert!(matches!(written, UploadResult::Written(42)));
+
         send(
             &mut s,
             &UploadResult::Error(ErrorInfo::new(ErrorCode::NotFound, "no such file")),
@@ -460,4 +1059,132 @@ async fn send_download_from_reader_streams() {
         let received = recv_download(&mut c).await.unwrap();
         assert_eq!(received, data);
     }
+
+    #[tokio::test]
+    async fn frame_reader_survives_cancellation() {
+        use tokio::io::AsyncWriteExt;
+
+        const FRAMES: usize = 500;
+        let byte_for = |i: usize| u8::try_from(i % 251).unwrap_or_default();
+
+        // Tiny buffers so frames arrive in pieces and `recv` is cancelled mid-frame.
+        let (mut host_w, guest_r) = tokio::io::duplex(64);
+        let (mut guest_out, mut host_out) = tokio::io::duplex(256);
+
+        let writer = tokio::spawn(async move {
+            for i in 0..FRAMES {
+                let data = vec![byte_for(i); i % 300 + 1];
+                send(&mut host_w, &ExecIn::Stdin(data)).await.unwrap();
+            }
+        });
+        let drainer = tokio::spawn(async move {
+            let mut sink = Vec::new();
+            host_out.read_to_end(&mut sink).await.unwrap();
+        });
+
+        let mut frames = FrameReader::new(guest_r);
+        let stdout_chunk = [0u8; 1024];
+        let mut received = 0;
+        loop {
+            tokio::select! {
+                msg = frames.recv::<ExecIn>() => match msg {
+                    Ok(ExecIn::Stdin(d)) => {
+                        assert_eq!(d.len(), received % 300 + 1);
+                        assert!(d.iter().all(|&b| b == byte_for(received)));
+                        received += 1;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                    other => unreachable!("unexpected frame: {other:?}"),
+                },
+                n = guest_out.write(&stdout_chunk) => {
+                    n.unwrap();
+                }
+            }
+        }
+        assert_eq!(received, FRAMES);
+
+        drop(guest_out);
+        writer.await.unwrap();
+        drainer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn frame_reader_reports_truncated_frame() {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut c, s) = tokio::io::duplex(64);
+        c.write_all(&[0, 0, 0, 10, 1, 2]).await.unwrap();
+        drop(c);
+
+        let result = FrameReader::new(s).recv::<ExecIn>().await;
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn json_frames_are_lines_and_roundtrip() {
+        use tokio::io::AsyncWriteExt;
+
+        Codec::Json
+            .scope(async {
+                let (mut tx, mut rx) = tokio::io::duplex(1024);
+                send(&mut tx, &Hello::Control { version: 5 }).await.unwrap();
+                send(&mut tx, &Upload::Chunk(vec![1, 2])).await.unwrap();
+                drop(tx);
+                let mut wire = Vec::new();
+                rx.read_to_end(&mut wire).await.unwrap();
+                assert_eq!(wire, b"{\"Control\":{\"version\":5}}\n{\"Chunk\":[1,2]}\n");
+
+                // As typed into `socat`, split across reads.
+                let (mut typist, mut agent) = tokio::io::duplex(1024);
+                typist.write_all(b"{\"Control\":{\"vers").await.unwrap();
+                typist.write_all(b"ion\":7}}\n{\"Exec\":").await.unwrap();
+                assert!(matches!(
+                    recv(&mut agent).await.unwrap(),
+                    Hello::Control { version: 7 }
+                ));
+                let mut reader = FrameReader::new(agent);
+                typist.write_all(b"\"Ping\"}\n").await.unwrap();
+                assert!(reader.recv::<Hello>().await.is_err());
+                drop(typist);
+                assert_eq!(
+                    reader.recv::<Hello>().await.err().map(|e| e.kind()),
+                    Some(io::ErrorKind::UnexpectedEof)
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn blocking_frames_match_async_ones() {
+        let mut wire = Vec::new();
+        send_blocking(&mut wire, &ControlReq::Ping).unwrap();
+        send_blocking(&mut wire, &Hello::Control { version: 9 }).unwrap();
+        let mut framed = Vec::new();
+        send(&mut framed, &ControlReq::Ping).await.unwrap();
+        assert_eq!(wire[..framed.len()], framed);
+
+        let mut r = wire.as_slice();
+        assert!(matches!(recv_blocking(&mut r).unwrap(), ControlReq::Ping));
+        assert!(matches!(
+            recv(&mut r).await.unwrap(),
+            Hello::Control { version: 9 }
+        ));
+        assert_eq!(
+            recv_blocking::<Hello>(&mut r).err().map(|e| e.kind()),
+            Some(io::ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn codec_is_detected_from_the_first_byte() {
+        assert_eq!(Codec::detect(0), Codec::Postcard);
+        assert_eq!(Codec::detect(1), Codec::Postcard);
+        #[cfg(feature = "json")]
+        assert_eq!(Codec::detect(b'{'), Codec::Json);
+        assert_eq!(Codec::current(), Codec::Postcard);
+    }
 }
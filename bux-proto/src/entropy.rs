@@ -0,0 +1,10 @@
+//! Host-provided seed for the guest kernel's random number generator.
+//!
+//! Once the agent answers, the host sends fresh random bytes in a
+//! [`ControlReq::SeedRng`](crate::ControlReq::SeedRng) and the agent credits
+//! them to the kernel pool, so `getrandom` does not block on a freshly
+//! booted guest that has gathered little entropy itself. Unlike the boot
+//! environment, the agent connection is not readable from inside the guest.
+
+/// Seed length in bytes.
+pub const RNG_SEED_LEN: usize = 32;
@@ -0,0 +1,61 @@
+//! Names of guest agent operations, as listed in
+//! [`AgentInfo::features`](crate::AgentInfo::features).
+//!
+//! Guest images ship their own agent, which may predate an operation the
+//! host knows about; the host checks these before relying on one.
+
+/// Run a command ([`Hello::Exec`](crate::Hello::Exec)).
+pub const EXEC: &str = "exec";
+/// Run a command on a pseudo-terminal ([`ExecStart::tty`](crate::ExecStart::tty)).
+pub const PTY: &str = "pty";
+/// Read a file ([`Hello::FileRead`](crate::Hello::FileRead)).
+pub const FILE_READ: &str = "file-read";
+/// Write a file ([`Hello::FileWrite`](crate::Hello::FileWrite)).
+pub const FILE_WRITE: &str = "file-write";
+/// Offset and append writes ([`Hello::FileWriteAt`](crate::Hello::FileWriteAt)).
+pub const FILE_WRITE_AT: &str = "file-write-at";
+/// Resumable upload ([`Hello::FileUpload`](crate::Hello::FileUpload)).
+pub const FILE_UPLOAD: &str = "file-upload";
+/// Resume uploads of the same source ([`Hello::ResumableUpload`](crate::Hello::ResumableUpload)).
+pub const RESUMABLE_UPLOAD: &str = "resumable-upload";
+/// Write a batch of files ([`Hello::WriteFiles`](crate::Hello::WriteFiles)).
+pub const WRITE_FILES: &str = "write-files";
+/// Extract a tar archive ([`Hello::CopyIn`](crate::Hello::CopyIn)).
+pub const COPY_IN: &str = "copy-in";
+/// Archive a path as tar ([`Hello::CopyOut`](crate::Hello::CopyOut)).
+pub const COPY_OUT: &str = "copy-out";
+/// Path metadata ([`Hello::Stat`](crate::Hello::Stat)).
+pub const STAT: &str = "stat";
+/// Change permissions ([`Hello::Chmod`](crate::Hello::Chmod)).
+pub const CHMOD: &str = "chmod";
+/// Change ownership ([`Hello::Chown`](crate::Hello::Chown)).
+pub const CHOWN: &str = "chown";
+/// Set timestamps ([`Hello::Utimes`](crate::Hello::Utimes)).
+pub const UTIMES: &str = "utimes";
+/// Create a directory ([`Hello::Mkdir`](crate::Hello::Mkdir)).
+pub const MKDIR: &str = "mkdir";
+/// Move a path ([`Hello::Rename`](crate::Hello::Rename)).
+pub const RENAME: &str = "rename";
+/// Create and read symlinks ([`Hello::Symlink`](crate::Hello::Symlink),
+/// [`Hello::Readlink`](crate::Hello::Readlink)).
+pub const SYMLINK: &str = "symlink";
+/// Mount and unmount at runtime ([`Hello::Mount`](crate::Hello::Mount),
+/// [`Hello::Unmount`](crate::Hello::Unmount)).
+pub const MOUNT: &str = "mount";
+/// Freeze and thaw filesystems ([`ControlReq::Quiesce`](crate::ControlReq::Quiesce)).
+pub const QUIESCE: &str = "quiesce";
+/// Agent environment ([`ControlReq::Env`](crate::ControlReq::Env)).
+pub const ENV: &str = "env";
+/// User lookup ([`ControlReq::ResolveUser`](crate::ControlReq::ResolveUser)).
+pub const RESOLVE_USER: &str = "resolve-user";
+/// Filesystem changes since boot ([`ControlReq::Diff`](crate::ControlReq::Diff)).
+pub const DIFF: &str = "diff";
+/// Signal the primary process ([`ControlReq::Signal`](crate::ControlReq::Signal)).
+pub const SIGNAL: &str = "signal";
+/// Change the agent environment ([`ControlReq::SetEnv`](crate::ControlReq::SetEnv)).
+pub const SET_ENV: &str = "set-env";
+/// Credit a host seed to the kernel RNG ([`ControlReq::SeedRng`](crate::ControlReq::SeedRng)).
+pub const SEED_RNG: &str = "seed-rng";
+/// Accepts [`Codec::Json`](crate::Codec::Json) connections (agents built
+/// with the `json` feature).
+pub const JSON_CODEC: &str = "json-codec";
@@ -0,0 +1,150 @@
+//! Boot-time commands handed from host to guest.
+//!
+//! The host sets [`INIT_ENV`] in the guest environment; the agent runs the
+//! command to completion before it starts serving. Like the mount table,
+//! the value travels on the kernel command line, so it must be free of
+//! whitespace: it is `abort:` or `continue:` (what to do if the command
+//! fails) followed by the `,`-separated arguments, each percent-encoded.
+//!
+//! When the host boots the agent in place of the VM's command, it passes
+//! that command in [`MAIN_ENV`], encoded by [`encode_argv`]. The agent
+//! starts it once it serves and exits with its status.
+//!
+//! [`PID1_ENV`] hands PID 1 over to another init (e.g. `tini --`) once the
+//! agent has booted: the agent keeps serving from a child process and the
+//! init gets the [`MAIN_ENV`] command, if any, as trailing arguments.
+
+/// Environment variable carrying the encoded init command.
+pub const INIT_ENV: &str = "BUX_INIT";
+
+/// Environment variable carrying the workload the agent starts.
+pub const MAIN_ENV: &str = "BUX_MAIN";
+
+/// Environment variable carrying the init, encoded by [`encode_argv`], that
+/// takes over PID 1 from the agent.
+pub const PID1_ENV: &str = "BUX_PID1";
+
+/// Every variable the host sets for the agent's boot. None of them belong
+/// in the environment of the VM's command or of commands run in the guest.
+pub const BOOT_ENVS: [&str; 6] = [
+    INIT_ENV,
+    MAIN_ENV,
+    PID1_ENV,
+    crate::AUTH_ENV,
+    crate::DIFF_ENV,
+    crate::MOUNTS_ENV,
+];
+
+/// Encodes an argument vector as `,`-separated percent-encoded arguments.
+pub fn encode_argv(argv: &[String]) -> String {
+    let args: Vec<String> = argv.iter().map(|a| escape(a)).collect();
+    args.join(",")
+}
+
+/// Reverses [`encode_argv`]; `None` if malformed or the program is empty.
+pub fn decode_argv(value: &str) -> Option<Vec<String>> {
+    let argv = value.split(',').map(unescape).collect::<Option<Vec<_>>>()?;
+    if argv.first().is_none_or(String::is_empty) {
+        return None;
+    }
+    Some(argv)
+}
+
+/// A command the guest agent runs before entering its accept loop.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestInit {
+    /// Program and arguments; `argv[0]` is resolved via `PATH`.
+    pub argv: Vec<String>,
+    /// Keep booting when the command fails instead of aborting.
+    pub may_fail: bool,
+}
+
+impl GuestInit {
+    /// Creates an init entry.
+    pub const fn new(argv: Vec<String>, may_fail: bool) -> Self {
+        Self { argv, may_fail }
+    }
+
+    /// Encodes the command into an [`INIT_ENV`] value.
+    pub fn encode(&self) -> String {
+        let policy = if self.may_fail { "continue" } else { "abort" };
+        format!("{policy}:{}", encode_argv(&self.argv))
+    }
+
+    /// Decodes an [`INIT_ENV`] value; `None` if it is malformed or empty.
+    pub fn decode(value: &str) -> Option<Self> {
+        let (policy, args) = value.split_once(':')?;
+        let may_fail = match policy {
+            "abort" => false,
+            "continue" => true,
+            _ => return None,
+        };
+        Some(Self::new(decode_argv(args)?, may_fail))
+    }
+}
+
+/// Percent-encodes everything outside printable ASCII, plus the separators
+/// and characters the kernel command line treats specially.
+fn escape(arg: &str) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::with_capacity(arg.len());
+    for &b in arg.as_bytes() {
+        if b.is_ascii_graphic() && !matches!(b, b'%' | b',' | b'"') {
+            out.push(char::from(b));
+        } else {
+            let _ = write!(out, "%{b:02X}");
+        }
+    }
+    out
+}
+
+/// Reverses [`escape`]; `None` on a truncated escape or invalid UTF-8.
+fn unescape(arg: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(arg.len());
+    let mut rest = arg.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init_command_round_trips() {
+        let init = GuestInit::new(
+            vec![
+                "/bin/sh".to_owned(),
+                "-c".to_owned(),
+                "echo \"100%\", done\té".to_owned(),
+            ],
+            false,
+        );
+        let encoded = init.encode();
+        assert!(!encoded.contains(char::is_whitespace));
+        assert!(encoded.starts_with("abort:/bin/sh,-c,echo%20%22100%25%22%2C"));
+        assert_eq!(GuestInit::decode(&encoded), Some(init));
+
+        let lenient = GuestInit::decode("continue:/etc/rc.local").unwrap();
+        assert!(lenient.may_fail);
+        assert_eq!(lenient.argv, ["/etc/rc.local"]);
+        assert_eq!(GuestInit::decode("abort:"), None);
+        assert_eq!(GuestInit::decode("maybe:/bin/true"), None);
+        assert_eq!(GuestInit::decode("abort:bad%2"), None);
+
+        let main = vec!["/docker-entrypoint.sh".to_owned(), "nginx -g".to_owned()];
+        assert_eq!(decode_argv(&encode_argv(&main)), Some(main));
+    }
+}
@@ -2,7 +2,8 @@
 //!
 //! Messages are serialized with [`postcard`] and framed with a 4-byte
 //! big-endian length prefix, suitable for any reliable byte stream
-//! (vsock, Unix socket, TCP).
+//! (vsock, Unix socket, TCP). The `json` feature adds a human-readable
+//! [`Codec::Json`] for debugging.
 //!
 //! # Per-Operation Connection Model
 //!
@@ -11,15 +12,28 @@
 //! operation type, followed by a [`HelloAck`] from the guest. Subsequent
 //! messages are operation-specific (e.g. [`ExecIn`]/[`ExecOut`] for exec).
 
+mod auth;
 mod codec;
+mod entropy;
+pub mod feature;
+mod init;
 mod message;
+mod mounts;
+mod user;
 
+pub use auth::{AUTH_ENV, MAX_TOKEN_LEN, is_valid_token, token_matches};
 pub use codec::{
-    recv, recv_download, recv_download_to_writer, recv_upload, recv_upload_to_writer, send,
+    Codec, FrameReader, MAX_CHUNK_SIZE, recv, recv_blocking, recv_download,
+    recv_download_to_writer, recv_upload, recv_upload_to_writer, send, send_blocking,
     send_download, send_download_from_reader, send_upload, send_upload_from_reader,
 };
+pub use entropy::RNG_SEED_LEN;
+pub use init::{BOOT_ENVS, GuestInit, INIT_ENV, MAIN_ENV, PID1_ENV, decode_argv, encode_argv};
 pub use message::{
-    AGENT_PORT, ControlReq, ControlResp, Download, ErrorCode, ErrorInfo, ExecIn, ExecOut,
-    ExecStart, Hello, HelloAck, MAX_UPLOAD_BYTES, PROTOCOL_VERSION, STREAM_CHUNK_SIZE, TtyConfig,
-    Upload, UploadResult,
+    AGENT_PORT, AgentInfo, Change, ChangeKind, ControlReq, ControlResp, DIFF_ENV, Download,
+    EXEC_OUTPUT_WINDOW, ErrorCode, ErrorInfo, ExecIn, ExecOut, ExecStart, FileSpec, Hello,
+    HelloAck, MAX_UPLOAD_BYTES, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, STREAM_CHUNK_SIZE,
+    TtyConfig, Upload, UploadResult,
 };
+pub use mounts::{MOUNTS_ENV, ShareMount};
+pub use user::resolve_user;
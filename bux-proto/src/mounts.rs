@@ -0,0 +1,87 @@
+//! Boot-time virtio-fs mount table handed from host to guest.
+//!
+//! The host sets [`MOUNTS_ENV`] in the guest environment; the agent mounts
+//! each share during startup. libkrun passes the environment on the kernel
+//! command line, so the encoding avoids whitespace: entries are separated by
+//! `,`, and each entry is `tag:ro|rw:guest_path` with the path last so it may
+//! contain `:`.
+
+/// Environment variable carrying the encoded mount table.
+pub const MOUNTS_ENV: &str = "BUX_MOUNTS";
+
+/// A virtio-fs share the guest agent should mount at boot.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShareMount {
+    /// virtio-fs tag of the share.
+    pub tag: String,
+    /// Absolute mount point inside the guest.
+    pub path: String,
+    /// Mount with `MS_RDONLY`.
+    pub read_only: bool,
+}
+
+impl ShareMount {
+    /// Creates a mount entry.
+    pub fn new(tag: impl Into<String>, path: impl Into<String>, read_only: bool) -> Self {
+        Self {
+            tag: tag.into(),
+            path: path.into(),
+            read_only,
+        }
+    }
+
+    /// Returns `true` if the entry survives [`encode`](Self::encode) intact.
+    pub fn is_encodable(&self) -> bool {
+        let clean = |s: &str| !s.is_empty() && !s.contains(|c: char| c == ',' || c.is_whitespace());
+        clean(&self.tag) && !self.tag.contains(':') && clean(&self.path)
+    }
+
+    /// Encodes mounts into a [`MOUNTS_ENV`] value.
+    pub fn encode(mounts: &[Self]) -> String {
+        mounts
+            .iter()
+            .map(|m| {
+                let mode = if m.read_only { "ro" } else { "rw" };
+                format!("{}:{mode}:{}", m.tag, m.path)
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Decodes a [`MOUNTS_ENV`] value, skipping malformed entries.
+    pub fn decode(value: &str) -> Vec<Self> {
+        value
+            .split(',')
+            .filter_map(|entry| {
+                let mut parts = entry.splitn(3, ':');
+                let tag = parts.next().filter(|t| !t.is_empty())?;
+                let read_only = match parts.next()? {
+                    "ro" => true,
+                    "rw" => false,
+                    _ => return None,
+                };
+                let path = parts.next().filter(|p| !p.is_empty())?;
+                Some(Self::new(tag, path, read_only))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount_table_round_trips() {
+        let mounts = vec![
+            ShareMount::new("vol0", "/src", true),
+            ShareMount::new("vol1", "/data:v2", false),
+        ];
+        let encoded = ShareMount::encode(&mounts);
+        assert_eq!(encoded, "vol0:ro:/src,vol1:rw:/data:v2");
+        assert_eq!(ShareMount::decode(&encoded), mounts);
+        assert!(ShareMount::decode("bad,vol2:xx:/p,:ro:/p").is_empty());
+        assert!(!ShareMount::new("vol0", "/my dir", false).is_encodable());
+    }
+}
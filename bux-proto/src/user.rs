@@ -0,0 +1,100 @@
+//! `user[:group]` specs, as used by `--user` and the OCI `User` field.
+//!
+//! Names are looked up in the contents of a Linux `/etc/passwd` and
+//! `/etc/group`; the caller reads those files from wherever the rootfs
+//! lives (the guest agent, or the host for an unpacked image).
+
+/// Resolves `spec` to a `(uid, gid)` pair.
+///
+/// Accepts `uid`, `uid:gid`, `name`, and `name:group`, in any mix of names
+/// and numbers. Without a group, a user listed in `passwd` gets its primary
+/// group and an unlisted numeric uid gets gid 0, as with Docker. Purely
+/// numeric parts are taken as ids without a lookup.
+pub fn resolve_user(spec: &str, passwd: &str, group: &str) -> Result<(u32, u32), String> {
+    let (user, grp) = match spec.split_once(':') {
+        Some((u, g)) => (u, Some(g)),
+        None => (spec, None),
+    };
+    if user.is_empty() || grp.is_some_and(str::is_empty) {
+        return Err(format!("invalid user spec {spec:?}"));
+    }
+
+    let numeric = user.parse::<u32>().ok();
+    let entry =
+        passwd_entries(passwd).find(|&(name, uid, _)| numeric.map_or(name == user, |id| uid == id));
+    let (uid, primary_gid) = match (numeric, entry) {
+        (Some(id), listed) => (id, listed.map_or(0, |(_, _, gid)| gid)),
+        (None, Some((_, uid, gid))) => (uid, gid),
+        (None, None) => return Err(format!("user {user:?} not found in /etc/passwd")),
+    };
+
+    let gid = match grp {
+        None => primary_gid,
+        Some(g) => match g.parse::<u32>() {
+            Ok(id) => id,
+            Err(_) => {
+                group_gid(group, g).ok_or_else(|| format!("group {g:?} not found in /etc/group"))?
+            }
+        },
+    };
+    Ok((uid, gid))
+}
+
+/// Parses `name:pw:uid:gid:...` lines, skipping comments and bad entries.
+fn passwd_entries(passwd: &str) -> impl Iterator<Item = (&str, u32, u32)> {
+    passwd.lines().filter_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields
+            .next()
+            .filter(|n| !n.is_empty() && !n.starts_with('#'))?;
+        let uid = fields.nth(1)?.parse().ok()?;
+        let gid = fields.next()?.parse().ok()?;
+        Some((name, uid, gid))
+    })
+}
+
+/// Looks up a group name in `name:pw:gid:members` lines.
+fn group_gid(group: &str, name: &str) -> Option<u32> {
+    group.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != name {
+            return None;
+        }
+        fields.nth(1)?.parse().ok()
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "\
+root:x:0:0:root:/root:/bin/sh
+# comment
+nginx:x:101:101:nginx:/var/cache/nginx:/sbin/nologin
+app:x:1000:1000::/home/app:/bin/sh
+";
+    const GROUP: &str = "root:x:0:\nwww-data:x:33:nginx\napp:x:1000:\n";
+
+    #[test]
+    fn resolves_names_and_numbers() {
+        let resolve = |spec| resolve_user(spec, PASSWD, GROUP);
+        assert_eq!(resolve("nginx"), Ok((101, 101)));
+        assert_eq!(resolve("nginx:www-data"), Ok((101, 33)));
+        assert_eq!(resolve("1000"), Ok((1000, 1000)));
+        assert_eq!(resolve("1000:0"), Ok((1000, 0)));
+        assert_eq!(resolve("4242"), Ok((4242, 0)));
+        assert_eq!(resolve("4242:7"), Ok((4242, 7)));
+        assert_eq!(resolve("app:5"), Ok((1000, 5)));
+
+        assert!(
+            resolve("ghost")
+                .unwrap_err()
+                .contains("\"ghost\" not found")
+        );
+        assert!(resolve("app:nogroup").unwrap_err().contains("/etc/group"));
+        assert!(resolve("").is_err());
+        assert!(resolve("app:").is_err());
+    }
+}
@@ -0,0 +1,14 @@
+//! Build script for bux.
+//!
+//! Rebuilds the crate when `BUX_SHIM_PATH` changes, which `option_env!`
+//! alone does not: cargo would keep a stale embedded path.
+//!
+//! # Environment variables
+//!
+//! - `BUX_SHIM_PATH` — Absolute path of the installed `bux-shim`, for
+//!   packagers that put it outside the `bux` binary's directory. Embedded
+//!   at compile time and tried first when spawning VMs.
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=BUX_SHIM_PATH");
+}
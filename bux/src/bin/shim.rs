@@ -2,9 +2,13 @@
 //!
 //! The parent (`Runtime::spawn`) writes a JSON-serialized [`VmConfig`] to a
 //! temp file and spawns this binary with the file path as the sole argument.
-//! The shim reads the config, deletes the temp file, rebuilds the
+//! The shim reads the config, deletes the temp file, checks that it was
+//! written by the same `bux` release, rebuilds the
 //! [`VmBuilder`], and calls [`Vm::start()`] which takes over the process
-//! via `krun_start_enter()`.
+//! via `krun_start_enter()`. A VM with a health check gets a thread that
+//! runs it for as long as the VM lives. The code the VM exits with is
+//! recorded next to its socket for waiters other than the parent; see
+//! [`bux::exit`].
 //!
 //! This replaces the previous `fork()` approach, which was undefined
 //! behavior in a multi-threaded tokio runtime.
@@ -28,6 +32,11 @@ fn main() {
     // Start watchdog thread if the parent passed a pipe FD.
     start_watchdog();
 
+    let status_file = bux::exit::path(std::path::Path::new(&config_path));
+    if let Err(e) = bux::exit::record_at_exit(status_file) {
+        eprintln!("[bux-shim] exit code will not be recorded: {e}");
+    }
+
     // Read and immediately delete the temp config file.
     let json = match std::fs::read_to_string(&config_path) {
         Ok(j) => {
@@ -40,14 +49,26 @@ fn main() {
         }
     };
 
-    let config: bux::VmConfig = match serde_json::from_str(&json) {
+    let config = match bux::VmConfig::from_shim_json(&json) {
         Ok(c) => c,
+        Err(e @ bux::Error::VersionMismatch { .. }) => {
+            let exe = std::env::current_exe().unwrap_or_default();
+            eprintln!(
+                "[bux-shim] {e}; install bux-shim from the same release as bux (running {})",
+                exe.display()
+            );
+            std::process::exit(1);
+        }
         Err(e) => {
             eprintln!("[bux-shim] invalid config JSON: {e}");
             std::process::exit(1);
         }
     };
 
+    if config.health_cmd.is_some() {
+        start_health_monitor(config.clone());
+    }
+
     let builder = bux::VmBuilder::from_config(&config);
 
     match builder.build().and_then(bux::Vm::start) {
@@ -97,3 +118,29 @@ fn start_watchdog() {
         eprintln!("[bux-shim] failed to spawn watchdog thread: {e}");
     }
 }
+
+/// Spawns a background thread that runs the VM's health check through the
+/// guest agent; see [`bux::health::monitor`].
+#[cfg(unix)]
+fn start_health_monitor(config: bux::VmConfig) {
+    let spawned = std::thread::Builder::new()
+        .name("health".into())
+        .spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(rt) => rt,
+                Err(e) => {
+                    eprintln!("[bux-shim] health check disabled: {e}");
+                    return;
+                }
+            };
+            if let Err(e) = runtime.block_on(bux::health::monitor(&config)) {
+                eprintln!("[bux-shim] health check stopped: {e}");
+            }
+        });
+    if let Err(e) = spawned {
+        eprintln!("[bux-shim] failed to spawn health check thread: {e}");
+    }
+}
@@ -9,15 +9,19 @@
 mod inner {
     use std::io;
     use std::path::{Path, PathBuf};
+    use std::sync::Arc;
 
     use bux_proto::{
-        ControlReq, ControlResp, ExecIn, ExecOut, ExecStart, Hello, HelloAck, PROTOCOL_VERSION,
-        STREAM_CHUNK_SIZE, UploadResult,
+        AgentInfo, Change, ControlReq, ControlResp, EXEC_OUTPUT_WINDOW, ExecIn, ExecOut, ExecStart,
+        FileSpec, FrameReader, Hello, HelloAck, MAX_CHUNK_SIZE, MIN_PROTOCOL_VERSION, RNG_SEED_LEN,
+        STREAM_CHUNK_SIZE, UploadResult, feature,
     };
-    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
     use tokio::net::UnixStream;
     use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
 
+    use super::exit_code;
+
     /// Output captured from a completed exec.
     #[derive(Debug)]
     #[non_exhaustive]
@@ -30,7 +34,8 @@ pub struct ExecOutput {
         pub stdout: Vec<u8>,
         /// Captured stderr bytes (empty in TTY mode).
         pub stderr: Vec<u8>,
-        /// Process exit code.
+        /// Process exit code; `128 + signal` if a signal killed it, as
+        /// shells report, and `-1` if the guest could not collect a status.
         pub code: i32,
         /// Signal that terminated the process, if any.
         pub signal: Option<i32>,
@@ -52,6 +57,24 @@ pub struct PongInfo {
         pub uptime_ms: u64,
     }
 
+    /// Metadata for a guest path, returned by [`Client::stat`].
+    #[derive(Debug, Clone, Copy)]
+    #[non_exhaustive]
+    pub struct FileStat {
+        /// Size in bytes.
+        pub size: u64,
+        /// Unix mode bits, including the file type.
+        pub mode: u32,
+        /// Whether the path is a directory.
+        pub is_dir: bool,
+        /// Owner UID.
+        pub uid: u32,
+        /// Owner GID.
+        pub gid: u32,
+        /// Modification time in seconds since the Unix epoch.
+        pub mtime: i64,
+    }
+
     /// Handle to a running exec with a dedicated connection.
     ///
     /// The connection is split into read/write halves so stdin writes and
@@ -63,9 +86,13 @@ pub struct ExecHandle {
         /// Child process ID inside the guest.
         pid: i32,
         /// Read half — receives [`ExecOut`] messages from the guest.
-        reader: OwnedReadHalf,
+        reader: FrameReader<OwnedReadHalf>,
         /// Write half — sends [`ExecIn`] messages to the guest.
         writer: OwnedWriteHalf,
+        /// Output bytes received but not yet acknowledged to the guest.
+        unacked: u64,
+        /// Encoded [`ExecIn::Ack`] bytes not yet written to the guest.
+        ack: Vec<u8>,
     }
 
     impl ExecHandle {
@@ -81,16 +108,19 @@ pub const fn pid(&self) -> i32 {
 
         /// Writes data to the process's stdin.
         pub async fn write_stdin(&mut self, data: &[u8]) -> io::Result<()> {
+            self.flush_ack().await?;
             bux_proto::send(&mut self.writer, &ExecIn::Stdin(data.to_vec())).await
         }
 
         /// Closes the process's stdin (sends EOF).
         pub async fn close_stdin(&mut self) -> io::Result<()> {
+            self.flush_ack().await?;
             bux_proto::send(&mut self.writer, &ExecIn::StdinClose).await
         }
 
         /// Sends a POSIX signal to the process.
         pub async fn signal(&mut self, sig: i32) -> io::Result<()> {
+            self.flush_ack().await?;
             bux_proto::send(&mut self.writer, &ExecIn::Signal(sig)).await
         }
 
@@ -102,6 +132,7 @@ pub async fn resize_tty(
             x_pixels: u16,
             y_pixels: u16,
         ) -> io::Result<()> {
+            self.flush_ack().await?;
             bux_proto::send(
                 &mut self.writer,
                 &ExecIn::ResizeTty(bux_proto::TtyConfig {
@@ -116,12 +147,104 @@ pub async fn resize_tty(
 
         /// Reads the next output event from the guest.
         ///
-        /// Returns `None` when the connection closes unexpectedly.
+        /// Consumed output is acknowledged to the guest, which stops reading
+        /// the child's pipes once [`EXEC_OUTPUT_WINDOW`] bytes are in flight.
+        /// Not calling this therefore applies backpressure to the process.
+        ///
+        /// Cancellation-safe: it may be raced in `tokio::select!` without
+        /// losing or corrupting output frames.
         pub async fn next_output(&mut self) -> io::Result<ExecOut> {
-            bux_proto::recv(&mut self.reader).await
+            // The acknowledgement is buffered and sent on the next call, so
+            // nothing is awaited once a frame has been taken off the wire.
+            // The guest may already have exited and closed its side; the
+            // pending Exit is still readable, so ignore failures.
+            let _ = self.flush_ack().await;
+            let msg = self.reader.recv().await?;
+            if let ExecOut::Stdout(d) | ExecOut::Stderr(d) = &msg {
+                self.unacked += d.len() as u64;
+                if self.unacked >= EXEC_OUTPUT_WINDOW / 4 {
+                    bux_proto::send(&mut self.ack, &ExecIn::Ack(self.unacked)).await?;
+                    self.unacked = 0;
+                }
+            }
+            Ok(msg)
+        }
+
+        /// Writes out a buffered acknowledgement.
+        ///
+        /// Each write either lands whole or not at all, so a cancelled flush
+        /// resumes where it stopped and never leaves half a frame ahead of
+        /// the next message. On error the acknowledgement is dropped.
+        async fn flush_ack(&mut self) -> io::Result<()> {
+            use tokio::io::AsyncWriteExt;
+
+            while !self.ack.is_empty() {
+                match self.writer.write(&self.ack).await {
+                    Ok(0) => {
+                        self.ack.clear();
+                        return Err(io::ErrorKind::WriteZero.into());
+                    }
+                    Ok(n) => {
+                        self.ack.drain(..n);
+                    }
+                    Err(e) => {
+                        self.ack.clear();
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Streams stdout/stderr into the given writers until the process exits.
+        ///
+        /// Memory use is bounded regardless of output size. The returned
+        /// [`ExecOutput`] has empty `stdout`/`stderr` buffers.
+        pub async fn copy_output(
+            mut self,
+            stdout: &mut (impl AsyncWrite + Unpin),
+            stderr: &mut (impl AsyncWrite + Unpin),
+        ) -> io::Result<ExecOutput> {
+            use tokio::io::AsyncWriteExt;
+
+            loop {
+                match self.next_output().await? {
+                    ExecOut::Stdout(d) => {
+                        stdout.write_all(&d).await?;
+                        stdout.flush().await?;
+                    }
+                    ExecOut::Stderr(d) => {
+                        stderr.write_all(&d).await?;
+                        stderr.flush().await?;
+                    }
+                    ExecOut::Exit {
+                        code,
+                        signal,
+                        timed_out,
+                        duration_ms,
+                        error_message,
+                    } => {
+                        return Ok(ExecOutput {
+                            exec_id: self.exec_id,
+                            pid: self.pid,
+                            stdout: Vec::new(),
+                            stderr: Vec::new(),
+                            code: exit_code(code, signal),
+                            signal,
+                            timed_out,
+                            duration_ms,
+                            error_message,
+                        });
+                    }
+                    ExecOut::Error(e) => return Err(io::Error::other(e)),
+                }
+            }
         }
 
         /// Waits for the process to exit, collecting all output.
+        ///
+        /// Output is buffered in memory without limit; use it for commands
+        /// with bounded output, and [`copy_output`](Self::copy_output) otherwise.
         pub async fn wait_with_output(mut self) -> io::Result<ExecOutput> {
             let mut stdout = Vec::new();
             let mut stderr = Vec::new();
@@ -141,7 +264,7 @@ pub async fn wait_with_output(mut self) -> io::Result<ExecOutput> {
                             pid: self.pid,
                             stdout,
                             stderr,
-                            code,
+                            code: exit_code(code, signal),
                             signal,
                             timed_out,
                             duration_ms,
@@ -154,6 +277,9 @@ pub async fn wait_with_output(mut self) -> io::Result<ExecOutput> {
         }
 
         /// Streams output via callback, returns collected output.
+        ///
+        /// Like [`wait_with_output`](Self::wait_with_output), all output is
+        /// also buffered in memory.
         pub async fn stream(mut self, mut on: impl FnMut(&ExecOut)) -> io::Result<ExecOutput> {
             let mut stdout = Vec::new();
             let mut stderr = Vec::new();
@@ -175,7 +301,7 @@ pub async fn stream(mut self, mut on: impl FnMut(&ExecOut)) -> io::Result<ExecOu
                             pid: self.pid,
                             stdout,
                             stderr,
-                            code,
+                            code: exit_code(code, signal),
                             signal,
                             timed_out,
                             duration_ms,
@@ -188,15 +314,32 @@ pub async fn stream(mut self, mut on: impl FnMut(&ExecOut)) -> io::Result<ExecOu
         }
     }
 
-    /// Stateless connection factory to a running guest agent.
+    /// Connection factory to a running guest agent.
     ///
     /// Each method opens a **dedicated connection**, sends a [`Hello`] message
     /// to identify the operation, and processes the response on that connection.
     /// Multiple operations can run concurrently without contention.
-    #[derive(Debug, Clone)]
+    #[derive(Clone)]
     pub struct Client {
         /// Socket path (Unix socket mapped from vsock by libkrun).
         socket_path: PathBuf,
+        /// Chunk size for streaming transfers in both directions.
+        chunk_size: usize,
+        /// Shared secret presented on every connection.
+        token: Option<Arc<str>>,
+        /// Agent description, fetched once and shared by clones.
+        info: Arc<tokio::sync::OnceCell<AgentInfo>>,
+    }
+
+    impl std::fmt::Debug for Client {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("Client")
+                .field("socket_path", &self.socket_path)
+                .field("chunk_size", &self.chunk_size)
+                .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+                .field("info", &self.info)
+                .finish()
+        }
     }
 
     impl Client {
@@ -206,32 +349,70 @@ impl Client {
         pub fn new(path: impl Into<PathBuf>) -> Self {
             Self {
                 socket_path: path.into(),
+                chunk_size: STREAM_CHUNK_SIZE,
+                token: None,
+                info: Arc::default(),
             }
         }
 
+        /// Authenticates every connection with `token`, for agents booted
+        /// with [`VmBuilder::auth_token`](crate::VmBuilder::auth_token).
+        #[must_use]
+        pub fn with_token(mut self, token: impl Into<String>) -> Self {
+            self.token = Some(token.into().into());
+            self
+        }
+
+        /// Sets the chunk size for file and tar transfers.
+        ///
+        /// Defaults to [`STREAM_CHUNK_SIZE`]. Values are clamped between 1 and
+        /// [`MAX_CHUNK_SIZE`], and each send further to its codec's
+        /// [`max_chunk_size`](bux_proto::Codec::max_chunk_size), so every
+        /// chunk fits in one frame. Downloads ask the guest to stream with
+        /// the same size.
+        #[must_use]
+        pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+            self.chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE);
+            self
+        }
+
+        /// Returns the chunk size used for streaming transfers.
+        pub const fn chunk_size(&self) -> usize {
+            self.chunk_size
+        }
+
+        /// Chunk size as sent to the guest.
+        fn wire_chunk_size(&self) -> u32 {
+            u32::try_from(self.chunk_size).unwrap_or(u32::MAX)
+        }
+
         /// Verifies connectivity and protocol version by opening a control
-        /// connection and performing a handshake.
+        /// connection and performing a handshake, then caches the agent's
+        /// [`info`](Self::info).
         pub async fn handshake(&self) -> io::Result<()> {
-            let mut stream = self.connect_raw().await?;
-            bux_proto::send(
-                &mut stream,
-                &Hello::Control {
-                    version: PROTOCOL_VERSION,
-                },
-            )
-            .await?;
-            match bux_proto::recv::<HelloAck>(&mut stream).await? {
-                HelloAck::Control { version } if version == PROTOCOL_VERSION => Ok(()),
-                HelloAck::Control { version } => Err(io::Error::new(
-                    io::ErrorKind::Unsupported,
-                    format!("protocol version mismatch: host={PROTOCOL_VERSION}, guest={version}"),
-                )),
-                HelloAck::Error(e) => Err(io::Error::other(e)),
-                _ => Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "expected Control ack",
-                )),
+            let mut stream = self.open_control().await?;
+            if self.info.initialized() {
+                return Ok(());
             }
+            let info = request_info(&mut stream).await?;
+            // A concurrent handshake may have won; both fetched the same data.
+            self.info.set(info).ok();
+            Ok(())
+        }
+
+        /// Returns the guest agent's version and supported operations.
+        ///
+        /// Fetched on the first call (or [`handshake`](Self::handshake)) and
+        /// cached, so checking [`AgentInfo::supports`] before each
+        /// operation is cheap.
+        pub async fn info(&self) -> io::Result<AgentInfo> {
+            self.info
+                .get_or_try_init(|| async {
+                    let mut stream = self.open_control().await?;
+                    request_info(&mut stream).await
+                })
+                .await
+                .cloned()
         }
 
         /// Requests graceful shutdown of the guest agent.
@@ -287,6 +468,111 @@ pub async fn thaw(&self) -> io::Result<u32> {
             }
         }
 
+        /// Returns the guest agent's environment as `KEY=VALUE` entries.
+        ///
+        /// Every exec starts from this environment, with [`ExecStart::env`]
+        /// layered on top.
+        pub async fn env(&self) -> io::Result<Vec<String>> {
+            let mut stream = self.open_control().await?;
+            bux_proto::send(&mut stream, &ControlReq::Env).await?;
+            match bux_proto::recv::<ControlResp>(&mut stream).await? {
+                ControlResp::Env(vars) => Ok(vars),
+                ControlResp::Error(e) => Err(io::Error::other(e)),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "expected Env")),
+            }
+        }
+
+        /// Changes the guest agent's environment for every later exec and
+        /// returns the result, as [`env`](Self::env) would.
+        ///
+        /// `KEY=VALUE` sets a variable and a bare `KEY` removes it, in
+        /// order. The agent refuses the whole change if it would leave
+        /// `PATH` empty or unset, or touches its boot variables. Needs an
+        /// agent with [`feature::SET_ENV`](bux_proto::feature::SET_ENV).
+        pub async fn set_env(&self, vars: &[String]) -> io::Result<Vec<String>> {
+            self.require(feature::SET_ENV).await?;
+            let mut stream = self.open_control().await?;
+            let req = ControlReq::SetEnv {
+                vars: vars.to_vec(),
+            };
+            bux_proto::send(&mut stream, &req).await?;
+            match bux_proto::recv::<ControlResp>(&mut stream).await? {
+                ControlResp::Env(env) => Ok(env),
+                ControlResp::Error(e) => Err(io::Error::other(e)),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "expected Env")),
+            }
+        }
+
+        /// Resolves a `user[:group]` spec (names or ids) to `(uid, gid)`
+        /// using the guest's `/etc/passwd` and `/etc/group`.
+        pub async fn resolve_user(&self, spec: &str) -> io::Result<(u32, u32)> {
+            let mut stream = self.open_control().await?;
+            let req = ControlReq::ResolveUser {
+                spec: spec.to_owned(),
+            };
+            bux_proto::send(&mut stream, &req).await?;
+            match bux_proto::recv::<ControlResp>(&mut stream).await? {
+                ControlResp::User { uid, gid } => Ok((uid, gid)),
+                ControlResp::Error(e) => Err(io::Error::other(e)),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "expected User")),
+            }
+        }
+
+        /// Lists root filesystem changes since the guest agent started,
+        /// sorted by path.
+        ///
+        /// Paths on other mounts (tmpfs, shares, `/proc`) are not tracked.
+        /// Fails unless the VM was built with
+        /// [`VmBuilder::track_changes`](crate::VmBuilder::track_changes).
+        pub async fn diff(&self) -> io::Result<Vec<Change>> {
+            let mut stream = self.open_control().await?;
+            bux_proto::send(&mut stream, &ControlReq::Diff).await?;
+            match bux_proto::recv::<ControlResp>(&mut stream).await? {
+                ControlResp::Diff(changes) => Ok(changes),
+                ControlResp::Error(e) => Err(io::Error::other(e)),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "expected Diff")),
+            }
+        }
+
+        /// Delivers signal `sig` to the VM's primary process: the command
+        /// the agent started in place of the image's own.
+        ///
+        /// Fails with a not-found error when the agent started no such
+        /// command (e.g. the VM boots the image command directly), and as
+        /// unsupported when the agent predates [`feature::SIGNAL`].
+        pub async fn signal_primary(&self, sig: i32) -> io::Result<()> {
+            self.require(feature::SIGNAL).await?;
+            let mut stream = self.open_control().await?;
+            bux_proto::send(&mut stream, &ControlReq::Signal { signal: sig }).await?;
+            match bux_proto::recv::<ControlResp>(&mut stream).await? {
+                ControlResp::SignalOk => Ok(()),
+                ControlResp::Error(e) => Err(io::Error::other(e)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected SignalOk",
+                )),
+            }
+        }
+
+        /// Credits `seed` to the guest kernel's entropy pool. Needs an
+        /// agent with [`feature::SEED_RNG`](bux_proto::feature::SEED_RNG).
+        pub async fn seed_rng(&self, seed: &[u8; RNG_SEED_LEN]) -> io::Result<()> {
+            self.require(feature::SEED_RNG).await?;
+            let mut stream = self.open_control().await?;
+            let req = ControlReq::SeedRng {
+                seed: seed.to_vec(),
+            };
+            bux_proto::send(&mut stream, &req).await?;
+            match bux_proto::recv::<ControlResp>(&mut stream).await? {
+                ControlResp::Seeded => Ok(()),
+                ControlResp::Error(e) => Err(io::Error::other(e)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected Seeded",
+                )),
+            }
+        }
+
         /// Starts a command on a dedicated exec connection.
         ///
         /// Returns an [`ExecHandle`] for reading output and writing stdin.
@@ -295,12 +581,15 @@ pub async fn exec(&self, req: ExecStart) -> io::Result<ExecHandle> {
             bux_proto::send(&mut stream, &Hello::Exec(req)).await?;
             match bux_proto::recv::<HelloAck>(&mut stream).await? {
                 HelloAck::ExecStarted { exec_id, pid } => {
+                    crate::metrics::exec_started();
                     let (reader, writer) = stream.into_split();
                     Ok(ExecHandle {
                         exec_id,
                         pid,
-                        reader,
+                        reader: FrameReader::new(reader),
                         writer,
+                        unacked: 0,
+                        ack: Vec::new(),
                     })
                 }
                 HelloAck::Error(e) => Err(io::Error::other(e)),
@@ -312,6 +601,10 @@ pub async fn exec(&self, req: ExecStart) -> io::Result<ExecHandle> {
         }
 
         /// Executes a command and collects all output.
+        ///
+        /// Intended for commands with bounded output: everything is buffered
+        /// in memory. Use [`exec`](Self::exec) with
+        /// [`ExecHandle::copy_output`] to stream large outputs instead.
         pub async fn exec_output(&self, req: ExecStart) -> io::Result<ExecOutput> {
             self.exec(req).await?.wait_with_output().await
         }
@@ -323,6 +616,7 @@ pub async fn read_file(&self, path: &str) -> io::Result<Vec<u8>> {
                 &mut stream,
                 &Hello::FileRead {
                     path: path.to_owned(),
+                    chunk_size: self.wire_chunk_size(),
                 },
             )
             .await?;
@@ -342,10 +636,120 @@ pub async fn write_file(&self, path: &str, data: &[u8], mode: u32) -> io::Result
             )
             .await?;
             Self::expect_ready(&mut stream).await?;
-            bux_proto::send_upload(&mut stream, data, STREAM_CHUNK_SIZE).await?;
+            bux_proto::send_upload(&mut stream, data, self.chunk_size).await?;
             Self::expect_upload_ok(&mut stream).await
         }
 
+        /// Overwrites part of a guest file starting at `offset`, creating it
+        /// with `mode` if missing. Returns the number of bytes written.
+        pub async fn write_at(
+            &self,
+            path: &str,
+            offset: u64,
+            data: &[u8],
+            mode: u32,
+        ) -> io::Result<u64> {
+            self.write_in_place(path, offset, false, data, mode).await
+        }
+
+        /// Appends to a guest file, creating it with `mode` if missing.
+        /// Returns the number of bytes written.
+        pub async fn append(&self, path: &str, data: &[u8], mode: u32) -> io::Result<u64> {
+            self.write_in_place(path, 0, true, data, mode).await
+        }
+
+        /// Sends [`Hello::FileWriteAt`] followed by `data`.
+        async fn write_in_place(
+            &self,
+            path: &str,
+            offset: u64,
+            append: bool,
+            data: &[u8],
+            mode: u32,
+        ) -> io::Result<u64> {
+            let mut stream = self.connect_raw().await?;
+            bux_proto::send(
+                &mut stream,
+                &Hello::FileWriteAt {
+                    path: path.to_owned(),
+                    offset,
+                    append,
+                    mode,
+                },
+            )
+            .await?;
+            Self::expect_ready(&mut stream).await?;
+            bux_proto::send_upload(&mut stream, data, self.chunk_size).await?;
+            match bux_proto::recv::<UploadResult>(&mut stream).await? {
+                UploadResult::Written(written) => Ok(written),
+                UploadResult::Error(e) => Err(io::Error::other(e)),
+                UploadResult::Ok => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected Written",
+                )),
+            }
+        }
+
+        /// Streams `size` bytes from `src` into a guest file.
+        ///
+        /// The guest writes into a partial file that survives interruption.
+        /// With a `source` identifying the content of `src` (e.g. its size
+        /// and modification time), bytes kept from an earlier attempt with
+        /// the same `source` are skipped by seeking `src` forward; anything
+        /// else starts over, as do agents without
+        /// [`feature::RESUMABLE_UPLOAD`](bux_proto::feature::RESUMABLE_UPLOAD).
+        /// Returns the offset the transfer resumed from.
+        pub async fn write_file_from_reader(
+            &self,
+            path: &str,
+            mode: u32,
+            size: u64,
+            source: Option<&str>,
+            src: &mut (impl AsyncRead + AsyncSeek + Unpin),
+        ) -> io::Result<u64> {
+            use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+            let hello = match source {
+                Some(tag) if self.info().await?.supports(feature::RESUMABLE_UPLOAD) => {
+                    Hello::ResumableUpload {
+                        path: path.to_owned(),
+                        mode,
+                        size,
+                        source: tag.to_owned(),
+                    }
+                }
+                _ => Hello::FileUpload {
+                    path: path.to_owned(),
+                    mode,
+                    size,
+                    resume: false,
+                },
+            };
+            let mut stream = self.connect_raw().await?;
+            bux_proto::send(&mut stream, &hello).await?;
+            let offset = match bux_proto::recv::<HelloAck>(&mut stream).await? {
+                HelloAck::Resume { offset } => offset,
+                HelloAck::Error(e) => return Err(io::Error::other(e)),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "expected Resume ack",
+                    ));
+                }
+            };
+            let Some(remaining) = size.checked_sub(offset) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("guest resumed at {offset}, past the {size}-byte source"),
+                ));
+            };
+            src.seek(io::SeekFrom::Start(offset)).await?;
+            let mut rest = src.take(remaining);
+            bux_proto::send_upload_from_reader(&mut stream, &mut rest, self.chunk_size).await?;
+            Self::expect_upload_ok(&mut stream).await?;
+            Ok(offset)
+        }
+
         /// Copies a tar archive into the guest, unpacking at `dest`.
         pub async fn copy_in(&self, dest: &str, tar_data: &[u8]) -> io::Result<()> {
             let mut stream = self.connect_raw().await?;
@@ -353,11 +757,12 @@ pub async fn copy_in(&self, dest: &str, tar_data: &[u8]) -> io::Result<()> {
                 &mut stream,
                 &Hello::CopyIn {
                     dest: dest.to_owned(),
+                    same_owner: false,
                 },
             )
             .await?;
             Self::expect_ready(&mut stream).await?;
-            bux_proto::send_upload(&mut stream, tar_data, STREAM_CHUNK_SIZE).await?;
+            bux_proto::send_upload(&mut stream, tar_data, self.chunk_size).await?;
             Self::expect_upload_ok(&mut stream).await
         }
 
@@ -369,17 +774,29 @@ pub async fn copy_in_from_reader(
             &self,
             dest: &str,
             reader: &mut (impl AsyncRead + Unpin),
+        ) -> io::Result<()> {
+            self.copy_in_from_reader_opts(dest, false, reader).await
+        }
+
+        /// Streams a tar archive into the guest, keeping the uid/gid of its
+        /// entries when `same_owner` is set.
+        pub async fn copy_in_from_reader_opts(
+            &self,
+            dest: &str,
+            same_owner: bool,
+            reader: &mut (impl AsyncRead + Unpin),
         ) -> io::Result<()> {
             let mut stream = self.connect_raw().await?;
             bux_proto::send(
                 &mut stream,
                 &Hello::CopyIn {
                     dest: dest.to_owned(),
+                    same_owner,
                 },
             )
             .await?;
             Self::expect_ready(&mut stream).await?;
-            bux_proto::send_upload_from_reader(&mut stream, reader, STREAM_CHUNK_SIZE).await?;
+            bux_proto::send_upload_from_reader(&mut stream, reader, self.chunk_size).await?;
             Self::expect_upload_ok(&mut stream).await
         }
 
@@ -400,6 +817,7 @@ pub async fn copy_out_opts(
                 &Hello::CopyOut {
                     path: path.to_owned(),
                     follow_symlinks,
+                    chunk_size: self.wire_chunk_size(),
                 },
             )
             .await?;
@@ -423,6 +841,7 @@ pub async fn copy_out_to_writer(
                 &Hello::CopyOut {
                     path: path.to_owned(),
                     follow_symlinks,
+                    chunk_size: self.wire_chunk_size(),
                 },
             )
             .await?;
@@ -430,14 +849,213 @@ pub async fn copy_out_to_writer(
             bux_proto::recv_download_to_writer(&mut stream, writer).await
         }
 
+        /// Uploads several host files to the guest as a single unit.
+        ///
+        /// Each entry is `(host_path, guest_path, mode)`. The guest stages every
+        /// file before moving any into place; on failure the error names the
+        /// offending file.
+        pub async fn upload(&self, files: &[(PathBuf, String, u32)]) -> io::Result<()> {
+            use tokio::io::AsyncReadExt;
+
+            let mut specs = Vec::with_capacity(files.len());
+            let mut reader: Box<dyn AsyncRead + Unpin + Send> = Box::new(tokio::io::empty());
+            for (host, guest, mode) in files {
+                let file = tokio::fs::File::open(host)
+                    .await
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", host.display())))?;
+                let len = file.metadata().await?.len();
+                specs.push(FileSpec::new(guest.as_str(), *mode, len));
+                reader = Box::new(reader.chain(file.take(len)));
+            }
+
+            let mut stream = self.connect_raw().await?;
+            bux_proto::send(&mut stream, &Hello::WriteFiles { files: specs }).await?;
+            Self::expect_ready(&mut stream).await?;
+            bux_proto::send_upload_from_reader(&mut stream, &mut reader, self.chunk_size).await?;
+            Self::expect_upload_ok(&mut stream).await
+        }
+
+        /// Returns metadata for a guest path (following symlinks).
+        pub async fn stat(&self, path: &str) -> io::Result<FileStat> {
+            let mut stream = self.connect_raw().await?;
+            bux_proto::send(
+                &mut stream,
+                &Hello::Stat {
+                    path: path.to_owned(),
+                },
+            )
+            .await?;
+            match bux_proto::recv::<HelloAck>(&mut stream).await? {
+                HelloAck::Stat {
+                    size,
+                    mode,
+                    is_dir,
+                    uid,
+                    gid,
+                    mtime,
+                } => Ok(FileStat {
+                    size,
+                    mode,
+                    is_dir,
+                    uid,
+                    gid,
+                    mtime,
+                }),
+                HelloAck::Error(e) => Err(io::Error::other(e)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected Stat ack",
+                )),
+            }
+        }
+
+        /// Changes the permission bits of a guest path.
+        pub async fn chmod(&self, path: &str, mode: u32) -> io::Result<()> {
+            self.oneshot(&Hello::Chmod {
+                path: path.to_owned(),
+                mode,
+            })
+            .await
+        }
+
+        /// Changes the owner and/or group of a guest path (`None` = unchanged).
+        pub async fn chown(
+            &self,
+            path: &str,
+            uid: Option<u32>,
+            gid: Option<u32>,
+        ) -> io::Result<()> {
+            self.oneshot(&Hello::Chown {
+                path: path.to_owned(),
+                uid,
+                gid,
+            })
+            .await
+        }
+
+        /// Sets the access and modification times of a guest path, in
+        /// seconds since the Unix epoch.
+        pub async fn utimes(&self, path: &str, atime: i64, mtime: i64) -> io::Result<()> {
+            self.oneshot(&Hello::Utimes {
+                path: path.to_owned(),
+                atime,
+                mtime,
+            })
+            .await
+        }
+
+        /// Creates a directory in the guest, optionally with missing parents.
+        pub async fn mkdir(&self, path: &str, mode: u32, parents: bool) -> io::Result<()> {
+            self.oneshot(&Hello::Mkdir {
+                path: path.to_owned(),
+                mode,
+                parents,
+            })
+            .await
+        }
+
+        /// Moves a guest path, replacing `to` if it exists.
+        ///
+        /// Atomic when both paths are on the same guest filesystem; see
+        /// [`Hello::Rename`] for the cross-filesystem fallback.
+        pub async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+            self.oneshot(&Hello::Rename {
+                from: from.to_owned(),
+                to: to.to_owned(),
+            })
+            .await
+        }
+
+        /// Creates a symlink at `link` pointing to `target`, like `ln -s`.
+        /// Fails if `link` exists.
+        pub async fn symlink(&self, target: &str, link: &str) -> io::Result<()> {
+            self.require(feature::SYMLINK).await?;
+            self.oneshot(&Hello::Symlink {
+                target: target.to_owned(),
+                link: link.to_owned(),
+            })
+            .await
+        }
+
+        /// Returns the target of a guest symlink, as stored.
+        pub async fn readlink(&self, path: &str) -> io::Result<String> {
+            self.require(feature::SYMLINK).await?;
+            let mut stream = self.connect_raw().await?;
+            let hello = Hello::Readlink {
+                path: path.to_owned(),
+            };
+            bux_proto::send(&mut stream, &hello).await?;
+            match bux_proto::recv::<HelloAck>(&mut stream).await? {
+                HelloAck::Path(target) => Ok(target),
+                HelloAck::Error(e) => Err(io::Error::other(e)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected Path ack",
+                )),
+            }
+        }
+
+        /// Mounts a filesystem in the running guest, e.g. a virtio-fs share
+        /// or a tmpfs; see [`Hello::Mount`] for the options and the mount
+        /// points the guest refuses.
+        pub async fn mount(
+            &self,
+            source: &str,
+            target: &str,
+            fstype: &str,
+            options: &[&str],
+        ) -> io::Result<()> {
+            self.oneshot(&Hello::Mount {
+                source: source.to_owned(),
+                target: target.to_owned(),
+                fstype: fstype.to_owned(),
+                options: options.iter().map(|&o| o.to_owned()).collect(),
+            })
+            .await
+        }
+
+        /// Unmounts the filesystem at `target` in the running guest.
+        pub async fn unmount(&self, target: &str) -> io::Result<()> {
+            self.oneshot(&Hello::Unmount {
+                target: target.to_owned(),
+            })
+            .await
+        }
+
         /// Returns the socket path this client targets.
         pub fn socket_path(&self) -> &Path {
             &self.socket_path
         }
 
-        /// Opens a raw Unix socket connection to the guest agent.
+        /// Opens a raw Unix socket connection to the guest agent,
+        /// authenticating it first when the client has a token.
         async fn connect_raw(&self) -> io::Result<UnixStream> {
-            UnixStream::connect(&self.socket_path).await
+            let mut stream = UnixStream::connect(&self.socket_path).await?;
+            if let Some(token) = &self.token {
+                let hello = Hello::Auth {
+                    token: token.to_string(),
+                };
+                bux_proto::send(&mut stream, &hello).await?;
+                Self::expect_ready(&mut stream).await?;
+            }
+            Ok(stream)
+        }
+
+        /// Fails as unsupported unless the agent advertises `feature`, for
+        /// operations added after [`MIN_PROTOCOL_VERSION`]: an older agent
+        /// cannot decode their requests.
+        async fn require(&self, feature: &str) -> io::Result<()> {
+            let info = self.info().await?;
+            if info.supports(feature) {
+                return Ok(());
+            }
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "guest agent {} does not support {feature}",
+                    info.agent_version
+                ),
+            ))
         }
 
         /// Opens a control connection (Hello::Control + HelloAck::Control).
@@ -446,15 +1064,17 @@ async fn open_control(&self) -> io::Result<UnixStream> {
             bux_proto::send(
                 &mut stream,
                 &Hello::Control {
-                    version: PROTOCOL_VERSION,
+                    version: MIN_PROTOCOL_VERSION,
                 },
             )
             .await?;
             match bux_proto::recv::<HelloAck>(&mut stream).await? {
-                HelloAck::Control { version } if version == PROTOCOL_VERSION => Ok(stream),
+                HelloAck::Control { version } if version >= MIN_PROTOCOL_VERSION => Ok(stream),
                 HelloAck::Control { version } => Err(io::Error::new(
                     io::ErrorKind::Unsupported,
-                    format!("protocol version mismatch: host={PROTOCOL_VERSION}, guest={version}"),
+                    format!(
+                        "protocol version mismatch: host={MIN_PROTOCOL_VERSION}+, guest={version}"
+                    ),
                 )),
                 HelloAck::Error(e) => Err(io::Error::other(e)),
                 _ => Err(io::Error::new(
@@ -464,6 +1084,20 @@ async fn open_control(&self) -> io::Result<UnixStream> {
             }
         }
 
+        /// Sends a single-shot [`Hello`] and expects a HelloAck::Done response.
+        async fn oneshot(&self, hello: &Hello) -> io::Result<()> {
+            let mut stream = self.connect_raw().await?;
+            bux_proto::send(&mut stream, hello).await?;
+            match bux_proto::recv::<HelloAck>(&mut stream).await? {
+                HelloAck::Done => Ok(()),
+                HelloAck::Error(e) => Err(io::Error::other(e)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected Done ack",
+                )),
+            }
+        }
+
         /// Expects a HelloAck::Ready response.
         async fn expect_ready(
             stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
@@ -483,12 +1117,86 @@ async fn expect_upload_ok(
             stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
         ) -> io::Result<()> {
             match bux_proto::recv::<UploadResult>(stream).await? {
-                UploadResult::Ok => Ok(()),
+                UploadResult::Ok | UploadResult::Written(_) => Ok(()),
                 UploadResult::Error(e) => Err(io::Error::other(e)),
             }
         }
     }
+
+    /// Sends [`ControlReq::Info`] on an open control connection.
+    async fn request_info(stream: &mut UnixStream) -> io::Result<AgentInfo> {
+        bux_proto::send(stream, &ControlReq::Info).await?;
+        match bux_proto::recv::<ControlResp>(stream).await? {
+            ControlResp::Info(info) => Ok(info),
+            ControlResp::Error(e) => Err(io::Error::other(e)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "expected Info")),
+        }
+    }
 }
 
 #[cfg(unix)]
-pub use inner::{Client, ExecHandle, ExecOutput, PongInfo};
+pub use inner::{Client, ExecHandle, ExecOutput, FileStat, PongInfo};
+
+/// Folds an [`ExecOut::Exit`](bux_proto::ExecOut::Exit) status into one
+/// shell-style code: the exit code, `128 + signal` after a signal death,
+/// or `-1` if neither is known.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn exit_code(code: Option<i32>, signal: Option<i32>) -> i32 {
+    code.or_else(|| signal.map(|sig| 128 + sig)).unwrap_or(-1)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_deaths_map_to_shell_exit_codes() {
+        assert_eq!(exit_code(Some(0), None), 0);
+        assert_eq!(exit_code(Some(255), None), 255);
+        assert_eq!(exit_code(None, Some(9)), 137);
+        assert_eq!(exit_code(None, Some(15)), 143);
+        assert_eq!(exit_code(None, None), -1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn older_agents_connect_but_newer_ops_are_refused() {
+        use bux_proto::{
+            AgentInfo, ControlReq, ControlResp, Hello, HelloAck, MIN_PROTOCOL_VERSION, feature,
+        };
+
+        let dir = std::env::temp_dir().join(format!("bux_client_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("agent.sock");
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        // An agent at the oldest compatible version, before signal
+        // forwarding and symlinks.
+        let agent = tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let hello: Hello = bux_proto::recv(&mut conn).await.unwrap();
+            assert!(matches!(hello, Hello::Control { version } if version == MIN_PROTOCOL_VERSION));
+            let ack = HelloAck::Control {
+                version: MIN_PROTOCOL_VERSION,
+            };
+            bux_proto::send(&mut conn, &ack).await.unwrap();
+            let req: ControlReq = bux_proto::recv(&mut conn).await.unwrap();
+            assert!(matches!(req, ControlReq::Info));
+            let info = AgentInfo::new("0.6.0", vec![feature::EXEC.into()]);
+            bux_proto::send(&mut conn, &ControlResp::Info(info))
+                .await
+                .unwrap();
+        });
+
+        let client = Client::new(&socket);
+        let signal = client.signal_primary(2).await.unwrap_err();
+        assert_eq!(signal.kind(), std::io::ErrorKind::Unsupported);
+        // The cached info answers without another connection.
+        let symlink = client.symlink("target", "/link").await.unwrap_err();
+        assert_eq!(symlink.kind(), std::io::ErrorKind::Unsupported);
+        agent.await.unwrap();
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
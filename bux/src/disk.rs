@@ -4,7 +4,8 @@
 //!
 //! - [`DiskFormat`] — Type-safe disk format enum (Raw / Qcow2) with serde support.
 //! - [`Disk`] — RAII handle that optionally auto-removes the file on drop.
-//! - [`DiskManager`] — Manages shared ext4 bases and per-VM QCOW2 overlays.
+//! - [`DiskManager`] — Manages shared ext4 bases, per-VM QCOW2 overlays and
+//!   named data disks.
 //! - [`qcow2`] — Pure-Rust QCOW2 v3 operations (create / read / flatten / resize).
 //!
 //! # Storage layout
@@ -13,6 +14,7 @@
 //! {data_dir}/disks/
 //!   bases/{digest}.raw     — shared read-only ext4 base images
 //!   vms/{vm_id}.qcow2     — per-VM QCOW2 COW overlays (~256 KiB each)
+//!   volumes/{name}.raw     — named data disks, kept across VMs
 //! ```
 
 use std::fmt;
@@ -204,6 +206,11 @@ pub struct DiskManager {
     bases_dir: PathBuf,
     /// Directory for per-VM QCOW2 overlays.
     vms_dir: PathBuf,
+    /// Directory for named data disks.
+    volumes_dir: PathBuf,
+    /// Where base images are built before moving into `bases_dir`
+    /// (`None` = in `bases_dir` itself).
+    staging: Option<PathBuf>,
 }
 
 #[cfg(unix)]
@@ -213,9 +220,26 @@ pub fn open(data_dir: impl AsRef<Path>) -> io::Result<Self> {
         let base = data_dir.as_ref().join("disks");
         let bases_dir = base.join("bases");
         let vms_dir = base.join("vms");
+        let volumes_dir = base.join("volumes");
         fs::create_dir_all(&bases_dir)?;
         fs::create_dir_all(&vms_dir)?;
-        Ok(Self { bases_dir, vms_dir })
+        fs::create_dir_all(&volumes_dir)?;
+        Ok(Self {
+            bases_dir,
+            vms_dir,
+            volumes_dir,
+            staging: None,
+        })
+    }
+
+    /// Builds base images in `dir` (e.g. fast local disk) and moves each
+    /// into the store once complete, copying when `dir` is on another
+    /// filesystem. Overlays are tiny and always written in place.
+    pub fn with_staging_dir(mut self, dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let staging = dir.into();
+        fs::create_dir_all(&staging)?;
+        self.staging = Some(staging);
+        Ok(self)
     }
 
     /// Returns `true` if a base image for the given digest already exists.
@@ -233,17 +257,83 @@ pub fn base_path(&self, digest: &str) -> PathBuf {
     /// Returns the path to the created image. If the image already exists
     /// for this digest, returns immediately (idempotent).
     pub fn create_base(&self, rootfs: &Path, digest: &str) -> Result<PathBuf> {
+        self.create_base_filtered(rootfs, digest, &bux_e2fs::IgnoreRules::default())
+    }
+
+    /// Like [`create_base`](Self::create_base), but leaves out entries
+    /// matched by `ignore` (`.dockerignore` syntax).
+    ///
+    /// The digest should cover the ignore rules too, since the same rootfs
+    /// yields different images under different rules.
+    pub fn create_base_filtered(
+        &self,
+        rootfs: &Path,
+        digest: &str,
+        ignore: &bux_e2fs::IgnoreRules,
+    ) -> Result<PathBuf> {
+        if self.has_base(digest) {
+            return Ok(self.base_path(digest));
+        }
+        let size = bux_e2fs::estimate_image_size(rootfs)?;
+        self.create_base_sized(rootfs, digest, ignore, size)
+    }
+
+    /// Like [`create_base_filtered`](Self::create_base_filtered), with the
+    /// image size already known, which spares a walk of `rootfs`.
+    ///
+    /// [`image_size_for`](crate::image_size_for) turns counts taken while the rootfs was
+    /// extracted (`bux_oci::PullResult::rootfs_usage`) into `size_bytes`.
+    /// Sizes counted before `ignore` is applied only overestimate. The
+    /// walk this skips took ~0.4 s on a 128k-entry, 4.8 GB tree with a warm
+    /// page cache (as right after extraction) and ~2 s cold.
+    pub fn create_base_sized(
+        &self,
+        rootfs: &Path,
+        digest: &str,
+        ignore: &bux_e2fs::IgnoreRules,
+        size_bytes: u64,
+    ) -> Result<PathBuf> {
         let path = self.base_path(digest);
         if path.exists() {
             return Ok(path);
         }
 
-        let size = bux_e2fs::estimate_image_size(rootfs)?;
-
         // Write to a temporary file first, then rename for atomicity.
-        let tmp = self.bases_dir.join(format!("{digest}.raw.tmp"));
-        bux_e2fs::create_from_dir(rootfs, &tmp, size)?;
-        fs::rename(&tmp, &path)?;
+        let tmp = self
+            .staging
+            .as_ref()
+            .unwrap_or(&self.bases_dir)
+            .join(format!("{digest}.raw.tmp"));
+        bux_e2fs::create_from_dir_filtered(rootfs, &tmp, size_bytes, ignore)?;
+        move_file(&tmp, &path)?;
+
+        Ok(path)
+    }
+
+    /// Creates a base ext4 image straight from an image's layer tarballs
+    /// (`Oci::layer_path`, base layer first), applying whiteouts on the way
+    /// without extracting a rootfs directory.
+    ///
+    /// Meant for block-device-only VMs; virtio-fs roots still need the
+    /// directory, built by [`create_base`](Self::create_base). Idempotent
+    /// like it.
+    pub fn create_base_from_layers(
+        &self,
+        layers: &[impl AsRef<Path>],
+        digest: &str,
+    ) -> Result<PathBuf> {
+        let path = self.base_path(digest);
+        if path.exists() {
+            return Ok(path);
+        }
+
+        let tmp = self
+            .staging
+            .as_ref()
+            .unwrap_or(&self.bases_dir)
+            .join(format!("{digest}.raw.tmp"));
+        bux_e2fs::create_from_layers(layers, &tmp)?;
+        move_file(&tmp, &path)?;
 
         Ok(path)
     }
@@ -305,6 +395,60 @@ pub fn remove_vm_disk(&self, vm_id: &str) -> io::Result<()> {
         Ok(())
     }
 
+    /// Returns the path of the named data disk `name` (may or may not exist).
+    pub fn volume_path(&self, name: &str) -> PathBuf {
+        self.volumes_dir.join(format!("{name}.raw"))
+    }
+
+    /// Returns the named data disk `name`, first creating it as an empty
+    /// sparse raw image of `size` bytes if it does not exist yet.
+    ///
+    /// Without a `size` the disk must exist. Names are ASCII letters,
+    /// digits, `_`, `.` and `-`, not starting with `.`, since they name both
+    /// the file and the guest block device.
+    pub fn volume(&self, name: &str, size: Option<u64>) -> Result<PathBuf> {
+        if name.is_empty()
+            || name.starts_with('.')
+            || !name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"_.-".contains(&b))
+        {
+            return Err(crate::Error::InvalidConfig(format!(
+                "invalid data disk name {name:?}"
+            )));
+        }
+        let path = self.volume_path(name);
+        let Some(len) = size else {
+            if !path.is_file() {
+                return Err(crate::Error::InvalidConfig(format!(
+                    "data disk {name} does not exist; give a size to create it"
+                )));
+            }
+            return Ok(path);
+        };
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => {
+                if let Err(e) = file.set_len(len) {
+                    let _ = fs::remove_file(&path);
+                    return Err(e.into());
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(path)
+    }
+
+    /// Returns `true` if `path` is a named data disk of this manager.
+    pub fn is_volume(&self, path: &Path) -> bool {
+        path.parent() == Some(self.volumes_dir.as_path())
+            && path.extension().is_some_and(|e| e == "raw")
+    }
+
     /// Lists all base image digests.
     pub fn list_bases(&self) -> io::Result<Vec<String>> {
         let mut digests = Vec::new();
@@ -327,6 +471,85 @@ pub fn remove_base(&self, digest: &str) -> io::Result<()> {
         }
         Ok(())
     }
+
+    /// Removes base images that no VM overlay is backed by, returning the
+    /// disk space freed in bytes.
+    ///
+    /// Bases are rebuilt on demand from their rootfs, so dropping an unused
+    /// one only costs a rebuild on next use.
+    pub fn prune_bases(&self) -> io::Result<u64> {
+        use std::collections::HashSet;
+        use std::os::unix::fs::MetadataExt;
+
+        let mut in_use = HashSet::new();
+        for dir_entry in fs::read_dir(&self.vms_dir)? {
+            let path = dir_entry?.path();
+            if path.extension().is_some_and(|e| e == "qcow2")
+                && let Ok(Some(backing)) = qcow2::read_backing_file(&path)
+            {
+                let backing_path = PathBuf::from(backing);
+                in_use.insert(fs::canonicalize(&backing_path).unwrap_or(backing_path));
+            }
+        }
+
+        let mut freed = 0;
+        for digest in self.list_bases()? {
+            let path = self.base_path(&digest);
+            let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if !in_use.contains(&canonical) {
+                // Bases are sparse; count allocated blocks, not length.
+                freed += fs::metadata(&path).map_or(0, |m| m.blocks() * 512);
+                self.remove_base(&digest)?;
+            }
+        }
+        Ok(freed)
+    }
+}
+
+/// Renames `from` to `to`, falling back to a sparse copy when they are on
+/// different filesystems. The copy is built at a temporary sibling of `to`
+/// and renamed over it, so `to` never holds a partial image.
+#[cfg(unix)]
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+        result => return result,
+    }
+    let mut name = to.file_name().unwrap_or_default().to_os_string();
+    name.push(".xdev");
+    let tmp = to.with_file_name(name);
+    if let Err(e) = copy_sparse(from, &tmp) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(&tmp, to)?;
+    fs::remove_file(from)
+}
+
+/// Copies a file, leaving holes where the source reads as zeros so that
+/// sparse disk images stay sparse.
+#[cfg(unix)]
+fn copy_sparse(from: &Path, to: &Path) -> io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    let mut src = fs::File::open(from)?;
+    let mut dst = fs::File::create(to)?;
+    let mut buf = vec![0u8; 64 << 10];
+    let mut offset = 0u64;
+    loop {
+        let n = src.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        if buf[..n].iter().any(|&b| b != 0) {
+            dst.seek(SeekFrom::Start(offset))?;
+            dst.write_all(&buf[..n])?;
+        }
+        offset += n as u64;
+    }
+    // Covers a trailing hole, which no write extended the file over.
+    dst.set_len(offset)?;
+    dst.sync_all()
 }
 
 // ───────────────────────────────────────────────────────────────────────────
@@ -1008,7 +1231,7 @@ fn creates_valid_qcow2_header() {
             let backing = "/tmp/base.raw";
             let vsize: u64 = 1 << 30; // 1 GiB
 
-            create_overlay(&path, backing, super::DiskFormat::Raw, vsize).unwrap();
+            create_overlay(&path, backing, crate::DiskFormat::Raw, vsize).unwrap();
 
             // Verify via read_header.
             let hdr = read_header(&path).unwrap();
@@ -1055,7 +1278,7 @@ fn l1_entries_scale_with_size() {
             let path = dir.join("big.qcow2");
             let vsize: u64 = 100 << 30; // 100 GiB
 
-            create_overlay(&path, "/tmp/big.raw", super::DiskFormat::Raw, vsize).unwrap();
+            create_overlay(&path, "/tmp/big.raw", crate::DiskFormat::Raw, vsize).unwrap();
 
             let hdr = read_header(&path).unwrap();
             // 100 GiB / 512 MiB per L1 entry = 200 entries.
@@ -1098,7 +1321,7 @@ fn overlay_with_qcow2_backing_format() {
             let path = dir.join("child.qcow2");
             let vsize: u64 = 1 << 30;
 
-            create_overlay(&path, "/tmp/base.qcow2", super::DiskFormat::Qcow2, vsize).unwrap();
+            create_overlay(&path, "/tmp/base.qcow2", crate::DiskFormat::Qcow2, vsize).unwrap();
 
             let hdr = read_header(&path).unwrap();
             assert_eq!(hdr.backing_file.as_deref(), Some("/tmp/base.qcow2"));
@@ -1113,7 +1336,7 @@ fn read_backing_file_returns_path() {
             let _ = std::fs::create_dir_all(&dir);
             let path = dir.join("overlay.qcow2");
 
-            create_overlay(&path, "/data/base.raw", super::DiskFormat::Raw, 1 << 30).unwrap();
+            create_overlay(&path, "/data/base.raw", crate::DiskFormat::Raw, 1 << 30).unwrap();
 
             let bf = read_backing_file(&path).unwrap();
             assert_eq!(bf.as_deref(), Some("/data/base.raw"));
@@ -1168,7 +1391,7 @@ fn flatten_two_layer_chain() {
             create_overlay(
                 &child,
                 &abs_base.to_string_lossy(),
-                super::DiskFormat::Raw,
+                crate::DiskFormat::Raw,
                 raw_size as u64,
             )
             .unwrap();
@@ -1202,3 +1425,73 @@ fn flatten_two_layer_chain() {
         }
     }
 }
+
+#[cfg(all(test, unix))]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_bases_keeps_referenced_bases() {
+        let dir = std::env::temp_dir().join(format!("bux_prune_bases_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let dm = DiskManager::open(&dir).unwrap();
+
+        fs::write(dm.base_path("used"), vec![0u8; 1 << 20]).unwrap();
+        fs::write(dm.base_path("unused"), vec![0u8; 1 << 20]).unwrap();
+        dm.create_overlay(&dm.base_path("used"), DiskFormat::Raw, "vm1")
+            .unwrap();
+
+        assert!(dm.prune_bases().unwrap() > 0);
+        assert_eq!(dm.list_bases().unwrap(), ["used"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn volumes_are_created_once_and_named_safely() {
+        let dir = std::env::temp_dir().join(format!("bux_volumes_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let dm = DiskManager::open(&dir).unwrap();
+
+        assert!(dm.volume("data", None).is_err());
+        let path = dm.volume("data", Some(1 << 20)).unwrap();
+        assert_eq!(path, dm.volume_path("data"));
+        assert_eq!(fs::metadata(&path).unwrap().len(), 1 << 20);
+        fs::write(&path, b"kept").unwrap();
+        // An existing disk is reused as is, whatever size is asked for.
+        assert_eq!(dm.volume("data", Some(2 << 20)).unwrap(), path);
+        assert_eq!(dm.volume("data", None).unwrap(), path);
+        assert_eq!(fs::read(&path).unwrap(), b"kept");
+        assert!(dm.is_volume(&path));
+        assert!(!dm.is_volume(&dm.vm_disk_path("vm1")));
+
+        for name in ["", ".hidden", "../escape", "a/b", "with space"] {
+            assert!(dm.volume(name, Some(1)).is_err(), "{name:?}");
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn copy_sparse_keeps_holes() {
+        use std::os::unix::fs::{FileExt, MetadataExt};
+
+        let dir = std::env::temp_dir().join(format!("bux_copy_sparse_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("src.raw");
+        let file = fs::File::create(&src).unwrap();
+        file.write_all_at(b"head", 0).unwrap();
+        file.write_all_at(b"middle", 4 << 20).unwrap();
+        file.set_len(16 << 20).unwrap();
+
+        let dst = dir.join("dst.raw");
+        copy_sparse(&src, &dst).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), fs::read(&src).unwrap());
+        // Two 64 KiB chunks of data, not 16 MiB.
+        assert!(fs::metadata(&dst).unwrap().blocks() * 512 < 1 << 20);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
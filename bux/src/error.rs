@@ -18,6 +18,14 @@ pub enum Error {
         code: i32,
     },
 
+    /// The libkrun library could not be loaded.
+    #[error("libkrun not found ({0}); set BUX_LIBKRUN to the library path or reinstall bux")]
+    LibkrunNotFound(String),
+
+    /// The libkrun build lacks a feature the VM configuration requires.
+    #[error("{} is not supported by this libkrun build", .0.as_str())]
+    Unsupported(crate::Feature),
+
     /// A string argument contained an interior NUL byte.
     #[error("interior NUL byte in string argument")]
     Nul(#[from] NulError),
@@ -38,6 +46,24 @@ pub enum Error {
     #[error("{0}")]
     InvalidState(String),
 
+    /// The VM was started without a guest agent, which the operation needs.
+    #[error("VM {0} runs without a guest agent (started with no_agent)")]
+    NoAgent(String),
+
+    /// The VM configuration is inconsistent or refers to missing resources.
+    #[error("invalid VM config: {0}")]
+    InvalidConfig(String),
+
+    /// A VM config was written by a different `bux` release than the
+    /// binary reading it (typically a stale `bux-shim`).
+    #[error("bux version mismatch: config written by bux {found}, this is bux {expected}")]
+    VersionMismatch {
+        /// Version recorded in the config.
+        found: String,
+        /// Version of the reading binary.
+        expected: &'static str,
+    },
+
     /// Unix syscall error (via nix).
     #[cfg(unix)]
     #[error(transparent)]
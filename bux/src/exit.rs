@@ -0,0 +1,139 @@
+//! Exit status of VMs, recorded by `bux-shim` for waiters that did not
+//! spawn them.
+//!
+//! Only the parent of a VM process can collect its status with `waitpid`.
+//! A detached VM outlives the `bux` process that spawned it, so its status
+//! would be lost; the shim therefore writes the code libkrun exits with to
+//! a file next to the agent socket (`{id}.exit`), and
+//! [`VmHandle::wait`](crate::VmHandle::wait) reads it back when `waitpid`
+//! cannot be used.
+//!
+//! Recording relies on glibc's `on_exit`. On other platforms nothing is
+//! recorded and the code of a detached VM stays unknown.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::{fs, io};
+
+/// Where [`record_at_exit`] writes the status, set once per process.
+static RECORD: OnceLock<PathBuf> = OnceLock::new();
+
+/// The status file of the VM whose socket (or spawn config, which shares
+/// its stem) is `sibling`.
+pub fn path(sibling: &Path) -> PathBuf {
+    sibling.with_extension("exit")
+}
+
+/// Reads the exit code recorded for the VM whose socket is `socket`.
+pub fn read(socket: &Path) -> Option<i32> {
+    fs::read_to_string(path(socket)).ok()?.trim().parse().ok()
+}
+
+/// Writes `code` to `file`, replacing it so readers never see it half
+/// written.
+#[cfg_attr(not(all(target_os = "linux", target_env = "gnu")), allow(dead_code))]
+fn write(file: &Path, code: i32) -> io::Result<()> {
+    let staging = file.with_extension("exit.tmp");
+    fs::write(&staging, code.to_string())?;
+    fs::rename(staging, file)
+}
+
+/// Arranges for the status this process exits with to be written to
+/// `file`, removing any left from an earlier run.
+///
+/// Meant for `bux-shim`, which libkrun ends with `exit(code)` once the
+/// guest stops. Deaths by signal run no handlers and record nothing.
+pub fn record_at_exit(file: PathBuf) -> io::Result<()> {
+    match fs::remove_file(&file) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    if RECORD.set(file).is_err() {
+        return Ok(());
+    }
+    register()
+}
+
+/// Registers [`on_exit_handler`] with glibc.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+#[allow(unsafe_code)]
+fn register() -> io::Result<()> {
+    unsafe extern "C" {
+        fn on_exit(
+            function: extern "C" fn(libc::c_int, *mut libc::c_void),
+            arg: *mut libc::c_void,
+        ) -> libc::c_int;
+    }
+    // SAFETY: the handler is a plain function that ignores `arg`.
+    if unsafe { on_exit(on_exit_handler, std::ptr::null_mut()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::other("on_exit registration failed"))
+    }
+}
+
+/// Without `on_exit` there is no way to see the status; record nothing.
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+#[allow(clippy::unnecessary_wraps)]
+const fn register() -> io::Result<()> {
+    Ok(())
+}
+
+/// Writes the exit status to the file set by [`record_at_exit`].
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+extern "C" fn on_exit_handler(status: libc::c_int, _arg: *mut libc::c_void) {
+    if let Some(file) = RECORD.get() {
+        let _ = write(file, status);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_round_trips_through_the_file() {
+        let dir = std::env::temp_dir().join(format!("bux_exit_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("abc.sock");
+        assert_eq!(path(&dir.join("abc.json")), path(&socket));
+        assert_eq!(read(&socket), None);
+        write(&path(&socket), 137).unwrap();
+        assert_eq!(read(&socket), Some(137));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Set to a status file path, [`exits_through_record`] records into it
+    /// and exits instead of passing.
+    const CHILD_ENV: &str = "BUX_EXIT_TEST_FILE";
+
+    /// Helper run as a child process by [`exit_code_is_recorded`].
+    #[test]
+    fn exits_through_record() {
+        if let Some(file) = std::env::var_os(CHILD_ENV) {
+            record_at_exit(PathBuf::from(file)).unwrap();
+            std::process::exit(42);
+        }
+    }
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    #[test]
+    fn exit_code_is_recorded() {
+        let dir = std::env::temp_dir().join(format!("bux_exit_child_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("vm.sock");
+        write(&path(&socket), 1).unwrap();
+
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "exit::tests::exits_through_record"])
+            .env(CHILD_ENV, path(&socket))
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(42));
+        assert_eq!(read(&socket), Some(42));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
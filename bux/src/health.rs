@@ -0,0 +1,165 @@
+//! Health checks for VMs spawned with
+//! [`VmBuilder::health_cmd`](crate::VmBuilder::health_cmd).
+//!
+//! `bux-shim` runs [`monitor`] beside the VM, so checks go on for as long as
+//! the VM runs, detached or not. Once the guest agent answers, the command
+//! runs through it every interval, and each change of [`Health`] is written
+//! to a file next to the agent socket (`{id}.health`). The runtime reads it
+//! back into [`VmState::health`](crate::VmState::health): the shim runs in a
+//! sandbox that cannot reach the state database.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{fs, io};
+
+use bux_proto::{AGENT_PORT, ExecStart};
+
+use crate::client::Client;
+use crate::state::{Health, VmConfig};
+
+/// Time between checks when none is configured, as in Docker.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Failed checks in a row before a VM is unhealthy, when none is configured.
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// How often to probe for the agent before the first check.
+const AGENT_POLL: Duration = Duration::from_millis(100);
+
+/// The file recording the health of the VM whose agent socket is `socket`.
+pub fn path(socket: &Path) -> PathBuf {
+    socket.with_extension("health")
+}
+
+/// Reads the health recorded for the VM whose agent socket is `socket`;
+/// `None` if nothing was recorded yet.
+pub fn read(socket: &Path) -> Option<Health> {
+    match fs::read_to_string(path(socket)).ok()?.trim() {
+        "starting" => Some(Health::Starting),
+        "healthy" => Some(Health::Healthy),
+        "unhealthy" => Some(Health::Unhealthy),
+        _ => None,
+    }
+}
+
+/// Records `health`, replacing the file so readers never see it half
+/// written.
+fn write(socket: &Path, health: Health) -> io::Result<()> {
+    let file = path(socket);
+    let staging = file.with_extension("health.tmp");
+    fs::write(&staging, health.as_str())?;
+    fs::rename(staging, file)
+}
+
+/// Runs the health check of the VM described by `config` until the process
+/// exits, recording each change of health.
+///
+/// Returns at once if the VM has no health check or no agent, and with an
+/// error if the health file cannot be written.
+pub async fn monitor(config: &VmConfig) -> io::Result<()> {
+    let Some((program, args)) = config.health_cmd.as_deref().and_then(<[_]>::split_first) else {
+        return Ok(());
+    };
+    let Some(socket) = config
+        .vsock_ports
+        .iter()
+        .find(|v| v.port == AGENT_PORT && v.listen)
+        .map(|v| PathBuf::from(&v.path))
+    else {
+        return Ok(());
+    };
+    let interval = config.health_interval.unwrap_or(DEFAULT_INTERVAL);
+    let mut client = Client::new(&socket);
+    if let Some(ref token) = config.auth_token {
+        client = client.with_token(token.clone());
+    }
+
+    write(&socket, Health::Starting)?;
+    while client.handshake().await.is_err() {
+        tokio::time::sleep(AGENT_POLL).await;
+    }
+
+    let mut check = ExecStart::new(program);
+    check.args = args.to_vec();
+    check.timeout_ms = u64::try_from(interval.as_millis()).unwrap_or(u64::MAX);
+    let mut tracker = Tracker::new(config.health_retries.unwrap_or(DEFAULT_RETRIES));
+    loop {
+        let passed = client
+            .exec_output(check.clone())
+            .await
+            .is_ok_and(|out| out.code == 0);
+        if let Some(health) = tracker.record(passed) {
+            write(&socket, health)?;
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Turns check outcomes into a [`Health`], the way Docker does.
+#[derive(Debug)]
+struct Tracker {
+    /// Current health.
+    health: Health,
+    /// Failed checks since the last one that passed.
+    failures: u32,
+    /// Failures in a row that make the VM unhealthy.
+    retries: u32,
+}
+
+impl Tracker {
+    /// Starts out [`Health::Starting`].
+    const fn new(retries: u32) -> Self {
+        Self {
+            health: Health::Starting,
+            failures: 0,
+            retries,
+        }
+    }
+
+    /// Records one check; returns the new health if it changed.
+    fn record(&mut self, passed: bool) -> Option<Health> {
+        let next = if passed {
+            self.failures = 0;
+            Health::Healthy
+        } else {
+            self.failures = self.failures.saturating_add(1);
+            if self.failures >= self.retries {
+                Health::Unhealthy
+            } else {
+                self.health
+            }
+        };
+        (next != self.health).then(|| {
+            self.health = next;
+            next
+        })
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_move_health_like_docker() {
+        let mut tracker = Tracker::new(2);
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.record(true), Some(Health::Healthy));
+        assert_eq!(tracker.record(true), None);
+        // One failure is forgiven; the second in a row is not.
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.record(false), Some(Health::Unhealthy));
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.record(true), Some(Health::Healthy));
+
+        let dir = std::env::temp_dir().join(format!("bux_health_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("abc.sock");
+        assert_eq!(read(&socket), None);
+        write(&socket, Health::Unhealthy).unwrap();
+        assert_eq!(read(&socket), Some(Health::Unhealthy));
+        assert_eq!(path(&socket), dir.join("abc.health"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
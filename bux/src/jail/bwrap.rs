@@ -62,6 +62,12 @@ fn wrap(&self, shim: &Path, config_path: &Path, jail: &JailConfig) -> Option<Com
             cmd.args(["--bind", &s, &s]);
         }
 
+        // Read-only virtiofs host paths.
+        for path in &jail.virtiofs_ro_paths {
+            let s = path.to_string_lossy();
+            cmd.args(["--ro-bind", &s, &s]);
+        }
+
         // Config file (read-only).
         let cfg = config_path.to_string_lossy();
         cmd.args(["--ro-bind", &cfg, &cfg]);
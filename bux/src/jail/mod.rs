@@ -81,6 +81,8 @@ pub struct JailConfig {
     pub socks_dir: PathBuf,
     /// Host paths for virtiofs mounts.
     pub virtiofs_paths: Vec<PathBuf>,
+    /// Host paths for read-only virtiofs mounts.
+    pub virtiofs_ro_paths: Vec<PathBuf>,
     /// Watchdog pipe read-end FD to preserve across exec.
     pub watchdog_fd: Option<RawFd>,
     /// Override the default platform sandbox.
@@ -122,7 +124,8 @@ pub fn spawn(
         cmd.env(crate::watchdog::ENV_WATCHDOG_FD, fd.to_string());
     }
 
-    pre_exec::apply(&mut cmd, config.watchdog_fd);
+    let preserve: Vec<RawFd> = config.watchdog_fd.into_iter().collect();
+    pre_exec::apply(&mut cmd, &preserve);
     let child = cmd.spawn()?;
 
     // Apply cgroup v2 resource limits (Linux only).
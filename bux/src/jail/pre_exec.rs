@@ -4,7 +4,10 @@
 //! 1. **Die with parent** — `PR_SET_PDEATHSIG(SIGKILL)` prevents orphaned VMs
 //!    (Linux only; on macOS the watchdog pipe provides equivalent detection).
 //! 2. **FD cleanup** — close all inherited file descriptors ≥ 3, except for
-//!    an optionally preserved FD (used by the watchdog pipe).
+//!    an explicit set of preserved FDs (e.g. the watchdog pipe).
+//! 3. **Own process group** — signals typed at the terminal (Ctrl-C,
+//!    Ctrl-\\, Ctrl-Z) reach only the parent, which decides whether to
+//!    forward them to the guest, instead of also hitting the VM process.
 
 #![allow(unsafe_code)] // pre_exec requires unsafe
 
@@ -12,18 +15,23 @@
 
 /// Install pre-exec hooks on the command.
 ///
-/// `preserve_fd` — an FD that must survive into the exec'd process (e.g.
-/// the watchdog pipe read end). Pass `None` to close everything.
+/// `preserve` — FDs that must survive into the exec'd process (e.g. the
+/// watchdog pipe read end). Pass an empty slice to close everything.
 ///
 /// On non-Unix platforms this is a no-op.
 #[cfg(not(unix))]
-pub fn apply(_cmd: &mut Command, _preserve_fd: Option<i32>) {}
+pub fn apply(_cmd: &mut Command, _preserve: &[i32]) {}
 
 /// Install pre-exec hooks on the command.
 #[cfg(unix)]
-pub fn apply(cmd: &mut Command, preserve_fd: Option<i32>) {
+pub fn apply(cmd: &mut Command, preserve: &[i32]) {
     use std::os::unix::process::CommandExt;
 
+    // Sort and dedup before fork: the hook itself must not allocate.
+    let mut keep: Vec<i32> = preserve.iter().copied().filter(|&fd| fd >= 3).collect();
+    keep.sort_unstable();
+    keep.dedup();
+
     // SAFETY: all operations inside are async-signal-safe syscalls.
     // pre_exec is inherently unsafe — it runs between fork and exec.
     unsafe {
@@ -32,15 +40,18 @@ pub fn apply(cmd: &mut Command, preserve_fd: Option<i32>) {
             #[cfg(target_os = "linux")]
             libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
 
-            // 2. Close all inherited file descriptors >= 3, except preserve_fd.
-            close_inherited_fds(preserve_fd);
+            // 2. Close all inherited file descriptors >= 3, except `keep`.
+            close_inherited_fds(&keep);
+
+            // 3. Leave the terminal's foreground process group.
+            libc::setpgid(0, 0);
 
             Ok(())
         });
     }
 }
 
-/// Close all file descriptors >= 3, optionally preserving one.
+/// Close all file descriptors >= 3 except the sorted, deduplicated `keep`.
 ///
 /// # Note
 ///
@@ -48,10 +59,11 @@ pub fn apply(cmd: &mut Command, preserve_fd: Option<i32>) {
 /// async-signal-safe functions may be called. Raw libc is intentional
 /// here — nix wrappers allocate and are not async-signal-safe.
 #[cfg(unix)]
-fn close_inherited_fds(preserve: Option<i32>) {
-    match preserve {
-        Some(keep) => close_fds_preserving(keep),
-        None => close_all_fds(),
+fn close_inherited_fds(keep: &[i32]) {
+    if keep.is_empty() {
+        close_all_fds();
+    } else {
+        close_fds_preserving(keep);
     }
 }
 
@@ -66,37 +78,101 @@ fn close_all_fds() {
             return;
         }
     }
+    #[cfg(target_os = "macos")]
+    {
+        if close_listed_fds(&[]) {
+            return;
+        }
+    }
     close_fd_range(3, max_fd());
 }
 
-/// Close all FDs >= 3 except `keep`.
+/// Close all FDs >= 3 except those in the sorted `keep`.
 ///
-/// On Linux 5.9+ uses two `close_range` calls to skip the preserved FD.
-/// Falls back to an iterative loop otherwise.
+/// On Linux 5.9+ issues one `close_range` per gap between preserved FDs; on
+/// macOS closes only the FDs that are actually open. Falls back to an
+/// iterative loop otherwise.
 #[cfg(unix)]
-fn close_fds_preserving(keep: i32) {
+fn close_fds_preserving(keep: &[i32]) {
     #[cfg(target_os = "linux")]
     {
-        #[allow(clippy::cast_sign_loss)]
-        let keep_u = keep as u32;
-        unsafe {
-            if keep > 3 {
-                libc::syscall(libc::SYS_close_range, 3_u32, keep_u - 1, 0_u32);
-            }
-            libc::syscall(libc::SYS_close_range, keep_u + 1, u32::MAX, 0_u32);
+        if close_gaps(keep) {
+            return;
         }
-        return;
     }
 
-    #[allow(unreachable_code)]
+    #[cfg(target_os = "macos")]
     {
-        let end = max_fd();
-        for fd in 3..end {
-            if fd != keep {
-                unsafe { libc::close(fd) };
-            }
+        if close_listed_fds(keep) {
+            return;
+        }
+    }
+
+    for fd in 3..max_fd() {
+        if keep.binary_search(&fd).is_err() {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+/// Close every gap between the sorted `keep` FDs with `close_range`.
+///
+/// Returns `false` if `close_range` fails (e.g. pre-5.9 kernels) so the
+/// caller can fall back to the loop.
+#[cfg(target_os = "linux")]
+fn close_gaps(keep: &[i32]) -> bool {
+    let close_range = |first: u32, last: u32| unsafe {
+        libc::syscall(libc::SYS_close_range, first, last, 0_u32) == 0
+    };
+    let mut next = 3_u32;
+    for &fd in keep {
+        #[allow(clippy::cast_sign_loss)]
+        let keep_u = fd as u32;
+        if keep_u > next && !close_range(next, keep_u - 1) {
+            return false;
+        }
+        next = keep_u + 1;
+    }
+    close_range(next, u32::MAX)
+}
+
+/// Close the open FDs >= 3 not in `keep` as listed by `proc_pidinfo`.
+///
+/// `_SC_OPEN_MAX` is often huge on macOS, so enumerating the open FDs
+/// avoids hundreds of thousands of `close()` calls per spawn. The listing
+/// goes into a fixed stack buffer (no allocation after fork); returns
+/// `false` if it fails or may have been truncated, so the caller can fall
+/// back to the range loop.
+#[cfg(target_os = "macos")]
+fn close_listed_fds(keep: &[i32]) -> bool {
+    const CAPACITY: usize = 1024;
+    let mut fds = [libc::proc_fdinfo {
+        proc_fd: 0,
+        proc_fdtype: 0,
+    }; CAPACITY];
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let size = std::mem::size_of_val(&fds) as libc::c_int;
+    let n = unsafe {
+        libc::proc_pidinfo(
+            libc::getpid(),
+            libc::PROC_PIDLISTFDS,
+            0,
+            fds.as_mut_ptr().cast(),
+            size,
+        )
+    };
+    // A full buffer may mean the list was cut short.
+    if n <= 0 || n >= size {
+        return false;
+    }
+    #[allow(clippy::cast_sign_loss)]
+    let count = n as usize / std::mem::size_of::<libc::proc_fdinfo>();
+    for info in &fds[..count] {
+        if info.proc_fd >= 3 && keep.binary_search(&info.proc_fd).is_err() {
+            unsafe { libc::close(info.proc_fd) };
         }
     }
+    true
 }
 
 /// Upper bound on FD numbers from `sysconf(_SC_OPEN_MAX)`.
@@ -114,3 +190,84 @@ fn close_fd_range(start: i32, end: i32) {
         unsafe { libc::close(fd) };
     }
 }
+
+#[cfg(all(test, unix))]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    /// Opens a pipe without `O_CLOEXEC`, so both ends would be inherited.
+    fn inheritable_pipe() -> [i32; 2] {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        fds
+    }
+
+    #[test]
+    fn only_preserved_fds_survive() {
+        let pipes: Vec<[i32; 2]> = (0..3).map(|_| inheritable_pipe()).collect();
+        let all: Vec<i32> = pipes.iter().flatten().copied().collect();
+        let keep = [pipes[0][1], pipes[2][0], pipes[0][1]];
+
+        let probe: Vec<String> = all
+            .iter()
+            .map(|fd| format!("[ -e /dev/fd/{fd} ] && echo {fd};"))
+            .collect();
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c").arg(format!("{} true", probe.join(" ")));
+        apply(&mut cmd, &keep);
+        let out = cmd.output().unwrap();
+
+        let mut survived: Vec<i32> = String::from_utf8(out.stdout)
+            .unwrap()
+            .lines()
+            .map(|l| l.parse().unwrap())
+            .collect();
+        survived.sort_unstable();
+        let mut expected = vec![pipes[0][1], pipes[2][0]];
+        expected.sort_unstable();
+        assert_eq!(survived, expected);
+
+        for fd in all {
+            unsafe { libc::close(fd) };
+        }
+    }
+
+    #[test]
+    #[ignore = "benchmark; run with --ignored --nocapture"]
+    #[allow(clippy::print_stderr)]
+    fn bench_spawn_fd_cleanup() {
+        use std::os::unix::process::CommandExt;
+        use std::time::Instant;
+
+        const RUNS: u32 = 200;
+        /// Spawns `true` `RUNS` times with `hook` and returns the mean time.
+        fn time(hook: impl Fn(&mut Command)) -> std::time::Duration {
+            let started = Instant::now();
+            for _ in 0..RUNS {
+                let mut cmd = Command::new("true");
+                hook(&mut cmd);
+                assert!(cmd.status().unwrap().success());
+            }
+            started.elapsed() / RUNS
+        }
+
+        // A handful of inherited FDs, as a spawning runtime would have.
+        let pipes: Vec<[i32; 2]> = (0..16).map(|_| inheritable_pipe()).collect();
+        let native = time(|cmd| apply(cmd, &[]));
+        let looped = time(|cmd| unsafe {
+            cmd.pre_exec(|| {
+                close_fd_range(3, max_fd());
+                Ok(())
+            });
+        });
+        eprintln!(
+            "spawn with FD cleanup (_SC_OPEN_MAX = {}): {native:?} with this platform's \
+             strategy, {looped:?} closing every FD number",
+            max_fd()
+        );
+        for fd in pipes.iter().flatten() {
+            unsafe { libc::close(*fd) };
+        }
+    }
+}
@@ -80,6 +80,11 @@ fn generate_profile(shim: &Path, config_path: &Path, config: &JailConfig) -> Str
         allow_readwrite(&mut p, &path.to_string_lossy());
     }
 
+    // Read-only virtiofs paths.
+    for path in &config.virtiofs_ro_paths {
+        allow_read(&mut p, &path.to_string_lossy());
+    }
+
     // Allow Hypervisor.framework (macOS KVM equivalent).
     p.push_str("(allow hv-all)\n");
 
@@ -28,7 +28,12 @@
 mod disk;
 mod error;
 #[cfg(unix)]
+pub mod exit;
+#[cfg(unix)]
+pub mod health;
+#[cfg(unix)]
 mod jail;
+pub mod metrics;
 #[cfg(unix)]
 mod runtime;
 mod state;
@@ -37,9 +42,11 @@
 #[cfg(unix)]
 pub mod watchdog;
 
-pub use bux_proto::ExecStart;
 #[cfg(unix)]
-pub use client::{Client, ExecHandle, ExecOutput, PongInfo};
+pub use bux_e2fs::{IgnoreRules, image_size_for};
+pub use bux_proto::{AgentInfo, Change, ChangeKind, Codec, ExecStart, feature};
+#[cfg(unix)]
+pub use client::{Client, ExecHandle, ExecOutput, FileStat, PongInfo};
 #[cfg(unix)]
 pub use disk::{Disk, DiskManager};
 pub use disk::{DiskFormat, QcowHeader};
@@ -47,9 +54,9 @@
 #[cfg(unix)]
 pub use jail::{JailConfig, NoopSandbox, ResourceLimits, Sandbox};
 #[cfg(unix)]
-pub use runtime::{Runtime, VmHandle};
+pub use runtime::{Reclaimed, RunOptions, RunOutcome, Runtime, StopOutcome, VmHandle};
 #[cfg(unix)]
 pub use state::StateDb;
-pub use state::{Status, VirtioFs, VmConfig, VmState, VsockPort};
+pub use state::{DataDisk, Health, ImageRef, Pid1, Status, VirtioFs, VmConfig, VmState, VsockPort};
 pub use sys::{Feature, KernelFormat, LogStyle, SyncMode};
-pub use vm::{LogLevel, Vm, VmBuilder};
+pub use vm::{Capabilities, LogLevel, Tee, TeeConfig, Vm, VmBuilder};
@@ -0,0 +1,114 @@
+//! Runtime metrics, recorded through the [`metrics`](https://docs.rs/metrics)
+//! facade when the `metrics` feature is enabled.
+//!
+//! Nothing is kept unless the embedder installs a recorder, e.g.
+//! [`Prometheus::install`]. Pull metrics come from `bux-oci`'s own
+//! `metrics` feature and land in the same recorder.
+//!
+//! ```no_run
+//! # #[cfg(feature = "metrics")]
+//! # fn serve() -> bux::Result<()> {
+//! let prometheus = bux::metrics::Prometheus::install()?;
+//! // In the embedder's `GET /metrics` handler:
+//! let body = prometheus.render();
+//! # Ok(())
+//! # }
+//! ```
+
+// Empty without the feature, which is no reason to make them `const`.
+#![cfg_attr(not(feature = "metrics"), allow(clippy::missing_const_for_fn))]
+
+/// Counter: VMs spawned by [`Runtime::spawn`](crate::Runtime::spawn).
+pub const VMS_SPAWNED: &str = "bux_vms_spawned_total";
+/// Gauge: active VMs, as last seen by [`Runtime::list`](crate::Runtime::list)
+/// and counting spawns since.
+pub const VMS_RUNNING: &str = "bux_vms_running";
+/// Counter: commands started in guests.
+pub const EXECS: &str = "bux_execs_total";
+
+/// Records a spawned VM.
+pub(crate) fn spawned() {
+    #[cfg(feature = "metrics")]
+    {
+        ::metrics::counter!(VMS_SPAWNED).increment(1);
+        ::metrics::gauge!(VMS_RUNNING).increment(1.0);
+    }
+}
+
+/// Records the number of active VMs.
+pub(crate) fn running(count: usize) {
+    #[cfg(feature = "metrics")]
+    #[allow(clippy::cast_precision_loss)]
+    ::metrics::gauge!(VMS_RUNNING).set(count as f64);
+    #[cfg(not(feature = "metrics"))]
+    let _ = count;
+}
+
+/// Records a started command.
+pub(crate) fn exec_started() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(EXECS).increment(1);
+}
+
+/// Collects metrics for scraping in the Prometheus text format.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone)]
+pub struct Prometheus {
+    /// Handle on the installed recorder.
+    handle: metrics_exporter_prometheus::PrometheusHandle,
+}
+
+#[cfg(feature = "metrics")]
+impl Prometheus {
+    /// Installs a Prometheus recorder as the process-wide `metrics`
+    /// recorder. Fails if one is already installed.
+    pub fn install() -> crate::Result<Self> {
+        let handle = metrics_exporter_prometheus::PrometheusBuilder::new()
+            .install_recorder()
+            .map_err(std::io::Error::other)?;
+        describe();
+        Ok(Self { handle })
+    }
+
+    /// Renders every metric recorded so far, ready to serve as the body of
+    /// a `/metrics` response (`text/plain; version=0.0.4`).
+    pub fn render(&self) -> String {
+        // Histograms are only folded in on upkeep.
+        self.handle.run_upkeep();
+        self.handle.render()
+    }
+}
+
+/// Registers help text for the metrics recorded here.
+#[cfg(feature = "metrics")]
+fn describe() {
+    ::metrics::describe_counter!(VMS_SPAWNED, "VMs spawned");
+    ::metrics::describe_gauge!(VMS_RUNNING, "Active VMs");
+    ::metrics::describe_counter!(EXECS, "Commands started in guests");
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_metrics_render_as_prometheus_text() {
+        let recorder = metrics_exporter_prometheus::PrometheusBuilder::new().build_recorder();
+        let prometheus = Prometheus {
+            handle: recorder.handle(),
+        };
+        ::metrics::with_local_recorder(&recorder, || {
+            describe();
+            spawned();
+            spawned();
+            running(1);
+            exec_started();
+        });
+
+        let text = prometheus.render();
+        assert!(text.contains("# HELP bux_vms_spawned_total VMs spawned"));
+        assert!(text.contains("bux_vms_spawned_total 2"));
+        assert!(text.contains("bux_vms_running 1"));
+        assert!(text.contains("bux_execs_total 1"));
+    }
+}
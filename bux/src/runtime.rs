@@ -8,6 +8,7 @@
 //!
 //! This module is only available on Unix (Linux / macOS).
 
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
@@ -20,11 +21,13 @@
 use nix::unistd::Pid;
 
 use crate::Result;
-use crate::client::{Client, ExecHandle, ExecOutput};
+use crate::client::{Client, ExecHandle, ExecOutput, FileStat};
 use crate::disk::DiskManager;
+use crate::exit;
+use crate::health;
 use crate::jail::{self, JailConfig};
-use crate::state::{self, StateDb, Status, VmState, VsockPort};
-use crate::vm::VmBuilder;
+use crate::state::{self, Health, ImageRef, StateDb, Status, VmState, VsockPort};
+use crate::vm::{Vm, VmBuilder};
 use crate::watchdog::{self, Keepalive};
 
 /// Manages the lifecycle of bux micro-VMs.
@@ -52,6 +55,9 @@ impl Runtime {
     /// Acquires an exclusive file lock (`bux.lock`) to prevent multiple
     /// processes from corrupting state. Returns an error if another
     /// `Runtime` already holds the lock.
+    ///
+    /// VMs left behind by an earlier runtime are [reconciled](Self::reconcile)
+    /// with the host before this returns.
     pub fn open(data_dir: impl AsRef<Path>) -> Result<Self> {
         let base = data_dir.as_ref();
         fs::create_dir_all(base)?;
@@ -66,21 +72,50 @@ pub fn open(data_dir: impl AsRef<Path>) -> Result<Self> {
                 )
             })?;
 
+        // Spawn configs and the state database hold agent auth tokens.
         let socks_dir = base.join("socks");
         fs::create_dir_all(&socks_dir)?;
+        fs::set_permissions(&socks_dir, fs::Permissions::from_mode(0o700))?;
 
         let db_path = base.join("bux.db");
+        private_file(&db_path)?;
         let db = StateDb::open(db_path)?;
         let disk = DiskManager::open(base)?;
 
         #[allow(clippy::arc_with_non_send_sync)]
         // StateDb uses rusqlite::Connection (not Sync), but Arc is needed for VmHandle sharing within a single-threaded tokio runtime.
-        Ok(Self {
+        let rt = Self {
             db: Arc::new(db),
             socks_dir,
             disk,
             _lock: lock,
-        })
+        };
+        rt.reconcile()?;
+        Ok(rt)
+    }
+
+    /// Checks every VM the database records as active against the host and
+    /// returns handles for the ones still running.
+    ///
+    /// Meant for a runtime restarting after a crash: shims outlive the
+    /// process that spawned them. A VM is live while its process exists
+    /// with the start time recorded at spawn, which rules out a PID reused
+    /// by an unrelated process; a slow or busy agent does not make it dead
+    /// (see [`VmHandle::agent_responsive`] for that). The rest are marked
+    /// [`Status::Stopped`], and auto-removed ones deleted.
+    pub fn reconcile(&self) -> Result<Vec<VmHandle>> {
+        let mut live = Vec::new();
+        for state in self.list()? {
+            if state.status.is_active() {
+                live.push(VmHandle::new(
+                    state,
+                    Arc::clone(&self.db),
+                    self.disk.clone(),
+                    None,
+                ));
+            }
+        }
+        Ok(live)
     }
 
     /// Returns a reference to the disk image manager.
@@ -93,10 +128,13 @@ pub const fn disk(&self) -> &DiskManager {
     /// The VM configuration is serialized to a temp JSON file, then
     /// `bux-shim` is spawned as a subprocess that reads the config and
     /// calls `krun_start_enter()` to become the VM.
+    ///
+    /// `image` is recorded in the VM state together with the manifest digest
+    /// it resolved to, so the VM stays traceable after the tag moves.
     pub async fn spawn(
         &self,
         builder: VmBuilder,
-        image: Option<String>,
+        image: Option<ImageRef>,
         name: Option<String>,
         auto_remove: bool,
     ) -> Result<VmHandle> {
@@ -108,6 +146,13 @@ pub async fn spawn(
                 "a VM named '{n}' already exists"
             )));
         }
+        builder.check_virtiofs()?;
+        builder.check_kernel()?;
+        builder.check_auth_token()?;
+        builder.check_agent()?;
+        // The shim would only report a missing libkrun through its exit code.
+        Vm::check_libkrun()?;
+        builder.check_tee()?;
 
         let id = state::gen_id();
         let socket = self.socks_dir.join(format!("{id}.sock"));
@@ -116,11 +161,13 @@ pub async fn spawn(
         // Build the full config including the internal agent vsock port.
         let mut config = builder.to_config();
         config.auto_remove = auto_remove;
-        config.vsock_ports.push(VsockPort {
-            port: AGENT_PORT,
-            path: socket_str,
-            listen: true,
-        });
+        if !config.no_agent {
+            config.vsock_ports.push(VsockPort {
+                port: AGENT_PORT,
+                path: socket_str,
+                listen: true,
+            });
+        }
 
         // If a base disk is specified, create a per-VM QCOW2 overlay.
         if let Some(ref base) = config.base_disk {
@@ -134,9 +181,15 @@ pub async fn spawn(
             config.base_disk = None; // consumed — shim doesn't need this
         }
 
+        if config.console_log {
+            let log = self.socks_dir.join(format!("{id}.log"));
+            config.console_output = Some(log.to_string_lossy().into_owned());
+        }
+
         // Write config to a temp file for the shim to read.
         let config_path = self.socks_dir.join(format!("{id}.json"));
         let json = serde_json::to_string(&config)?;
+        private_file(&config_path)?;
         fs::write(&config_path, &json)?;
 
         // Create watchdog pipe — parent holds write end (Keepalive),
@@ -149,10 +202,34 @@ pub async fn spawn(
             rootfs: config.rootfs.as_deref().map(PathBuf::from),
             root_disk: config.root_disk.as_deref().map(PathBuf::from),
             socks_dir: self.socks_dir.clone(),
+            // Data disks are bound like shares of the same mode.
             virtiofs_paths: config
                 .virtiofs
                 .iter()
+                .filter(|v| !v.read_only)
                 .map(|v| PathBuf::from(&v.path))
+                .chain(
+                    config
+                        .data_disks
+                        .iter()
+                        .filter(|d| !d.read_only)
+                        .map(|d| PathBuf::from(&d.path)),
+                )
+                .collect(),
+            // The external kernel image only needs reading, like a ro share.
+            virtiofs_ro_paths: config
+                .virtiofs
+                .iter()
+                .filter(|v| v.read_only)
+                .map(|v| PathBuf::from(&v.path))
+                .chain(
+                    config
+                        .data_disks
+                        .iter()
+                        .filter(|d| d.read_only)
+                        .map(|d| PathBuf::from(&d.path)),
+                )
+                .chain(config.kernel.as_deref().map(PathBuf::from))
                 .collect(),
             watchdog_fd: Some(std::os::unix::io::AsRawFd::as_raw_fd(&shim_wd_fd)),
             sandbox: None,         // use auto-detected platform sandbox
@@ -170,31 +247,84 @@ pub async fn spawn(
             id,
             name,
             pid: child_pid,
-            image,
+            pid_start: process_start(child_pid),
+            image_digest: image.as_ref().and_then(|i| i.digest.clone()),
+            image: image.map(|i| i.reference),
             socket,
             status: Status::Running,
             config,
             created_at: SystemTime::now(),
+            exit_code: None,
+            health: None,
         };
         self.db.insert(&vm_state)?;
+        crate::metrics::spawned();
 
         // Drop the shim's read end in the parent — the child already
         // inherited it before exec.
         drop(shim_wd_fd);
 
-        let handle = VmHandle::new(
+        let mut handle = VmHandle::new(
             vm_state,
             Arc::clone(&self.db),
             self.disk.clone(),
             Some(keepalive),
         );
+        handle.state.health = current_health(&handle.state);
 
         // Best-effort readiness wait.
-        let _ = handle.wait_ready(Duration::from_secs(5)).await;
+        if !handle.state.config.no_agent {
+            let _ = handle.wait_ready(Duration::from_secs(5)).await;
+        }
 
         Ok(handle)
     }
 
+    /// Spawns a VM, runs `options.command` in it, collects the output and
+    /// stops the VM again: the one-shot "run this in a VM and give me the
+    /// result" path.
+    ///
+    /// The VM is stopped even when the agent never comes up or the command
+    /// fails to start, and removed with [`RunOptions::auto_remove`]. Output
+    /// is buffered in memory, as with [`VmHandle::exec_output`].
+    pub async fn run_and_wait(
+        &self,
+        builder: VmBuilder,
+        options: RunOptions,
+    ) -> Result<RunOutcome> {
+        let RunOptions {
+            command,
+            image,
+            name,
+            ready_timeout,
+            stop_timeout,
+            auto_remove,
+        } = options;
+        if !builder.runs_agent() {
+            return Err(crate::Error::InvalidConfig(
+                "run_and_wait runs its command through the guest agent".to_owned(),
+            ));
+        }
+        let mut handle = self.spawn(builder, image, name, auto_remove).await?;
+
+        let ran: Result<ExecOutput> = async {
+            if let Some(timeout) = ready_timeout {
+                handle.wait_ready(timeout).await?;
+            }
+            handle.exec_output(command).await
+        }
+        .await;
+        let stopped = handle.stop_timeout(stop_timeout).await;
+        let output = ran?;
+        stopped?;
+
+        Ok(RunOutcome {
+            exit_code: output.code,
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
     /// Lists all known VMs, reconciling liveness and auto-removing stopped VMs.
     pub fn list(&self) -> Result<Vec<VmState>> {
         let vms = self.db.list()?;
@@ -202,23 +332,39 @@ pub fn list(&self) -> Result<Vec<VmState>> {
 
         for mut vm in vms {
             // Reconcile: mark dead processes as stopped.
-            if vm.status.is_active() && !is_pid_alive(vm.pid) {
+            if vm.status.is_active() && !is_vm_alive(&vm) {
                 vm.status = Status::Stopped;
                 let _ = self.db.update_status(&vm.id, Status::Stopped);
+                self.record_exit_code(&mut vm);
             }
 
-            // Auto-remove stopped VMs with auto_remove flag.
-            if vm.status == Status::Stopped && vm.config.auto_remove {
-                let _ = fs::remove_file(&vm.socket);
-                let _ = self.db.delete(&vm.id);
+            // Auto-remove stopped VMs with auto_remove flag, once their
+            // process is gone (a kill may not have landed yet).
+            if vm.status == Status::Stopped && vm.config.auto_remove && !is_vm_alive(&vm) {
+                if reclaim_files(&vm, &self.disk).is_ok() {
+                    let _ = self.db.delete(&vm.id);
+                }
                 continue;
             }
 
+            vm.health = current_health(&vm);
             keep.push(vm);
         }
+        crate::metrics::running(keep.iter().filter(|vm| vm.status.is_active()).count());
         Ok(keep)
     }
 
+    /// Fills in the exit code the shim recorded for a VM found dead, unless
+    /// one is already known.
+    fn record_exit_code(&self, state: &mut VmState) {
+        if state.exit_code.is_none() {
+            state.exit_code = exit::read(&state.socket);
+            if state.exit_code.is_some() {
+                let _ = self.db.update_exit_code(&state.id, state.exit_code);
+            }
+        }
+    }
+
     /// Retrieves a handle by name or ID prefix.
     pub fn get(&self, id_or_name: &str) -> Result<VmHandle> {
         // Try name lookup first (O(1) via UNIQUE index).
@@ -229,10 +375,12 @@ pub fn get(&self, id_or_name: &str) -> Result<VmHandle> {
         };
 
         // Reconcile liveness.
-        if state.status.is_active() && !is_pid_alive(state.pid) {
+        if state.status.is_active() && !is_vm_alive(&state) {
             state.status = Status::Stopped;
             let _ = self.db.update_status(&state.id, Status::Stopped);
+            self.record_exit_code(&mut state);
         }
+        state.health = current_health(&state);
 
         Ok(VmHandle::new(
             state,
@@ -256,25 +404,158 @@ pub fn rename(&self, id_or_name: &str, new_name: &str) -> Result<()> {
         Ok(())
     }
 
-    /// Removes a stopped VM's state and socket.
-    pub fn remove(&self, id_or_name: &str) -> Result<()> {
-        let handle = self.get(id_or_name)?;
+    /// Removes a stopped VM and everything it left on disk: disk overlay,
+    /// console log (if the runtime created it, see
+    /// [`VmBuilder::console_log`]), agent socket, spawn config, health and
+    /// exit files, and finally the state row.
+    ///
+    /// Running VMs are refused unless `force` is set, in which case they are
+    /// killed first. With `volumes`, the VM's named data disks
+    /// ([`DiskManager::volume`]) go too, except those another VM on record
+    /// still uses. Files that are already gone are skipped; any other
+    /// failure keeps the state row so the removal can be retried.
+    pub fn remove(&self, id_or_name: &str, force: bool, volumes: bool) -> Result<Reclaimed> {
+        let mut handle = self.get(id_or_name)?;
+
+        if !handle.state().status.can_remove() {
+            if !force {
+                return Err(crate::Error::InvalidState(format!(
+                    "VM {} cannot be removed (status: {:?}); stop it first or force removal",
+                    handle.state().id,
+                    handle.state().status
+                )));
+            }
+            let _ = signal::kill(Pid::from_raw(handle.state().pid), Signal::SIGKILL);
+            wait_for_exit(handle.state().pid);
+            handle.state.status = Status::Stopped;
+        }
+
         let state = handle.state();
+        let mut reclaimed = reclaim_files(state, &self.disk)?;
+        if volumes {
+            let others = self.db.list()?;
+            let shared = |path: &Path| {
+                let mut disks = others
+                    .iter()
+                    .filter(|vm| vm.id != state.id)
+                    .flat_map(|vm| &vm.config.data_disks);
+                disks.any(|d| Path::new(&d.path) == path)
+            };
+            let unused = state
+                .config
+                .data_disks
+                .iter()
+                .map(|d| PathBuf::from(&d.path))
+                .filter(|path| self.disk.is_volume(path) && !shared(path));
+            remove_files(unused, &mut reclaimed)?;
+        }
+        self.db.delete(&state.id)?;
+        Ok(reclaimed)
+    }
+}
 
-        if !state.status.can_remove() {
-            return Err(crate::Error::InvalidState(format!(
-                "VM {} cannot be removed (status: {:?}); stop it first",
-                state.id, state.status
-            )));
+/// Options for [`Runtime::run_and_wait`].
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Command to run once the guest agent is up.
+    pub command: ExecStart,
+    /// Image recorded in the VM state (see [`Runtime::spawn`]).
+    pub image: Option<ImageRef>,
+    /// VM name; `None` leaves it unnamed.
+    pub name: Option<String>,
+    /// How long to wait for the guest agent before running the command.
+    /// `None` runs it as soon as [`Runtime::spawn`] returns. Defaults to 30 s.
+    pub ready_timeout: Option<Duration>,
+    /// Grace period for the shutdown after the command exits, before the
+    /// VM is killed. Defaults to 10 s.
+    pub stop_timeout: Duration,
+    /// Delete the VM and its files once stopped. Defaults to `true`.
+    pub auto_remove: bool,
+}
+
+impl RunOptions {
+    /// Runs `command` with the defaults above.
+    #[must_use]
+    pub const fn new(command: ExecStart) -> Self {
+        Self {
+            command,
+            image: None,
+            name: None,
+            ready_timeout: Some(Duration::from_secs(30)),
+            stop_timeout: Duration::from_secs(10),
+            auto_remove: true,
         }
+    }
 
-        let _ = fs::remove_file(&state.socket);
-        let _ = self.disk.remove_vm_disk(&state.id);
-        self.db.delete(&state.id)?;
-        Ok(())
+    /// Records the image the VM was created from.
+    #[must_use]
+    pub fn image(mut self, image: ImageRef) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    /// Names the VM.
+    #[must_use]
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets how long to wait for the guest agent (`None` = don't wait).
+    #[must_use]
+    pub const fn ready_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.ready_timeout = timeout;
+        self
+    }
+
+    /// Sets the shutdown grace period.
+    #[must_use]
+    pub const fn stop_timeout(mut self, timeout: Duration) -> Self {
+        self.stop_timeout = timeout;
+        self
+    }
+
+    /// Keeps (`false`) or deletes (`true`) the VM once it stops.
+    #[must_use]
+    pub const fn auto_remove(mut self, auto_remove: bool) -> Self {
+        self.auto_remove = auto_remove;
+        self
     }
 }
 
+/// Result of [`Runtime::run_and_wait`].
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct RunOutcome {
+    /// Exit code of the command; `128 + signal` if a signal killed it.
+    pub exit_code: i32,
+    /// Captured stdout bytes.
+    pub stdout: Vec<u8>,
+    /// Captured stderr bytes.
+    pub stderr: Vec<u8>,
+}
+
+/// How [`VmHandle::stop_timeout`] ended a VM.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopOutcome {
+    /// The VM exited on its own within the grace period.
+    Graceful,
+    /// The grace period ran out and the VM was sent `SIGKILL`.
+    Killed,
+}
+
+/// Resources freed by [`Runtime::remove`].
+#[non_exhaustive]
+#[derive(Debug, Clone, Default)]
+pub struct Reclaimed {
+    /// Files that were deleted.
+    pub files: Vec<PathBuf>,
+    /// Total size of the deleted files in bytes.
+    pub bytes: u64,
+}
+
 /// Handle to a single managed VM.
 #[derive(Debug)]
 pub struct VmHandle {
@@ -288,7 +569,7 @@ pub struct VmHandle {
     client: Client,
     /// Watchdog keepalive — dropping this signals the shim to shut down.
     /// `None` when reconnecting to a VM spawned in a previous session.
-    _keepalive: Option<Keepalive>,
+    keepalive: Option<Keepalive>,
 }
 
 impl VmHandle {
@@ -299,13 +580,16 @@ fn new(
         disk: DiskManager,
         keepalive: Option<Keepalive>,
     ) -> Self {
-        let client = Client::new(&state.socket);
+        let mut client = Client::new(&state.socket);
+        if let Some(ref token) = state.config.auth_token {
+            client = client.with_token(token.clone());
+        }
         Self {
             state,
             db,
             disk,
             client,
-            _keepalive: keepalive,
+            keepalive,
         }
     }
 
@@ -314,29 +598,39 @@ pub const fn state(&self) -> &VmState {
         &self.state
     }
 
-    /// Returns a reference to the stateless client.
-    pub const fn client(&self) -> &Client {
-        &self.client
+    /// Returns the stateless client, or [`Error::NoAgent`](crate::Error::NoAgent)
+    /// if the VM was started without a guest agent.
+    pub fn client(&self) -> Result<&Client> {
+        if self.state.config.no_agent {
+            return Err(crate::Error::NoAgent(self.state.id.clone()));
+        }
+        Ok(&self.client)
     }
 
     /// Starts a command on a dedicated exec connection.
     pub async fn exec(&self, req: ExecStart) -> Result<ExecHandle> {
-        Ok(self.client.exec(req).await?)
+        Ok(self.client()?.exec(req).await?)
     }
 
     /// Executes a command and collects all output.
+    ///
+    /// Output is buffered in memory; see [`Client::exec_output`].
     pub async fn exec_output(&self, req: ExecStart) -> Result<ExecOutput> {
-        Ok(self.client.exec_output(req).await?)
+        Ok(self.client()?.exec_output(req).await?)
     }
 
     /// Graceful shutdown with default 10 s timeout.
-    pub async fn stop(&mut self) -> Result<()> {
+    pub async fn stop(&mut self) -> Result<StopOutcome> {
         self.stop_timeout(Duration::from_secs(10)).await
     }
 
-    /// Graceful shutdown: sends `Shutdown` request, waits up to `timeout`,
-    /// then falls back to `SIGKILL`.
-    pub async fn stop_timeout(&mut self, timeout: Duration) -> Result<()> {
+    /// Graceful shutdown: sends `Shutdown` request (`SIGTERM` to the VM
+    /// process for VMs without an agent), waits up to `timeout`, then falls
+    /// back to `SIGKILL`. Reports which of the two ended the VM.
+    ///
+    /// `timeout` covers reaching the agent too, so an agent that accepts
+    /// the connection but never answers cannot hold up the kill.
+    pub async fn stop_timeout(&mut self, timeout: Duration) -> Result<StopOutcome> {
         if !self.state.status.can_stop() {
             return Err(crate::Error::InvalidState(format!(
                 "VM {} cannot be stopped (status: {:?})",
@@ -348,19 +642,24 @@ pub async fn stop_timeout(&mut self, timeout: Duration) -> Result<()> {
         self.state.status = Status::Stopping;
         self.db.update_status(&self.state.id, Status::Stopping)?;
 
-        let _ = self.client.shutdown().await;
-
         let pid = self.state.pid;
-        let result = tokio::time::timeout(
-            timeout,
-            tokio::task::spawn_blocking(move || wait_for_exit(pid)),
-        )
-        .await;
+        let no_agent = self.state.config.no_agent;
+        let client = &self.client;
+        let graceful = async move {
+            if no_agent {
+                let _ = signal::kill(Pid::from_raw(pid), Signal::SIGTERM);
+            } else {
+                let _ = client.shutdown().await;
+            }
+            tokio::task::spawn_blocking(move || wait_for_exit(pid)).await
+        };
 
-        if result.is_ok() {
-            return self.mark_stopped();
+        if tokio::time::timeout(timeout, graceful).await.is_ok() {
+            self.mark_stopped()?;
+            return Ok(StopOutcome::Graceful);
         }
-        self.kill()
+        self.kill()?;
+        Ok(StopOutcome::Killed)
     }
 
     /// Sends `SIGKILL` to the VM process.
@@ -371,13 +670,113 @@ pub fn kill(&mut self) -> Result<()> {
 
     /// Returns `true` if the VM process is still alive.
     pub fn is_alive(&self) -> bool {
-        is_pid_alive(self.state.pid)
+        is_vm_alive(&self.state)
+    }
+
+    /// Returns `true` if the guest agent answers a ping within a second.
+    ///
+    /// Separate from [`is_alive`](Self::is_alive): a VM whose agent is busy
+    /// or still booting is running all the same. Always `false` for VMs
+    /// without an agent.
+    pub fn agent_responsive(&self) -> bool {
+        !self.state.config.no_agent && agent_answers(&self.state).is_ok()
+    }
+
+    /// Resolves as soon as the VM process exits, with its exit code if it
+    /// is known.
+    ///
+    /// For VMs spawned by this handle the watchdog pipe wakes the future the
+    /// moment the shim exits; handles reattached via [`Runtime::get`] fall
+    /// back to polling and take the code the shim recorded (see
+    /// [`crate::exit`]). The process is not reaped and the stored status is
+    /// not updated — follow with [`wait`](Self::wait) for that.
+    pub async fn liveness(&self) -> Option<i32> {
+        let notified = match self.keepalive {
+            Some(ref keepalive) => watchdog::shim_exited(keepalive).await.is_ok(),
+            None => false,
+        };
+        if !notified {
+            while is_pid_alive(self.state.pid) {
+                tokio::time::sleep(LIVENESS_POLL_INTERVAL).await;
+            }
+        }
+        let pid = self.state.pid;
+        tokio::task::spawn_blocking(move || peek_exit(pid))
+            .await
+            .ok()
+            .flatten()
+            .or_else(|| exit::read(&self.state.socket))
+    }
+
+    /// Reads the VM's current health; `None` if it has no health check or
+    /// is no longer active. [`state`](Self::state) holds the health as of
+    /// the lookup.
+    pub fn health(&self) -> Option<Health> {
+        current_health(&self.state)
+    }
+
+    /// Waits for the VM's health check to pass for the first time, e.g. to
+    /// hand a service VM out only once it serves.
+    ///
+    /// Fails with [`Error::InvalidConfig`](crate::Error::InvalidConfig) if
+    /// the VM has no health check, and with an I/O error as soon as the VM
+    /// turns [`Unhealthy`](Health::Unhealthy) or its process exits, or once
+    /// `timeout` elapses.
+    pub async fn wait_healthy(&self, timeout: Duration) -> Result<()> {
+        if self.state.config.health_cmd.is_none() {
+            return Err(crate::Error::InvalidConfig(format!(
+                "VM {} has no health check",
+                self.state.id
+            )));
+        }
+        let id = &self.state.id;
+        tokio::time::timeout(timeout, async {
+            let checks = async {
+                loop {
+                    match health::read(&self.state.socket) {
+                        Some(Health::Healthy) => return Ok(()),
+                        Some(Health::Unhealthy) => {
+                            return Err(io::Error::other(format!("VM {id} is unhealthy")));
+                        }
+                        _ => tokio::time::sleep(HEALTH_POLL_INTERVAL).await,
+                    }
+                }
+            };
+            let exited = async {
+                let code = self
+                    .liveness()
+                    .await
+                    .map(|c| format!(" with code {c}"))
+                    .unwrap_or_default();
+                Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    format!("VM {id} exited{code} before its health check passed"),
+                ))
+            };
+            tokio::select! {
+                result = checks => result,
+                result = exited => result,
+            }
+        })
+        .await
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("VM {id} did not become healthy"),
+            )
+        })??;
+        Ok(())
     }
 
     /// Pauses the VM by quiescing its filesystems and sending `SIGSTOP`.
     ///
     /// The guest's filesystems are frozen (FIFREEZE) for point-in-time
     /// consistency before the VM process is stopped.
+    ///
+    /// A paused VM lives only in its process: libkrun has no API to save or
+    /// restore vCPU and device state, and guest RAM is not file-backed (see
+    /// [`VmBuilder::ram_mib`](crate::VmBuilder::ram_mib)), so it cannot be
+    /// checkpointed to disk. Copies of a warmed-up VM boot from its disk.
     pub async fn pause(&mut self) -> Result<()> {
         if !self.state.status.can_pause() {
             return Err(crate::Error::InvalidState(format!(
@@ -417,29 +816,78 @@ pub fn signal(&self, sig: i32) -> Result<()> {
         Ok(())
     }
 
-    /// Waits for the VM process to exit.
+    /// Sends a POSIX signal to the guest's primary process through the
+    /// agent; see [`Client::signal_primary`].
+    pub async fn signal_primary(&self, sig: i32) -> Result<()> {
+        Ok(self.client()?.signal_primary(sig).await?)
+    }
+
+    /// Waits for the VM process to exit and returns its exit code.
     ///
     /// Uses `waitpid` for child processes (zero CPU, zero latency).
-    /// Falls back to `kill(pid, 0)` polling for non-child processes.
-    pub async fn wait(&mut self) -> Result<()> {
+    /// Falls back to `kill(pid, 0)` polling for non-child processes, whose
+    /// exit code is read from the file the shim records it in (see
+    /// [`crate::exit`]); `None` if it left none and none was known before.
+    pub async fn wait(&mut self) -> Result<Option<i32>> {
+        // Await exit without tying up a blocking thread; reaping is then
+        // immediate.
+        let seen = self.liveness().await;
         let pid = self.state.pid;
-        let _ = tokio::task::spawn_blocking(move || wait_for_exit(pid)).await;
-        self.mark_stopped()
+        let code = tokio::task::spawn_blocking(move || wait_for_exit(pid))
+            .await
+            .ok()
+            .flatten()
+            .or(seen);
+        if code.is_some() {
+            self.state.exit_code = code;
+            if !self.state.config.auto_remove {
+                self.db.update_exit_code(&self.state.id, code)?;
+            }
+        }
+        self.mark_stopped()?;
+        Ok(self.state.exit_code)
     }
 
     /// Reads a file from the guest filesystem.
     pub async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
-        Ok(self.client.read_file(path).await?)
+        Ok(self.client()?.read_file(path).await?)
     }
 
     /// Writes a file to the guest filesystem.
     pub async fn write_file(&self, path: &str, data: &[u8], mode: u32) -> Result<()> {
-        Ok(self.client.write_file(path, data, mode).await?)
+        Ok(self.client()?.write_file(path, data, mode).await?)
+    }
+
+    /// Overwrites part of a guest file at `offset`; returns bytes written.
+    pub async fn write_at(&self, path: &str, offset: u64, data: &[u8], mode: u32) -> Result<u64> {
+        Ok(self.client()?.write_at(path, offset, data, mode).await?)
+    }
+
+    /// Appends to a guest file; returns bytes written.
+    pub async fn append(&self, path: &str, data: &[u8], mode: u32) -> Result<u64> {
+        Ok(self.client()?.append(path, data, mode).await?)
+    }
+
+    /// Streams `size` bytes from `src` into a guest file, resuming an
+    /// interrupted transfer of the same `source`. Returns the offset it
+    /// resumed from; see [`Client::write_file_from_reader`].
+    pub async fn write_file_from_reader(
+        &self,
+        path: &str,
+        mode: u32,
+        size: u64,
+        source: Option<&str>,
+        src: &mut (impl tokio::io::AsyncRead + tokio::io::AsyncSeek + Unpin),
+    ) -> Result<u64> {
+        Ok(self
+            .client()?
+            .write_file_from_reader(path, mode, size, source, src)
+            .await?)
     }
 
     /// Copies a tar archive into the guest, unpacking at `dest`.
     pub async fn copy_in(&self, dest: &str, tar_data: &[u8]) -> Result<()> {
-        Ok(self.client.copy_in(dest, tar_data).await?)
+        Ok(self.client()?.copy_in(dest, tar_data).await?)
     }
 
     /// Streams a tar archive from `reader` into the guest, unpacking at `dest`.
@@ -450,12 +898,26 @@ pub async fn copy_in_from_reader(
         dest: &str,
         reader: &mut (impl tokio::io::AsyncRead + Unpin),
     ) -> Result<()> {
-        Ok(self.client.copy_in_from_reader(dest, reader).await?)
+        Ok(self.client()?.copy_in_from_reader(dest, reader).await?)
+    }
+
+    /// Like [`copy_in_from_reader`](Self::copy_in_from_reader), keeping the
+    /// uid/gid of archive entries when `same_owner` is set.
+    pub async fn copy_in_from_reader_opts(
+        &self,
+        dest: &str,
+        same_owner: bool,
+        reader: &mut (impl tokio::io::AsyncRead + Unpin),
+    ) -> Result<()> {
+        Ok(self
+            .client()?
+            .copy_in_from_reader_opts(dest, same_owner, reader)
+            .await?)
     }
 
     /// Copies a path from the guest as a tar archive.
     pub async fn copy_out(&self, path: &str) -> Result<Vec<u8>> {
-        Ok(self.client.copy_out(path).await?)
+        Ok(self.client()?.copy_out(path).await?)
     }
 
     /// Streams a path from the guest as a tar archive directly to `writer`.
@@ -468,18 +930,85 @@ pub async fn copy_out_to_writer(
         writer: &mut (impl tokio::io::AsyncWrite + Unpin),
     ) -> Result<u64> {
         Ok(self
-            .client
+            .client()?
             .copy_out_to_writer(path, follow_symlinks, writer)
             .await?)
     }
 
+    /// Uploads several host files to the guest as a single unit.
+    ///
+    /// Each entry is `(host_path, guest_path, mode)`.
+    pub async fn upload(&self, files: &[(PathBuf, String, u32)]) -> Result<()> {
+        Ok(self.client()?.upload(files).await?)
+    }
+
+    /// Returns metadata for a guest path (following symlinks).
+    pub async fn stat(&self, path: &str) -> Result<FileStat> {
+        Ok(self.client()?.stat(path).await?)
+    }
+
+    /// Changes the permission bits of a guest path.
+    pub async fn chmod(&self, path: &str, mode: u32) -> Result<()> {
+        Ok(self.client()?.chmod(path, mode).await?)
+    }
+
+    /// Changes the owner and/or group of a guest path (`None` = unchanged).
+    pub async fn chown(&self, path: &str, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        Ok(self.client()?.chown(path, uid, gid).await?)
+    }
+
+    /// Sets the access and modification times of a guest path, in seconds
+    /// since the Unix epoch.
+    pub async fn utimes(&self, path: &str, atime: i64, mtime: i64) -> Result<()> {
+        Ok(self.client()?.utimes(path, atime, mtime).await?)
+    }
+
+    /// Creates a directory in the guest, optionally with missing parents.
+    pub async fn mkdir(&self, path: &str, mode: u32, parents: bool) -> Result<()> {
+        Ok(self.client()?.mkdir(path, mode, parents).await?)
+    }
+
+    /// Moves a guest path, replacing `to` if it exists.
+    pub async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        Ok(self.client()?.rename(from, to).await?)
+    }
+
+    /// Creates a symlink at `link` pointing to `target` in the guest.
+    pub async fn symlink(&self, target: &str, link: &str) -> Result<()> {
+        Ok(self.client()?.symlink(target, link).await?)
+    }
+
+    /// Returns the target of a guest symlink.
+    pub async fn readlink(&self, path: &str) -> Result<String> {
+        Ok(self.client()?.readlink(path).await?)
+    }
+
+    /// Mounts a filesystem in the running guest; see [`Client::mount`](crate::Client::mount).
+    pub async fn mount(
+        &self,
+        source: &str,
+        target: &str,
+        fstype: &str,
+        options: &[&str],
+    ) -> Result<()> {
+        Ok(self
+            .client()?
+            .mount(source, target, fstype, options)
+            .await?)
+    }
+
+    /// Unmounts the filesystem at `target` in the running guest.
+    pub async fn unmount(&self, target: &str) -> Result<()> {
+        Ok(self.client()?.unmount(target).await?)
+    }
+
     /// Performs a version handshake with the guest agent.
     pub async fn handshake(&self) -> Result<()> {
-        Ok(self.client.handshake().await?)
+        Ok(self.client()?.handshake().await?)
     }
 
     /// Waits for the guest agent to become reachable, racing handshake probes
-    /// against shim process death detection.
+    /// against shim process death detection, then seeds the guest RNG.
     ///
     /// If the shim exits before the agent is ready, returns immediately with
     /// a diagnostic error instead of waiting for the full timeout.
@@ -499,21 +1028,21 @@ async fn wait_ready(&self, timeout: Duration) -> io::Result<()> {
             };
 
             let process_monitor = async {
-                // Poll for process death (cannot use waitpid here — it would
-                // consume the zombie before wait()/stop() can reap it).
-                loop {
-                    if !is_pid_alive(pid) {
-                        let console_hint = console_output
-                            .as_deref()
-                            .map(|p| format!("\n  console log: {p}"))
-                            .unwrap_or_default();
-                        let msg = format!(
-                            "VM process (pid {pid}) exited before guest agent became ready{console_hint}"
-                        );
-                        return Err(io::Error::new(io::ErrorKind::BrokenPipe, msg));
-                    }
-                    tokio::time::sleep(Duration::from_millis(50)).await;
-                }
+                // Not waitpid — it would consume the zombie before
+                // wait()/stop() can reap it.
+                let code = self
+                    .liveness()
+                    .await
+                    .map(|c| format!(" with code {c}"))
+                    .unwrap_or_default();
+                let console_hint = console_output
+                    .as_deref()
+                    .map(|p| format!("\n  console log: {p}"))
+                    .unwrap_or_default();
+                let msg = format!(
+                    "VM process (pid {pid}) exited{code} before guest agent became ready{console_hint}"
+                );
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, msg))
             };
 
             tokio::select! {
@@ -522,17 +1051,38 @@ async fn wait_ready(&self, timeout: Duration) -> io::Result<()> {
             }
         })
         .await
-        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "guest agent did not become ready"))?
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "guest agent did not become ready"))??;
+        self.seed_rng().await;
+        Ok(())
+    }
+
+    /// Credits fresh bytes from the host's RNG to the guest kernel, so
+    /// `getrandom` in the guest need not wait for entropy of its own.
+    ///
+    /// Skipped when [`VmBuilder::rng`](crate::VmBuilder::rng) turned it off;
+    /// best-effort otherwise, leaving agents without
+    /// [`feature::SEED_RNG`](bux_proto::feature::SEED_RNG) alone.
+    async fn seed_rng(&self) {
+        use std::io::Read;
+
+        if self.state.config.rng == Some(false) {
+            return;
+        }
+        let mut seed = [0; bux_proto::RNG_SEED_LEN];
+        let read = fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut seed));
+        if read.is_ok() {
+            let _ = self.client.seed_rng(&seed).await;
+        }
     }
 
     /// Updates status to Stopped and persists. If `auto_remove` is set,
-    /// deletes the VM record, socket, and disk image.
+    /// deletes the VM record, socket, and disk image, unless the process
+    /// has yet to exit; [`Runtime::list`] removes it once it has.
     fn mark_stopped(&mut self) -> Result<()> {
         self.state.status = Status::Stopped;
 
-        if self.state.config.auto_remove {
-            let _ = fs::remove_file(&self.state.socket);
-            let _ = self.disk.remove_vm_disk(&self.state.id);
+        if self.state.config.auto_remove && !is_vm_alive(&self.state) {
+            let _ = reclaim_files(&self.state, &self.disk);
             self.db.delete(&self.state.id)?;
         } else {
             self.db.update_status(&self.state.id, Status::Stopped)?;
@@ -541,38 +1091,225 @@ fn mark_stopped(&mut self) -> Result<()> {
     }
 }
 
+/// Deletes a VM's on-disk artifacts, tolerating ones that are already gone.
+///
+/// Stops at the first real failure so the caller can keep the state row.
+fn reclaim_files(state: &VmState, disk: &DiskManager) -> io::Result<Reclaimed> {
+    let mut paths = vec![
+        disk.vm_disk_path(&state.id),
+        state.socket.clone(),
+        // Spawn config, normally already deleted by the shim.
+        state.socket.with_extension("json"),
+        health::path(&state.socket),
+        exit::path(&state.socket),
+    ];
+    // A console log the user picked stays theirs.
+    if state.config.console_log {
+        paths.extend(state.config.console_output.as_ref().map(PathBuf::from));
+    }
+
+    let mut reclaimed = Reclaimed::default();
+    remove_files(paths, &mut reclaimed)?;
+    Ok(reclaimed)
+}
+
+/// Deletes `paths`, recording each into `reclaimed` and skipping ones that
+/// are already gone.
+fn remove_files(
+    paths: impl IntoIterator<Item = PathBuf>,
+    reclaimed: &mut Reclaimed,
+) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    for path in paths {
+        let Ok(meta) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        // Never delete anything but regular files and sockets.
+        let ft = meta.file_type();
+        if !(ft.is_file() || ft.is_socket()) {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                reclaimed.bytes += meta.len();
+                reclaimed.files.push(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("failed to remove {}: {e}", path.display()),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Poll interval for [`VmHandle::liveness`] when no watchdog pipe is held.
+const LIVENESS_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Poll interval for [`VmHandle::wait_healthy`].
+const HEALTH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The health for [`VmState::health`], from the file the shim keeps; a VM
+/// whose shim has not written it yet is still starting.
+fn current_health(state: &VmState) -> Option<Health> {
+    (state.status.is_active() && state.config.health_cmd.is_some())
+        .then(|| health::read(&state.socket).unwrap_or(Health::Starting))
+}
+
+/// Creates `path` if missing, readable by its owner only, and narrows an
+/// existing file to that too. SQLite gives the WAL and shared memory files
+/// of a database the database file's mode.
+fn private_file(path: &Path) -> io::Result<()> {
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))
+}
+
 /// Checks if a process is alive via `kill(pid, 0)`.
 fn is_pid_alive(pid: i32) -> bool {
     signal::kill(Pid::from_raw(pid), None).is_ok()
 }
 
-/// Blocks until a process exits.
+/// Start time of process `pid` in clock ticks since boot, from
+/// `/proc/<pid>/stat`; `None` once it has exited, zombies included.
+#[cfg(target_os = "linux")]
+fn process_start(pid: i32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name may hold spaces and parentheses; fields follow the
+    // last `)`, starting with the state (field 3). The start time is field 22.
+    let mut fields = stat.rsplit_once(')')?.1.split_whitespace();
+    if fields.next()? == "Z" {
+        return None;
+    }
+    fields.nth(18)?.parse().ok()
+}
+
+/// Without `/proc` the start time is unknown; liveness falls back to the PID.
+#[cfg(not(target_os = "linux"))]
+const fn process_start(_pid: i32) -> Option<u64> {
+    None
+}
+
+/// Checks that a VM's shim is still running: a process with its PID exists
+/// and, where the start time was recorded, started when the shim did.
+///
+/// Nothing here talks to the agent, which may be busy or still booting
+/// while the VM runs fine.
+fn is_vm_alive(state: &VmState) -> bool {
+    match state.pid_start {
+        Some(start) => process_start(state.pid) == Some(start),
+        None => is_pid_alive(state.pid),
+    }
+}
+
+/// How long [`agent_answers`] waits on each read and write.
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Pings the agent on a VM's socket within [`PING_TIMEOUT`], with blocking
+/// I/O so synchronous callers can use it. An agent that rejects the
+/// handshake (another protocol version, a wrong token) still answered.
+fn agent_answers(state: &VmState) -> io::Result<()> {
+    use bux_proto::{ControlReq, ControlResp, Hello, HelloAck, MIN_PROTOCOL_VERSION};
+
+    let mut stream = std::os::unix::net::UnixStream::connect(&state.socket)?;
+    stream.set_read_timeout(Some(PING_TIMEOUT))?;
+    stream.set_write_timeout(Some(PING_TIMEOUT))?;
+    if let Some(token) = &state.config.auth_token {
+        let hello = Hello::Auth {
+            token: token.clone(),
+        };
+        bux_proto::send_blocking(&mut stream, &hello)?;
+        if !matches!(bux_proto::recv_blocking(&mut stream)?, HelloAck::Ready) {
+            return Ok(());
+        }
+    }
+    let hello = Hello::Control {
+        version: MIN_PROTOCOL_VERSION,
+    };
+    bux_proto::send_blocking(&mut stream, &hello)?;
+    match bux_proto::recv_blocking(&mut stream)? {
+        HelloAck::Control { version } if version >= MIN_PROTOCOL_VERSION => {}
+        _ => return Ok(()),
+    }
+    bux_proto::send_blocking(&mut stream, &ControlReq::Ping)?;
+    bux_proto::recv_blocking::<ControlResp>(&mut stream).map(drop)
+}
+
+/// Blocks until a process exits, returning its exit code when observable.
 ///
 /// Tries `waitpid` first (works for child processes — zero CPU, zero delay).
 /// Falls back to `kill(pid, 0)` polling if the process is not a direct child
-/// (e.g. `ECHILD` from attached mode).
-fn wait_for_exit(pid: i32) {
+/// (e.g. `ECHILD` from attached mode), in which case the code is unknown.
+/// Signal deaths are reported shell-style as `128 + signo`.
+fn wait_for_exit(pid: i32) -> Option<i32> {
     let nix_pid = Pid::from_raw(pid);
     // Try waitpid — only succeeds for our own child processes.
     match waitpid(nix_pid, None) {
-        Ok(WaitStatus::Exited(..)) | Ok(WaitStatus::Signaled(..)) => return,
+        Ok(WaitStatus::Exited(_, code)) => return Some(code),
+        Ok(WaitStatus::Signaled(_, sig, _)) => return Some(128 + sig as i32),
         _ => {}
     }
     // Not our child (ECHILD) or other error — fall back to polling.
     while is_pid_alive(pid) {
         std::thread::sleep(Duration::from_millis(50));
     }
+    None
+}
+
+/// Reads the exit code of the exited child `pid` without reaping it, so a
+/// later [`wait_for_exit`] still can. `None` for processes that are not
+/// our children.
+#[cfg(target_os = "linux")]
+fn peek_exit(pid: i32) -> Option<i32> {
+    use nix::sys::wait::{Id, WaitPidFlag, waitid};
+
+    match waitid(
+        Id::Pid(Pid::from_raw(pid)),
+        WaitPidFlag::WEXITED | WaitPidFlag::WNOWAIT,
+    ) {
+        Ok(WaitStatus::Exited(_, code)) => Some(code),
+        Ok(WaitStatus::Signaled(_, sig, _)) => Some(128 + sig as i32),
+        _ => None,
+    }
+}
+
+/// Without `WNOWAIT` the status cannot be read without reaping.
+#[cfg(not(target_os = "linux"))]
+const fn peek_exit(_pid: i32) -> Option<i32> {
+    None
 }
 
 /// Locates the `bux-shim` binary.
 ///
 /// Search order:
-/// 1. Next to the current executable (e.g. `/usr/bin/bux-shim`).
-/// 2. In `$PATH` via `which`.
+/// 1. The path baked in at build time via `BUX_SHIM_PATH` (see `build.rs`),
+///    for packagers that install the shim outside the `bux` binary's directory.
+/// 2. Next to the current executable (e.g. `/usr/bin/bux-shim`).
+/// 3. In `$PATH` via `which`.
+///
+/// The first two pin the shim to this build so an older `bux-shim` earlier
+/// in `$PATH` is not picked up; if one still is, it rejects the config with
+/// a version-mismatch error.
 fn find_shim() -> io::Result<PathBuf> {
     const NAME: &str = "bux-shim";
 
-    // 1. Sibling of the current executable.
+    // 1. Path embedded at build time.
+    if let Some(path) = option_env!("BUX_SHIM_PATH") {
+        let embedded = PathBuf::from(path);
+        if embedded.is_file() {
+            return Ok(embedded);
+        }
+    }
+
+    // 2. Sibling of the current executable.
     if let Ok(exe) = std::env::current_exe() {
         let sibling = exe.with_file_name(NAME);
         if sibling.is_file() {
@@ -580,7 +1317,7 @@ fn find_shim() -> io::Result<PathBuf> {
         }
     }
 
-    // 2. Search $PATH.
+    // 3. Search $PATH.
     if let Ok(path_var) = std::env::var("PATH") {
         for dir in std::env::split_paths(&path_var) {
             let candidate = dir.join(NAME);
@@ -595,3 +1332,333 @@ fn find_shim() -> io::Result<PathBuf> {
         format!("'{NAME}' not found; install it next to the bux binary or in $PATH"),
     ))
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    /// Records a stopped VM `id` with `builder`'s config in `rt`, creating
+    /// the files a run leaves behind.
+    fn stopped_vm(rt: &Runtime, id: &str, builder: &VmBuilder) -> VmState {
+        let socket = rt.socks_dir.join(format!("{id}.sock"));
+        let mut config = builder.to_config();
+        if config.console_log {
+            let log = socket.with_extension("log");
+            config.console_output = Some(log.to_string_lossy().into_owned());
+        }
+        let state = VmState {
+            id: id.to_owned(),
+            name: None,
+            pid: i32::MAX,
+            pid_start: None,
+            image: None,
+            image_digest: None,
+            socket,
+            status: Status::Stopped,
+            config,
+            created_at: SystemTime::now(),
+            exit_code: Some(0),
+            health: None,
+        };
+        for path in [
+            rt.disk.vm_disk_path(id),
+            state.socket.clone(),
+            health::path(&state.socket),
+            exit::path(&state.socket),
+        ]
+        .into_iter()
+        .chain(state.config.console_output.as_ref().map(PathBuf::from))
+        {
+            fs::write(path, b"x").unwrap();
+        }
+        rt.db.insert(&state).unwrap();
+        state
+    }
+
+    #[test]
+    fn remove_reclaims_only_files_the_runtime_created() {
+        let dir = std::env::temp_dir().join(format!("bux_remove_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let rt = Runtime::open(dir.join("data")).unwrap();
+
+        let user_log = dir.join("console.log");
+        let mine = stopped_vm(
+            &rt,
+            "a1",
+            &Vm::builder().console_output(user_log.to_string_lossy()),
+        );
+        let reclaimed = rt.remove("a1", false, false).unwrap();
+        assert_eq!(reclaimed.files.len(), 4);
+        assert_eq!(reclaimed.bytes, 4);
+        assert!(user_log.exists(), "the user's console log was deleted");
+        assert!(!mine.socket.exists());
+        assert!(rt.get("a1").is_err());
+
+        let managed = stopped_vm(&rt, "b2", &Vm::builder().console_log());
+        let log = PathBuf::from(managed.config.console_output.unwrap());
+        assert_eq!(log.parent(), Some(rt.socks_dir.as_path()));
+        assert_eq!(rt.remove("b2", false, false).unwrap().files.len(), 5);
+        assert!(!log.exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn remove_drops_unshared_volumes_only_when_asked() {
+        let dir = std::env::temp_dir().join(format!("bux_remove_vols_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let rt = Runtime::open(dir.join("data")).unwrap();
+        let own = rt.disk.volume("own", Some(1 << 20)).unwrap();
+        let shared = rt.disk.volume("shared", Some(1 << 20)).unwrap();
+        let outside = dir.join("disk.img");
+        fs::write(&outside, b"user data").unwrap();
+        let with = |b: VmBuilder, name: &str, path: &Path| {
+            b.data_disk(name, path.to_string_lossy(), false)
+        };
+
+        let all = with(with(Vm::builder(), "own", &own), "shared", &shared);
+        stopped_vm(&rt, "c3", &with(all, "user", &outside));
+        stopped_vm(&rt, "d4", &with(Vm::builder(), "shared", &shared));
+        stopped_vm(&rt, "e5", &with(Vm::builder(), "own", &own));
+
+        rt.remove("e5", false, false).unwrap();
+        assert!(own.exists());
+        let reclaimed = rt.remove("c3", false, true).unwrap();
+        assert!(reclaimed.files.contains(&own));
+        assert!(!own.exists());
+        assert!(shared.exists(), "a volume still in use was deleted");
+        assert!(outside.exists(), "a disk outside the store was deleted");
+
+        rt.remove("d4", false, true).unwrap();
+        assert!(!shared.exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn state_holding_tokens_is_private() {
+        let dir = std::env::temp_dir().join(format!("bux_private_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("socks")).unwrap();
+        fs::set_permissions(dir.join("socks"), fs::Permissions::from_mode(0o755)).unwrap();
+        let rt = Runtime::open(&dir).unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&rt.socks_dir), 0o700);
+        assert_eq!(mode(&dir.join("bux.db")), 0o600);
+
+        let config = rt.socks_dir.join("a1.json");
+        private_file(&config).unwrap();
+        fs::write(&config, "{}").unwrap();
+        assert_eq!(mode(&config), 0o600);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn vms_are_alive_while_their_process_is() {
+        let dir = std::env::temp_dir().join(format!("bux_alive_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let rt = Runtime::open(&dir).unwrap();
+        let mut state = stopped_vm(&rt, "f6", &Vm::builder());
+        state.status = Status::Running;
+        state.pid = i32::try_from(std::process::id()).unwrap();
+        state.pid_start = process_start(state.pid);
+        assert!(state.pid_start.is_some());
+        // No agent socket serves it; liveness does not ask the agent.
+        fs::remove_file(&state.socket).unwrap();
+        assert!(is_vm_alive(&state));
+
+        // The PID now belongs to a process started at another time.
+        state.pid_start = state.pid_start.map(|t| t + 1);
+        assert!(!is_vm_alive(&state));
+        state.pid_start = None;
+        assert!(is_vm_alive(&state), "without a start time the PID decides");
+
+        // An exited child nobody reaped yet is dead too.
+        let mut child = std::process::Command::new("sleep")
+            .arg("0.2")
+            .spawn()
+            .unwrap();
+        state.pid = i32::try_from(child.id()).unwrap();
+        state.pid_start = process_start(state.pid);
+        assert!(state.pid_start.is_some());
+        while process_start(state.pid).is_some() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!is_vm_alive(&state));
+        child.wait().unwrap();
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn open_reconciles_and_list_keeps_files_of_live_vms() {
+        let dir = std::env::temp_dir().join(format!("bux_reconcile_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let rt = Runtime::open(&dir).unwrap();
+        let dead = stopped_vm(&rt, "a7", &Vm::builder());
+        rt.db.update_status(&dead.id, Status::Running).unwrap();
+
+        // Stopped and auto-removed on record, but its process still runs.
+        let mut live = stopped_vm(&rt, "b8", &Vm::builder());
+        live.config.auto_remove = true;
+        live.pid = i32::try_from(std::process::id()).unwrap();
+        live.pid_start = process_start(live.pid);
+        rt.db.delete(&live.id).unwrap();
+        rt.db.insert(&live).unwrap();
+        drop(rt);
+
+        let reopened = Runtime::open(&dir).unwrap();
+        let reconciled = reopened.db.get_by_id_prefix("a7").unwrap();
+        assert_eq!(reconciled.status, Status::Stopped);
+        assert_eq!(reopened.list().unwrap().len(), 2);
+        assert!(live.socket.exists(), "files of a running VM were reclaimed");
+        assert!(reopened.disk.vm_disk_path("b8").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn agents_answer_pings_or_not() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = std::env::temp_dir().join(format!("bux_ping_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let rt = Runtime::open(&dir).unwrap();
+        let state = stopped_vm(&rt, "c9", &Vm::builder());
+        let handle = VmHandle::new(state, Arc::clone(&rt.db), rt.disk.clone(), None);
+        let socket = handle.state().socket.clone();
+        fs::remove_file(&socket).unwrap();
+        assert!(!handle.agent_responsive(), "no socket");
+
+        // Something listening that never answers.
+        let silent = UnixListener::bind(&socket).unwrap();
+        assert!(!handle.agent_responsive(), "silent listener");
+        drop(silent);
+
+        fs::remove_file(&socket).unwrap();
+        let agent = UnixListener::bind(&socket).unwrap();
+        let serve = std::thread::spawn(move || {
+            use bux_proto::{ControlReq, ControlResp, Hello, HelloAck, PROTOCOL_VERSION};
+            let (mut conn, _) = agent.accept().unwrap();
+            let hello: Hello = bux_proto::recv_blocking(&mut conn).unwrap();
+            assert!(matches!(hello, Hello::Control { .. }));
+            let ack = HelloAck::Control {
+                version: PROTOCOL_VERSION,
+            };
+            bux_proto::send_blocking(&mut conn, &ack).unwrap();
+            let req: ControlReq = bux_proto::recv_blocking(&mut conn).unwrap();
+            assert!(matches!(req, ControlReq::Ping));
+            let pong = ControlResp::Pong {
+                version: "test".to_owned(),
+                uptime_ms: 1,
+            };
+            bux_proto::send_blocking(&mut conn, &pong).unwrap();
+        });
+        assert!(handle.agent_responsive(), "answering agent");
+        serve.join().unwrap();
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn stop_kills_vms_whose_agent_does_not_answer() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = std::env::temp_dir().join(format!("bux_stop_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let rt = Runtime::open(&dir).unwrap();
+        let mut child = std::process::Command::new("sleep")
+            .arg("60")
+            .spawn()
+            .unwrap();
+        let mut state = stopped_vm(&rt, "f7", &Vm::builder());
+        state.pid = i32::try_from(child.id()).unwrap();
+        state.pid_start = process_start(state.pid);
+        state.status = Status::Running;
+        rt.db.update_status(&state.id, Status::Running).unwrap();
+        fs::remove_file(&state.socket).unwrap();
+        // Accepts the connection, then never reads or answers.
+        let _silent = UnixListener::bind(&state.socket).unwrap();
+
+        let mut handle = VmHandle::new(state, Arc::clone(&rt.db), rt.disk.clone(), None);
+        let outcome = tokio::time::timeout(
+            Duration::from_secs(5),
+            handle.stop_timeout(Duration::from_millis(200)),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(outcome, StopOutcome::Killed);
+        assert_eq!(handle.state().status, Status::Stopped);
+        // The kill landed. The wait `stop_timeout` gave up on may reap the
+        // process first, so only its absence matters here.
+        let _ = child.wait();
+        assert!(!is_pid_alive(handle.state().pid));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn exit_code_is_peeked_without_reaping() {
+        let mut child = std::process::Command::new("/bin/sh")
+            .args(["-c", "exit 7"])
+            .spawn()
+            .unwrap();
+        #[allow(clippy::cast_possible_wrap)]
+        let pid = child.id() as i32;
+        assert_eq!(peek_exit(pid), Some(7));
+        assert_eq!(peek_exit(pid), Some(7));
+        assert_eq!(child.wait().unwrap().code(), Some(7));
+        assert_eq!(peek_exit(pid), None);
+    }
+
+    /// Boots `BUX_TEST_ROOTFS` with the agent at `BUX_TEST_AGENT` (a guest
+    /// path) starting the VM's command in a share mounted on its workdir,
+    /// which the agent mounts only after the workdir was entered.
+    #[tokio::test]
+    #[ignore = "boots a VM; needs KVM, libkrun, bux-shim, BUX_TEST_ROOTFS and BUX_TEST_AGENT"]
+    async fn primary_process_starts_in_workdir_with_configured_env() {
+        let rootfs = std::env::var("BUX_TEST_ROOTFS").unwrap();
+        let agent = std::env::var("BUX_TEST_AGENT").unwrap();
+        let dir = std::env::temp_dir().join(format!("bux_workdir_env_{}", std::process::id()));
+        let work = dir.join("work");
+        fs::create_dir_all(&work).unwrap();
+
+        let rt = Runtime::open(dir.join("data")).unwrap();
+        let builder = Vm::builder()
+            .root(rootfs)
+            .agent_path(agent)
+            .virtiofs_mount("work", work.to_string_lossy(), "/srv", false)
+            .workdir("/srv")
+            .env(&["PATH=/bin:/usr/bin", "GREETING=hello"])
+            .exec("/bin/sh", &["-c", "env > env.out; pwd > pwd.out; sleep 60"]);
+        let mut handle = rt.spawn(builder, None, None, false).await.unwrap();
+
+        // Relative paths: the files only reach the host through the share
+        // if the command runs in it.
+        let out = work.join("pwd.out");
+        tokio::time::timeout(Duration::from_secs(30), async {
+            while !out.exists() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let id = handle.state.id.clone();
+        handle.kill().unwrap();
+        rt.remove(&id, true, false).unwrap();
+
+        assert_eq!(fs::read_to_string(&out).unwrap().trim(), "/srv");
+        let env = fs::read_to_string(work.join("env.out")).unwrap();
+        assert!(env.lines().any(|l| l == "GREETING=hello"), "{env}");
+        for var in bux_proto::BOOT_ENVS {
+            assert!(!env.contains(var), "{var} leaked: {env}");
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
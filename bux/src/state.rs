@@ -1,11 +1,12 @@
 //! VM state types and SQLite persistence.
 
 use std::path::PathBuf;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use serde::{Deserialize, Serialize};
 
 use crate::disk::DiskFormat;
+use crate::sys::KernelFormat;
 
 /// VM lifecycle status.
 ///
@@ -65,6 +66,33 @@ pub const fn can_remove(self) -> bool {
     }
 }
 
+/// Outcome of a VM's health check, for VMs spawned with
+/// [`VmBuilder::health_cmd`](crate::VmBuilder::health_cmd).
+///
+/// Separate from [`Status`]: a VM is [`Running`](Status::Running) from boot
+/// on, but only ready to serve once its health check passes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Health {
+    /// No check has passed yet, and fewer than the allowed retries failed.
+    Starting,
+    /// The last check passed.
+    Healthy,
+    /// The allowed number of checks in a row failed.
+    Unhealthy,
+}
+
+impl Health {
+    /// Lowercase name, as `bux ps` shows it.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Starting => "starting",
+            Self::Healthy => "healthy",
+            Self::Unhealthy => "unhealthy",
+        }
+    }
+}
+
 /// A virtio-fs shared directory.
 #[non_exhaustive]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,6 +101,53 @@ pub struct VirtioFs {
     pub tag: String,
     /// Absolute host directory path.
     pub path: String,
+    /// Where the guest agent mounts the share at boot; `None` = export only.
+    #[serde(default)]
+    pub guest_path: Option<String>,
+    /// Share is read-only (host sandbox bind and guest mount).
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// A named data disk, attached to the guest as an extra block device.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataDisk {
+    /// Disk name, also used as the block device ID.
+    pub name: String,
+    /// Raw disk image path on the host.
+    pub path: String,
+    /// Attach the disk read-only.
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// What runs as the guest's PID 1 once the agent has booted.
+///
+/// PID 1 reaps orphaned processes and receives the signals meant for the
+/// workload, and the VM ends when it exits. The agent covers the basics: it
+/// lets the kernel reap every child (by ignoring `SIGCHLD`) and forwards
+/// [`signal_primary`](crate::VmHandle::signal_primary) to the command it
+/// started. A dedicated init such as `tini` or `dumb-init` also forwards
+/// signals aimed at PID 1, waits for the command's process group and
+/// reports exit statuses the way container workloads expect, at the cost
+/// of one more process and of the agent leaving PID 1.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Pid1 {
+    /// The agent stays PID 1 (the default).
+    #[default]
+    Agent,
+    /// After the mounts and the guest init command, the agent execs this
+    /// program and arguments (resolved via `PATH`) as PID 1 and keeps
+    /// serving from a child process. The command the agent would have
+    /// started ([`VmBuilder::agent_path`](crate::VmBuilder::agent_path))
+    /// is appended, as `tini --` expects.
+    ///
+    /// The command starts before the agent is ready, `stop` ends the VM by
+    /// signalling the init, and the agent no longer sees the command's
+    /// exit status.
+    AgentThenExec(Vec<String>),
 }
 
 /// A vsock port mapping.
@@ -94,6 +169,12 @@ pub struct VsockPort {
 #[non_exhaustive]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VmConfig {
+    /// `bux` release that wrote this config; see [`VmConfig::VERSION`].
+    ///
+    /// Empty for configs persisted by releases that predate the field.
+    #[serde(default)]
+    pub version: String,
+
     /// Number of virtual CPUs.
     pub vcpus: u8,
     /// RAM size in MiB.
@@ -136,6 +217,9 @@ pub struct VmConfig {
     /// virtio-fs shared directories.
     #[serde(default)]
     pub virtiofs: Vec<VirtioFs>,
+    /// Named data disks, attached after the root disk.
+    #[serde(default)]
+    pub data_disks: Vec<DataDisk>,
     /// vsock port mappings (includes internal agent port).
     #[serde(default)]
     pub vsock_ports: Vec<VsockPort>,
@@ -158,15 +242,99 @@ pub struct VmConfig {
     /// Enable/disable virtio-snd.
     #[serde(default)]
     pub snd_device: Option<bool>,
+    /// Seed the guest kernel RNG once the agent answers (`None` = on).
+    #[serde(default)]
+    pub rng: Option<bool>,
+    /// Snapshot the root filesystem at boot so `diff` can list changes.
+    #[serde(default)]
+    pub track_changes: bool,
     /// Redirect console output to a file.
     #[serde(default)]
     pub console_output: Option<String>,
+    /// `console_output` is a log the runtime created, deleted with the VM.
+    #[serde(default)]
+    pub console_log: bool,
+
+    /// Command the guest agent runs to completion before serving requests.
+    #[serde(default)]
+    pub guest_init: Option<Vec<String>>,
+    /// Keep booting if `guest_init` fails instead of aborting.
+    #[serde(default)]
+    pub guest_init_may_fail: bool,
+    /// Shared secret the guest agent requires on every connection.
+    #[serde(default)]
+    pub auth_token: Option<String>,
+    /// Guest agent booted in place of the command, which it then starts.
+    #[serde(default)]
+    pub agent_path: Option<String>,
+    /// The guest runs no agent; agent-backed operations are refused.
+    #[serde(default)]
+    pub no_agent: bool,
+    /// What runs as PID 1 once the agent has booted.
+    #[serde(default)]
+    pub pid1: Pid1,
+    /// Health check command, run in the guest through the agent.
+    #[serde(default)]
+    pub health_cmd: Option<Vec<String>>,
+    /// Time between health checks (`None` = 30 s).
+    #[serde(default)]
+    pub health_interval: Option<Duration>,
+    /// Failed health checks in a row before the VM is unhealthy (`None` = 3).
+    #[serde(default)]
+    pub health_retries: Option<u32>,
+
+    /// External kernel image; `None` boots libkrunfw's bundled kernel.
+    #[serde(default)]
+    pub kernel: Option<String>,
+    /// Format of `kernel`.
+    #[serde(default)]
+    pub kernel_format: KernelFormat,
+    /// Kernel command line (requires `kernel`).
+    #[serde(default)]
+    pub kernel_cmdline: Option<String>,
+    /// Guest PID 1 and its arguments, passed as `init=` (requires `kernel`).
+    #[serde(default)]
+    pub init: Option<Vec<String>>,
+
+    /// Confidential-computing launch settings.
+    #[serde(default)]
+    pub tee: Option<crate::vm::TeeConfig>,
 
     /// Remove VM state automatically when it stops.
     #[serde(default)]
     pub auto_remove: bool,
 }
 
+impl VmConfig {
+    /// Version stamped into every config this build writes.
+    pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+    /// Parses a config handed to `bux-shim`, rejecting one written by a
+    /// different `bux` release before any other field is interpreted.
+    pub fn from_shim_json(json: &str) -> crate::Result<Self> {
+        /// Just the version, so a mismatch is reported even when the rest
+        /// of the format changed.
+        #[derive(Deserialize)]
+        struct Header {
+            #[serde(default)]
+            version: String,
+        }
+
+        let header: Header = serde_json::from_str(json)?;
+        if header.version != Self::VERSION {
+            return Err(crate::Error::VersionMismatch {
+                found: if header.version.is_empty() {
+                    "unknown".to_owned()
+                } else {
+                    header.version
+                },
+                expected: Self::VERSION,
+            });
+        }
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
 /// Persisted state of a managed VM.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[non_exhaustive]
@@ -177,8 +345,17 @@ pub struct VmState {
     pub name: Option<String>,
     /// Host PID of the VM process (matches `libc::pid_t`).
     pub pid: i32,
-    /// OCI image reference (if pulled from a registry).
+    /// Start time of the VM process, in clock ticks since boot, which tells
+    /// it apart from a later process that reuses [`pid`](Self::pid). `None`
+    /// where the host does not expose it (and for VMs recorded before it
+    /// was).
+    #[serde(default)]
+    pub pid_start: Option<u64>,
+    /// OCI image reference as given at creation (if pulled from a registry).
     pub image: Option<String>,
+    /// Manifest digest `image` resolved to when the VM was created.
+    #[serde(default)]
+    pub image_digest: Option<String>,
     /// Unix socket path for host↔guest communication.
     pub socket: PathBuf,
     /// Current lifecycle status.
@@ -187,6 +364,51 @@ pub struct VmState {
     pub config: VmConfig,
     /// Timestamp when the VM was created.
     pub created_at: SystemTime,
+    /// Exit code of the VM process, if it was observed when the VM stopped.
+    ///
+    /// Only the process that spawned the VM can reap it, so this stays `None`
+    /// for VMs that were detached and exited unobserved.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// Outcome of the health check while the VM is active; `None` without
+    /// one. Recorded by the shim beside the socket rather than in the
+    /// database, and filled in by [`Runtime`](crate::Runtime) lookups.
+    #[serde(default)]
+    pub health: Option<Health>,
+}
+
+impl VmState {
+    /// A copy fit to show users, with the agent auth token (which grants
+    /// full access to the guest) replaced by `<redacted>`. The database and
+    /// the shim keep the real one.
+    #[must_use]
+    pub fn redacted(&self) -> Self {
+        let mut state = self.clone();
+        if let Some(token) = &mut state.config.auth_token {
+            "<redacted>".clone_into(token);
+        }
+        state
+    }
+}
+
+/// The OCI image a VM is created from, for [`Runtime::spawn`](crate::Runtime::spawn).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct ImageRef {
+    /// Reference as the user wrote it (e.g. `ubuntu`).
+    pub reference: String,
+    /// Manifest digest the reference resolved to (e.g. `sha256:…`).
+    pub digest: Option<String>,
+}
+
+impl ImageRef {
+    /// Creates an image reference with an optional resolved digest.
+    pub fn new(reference: impl Into<String>, digest: Option<String>) -> Self {
+        Self {
+            reference: reference.into(),
+            digest,
+        }
+    }
 }
 
 /// Generates a 12-character hex VM identifier.
@@ -227,9 +449,10 @@ struct Migration {
     }
 
     /// Ordered list of schema migrations. New migrations are appended here.
-    const MIGRATIONS: &[Migration] = &[Migration {
-        version: 1,
-        sql: "
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            sql: "
             CREATE TABLE IF NOT EXISTS vms (
                 id          TEXT PRIMARY KEY NOT NULL,
                 name        TEXT UNIQUE,
@@ -241,7 +464,20 @@ struct Migration {
                 created_at  REAL NOT NULL
             );
         ",
-    }];
+        },
+        Migration {
+            version: 2,
+            sql: "ALTER TABLE vms ADD COLUMN exit_code INTEGER;",
+        },
+        Migration {
+            version: 3,
+            sql: "ALTER TABLE vms ADD COLUMN image_digest TEXT;",
+        },
+        Migration {
+            version: 4,
+            sql: "ALTER TABLE vms ADD COLUMN pid_start INTEGER;",
+        },
+    ];
 
     /// SQLite-backed VM state database.
     #[derive(Debug)]
@@ -264,13 +500,16 @@ pub fn insert(&self, s: &VmState) -> Result<()> {
             let config_json = serde_json::to_string(&s.config)?;
             let ts = system_time_to_f64(s.created_at);
             self.conn.execute(
-                "INSERT INTO vms (id, name, pid, image, socket, status, config, created_at)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                "INSERT INTO vms (id, name, pid, pid_start, image, image_digest, socket, status,
+                                  config, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
                 params![
                     s.id,
                     s.name,
                     s.pid,
+                    s.pid_start.map(u64::cast_signed),
                     s.image,
+                    s.image_digest,
                     s.socket.to_string_lossy(),
                     status_str(s.status),
                     config_json,
@@ -289,6 +528,15 @@ pub fn update_status(&self, id: &str, status: Status) -> Result<()> {
             Ok(())
         }
 
+        /// Records the exit code of a stopped VM.
+        pub fn update_exit_code(&self, id: &str, code: Option<i32>) -> Result<()> {
+            self.conn.execute(
+                "UPDATE vms SET exit_code = ?1 WHERE id = ?2",
+                params![code, id],
+            )?;
+            Ok(())
+        }
+
         /// Finds a VM by exact name.
         pub fn get_by_name(&self, name: &str) -> Result<Option<VmState>> {
             let mut stmt = self.conn.prepare("SELECT * FROM vms WHERE name = ?1")?;
@@ -381,7 +629,11 @@ fn row_to_state(row: &rusqlite::Row<'_>) -> rusqlite::Result<VmState> {
             id: row.get("id")?,
             name: row.get("name")?,
             pid: row.get("pid")?,
+            pid_start: row
+                .get::<_, Option<i64>>("pid_start")?
+                .map(i64::cast_unsigned),
             image: row.get("image")?,
+            image_digest: row.get("image_digest")?,
             socket: socket_str.into(),
             status: parse_status(&status_text),
             config: serde_json::from_str(&config_json).map_err(|e| {
@@ -392,6 +644,8 @@ fn row_to_state(row: &rusqlite::Row<'_>) -> rusqlite::Result<VmState> {
                 )
             })?,
             created_at: f64_to_system_time(ts),
+            exit_code: row.get("exit_code")?,
+            health: None,
         })
     }
 
@@ -446,10 +700,13 @@ fn test_vm(id: &str, name: Option<&str>) -> VmState {
             id: id.to_owned(),
             name: name.map(ToOwned::to_owned),
             pid: 1234,
+            pid_start: Some(42),
             image: Some("alpine:latest".to_owned()),
+            image_digest: Some("sha256:0123456789abcdef".to_owned()),
             socket: format!("/tmp/{id}.sock").into(),
             status: Status::Running,
             config: VmConfig {
+                version: VmConfig::VERSION.to_owned(),
                 vcpus: 2,
                 ram_mib: 512,
                 rootfs: None,
@@ -462,6 +719,7 @@ fn test_vm(id: &str, name: Option<&str>) -> VmState {
                 workdir: None,
                 ports: vec![],
                 virtiofs: vec![],
+                data_disks: vec![],
                 vsock_ports: vec![],
                 log_level: None,
                 uid: None,
@@ -469,10 +727,29 @@ fn test_vm(id: &str, name: Option<&str>) -> VmState {
                 rlimits: vec![],
                 nested_virt: None,
                 snd_device: None,
+                rng: None,
+                track_changes: false,
                 console_output: None,
+                console_log: false,
+                guest_init: None,
+                guest_init_may_fail: false,
+                auth_token: None,
+                agent_path: None,
+                no_agent: false,
+                pid1: Pid1::Agent,
+                health_cmd: None,
+                health_interval: None,
+                health_retries: None,
+                kernel: None,
+                kernel_format: KernelFormat::default(),
+                kernel_cmdline: None,
+                init: None,
+                tee: None,
                 auto_remove: false,
             },
             created_at: SystemTime::now(),
+            exit_code: None,
+            health: None,
         }
     }
 
@@ -481,6 +758,21 @@ fn open_test_db() -> StateDb {
         StateDb::open(":memory:").expect("open in-memory db")
     }
 
+    #[test]
+    fn redacted_states_never_print_the_auth_token() {
+        let db = open_test_db();
+        let mut vm = test_vm("ccc333ddd444", None);
+        vm.config.auth_token = Some("s3cret-token".to_owned());
+        db.insert(&vm).unwrap();
+
+        let stored = db.get_by_id_prefix("ccc").unwrap();
+        assert_eq!(stored.config.auth_token.as_deref(), Some("s3cret-token"));
+        let shown = serde_json::to_string_pretty(&stored.redacted()).unwrap();
+        assert!(!shown.contains("s3cret-token"), "{shown}");
+        assert!(shown.contains("<redacted>"));
+        assert!(test_vm("x", None).redacted().config.auth_token.is_none());
+    }
+
     #[test]
     fn insert_and_list() {
         let db = open_test_db();
@@ -492,7 +784,12 @@ fn insert_and_list() {
         assert_eq!(all[0].id, "aaa111bbb222");
         assert_eq!(all[0].name.as_deref(), Some("myvm"));
         assert_eq!(all[0].pid, 1234);
+        assert_eq!(all[0].pid_start, Some(42));
         assert_eq!(all[0].status, Status::Running);
+        assert_eq!(
+            all[0].image_digest.as_deref(),
+            Some("sha256:0123456789abcdef")
+        );
     }
 
     #[test]
@@ -548,6 +845,16 @@ fn update_status() {
         assert_eq!(vm.status, Status::Stopped);
     }
 
+    #[test]
+    fn update_exit_code() {
+        let db = open_test_db();
+        db.insert(&test_vm("aaa111", None)).unwrap();
+        assert_eq!(db.get_by_id_prefix("aaa111").unwrap().exit_code, None);
+
+        db.update_exit_code("aaa111", Some(3)).unwrap();
+        assert_eq!(db.get_by_id_prefix("aaa111").unwrap().exit_code, Some(3));
+    }
+
     #[test]
     fn update_name() {
         let db = open_test_db();
@@ -587,4 +894,19 @@ fn pid_stored_as_i32() {
         let loaded = db.get_by_id_prefix("aaa111").unwrap();
         assert_eq!(loaded.pid, -1);
     }
+
+    #[test]
+    fn shim_json_rejects_other_versions() {
+        let config = test_vm("aaa111", None).config;
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(VmConfig::from_shim_json(&json).unwrap().vcpus, 2);
+
+        // An old config whose format also changed still reports the version.
+        let stale = r#"{"vcpus": "two"}"#;
+        let err = VmConfig::from_shim_json(stale).unwrap_err();
+        assert!(
+            matches!(err, crate::Error::VersionMismatch { ref found, .. } if found == "unknown"),
+            "{err}"
+        );
+    }
 }
@@ -1,11 +1,45 @@
 //! Virtual machine builder and lifecycle management.
 
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use bux_proto::{
+    AUTH_ENV, BOOT_ENVS, DIFF_ENV, GuestInit, INIT_ENV, MAIN_ENV, MOUNTS_ENV, PID1_ENV, ShareMount,
+};
+
 use crate::disk::DiskFormat;
-use crate::error::Result;
+use crate::error::{Error, Result};
 #[cfg(unix)]
 use crate::state::VmConfig;
+use crate::state::{DataDisk, Pid1, VirtioFs};
 use crate::sys::{self, Feature, KernelFormat, LogStyle, SyncMode};
 
+/// Hypervisor and libkrun build capabilities, probed once by
+/// [`Vm::capabilities`].
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Capabilities {
+    /// Maximum vCPUs per VM.
+    pub max_vcpus: u32,
+    /// Every [`Feature`] and whether this libkrun build supports it.
+    pub features: BTreeMap<Feature, bool>,
+    /// Nested virtualization support; `None` where libkrun cannot tell
+    /// (it is only reported on macOS).
+    pub nested_virt: Option<bool>,
+}
+
+impl Capabilities {
+    /// Returns `true` if `feature` is supported.
+    pub fn has(&self, feature: Feature) -> bool {
+        self.features.get(&feature).copied().unwrap_or(false)
+    }
+
+    /// Supported features, in flag order.
+    pub fn supported(&self) -> impl Iterator<Item = Feature> + '_ {
+        self.features.iter().filter(|(_, on)| **on).map(|(f, _)| *f)
+    }
+}
+
 /// Log verbosity level for libkrun.
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
@@ -55,6 +89,69 @@ fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
     }
 }
 
+/// Confidential-computing technology a VM can be launched with.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tee {
+    /// AMD Secure Encrypted Virtualization.
+    Sev,
+}
+
+impl Tee {
+    /// Lowercase name, as accepted by [`FromStr`](std::str::FromStr).
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Sev => "sev",
+        }
+    }
+
+    /// libkrun build feature required to launch with this TEE.
+    pub const fn feature(self) -> Feature {
+        match self {
+            Self::Sev => Feature::AmdSev,
+        }
+    }
+}
+
+impl std::fmt::Display for Tee {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Tee {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sev" => Ok(Self::Sev),
+            _ => Err(format!("unknown TEE: {s}")),
+        }
+    }
+}
+
+/// Confidential-computing launch settings for [`VmBuilder::tee`].
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TeeConfig {
+    /// Technology to launch with.
+    pub tee: Tee,
+    /// libkrun TEE config file (JSON): workload ID, attestation server URL
+    /// and the launch parameters the measurement covers.
+    pub config_file: String,
+}
+
+impl TeeConfig {
+    /// Launches with `tee`, configured by libkrun's TEE config file.
+    pub fn new(tee: Tee, config_file: impl Into<String>) -> Self {
+        Self {
+            tee,
+            config_file: config_file.into(),
+        }
+    }
+}
+
 /// Builder for configuring a micro-VM.
 ///
 /// Defaults: 1 vCPU, 512 MiB RAM, host environment inherited.
@@ -97,8 +194,10 @@ pub struct VmBuilder {
     workdir: Option<String>,
     /// TCP port mappings (`"host_port:guest_port"`).
     ports: Vec<String>,
-    /// virtio-fs shared directories `(tag, host_path)`.
-    virtiofs: Vec<(String, String)>,
+    /// virtio-fs shared directories.
+    virtiofs: Vec<VirtioFs>,
+    /// Named data disks attached after the root disk.
+    data_disks: Vec<DataDisk>,
     /// Global log level for libkrun.
     log_level: Option<LogLevel>,
     /// UID to set before starting the VM.
@@ -111,10 +210,42 @@ pub struct VmBuilder {
     nested_virt: Option<bool>,
     /// Enable/disable virtio-snd.
     snd_device: Option<bool>,
+    /// Seed the guest kernel RNG once the agent answers (`None` = on).
+    rng: Option<bool>,
+    /// Have the agent snapshot the root filesystem at boot for `diff`.
+    track_changes: bool,
     /// Redirect console output to a file.
     console_output: Option<String>,
+    /// Log the console to a file the runtime creates and deletes.
+    console_log: bool,
     /// vsock port mappings `(guest_port, host_socket_path, listen)`.
     vsock_ports: Vec<(u32, String, bool)>,
+    /// Command the guest agent runs before serving.
+    guest_init: Option<Vec<String>>,
+    /// Keep booting if `guest_init` fails.
+    guest_init_may_fail: bool,
+    /// Shared secret the guest agent requires on every connection.
+    auth_token: Option<String>,
+    /// Guest agent booted in place of the command, which it then starts.
+    agent_path: Option<String>,
+    /// The guest runs no agent; agent-backed operations are refused.
+    no_agent: bool,
+    /// What runs as PID 1 once the agent has booted.
+    pid1: Pid1,
+    /// Health check command, run through the agent.
+    health_cmd: Option<Vec<String>>,
+    /// Time between health checks.
+    health_interval: Option<Duration>,
+    /// Failed health checks in a row before the VM is unhealthy.
+    health_retries: Option<u32>,
+    /// External kernel image and its format (default: libkrunfw's bundled kernel).
+    kernel: Option<(String, KernelFormat)>,
+    /// Kernel command line for the external kernel.
+    kernel_cmdline: Option<String>,
+    /// Init program and arguments for the external kernel.
+    init: Option<Vec<String>>,
+    /// Confidential-computing launch settings.
+    tee: Option<TeeConfig>,
 }
 
 impl VmBuilder {
@@ -125,6 +256,10 @@ pub const fn vcpus(mut self, n: u8) -> Self {
     }
 
     /// Sets the RAM size in MiB (default: 512).
+    ///
+    /// Guest RAM is always anonymous memory allocated by libkrun, whose
+    /// `krun_set_vm_config` only takes a size: there is no option to back it
+    /// with hugepages or a file.
     pub const fn ram_mib(mut self, mib: u32) -> Self {
         self.ram_mib = mib;
         self
@@ -172,12 +307,18 @@ pub fn exec(mut self, path: impl Into<String>, args: &[&str]) -> Self {
     /// Sets explicit environment variables (`KEY=VALUE` format).
     ///
     /// If never called, the host environment is inherited automatically.
+    /// Either way the VM's command gets exactly this environment: the
+    /// variables the agent boots with ([`bux_proto::BOOT_ENVS`]) are
+    /// removed before it starts.
     pub fn env(mut self, vars: &[&str]) -> Self {
         self.env = Some(vars.iter().map(|s| (*s).to_owned()).collect());
         self
     }
 
     /// Sets the working directory inside the VM.
+    ///
+    /// The VM's command starts there, as do guest commands run without a
+    /// `cwd` of their own, including when a share is mounted on it.
     pub fn workdir(mut self, path: impl Into<String>) -> Self {
         self.workdir = Some(path.into());
         self
@@ -194,7 +335,56 @@ pub fn port(mut self, mapping: impl Into<String>) -> Self {
     /// - `tag` — identifier used to mount the filesystem in the guest.
     /// - `host_path` — absolute path to the directory on the host.
     pub fn virtiofs(mut self, tag: impl Into<String>, host_path: impl Into<String>) -> Self {
-        self.virtiofs.push((tag.into(), host_path.into()));
+        self.virtiofs.push(VirtioFs {
+            tag: tag.into(),
+            path: host_path.into(),
+            guest_path: None,
+            read_only: false,
+        });
+        self
+    }
+
+    /// Adds a virtio-fs share that the guest agent mounts at `guest_path`
+    /// during boot.
+    ///
+    /// With `read_only`, the sandbox binds `host_path` read-only and the
+    /// guest mounts it `MS_RDONLY`. libkrun itself has no read-only
+    /// virtio-fs mode, so an unsandboxed VM only gets the guest-side mount
+    /// flag.
+    pub fn virtiofs_mount(
+        mut self,
+        tag: impl Into<String>,
+        host_path: impl Into<String>,
+        guest_path: impl Into<String>,
+        read_only: bool,
+    ) -> Self {
+        self.virtiofs.push(VirtioFs {
+            tag: tag.into(),
+            path: host_path.into(),
+            guest_path: Some(guest_path.into()),
+            read_only,
+        });
+        self
+    }
+
+    /// Attaches the raw disk image at `path` as an extra block device with
+    /// ID `name`, e.g. a volume from
+    /// [`DiskManager::volume`](crate::DiskManager::volume).
+    ///
+    /// Data disks follow the root disk in the order they are added, so the
+    /// first one is `/dev/vdb` in a VM booted from a disk image and
+    /// `/dev/vda` otherwise. The guest does not mount them.
+    pub fn data_disk(
+        mut self,
+        name: impl Into<String>,
+        path: impl Into<String>,
+        read_only: bool,
+    ) -> Self {
+        self.data_disks.push(DataDisk {
+            name: name.into(),
+            path: path.into(),
+            read_only,
+        });
         self
     }
 
@@ -234,9 +424,180 @@ pub const fn snd_device(mut self, enable: bool) -> Self {
         self
     }
 
+    /// Seeds the guest kernel's random number generator from the host
+    /// (default: on).
+    ///
+    /// With this on, the host sends new random bytes to the guest agent
+    /// once it answers, and the agent credits them to the kernel pool as
+    /// entropy, so `getrandom` in the guest does not stall on a fresh boot.
+    /// A command started at boot may run before they arrive; VMs without
+    /// the agent get no seed.
+    pub const fn rng(mut self, enable: bool) -> Self {
+        self.rng = Some(enable);
+        self
+    }
+
+    /// Has the guest agent snapshot the root filesystem at boot so
+    /// [`Client::diff`](crate::Client::diff) can list changes since
+    /// (default: off).
+    ///
+    /// The snapshot walks every path on the root device, which costs time
+    /// and memory proportional to the image; without it `diff` fails.
+    pub const fn track_changes(mut self) -> Self {
+        self.track_changes = true;
+        self
+    }
+
     /// Redirects console output to a file (ignores stdin).
     pub fn console_output(mut self, path: impl Into<String>) -> Self {
         self.console_output = Some(path.into());
+        self.console_log = false;
+        self
+    }
+
+    /// Redirects console output to a log file that
+    /// [`Runtime::spawn()`](crate::Runtime::spawn) creates next to the VM's
+    /// agent socket and [`Runtime::remove()`](crate::Runtime::remove)
+    /// deletes with the VM. The spawned VM's `console_output` holds the
+    /// path.
+    pub fn console_log(mut self) -> Self {
+        self.console_output = None;
+        self.console_log = true;
+        self
+    }
+
+    /// Runs `argv` in the guest before the agent starts serving requests.
+    ///
+    /// The agent waits for it to exit, with its output going to the
+    /// console, so it can seed state, create users or start daemons without
+    /// an `exec` round-trip after readiness. A failing command aborts boot
+    /// unless [`guest_init_may_fail`](Self::guest_init_may_fail) is set.
+    /// The command rides on the kernel command line, so keep it short.
+    pub fn guest_init(mut self, argv: &[&str]) -> Self {
+        self.guest_init = Some(argv.iter().map(|s| (*s).to_owned()).collect());
+        self
+    }
+
+    /// Keeps booting when the [`guest_init`](Self::guest_init) command fails.
+    pub const fn guest_init_may_fail(mut self, may_fail: bool) -> Self {
+        self.guest_init_may_fail = may_fail;
+        self
+    }
+
+    /// Makes the guest agent refuse connections that do not present `token`.
+    ///
+    /// Handles from [`Runtime`](crate::Runtime) send it automatically; a
+    /// hand-made [`Client`](crate::Client) needs
+    /// [`with_token`](crate::Client::with_token). The token rides on the
+    /// kernel command line, so it must be printable ASCII without spaces or
+    /// quotes, and is visible to code inside the guest.
+    pub fn auth_token(mut self, token: impl Into<String>) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+
+    /// Boots the guest agent at `path` (inside the rootfs) instead of the
+    /// [`exec`](Self::exec) command.
+    ///
+    /// The agent starts the command once it serves and shuts the VM down
+    /// with its exit status, so images whose entrypoint does not start the
+    /// agent still get `exec`, `cp` and the other agent operations. The
+    /// [`uid`](Self::uid) and [`gid`](Self::gid) apply to the agent, and
+    /// so to the command too.
+    pub fn agent_path(mut self, path: impl Into<String>) -> Self {
+        self.agent_path = Some(path.into());
+        self.no_agent = false;
+        self
+    }
+
+    /// Declares that the guest runs no bux agent: the [`exec`](Self::exec)
+    /// command boots as is (typically the image's own init).
+    ///
+    /// The runtime then skips the agent socket and readiness wait, stops
+    /// the VM with `SIGTERM` instead of a shutdown request, and fails every
+    /// agent-backed [`VmHandle`](crate::VmHandle) operation (exec, file
+    /// transfer, stat, ...) with [`Error::NoAgent`]. Options the agent
+    /// implements (guest init, mount points, auth token) are rejected.
+    pub fn no_agent(mut self) -> Self {
+        self.no_agent = true;
+        self.agent_path = None;
+        self
+    }
+
+    /// Chooses what runs as the guest's PID 1 once the agent has booted;
+    /// see [`Pid1`] for the tradeoffs. Defaults to the agent itself.
+    pub fn pid1(mut self, pid1: Pid1) -> Self {
+        self.pid1 = pid1;
+        self
+    }
+
+    /// Checks the VM's health by running `argv` in the guest, through the
+    /// agent, once it answers and then every
+    /// [`health_interval`](Self::health_interval).
+    ///
+    /// A zero exit status passes. The VM is
+    /// [`Starting`](crate::Health::Starting) until a check passes,
+    /// [`Healthy`](crate::Health::Healthy) while they do, and
+    /// [`Unhealthy`](crate::Health::Unhealthy) after [`health_retries`](Self::health_retries)
+    /// failures in a row; see [`VmState::health`](crate::VmState::health)
+    /// and [`VmHandle::wait_healthy`](crate::VmHandle::wait_healthy). Only
+    /// VMs spawned through [`Runtime`](crate::Runtime) are checked: the
+    /// shim runs the checks for as long as the VM runs.
+    pub fn health_cmd(mut self, argv: &[&str]) -> Self {
+        self.health_cmd = Some(argv.iter().map(|s| (*s).to_owned()).collect());
+        self
+    }
+
+    /// Sets the time between health checks (default: 30 s). A check still
+    /// running after this long is killed and counts as failed.
+    pub const fn health_interval(mut self, interval: Duration) -> Self {
+        self.health_interval = Some(interval);
+        self
+    }
+
+    /// Sets how many health checks in a row must fail before the VM is
+    /// unhealthy (default: 3).
+    pub const fn health_retries(mut self, retries: u32) -> Self {
+        self.health_retries = Some(retries);
+        self
+    }
+
+    /// Boots an external kernel image instead of libkrunfw's bundled one.
+    ///
+    /// libkrun only accepts a custom command line together with an external
+    /// kernel; without one, it builds the command line itself.
+    pub fn kernel(mut self, path: impl Into<String>, format: KernelFormat) -> Self {
+        self.kernel = Some((path.into(), format));
+        self
+    }
+
+    /// Sets the guest kernel command line (e.g. `quiet console=hvc0`).
+    ///
+    /// Requires [`kernel`](Self::kernel); the default stays libkrun's own.
+    pub fn kernel_cmdline(mut self, cmdline: &str) -> Self {
+        self.kernel_cmdline = Some(cmdline.to_owned());
+        self
+    }
+
+    /// Runs `path` as the guest's PID 1 with `args`, via `init=` on the
+    /// kernel command line.
+    ///
+    /// Requires [`kernel`](Self::kernel). Arguments cannot contain
+    /// whitespace, since the kernel splits its command line on it.
+    pub fn init(mut self, path: impl Into<String>, args: &[&str]) -> Self {
+        let mut argv = vec![path.into()];
+        argv.extend(args.iter().map(|s| (*s).to_owned()));
+        self.init = Some(argv);
+        self
+    }
+
+    /// Launches the VM as a confidential guest.
+    ///
+    /// Requires a libkrun build with the TEE's feature (e.g. libkrun-sev for
+    /// [`Tee::Sev`]); otherwise building fails with
+    /// [`Error::Unsupported`](crate::Error::Unsupported).
+    pub fn tee(mut self, config: TeeConfig) -> Self {
+        self.tee = Some(config);
         self
     }
 
@@ -252,8 +613,9 @@ pub fn vsock_port(mut self, port: u32, host_path: impl Into<String>, listen: boo
     /// Extracts a serializable configuration snapshot.
     #[cfg(unix)]
     pub(crate) fn to_config(&self) -> VmConfig {
-        use crate::state::{VirtioFs, VsockPort};
+        use crate::state::VsockPort;
         VmConfig {
+            version: VmConfig::VERSION.to_owned(),
             vcpus: self.vcpus,
             ram_mib: self.ram_mib,
             rootfs: self.root.clone(),
@@ -265,14 +627,8 @@ pub(crate) fn to_config(&self) -> VmConfig {
             env: self.env.clone(),
             workdir: self.workdir.clone(),
             ports: self.ports.clone(),
-            virtiofs: self
-                .virtiofs
-                .iter()
-                .map(|(tag, path)| VirtioFs {
-                    tag: tag.clone(),
-                    path: path.clone(),
-                })
-                .collect(),
+            virtiofs: self.virtiofs.clone(),
+            data_disks: self.data_disks.clone(),
             vsock_ports: self
                 .vsock_ports
                 .iter()
@@ -288,7 +644,24 @@ pub(crate) fn to_config(&self) -> VmConfig {
             rlimits: self.rlimits.clone(),
             nested_virt: self.nested_virt,
             snd_device: self.snd_device,
+            rng: self.rng,
+            track_changes: self.track_changes,
             console_output: self.console_output.clone(),
+            console_log: self.console_log,
+            guest_init: self.guest_init.clone(),
+            guest_init_may_fail: self.guest_init_may_fail,
+            auth_token: self.auth_token.clone(),
+            agent_path: self.agent_path.clone(),
+            no_agent: self.no_agent,
+            pid1: self.pid1.clone(),
+            health_cmd: self.health_cmd.clone(),
+            health_interval: self.health_interval,
+            health_retries: self.health_retries,
+            kernel: self.kernel.as_ref().map(|(path, _)| path.clone()),
+            kernel_format: self.kernel.as_ref().map(|k| k.1).unwrap_or_default(),
+            kernel_cmdline: self.kernel_cmdline.clone(),
+            init: self.init.clone(),
+            tee: self.tee.clone(),
             auto_remove: false,
         }
     }
@@ -310,11 +683,8 @@ pub fn from_config(c: &VmConfig) -> Self {
             env: c.env.clone(),
             workdir: c.workdir.clone(),
             ports: c.ports.clone(),
-            virtiofs: c
-                .virtiofs
-                .iter()
-                .map(|v| (v.tag.clone(), v.path.clone()))
-                .collect(),
+            virtiofs: c.virtiofs.clone(),
+            data_disks: c.data_disks.clone(),
             vsock_ports: c
                 .vsock_ports
                 .iter()
@@ -326,10 +696,259 @@ pub fn from_config(c: &VmConfig) -> Self {
             rlimits: c.rlimits.clone(),
             nested_virt: c.nested_virt,
             snd_device: c.snd_device,
+            rng: c.rng,
+            track_changes: c.track_changes,
             console_output: c.console_output.clone(),
+            console_log: c.console_log,
+            guest_init: c.guest_init.clone(),
+            guest_init_may_fail: c.guest_init_may_fail,
+            auth_token: c.auth_token.clone(),
+            agent_path: c.agent_path.clone(),
+            no_agent: c.no_agent,
+            pid1: c.pid1.clone(),
+            health_cmd: c.health_cmd.clone(),
+            health_interval: c.health_interval,
+            health_retries: c.health_retries,
+            kernel: c.kernel.clone().map(|path| (path, c.kernel_format)),
+            kernel_cmdline: c.kernel_cmdline.clone(),
+            init: c.init.clone(),
+            tee: c.tee.clone(),
         }
     }
 
+    /// Rejects kernel options libkrun cannot apply: a command line or init
+    /// without an external kernel, a missing kernel image, NUL bytes, and
+    /// init arguments the kernel would split.
+    pub(crate) fn check_kernel(&self) -> Result<()> {
+        let Some((ref path, _)) = self.kernel else {
+            if self.kernel_cmdline.is_some() || self.init.is_some() {
+                return Err(Error::InvalidConfig(
+                    "a kernel command line or init requires an external kernel \
+                     (libkrun builds the command line for its bundled kernel)"
+                        .to_owned(),
+                ));
+            }
+            return Ok(());
+        };
+        if !std::path::Path::new(path).is_file() {
+            return Err(Error::InvalidConfig(format!(
+                "kernel image {path} does not exist"
+            )));
+        }
+        if self
+            .kernel_cmdline
+            .as_deref()
+            .is_some_and(|c| c.contains('\0'))
+        {
+            return Err(Error::InvalidConfig(
+                "kernel command line contains a NUL byte".to_owned(),
+            ));
+        }
+        if let Some(ref argv) = self.init
+            && argv
+                .iter()
+                .any(|a| a.is_empty() || a.contains(|c: char| c == '\0' || c.is_whitespace()))
+        {
+            return Err(Error::InvalidConfig(format!(
+                "init {argv:?}: arguments must be non-empty and free of whitespace and NUL bytes"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Rejects an auth token the kernel command line cannot carry.
+    pub(crate) fn check_auth_token(&self) -> Result<()> {
+        match self.auth_token {
+            Some(ref token) if !bux_proto::is_valid_token(token) => {
+                Err(Error::InvalidConfig(format!(
+                    "auth token must be 1-{} printable ASCII characters without spaces or quotes",
+                    bux_proto::MAX_TOKEN_LEN
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the guest will run the bux agent.
+    pub(crate) const fn runs_agent(&self) -> bool {
+        !self.no_agent
+    }
+
+    /// Rejects agent options that contradict each other: a relative agent
+    /// path, or agent features on a VM without an agent.
+    pub(crate) fn check_agent(&self) -> Result<()> {
+        if let Some(ref path) = self.agent_path
+            && !path.starts_with('/')
+        {
+            return Err(Error::InvalidConfig(format!(
+                "agent path {path} must be absolute"
+            )));
+        }
+        if matches!(self.pid1, Pid1::AgentThenExec(ref argv) if argv.is_empty()) {
+            return Err(Error::InvalidConfig("PID 1 init command is empty".into()));
+        }
+        if self.health_cmd.as_ref().is_some_and(Vec::is_empty)
+            || self.health_interval.is_some_and(|i| i.is_zero())
+            || self.health_retries == Some(0)
+        {
+            return Err(Error::InvalidConfig(
+                "health check needs a command, a non-zero interval and at least one retry".into(),
+            ));
+        }
+        if !self.no_agent {
+            return Ok(());
+        }
+        let needs_agent = [
+            (self.guest_init.is_some(), "a guest init command"),
+            (self.pid1 != Pid1::Agent, "a PID 1 handoff"),
+            (self.auth_token.is_some(), "an auth token"),
+            (self.health_cmd.is_some(), "a health check"),
+            (self.track_changes, "change tracking"),
+            (
+                self.virtiofs.iter().any(|v| v.guest_path.is_some()),
+                "share mount points",
+            ),
+        ];
+        match needs_agent.iter().find(|(set, _)| *set) {
+            Some((_, what)) => Err(Error::InvalidConfig(format!(
+                "the guest agent is needed for {what}"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Rejects a TEE this libkrun build cannot launch, or a missing TEE
+    /// config file.
+    pub(crate) fn check_tee(&self) -> Result<()> {
+        let Some(ref tee) = self.tee else {
+            return Ok(());
+        };
+        let feature = tee.tee.feature();
+        if !sys::has_feature(feature)? {
+            return Err(Error::Unsupported(feature));
+        }
+        if !std::path::Path::new(&tee.config_file).is_file() {
+            return Err(Error::InvalidConfig(format!(
+                "TEE config file {} does not exist",
+                tee.config_file
+            )));
+        }
+        Ok(())
+    }
+
+    /// Joins the configured command line with `init=` and its arguments.
+    fn full_cmdline(&self) -> Option<String> {
+        let mut parts: Vec<String> = self.kernel_cmdline.iter().cloned().collect();
+        if let Some((path, args)) = self.init.as_ref().and_then(|a| a.split_first()) {
+            parts.push(format!("init={path}"));
+            if !args.is_empty() {
+                parts.push("--".to_owned());
+                parts.extend(args.iter().cloned());
+            }
+        }
+        (!parts.is_empty()).then(|| parts.join(" "))
+    }
+
+    /// Rejects missing host directories, duplicate tags and mount points
+    /// that cannot be passed to the guest.
+    pub(crate) fn check_virtiofs(&self) -> Result<()> {
+        let mut tags = std::collections::HashSet::new();
+        for share in &self.virtiofs {
+            if !tags.insert(share.tag.as_str()) {
+                return Err(Error::InvalidConfig(format!(
+                    "duplicate virtio-fs tag {:?}",
+                    share.tag
+                )));
+            }
+            if !std::path::Path::new(&share.path).is_dir() {
+                return Err(Error::InvalidConfig(format!(
+                    "virtio-fs share {:?}: host directory {} does not exist",
+                    share.tag, share.path
+                )));
+            }
+            if let Some(ref guest) = share.guest_path {
+                let mount = ShareMount::new(&share.tag, guest, share.read_only);
+                if !guest.starts_with('/') || !mount.is_encodable() {
+                    return Err(Error::InvalidConfig(format!(
+                        "virtio-fs share {:?}: guest path {guest:?} must be absolute and \
+                         free of whitespace and commas",
+                        share.tag
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the guest environment with the boot mount table, init
+    /// command, auth token, change tracking switch and (when
+    /// booting the agent in its place) the VM's command appended.
+    ///
+    /// With no explicit environment, the host environment is copied so the
+    /// guest still inherits it as it would without them.
+    fn guest_env(&self) -> Option<Vec<String>> {
+        let mounts: Vec<ShareMount> = self
+            .virtiofs
+            .iter()
+            .filter_map(|v| {
+                let guest = v.guest_path.as_ref()?;
+                Some(ShareMount::new(&v.tag, guest, v.read_only))
+            })
+            .collect();
+        let init = self
+            .guest_init
+            .as_ref()
+            .filter(|argv| !argv.is_empty())
+            .map(|argv| GuestInit::new(argv.clone(), self.guest_init_may_fail));
+        let main = self
+            .agent_path
+            .as_ref()
+            .and(self.exec_path.as_ref())
+            .map(|path| {
+                let mut argv = vec![path.clone()];
+                argv.extend(self.exec_args.iter().cloned());
+                bux_proto::encode_argv(&argv)
+            });
+        let pid1 = match self.pid1 {
+            Pid1::AgentThenExec(ref argv) => Some(bux_proto::encode_argv(argv)),
+            Pid1::Agent => None,
+        };
+        if mounts.is_empty()
+            && init.is_none()
+            && self.auth_token.is_none()
+            && main.is_none()
+            && pid1.is_none()
+            && !self.track_changes
+        {
+            return self.env.clone();
+        }
+        let mut env = self.env.clone().unwrap_or_else(|| {
+            std::env::vars()
+                .filter(|(k, _)| !BOOT_ENVS.contains(&k.as_str()))
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect()
+        });
+        if !mounts.is_empty() {
+            env.push(format!("{MOUNTS_ENV}={}", ShareMount::encode(&mounts)));
+        }
+        if let Some(cmd) = init {
+            env.push(format!("{INIT_ENV}={}", cmd.encode()));
+        }
+        if let Some(ref token) = self.auth_token {
+            env.push(format!("{AUTH_ENV}={token}"));
+        }
+        if let Some(argv) = main {
+            env.push(format!("{MAIN_ENV}={argv}"));
+        }
+        if let Some(argv) = pid1 {
+            env.push(format!("{PID1_ENV}={argv}"));
+        }
+        if self.track_changes {
+            env.push(format!("{DIFF_ENV}=1"));
+        }
+        Some(env)
+    }
+
     /// Builds and returns the configured [`Vm`].
     ///
     /// Creates a libkrun context and applies all configuration. If any step
@@ -345,6 +964,18 @@ pub fn build(self) -> Result<Vm> {
 
         sys::set_vm_config(vm.ctx, self.vcpus, self.ram_mib)?;
 
+        self.check_kernel()?;
+        self.check_auth_token()?;
+        self.check_agent()?;
+        if let Some((ref path, format)) = self.kernel {
+            sys::set_kernel(vm.ctx, path, format, None, self.full_cmdline().as_deref())?;
+        }
+
+        self.check_tee()?;
+        if let Some(ref tee) = self.tee {
+            sys::set_tee_config_file(vm.ctx, &tee.config_file)?;
+        }
+
         if let Some(ref root) = self.root {
             sys::set_root(vm.ctx, root)?;
         } else if let Some(ref disk) = self.root_disk {
@@ -355,9 +986,19 @@ pub fn build(self) -> Result<Vm> {
             sys::add_disk2(vm.ctx, "rootfs", disk, sys_fmt, false)?;
             sys::set_root_disk_remount(vm.ctx, "/dev/vda", Some("ext4"), None)?;
         }
+        for disk in &self.data_disks {
+            sys::add_disk2(
+                vm.ctx,
+                &disk.name,
+                &disk.path,
+                sys::DiskFormat::Raw,
+                disk.read_only,
+            )?;
+        }
 
-        for (tag, host_path) in &self.virtiofs {
-            sys::add_virtiofs(vm.ctx, tag, host_path)?;
+        self.check_virtiofs()?;
+        for share in &self.virtiofs {
+            sys::add_virtiofs(vm.ctx, &share.tag, &share.path)?;
         }
 
         if !self.ports.is_empty() {
@@ -368,9 +1009,12 @@ pub fn build(self) -> Result<Vm> {
             sys::set_workdir(vm.ctx, workdir)?;
         }
 
-        if let Some(ref exec_path) = self.exec_path {
-            sys::set_exec(vm.ctx, exec_path, &self.exec_args, self.env.as_deref())?;
-        } else if let Some(ref env) = self.env {
+        let guest_env = self.guest_env();
+        if let Some(ref agent) = self.agent_path {
+            sys::set_exec(vm.ctx, agent, &[], guest_env.as_deref())?;
+        } else if let Some(ref exec_path) = self.exec_path {
+            sys::set_exec(vm.ctx, exec_path, &self.exec_args, guest_env.as_deref())?;
+        } else if let Some(ref env) = guest_env {
             sys::set_env(vm.ctx, env)?;
         }
 
@@ -426,14 +1070,31 @@ pub fn builder() -> VmBuilder {
             workdir: None,
             ports: Vec::new(),
             virtiofs: Vec::new(),
+            data_disks: Vec::new(),
             log_level: None,
             uid: None,
             gid: None,
             rlimits: Vec::new(),
             nested_virt: None,
             snd_device: None,
+            rng: None,
+            track_changes: false,
             console_output: None,
+            console_log: false,
             vsock_ports: Vec::new(),
+            guest_init: None,
+            guest_init_may_fail: false,
+            auth_token: None,
+            agent_path: None,
+            no_agent: false,
+            pid1: Pid1::Agent,
+            health_cmd: None,
+            health_interval: None,
+            health_retries: None,
+            kernel: None,
+            kernel_cmdline: None,
+            init: None,
+            tee: None,
         }
     }
 
@@ -447,11 +1108,30 @@ pub fn has_feature(feature: Feature) -> Result<bool> {
         sys::has_feature(feature)
     }
 
+    /// Loads libkrun, failing with [`Error::LibkrunNotFound`](crate::Error::LibkrunNotFound)
+    /// if it is not installed. Every other libkrun call does this implicitly.
+    pub fn check_libkrun() -> Result<()> {
+        sys::load()
+    }
+
     /// Checks if nested virtualization is supported (macOS only).
     pub fn check_nested_virt() -> Result<bool> {
         sys::check_nested_virt()
     }
 
+    /// Probes vCPU limits, every [`Feature`] and nested virtualization in
+    /// one call. A feature whose probe fails is reported as unsupported.
+    pub fn capabilities() -> Result<Capabilities> {
+        Ok(Capabilities {
+            max_vcpus: sys::get_max_vcpus()?,
+            features: Feature::ALL
+                .into_iter()
+                .map(|f| (f, sys::has_feature(f).unwrap_or(false)))
+                .collect(),
+            nested_virt: sys::check_nested_virt().ok(),
+        })
+    }
+
     /// Adds a raw disk image as a general partition.
     pub fn add_disk(&mut self, block_id: &str, path: &str, read_only: bool) -> Result<()> {
         sys::add_disk(self.ctx, block_id, path, read_only)
@@ -706,3 +1386,222 @@ fn drop(&mut self) {
         let _ = sys::free_ctx(self.ctx);
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn virtiofs_shares_are_validated_and_exported() {
+        let dir = std::env::temp_dir();
+        let tmp = dir.to_string_lossy();
+
+        let dup = Vm::builder().virtiofs("a", &*tmp).virtiofs("a", &*tmp);
+        assert!(matches!(dup.check_virtiofs(), Err(Error::InvalidConfig(_))));
+        let missing = Vm::builder().virtiofs("a", "/nonexistent/bux/share");
+        assert!(matches!(
+            missing.check_virtiofs(),
+            Err(Error::InvalidConfig(_))
+        ));
+        let spaced = Vm::builder().virtiofs_mount("a", &*tmp, "/my dir", false);
+        assert!(matches!(
+            spaced.check_virtiofs(),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        let ok = Vm::builder()
+            .env(&["A=1"])
+            .virtiofs("plain", &*tmp)
+            .virtiofs_mount("code", &*tmp, "/src", true);
+        assert!(ok.check_virtiofs().is_ok());
+        assert_eq!(
+            ok.guest_env().as_deref(),
+            Some(&["A=1".to_owned(), format!("{MOUNTS_ENV}=code:ro:/src")][..])
+        );
+
+        let init = Vm::builder()
+            .env(&["A=1"])
+            .guest_init(&["/etc/rc.local"])
+            .guest_init_may_fail(true);
+        assert_eq!(
+            init.guest_env().as_deref(),
+            Some(
+                &[
+                    "A=1".to_owned(),
+                    format!("{INIT_ENV}=continue:/etc/rc.local")
+                ][..]
+            )
+        );
+    }
+
+    #[test]
+    fn auth_token_is_validated_and_exported() {
+        let spaced = Vm::builder().auth_token("two words");
+        assert!(matches!(
+            spaced.check_auth_token(),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        let ok = Vm::builder().env(&["A=1"]).auth_token("s3cret");
+        assert!(ok.check_auth_token().is_ok());
+        assert_eq!(
+            ok.guest_env().as_deref(),
+            Some(&["A=1".to_owned(), format!("{AUTH_ENV}=s3cret")][..])
+        );
+        let rebuilt = VmBuilder::from_config(&ok.to_config());
+        assert_eq!(rebuilt.auth_token.as_deref(), Some("s3cret"));
+    }
+
+    #[test]
+    fn agent_options_are_validated_and_exported() {
+        let relative = Vm::builder().agent_path("sbin/bux-guest");
+        assert!(matches!(
+            relative.check_agent(),
+            Err(Error::InvalidConfig(_))
+        ));
+        let init = Vm::builder().guest_init(&["/bin/true"]).no_agent();
+        assert!(matches!(init.check_agent(), Err(Error::InvalidConfig(_))));
+        let unchecked = Vm::builder().health_cmd(&["/bin/true"]).no_agent();
+        assert!(matches!(
+            unchecked.check_agent(),
+            Err(Error::InvalidConfig(_))
+        ));
+        let never = Vm::builder().health_cmd(&["/bin/true"]).health_retries(0);
+        assert!(matches!(never.check_agent(), Err(Error::InvalidConfig(_))));
+        let checked = Vm::builder()
+            .health_cmd(&["/bin/true"])
+            .health_interval(Duration::from_secs(5));
+        assert!(checked.check_agent().is_ok());
+        let config = checked.to_config();
+        assert_eq!(config.health_interval, Some(Duration::from_secs(5)));
+        assert_eq!(
+            VmBuilder::from_config(&config).health_cmd,
+            config.health_cmd
+        );
+
+        let bare = Vm::builder()
+            .env(&["A=1"])
+            .exec("/sbin/init", &[])
+            .no_agent();
+        assert!(bare.check_agent().is_ok());
+        assert_eq!(bare.guest_env().as_deref(), Some(&["A=1".to_owned()][..]));
+
+        let agent = Vm::builder()
+            .env(&["A=1"])
+            .exec("/bin/sh", &["-c", "echo hi"])
+            .agent_path("/usr/local/bin/bux-guest");
+        assert!(agent.check_agent().is_ok());
+        assert_eq!(
+            agent.guest_env().as_deref(),
+            Some(&["A=1".to_owned(), format!("{MAIN_ENV}=/bin/sh,-c,echo%20hi")][..])
+        );
+    }
+
+    #[test]
+    fn pid1_handoff_needs_the_agent_and_an_init() {
+        let tini = Pid1::AgentThenExec(vec!["/sbin/tini".into(), "--".into()]);
+        let bare = Vm::builder().pid1(tini.clone()).no_agent();
+        assert!(matches!(bare.check_agent(), Err(Error::InvalidConfig(_))));
+        let empty = Vm::builder().pid1(Pid1::AgentThenExec(Vec::new()));
+        assert!(matches!(empty.check_agent(), Err(Error::InvalidConfig(_))));
+
+        let handoff = Vm::builder().env(&["A=1"]).pid1(tini);
+        assert!(handoff.check_agent().is_ok());
+        assert_eq!(
+            handoff.guest_env().as_deref(),
+            Some(&["A=1".to_owned(), format!("{PID1_ENV}=/sbin/tini,--")][..])
+        );
+        let config = handoff.to_config();
+        assert_eq!(VmBuilder::from_config(&config).pid1, config.pid1);
+    }
+
+    #[test]
+    fn rng_seed_stays_off_the_boot_environment() {
+        // The seed goes over the agent connection once it answers.
+        assert_eq!(
+            Vm::builder().env(&["A=1"]).guest_env().as_deref(),
+            Some(&["A=1".to_owned()][..])
+        );
+        let rebuilt = VmBuilder::from_config(&Vm::builder().rng(false).to_config());
+        assert_eq!(rebuilt.rng, Some(false));
+    }
+
+    #[test]
+    fn change_tracking_is_opt_in() {
+        let on = Vm::builder().env(&["A=1"]).track_changes();
+        assert_eq!(
+            on.guest_env().as_deref(),
+            Some(&["A=1".to_owned(), format!("{DIFF_ENV}=1")][..])
+        );
+        assert!(VmBuilder::from_config(&on.to_config()).track_changes);
+        assert_eq!(
+            Vm::builder().env(&["A=1"]).guest_env().as_deref(),
+            Some(&["A=1".to_owned()][..])
+        );
+        let bare = Vm::builder().track_changes().no_agent();
+        assert!(matches!(bare.check_agent(), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn kernel_options_need_an_external_kernel() {
+        let bare = Vm::builder().kernel_cmdline("quiet");
+        assert!(matches!(bare.check_kernel(), Err(Error::InvalidConfig(_))));
+        assert!(Vm::builder().check_kernel().is_ok());
+
+        let exe = std::env::current_exe().unwrap();
+        let kernel = exe.to_string_lossy();
+        let custom = Vm::builder()
+            .kernel(&*kernel, KernelFormat::Elf)
+            .kernel_cmdline("quiet console=hvc0")
+            .init("/sbin/myinit", &["--fast"]);
+        assert!(custom.check_kernel().is_ok());
+        assert_eq!(
+            custom.full_cmdline().as_deref(),
+            Some("quiet console=hvc0 init=/sbin/myinit -- --fast")
+        );
+
+        let nul = Vm::builder()
+            .kernel(&*kernel, KernelFormat::Raw)
+            .kernel_cmdline("quiet\0");
+        assert!(matches!(nul.check_kernel(), Err(Error::InvalidConfig(_))));
+        let spaced = Vm::builder()
+            .kernel(&*kernel, KernelFormat::Raw)
+            .init("/init", &["a b"]);
+        assert!(matches!(
+            spaced.check_kernel(),
+            Err(Error::InvalidConfig(_))
+        ));
+        assert_eq!("image-gz".parse(), Ok(KernelFormat::ImageGz));
+    }
+
+    #[test]
+    fn tee_config_survives_the_shim_config() {
+        assert_eq!("SEV".parse(), Ok(Tee::Sev));
+        assert!("tdx".parse::<Tee>().is_err());
+        assert_eq!(Tee::Sev.feature(), Feature::AmdSev);
+
+        let tee = TeeConfig::new(Tee::Sev, "/etc/bux/sev.json");
+        let config = Vm::builder().tee(tee.clone()).to_config();
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["tee"]["tee"], "sev");
+        assert_eq!(VmBuilder::from_config(&config).tee, Some(tee));
+    }
+
+    #[test]
+    fn capabilities_serialize_with_feature_names() {
+        let caps = Capabilities {
+            max_vcpus: 8,
+            features: Feature::ALL
+                .into_iter()
+                .map(|f| (f, f == Feature::AmdSev))
+                .collect(),
+            nested_virt: None,
+        };
+        assert_eq!(caps.supported().collect::<Vec<_>>(), [Feature::AmdSev]);
+        let json = serde_json::to_value(&caps).unwrap();
+        assert_eq!(json["features"]["amd-sev"], true);
+        assert_eq!(json["features"]["virgl-resource-map2"], false);
+        assert_eq!(json["max_vcpus"], 8);
+    }
+}
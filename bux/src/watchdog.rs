@@ -6,20 +6,26 @@
 //!
 //! This mechanism works on **all** Unix platforms, unlike
 //! `PR_SET_PDEATHSIG` which is Linux-only.
+//!
+//! The pipe also works in the other direction: once the shim exits, no
+//! reader is left and the parent's write end reports write-closed, which
+//! [`shim_exited`] turns into an awaitable event.
 
 use std::io;
-use std::os::fd::{AsBorrowedFd, BorrowedFd, OwnedFd};
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
 
 use nix::fcntl::{FcntlArg, FdFlag, fcntl};
 use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
 use nix::unistd::pipe;
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
 
 /// Parent-side handle that keeps the watchdog pipe alive.
 ///
 /// When this value is dropped, the write end of the pipe closes,
 /// causing `POLLHUP` on the shim's read end — signaling it to shut down.
 #[derive(Debug)]
-pub struct Keepalive(#[allow(dead_code)] OwnedFd);
+pub struct Keepalive(OwnedFd);
 
 /// Creates a watchdog pipe pair.
 ///
@@ -62,3 +68,21 @@ pub fn wait_for_parent_death(fd: BorrowedFd<'_>) {
         }
     }
 }
+
+/// Resolves once every process holding the read end (the shim) has exited.
+///
+/// Without readers the write end reports write-closed (`POLLERR` on Linux,
+/// `EV_EOF` on macOS), so this wakes immediately instead of polling. A
+/// duplicate of the write end is registered, so concurrent waiters on the
+/// same [`Keepalive`] do not conflict.
+pub async fn shim_exited(keepalive: &Keepalive) -> io::Result<()> {
+    let fd = AsyncFd::with_interest(keepalive.0.try_clone()?, Interest::WRITABLE)?;
+    loop {
+        let mut guard = fd.writable().await?;
+        if guard.ready().is_write_closed() {
+            return Ok(());
+        }
+        // Plain writability (the pipe has room) — wait for the next edge.
+        guard.clear_ready();
+    }
+}